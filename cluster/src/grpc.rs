@@ -0,0 +1,413 @@
+//! This module contains the gRPC implementations of `ConnectionManager` and
+//! `RemoteServer`, used to send replicated writes and queries to other IOx servers,
+//! and the `Replication` and `RemoteQuery` services that receive them.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use arrow_deps::arrow::{
+    error::{ArrowError, Result as ArrowResult},
+    ipc::{reader::StreamReader, writer::StreamWriter},
+    record_batch::RecordBatch,
+};
+use async_trait::async_trait;
+use data_types::data::ReplicatedWrite;
+use generated_types::{
+    remote_query_client::RemoteQueryClient,
+    remote_query_server::{RemoteQuery, RemoteQueryServer},
+    replication_client::ReplicationClient,
+    replication_server::{Replication, ReplicationServer},
+    QueryRequest, QueryResponse, ReplicateRequest, ReplicateResponse,
+};
+use snafu::{ResultExt, Snafu};
+use storage::{Database, DatabaseError, DatabaseStore};
+use tokio::sync::RwLock;
+use tonic::{
+    transport::{Channel, Endpoint},
+    Request, Response, Status,
+};
+
+use crate::{ConnectionManager, RemoteServer};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("error connecting to {}: {}", server, source))]
+    Connecting {
+        server: String,
+        source: tonic::transport::Error,
+    },
+
+    #[snafu(display("error replicating to {}: {}", server, source))]
+    Replicating { server: String, source: Status },
+
+    #[snafu(display("error querying {}: {}", server, source))]
+    Querying { server: String, source: Status },
+
+    #[snafu(display("error decoding query results from {}: {}", server, source))]
+    DecodingResults { server: String, source: ArrowError },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A `ConnectionManager` that talks to remote servers over gRPC. Host connection
+/// strings are either a `host:port` or a URL like `http://host:port`. A connection
+/// is made the first time a host is used and then shared by all later requests.
+#[derive(Debug, Default)]
+pub struct GrpcConnectionManager {
+    remotes: RwLock<BTreeMap<String, Arc<GrpcRemoteServer>>>,
+}
+
+impl GrpcConnectionManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ConnectionManager for GrpcConnectionManager {
+    type Error = Error;
+    type RemoteServer = GrpcRemoteServer;
+
+    async fn remote_server(&self, connect: &str) -> Result<Arc<GrpcRemoteServer>> {
+        if let Some(remote) = self.remotes.read().await.get(connect) {
+            return Ok(Arc::clone(remote));
+        }
+
+        let channel = Endpoint::new(endpoint(connect))
+            .context(Connecting { server: connect })?
+            .connect()
+            .await
+            .context(Connecting { server: connect })?;
+        let remote = GrpcRemoteServer {
+            server: connect.to_string(),
+            client: ReplicationClient::new(channel.clone()),
+            query_client: RemoteQueryClient::new(channel),
+        };
+
+        // keep the existing connection if another request got there first
+        let mut remotes = self.remotes.write().await;
+        let remote = remotes
+            .entry(connect.to_string())
+            .or_insert_with(|| Arc::new(remote));
+
+        Ok(Arc::clone(remote))
+    }
+}
+
+fn endpoint(connect: &str) -> String {
+    if connect.contains("://") {
+        connect.to_string()
+    } else {
+        format!("http://{}", connect)
+    }
+}
+
+/// A connection to a remote server's `Replication` and `RemoteQuery` gRPC services
+#[derive(Debug)]
+pub struct GrpcRemoteServer {
+    server: String,
+    client: ReplicationClient<Channel>,
+    query_client: RemoteQueryClient<Channel>,
+}
+
+#[async_trait]
+impl RemoteServer for GrpcRemoteServer {
+    type Error = Error;
+
+    async fn replicate(&self, db: &str, replicated_write: &ReplicatedWrite) -> Result<()> {
+        let request = ReplicateRequest {
+            db_name: db.to_string(),
+            replicated_write: replicated_write.data.clone(),
+        };
+
+        // clients are cheap handles to the shared channel, but calls need `&mut`
+        let mut client = self.client.clone();
+        client.replicate(request).await.context(Replicating {
+            server: &self.server,
+        })?;
+
+        Ok(())
+    }
+
+    async fn query(&self, db: &str, query: &str) -> Result<Vec<RecordBatch>> {
+        let request = QueryRequest {
+            db_name: db.to_string(),
+            query: query.to_string(),
+        };
+
+        let mut client = self.query_client.clone();
+        let response = client.query(request).await.context(Querying {
+            server: &self.server,
+        })?;
+
+        decode_batches(&response.into_inner().record_batches).context(DecodingResults {
+            server: &self.server,
+        })
+    }
+}
+
+/// Implements the `Replication` gRPC service, storing the writes it receives in the
+/// databases of a `DatabaseStore`. Writes to databases that don't exist are rejected.
+#[derive(Debug)]
+pub struct ReplicationService<T> {
+    db_store: Arc<T>,
+}
+
+impl<T> ReplicationService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    pub fn new(db_store: Arc<T>) -> Self {
+        Self { db_store }
+    }
+
+    /// Returns the tonic service, to be added to a `tonic::transport::Server`
+    pub fn into_server(self) -> ReplicationServer<Self> {
+        ReplicationServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl<T> Replication for ReplicationService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    async fn replicate(
+        &self,
+        request: Request<ReplicateRequest>,
+    ) -> Result<Response<ReplicateResponse>, Status> {
+        let ReplicateRequest {
+            db_name,
+            replicated_write,
+        } = request.into_inner();
+
+        if replicated_write.is_empty() {
+            return Err(Status::invalid_argument("replicated write is empty"));
+        }
+
+        let db = self
+            .db_store
+            .db(&db_name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("database {} not found", db_name)))?;
+
+        let write = ReplicatedWrite {
+            data: replicated_write,
+        };
+        db.store_replicated_write(&write).await.map_err(|e| {
+            let message = format!("error storing write in {}: {}", db_name, e);
+            if e.is_resource_exhausted() {
+                Status::resource_exhausted(message)
+            } else {
+                Status::internal(message)
+            }
+        })?;
+
+        Ok(Response::new(ReplicateResponse {}))
+    }
+}
+
+/// Implements the `RemoteQuery` gRPC service, answering queries from the databases of a
+/// `DatabaseStore`.
+#[derive(Debug)]
+pub struct RemoteQueryService<T> {
+    db_store: Arc<T>,
+}
+
+impl<T> RemoteQueryService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    pub fn new(db_store: Arc<T>) -> Self {
+        Self { db_store }
+    }
+
+    /// Returns the tonic service, to be added to a `tonic::transport::Server`
+    pub fn into_server(self) -> RemoteQueryServer<Self> {
+        RemoteQueryServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl<T> RemoteQuery for RemoteQueryService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    async fn query(
+        &self,
+        request: Request<QueryRequest>,
+    ) -> Result<Response<QueryResponse>, Status> {
+        let QueryRequest { db_name, query } = request.into_inner();
+
+        let db = self
+            .db_store
+            .db(&db_name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("database {} not found", db_name)))?;
+
+        let batches = db
+            .query(&query)
+            .await
+            .map_err(|e| Status::internal(format!("error querying {}: {}", db_name, e)))?;
+
+        let record_batches = encode_batches(&batches)
+            .map_err(|e| Status::internal(format!("error encoding query results: {}", e)))?;
+
+        Ok(Response::new(QueryResponse { record_batches }))
+    }
+}
+
+/// Encodes record batches, which are expected to share a schema, as an Arrow IPC stream
+fn encode_batches(batches: &[RecordBatch]) -> ArrowResult<Vec<u8>> {
+    let mut data = vec![];
+
+    if let Some(first) = batches.first() {
+        let mut writer = StreamWriter::try_new(&mut data, &first.schema())?;
+        for batch in batches {
+            writer.write(batch)?;
+        }
+        writer.finish()?;
+    }
+
+    Ok(data)
+}
+
+fn decode_batches(data: &[u8]) -> ArrowResult<Vec<RecordBatch>> {
+    if data.is_empty() {
+        return Ok(vec![]);
+    }
+
+    StreamReader::try_new(data)?.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Server;
+    use data_types::database_rules::DatabaseRules;
+    use influxdb_line_protocol::parse_lines;
+    use object_store::{InMemory, ObjectStore};
+    use std::{net::SocketAddr, time::Duration};
+    use storage::test::TestDatabaseStore;
+    use tokio::net::TcpListener;
+    use write_buffer::WriteBufferDatabases;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn replicate_between_servers() -> Result {
+        let (listener, bind_addr) = bind_any_port().await?;
+        let receiver = Arc::new(TestDatabaseStore::new());
+        receiver.db_or_create("foo").await?;
+
+        let grpc_server = tonic::transport::Server::builder()
+            .add_service(ReplicationService::new(Arc::clone(&receiver)).into_server())
+            .serve_with_incoming(listener);
+        tokio::task::spawn(grpc_server);
+
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut router = Server::new(GrpcConnectionManager::new(), store);
+        router.set_id(1);
+
+        wait_for_connection(&router.connection_manager, &bind_addr.to_string()).await?;
+
+        router
+            .create_host_group("az1".to_string(), vec![bind_addr.to_string()])
+            .await?;
+        let rules = DatabaseRules {
+            replication: vec!["az1".to_string()],
+            replication_count: 1,
+            ..Default::default()
+        };
+        router.create_database("foo", rules).await?;
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").collect::<Result<_, _>>()?;
+        router.write_lines("foo", &lines).await?;
+
+        let db = receiver.db("foo").await.expect("database exists");
+        let writes = db.get_writes().await;
+        assert_eq!(writes.len(), 1);
+
+        // writes to databases the receiver doesn't have are rejected
+        let remote = router
+            .connection_manager
+            .remote_server(&bind_addr.to_string())
+            .await?;
+        let err = remote.replicate("bar", &writes[0]).await.unwrap_err();
+        match &err {
+            Error::Replicating { source, .. } => {
+                assert_eq!(source.code(), tonic::Code::NotFound, "{}", err)
+            }
+            _ => panic!("unexpected error {}", err),
+        }
+        assert!(receiver.db("bar").await.is_none());
+
+        let write_text = r#"
+writer:1, sequence:1, checksum:226387645
+partition_key:
+  table:cpu
+    bar:1 time:10
+"#;
+        assert_eq!(writes[0].to_string(), write_text);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_remote_server() -> Result {
+        let (listener, bind_addr) = bind_any_port().await?;
+        let dir = test_helpers::tmp_dir()?;
+        let receiver = Arc::new(WriteBufferDatabases::new(dir.path()));
+
+        let db = receiver.db_or_create("foo").await?;
+        let lines: Vec<_> = parse_lines("cpu bar=1 10\ncpu bar=2 20").collect::<Result<_, _>>()?;
+        db.write_lines(&lines).await?;
+
+        let grpc_server = tonic::transport::Server::builder()
+            .add_service(RemoteQueryService::new(Arc::clone(&receiver)).into_server())
+            .serve_with_incoming(listener);
+        tokio::task::spawn(grpc_server);
+
+        let manager = GrpcConnectionManager::new();
+        wait_for_connection(&manager, &bind_addr.to_string()).await?;
+        let remote = manager.remote_server(&bind_addr.to_string()).await?;
+
+        let batches = remote.query("foo", "select bar, time from cpu").await?;
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 2);
+        assert_eq!(batches[0].schema().field(0).name(), "bar");
+
+        let err = remote.query("bar", "select * from cpu").await.unwrap_err();
+        assert!(matches!(err, Error::Querying { .. }), "{}", err);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unreachable_server() {
+        let manager = GrpcConnectionManager::new();
+
+        let err = manager.remote_server("127.0.0.1:1").await.unwrap_err();
+        assert!(matches!(err, Error::Connecting { .. }), "{}", err);
+    }
+
+    /// Binds a listener to a port picked by the OS, so tests can run in parallel
+    async fn bind_any_port() -> Result<(TcpListener, SocketAddr)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        Ok((listener, addr))
+    }
+
+    /// Tries connecting to the server for 5 seconds, to give it time to start up
+    async fn wait_for_connection(manager: &GrpcConnectionManager, server: &str) -> Result {
+        let mut retries = 0;
+        loop {
+            match manager.remote_server(server).await {
+                Ok(_) => return Ok(()),
+                Err(e) if retries >= 10 => return Err(e.into()),
+                Err(_) => retries += 1,
+            }
+            tokio::time::delay_for(Duration::from_millis(500)).await;
+        }
+    }
+}
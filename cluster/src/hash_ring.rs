@@ -0,0 +1,158 @@
+//! A consistent hash ring used to pick which host in a `HostGroup` receives the
+//! data for a given partition key.
+//!
+//! Each host is placed on the ring at `VIRTUAL_NODES_PER_HOST` points so that keys
+//! are spread evenly, and adding or removing a host only moves the keys that land
+//! next to that host's points. The hash function is fixed (64 bit FNV-1a followed
+//! by the splitmix64 finalizer, which spreads out similar short keys) rather than
+//! `std`'s randomly seeded hasher so that every router agrees on the owner of a key.
+
+use std::collections::BTreeMap;
+
+/// The number of points each host gets on the ring
+const VIRTUAL_NODES_PER_HOST: usize = 100;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+#[derive(Debug, Clone, Default)]
+pub struct HashRing {
+    /// Maps a point on the ring to the index of a host in `hosts`
+    ring: BTreeMap<u64, usize>,
+    hosts: Vec<String>,
+}
+
+impl HashRing {
+    pub fn new(hosts: &[String]) -> Self {
+        let mut hosts = hosts.to_vec();
+        hosts.sort();
+        hosts.dedup();
+
+        let mut ring = BTreeMap::new();
+        for (idx, host) in hosts.iter().enumerate() {
+            for vnode in 0..VIRTUAL_NODES_PER_HOST {
+                let point = hash(format!("{}-{}", host, vnode).as_bytes());
+                // on the (very unlikely) collision the lowest sorted host keeps the point
+                ring.entry(point).or_insert(idx);
+            }
+        }
+
+        Self { ring, hosts }
+    }
+
+    /// Returns every host on the ring in the order they should be tried for `key`. The
+    /// first host owns the key, the rest are the remaining hosts met walking clockwise
+    /// around the ring, to be used as fallbacks.
+    pub fn hosts_for(&self, key: &str) -> Vec<&str> {
+        let point = hash(key.as_bytes());
+
+        let mut seen = vec![false; self.hosts.len()];
+        let mut hosts = Vec::with_capacity(self.hosts.len());
+
+        let clockwise = self.ring.range(point..).chain(self.ring.range(..point));
+        for (_, &idx) in clockwise {
+            if !seen[idx] {
+                seen[idx] = true;
+                hosts.push(self.hosts[idx].as_str());
+
+                if hosts.len() == self.hosts.len() {
+                    break;
+                }
+            }
+        }
+
+        hosts
+    }
+}
+
+fn hash(data: &[u8]) -> u64 {
+    let hash = data.iter().fold(FNV_OFFSET_BASIS, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(FNV_PRIME)
+    });
+
+    let hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn keys() -> Vec<String> {
+        (0..1000)
+            .map(|i| format!("2020-10-{}T{}", i % 30, i))
+            .collect()
+    }
+
+    #[test]
+    fn empty_ring() {
+        let ring = HashRing::new(&[]);
+        assert!(ring.hosts_for("foo").is_empty());
+    }
+
+    #[test]
+    fn assignment_is_stable() {
+        let ring = HashRing::new(&hosts(&["a", "b", "c"]));
+        let other = HashRing::new(&hosts(&["c", "a", "b", "a"]));
+
+        for key in keys() {
+            let owners = ring.hosts_for(&key);
+            assert_eq!(owners, other.hosts_for(&key));
+
+            let mut sorted = owners.clone();
+            sorted.sort();
+            assert_eq!(sorted, vec!["a", "b", "c"]);
+        }
+    }
+
+    #[test]
+    fn keys_are_spread_across_hosts() {
+        let ring = HashRing::new(&hosts(&["a", "b", "c"]));
+
+        let mut counts = BTreeMap::new();
+        for key in keys() {
+            *counts.entry(ring.hosts_for(&key)[0]).or_insert(0) += 1;
+        }
+
+        assert_eq!(counts.len(), 3);
+        for (host, count) in counts {
+            assert!(count > 200, "host {} only owns {} keys", host, count);
+        }
+    }
+
+    #[test]
+    fn adding_a_host_only_moves_keys_to_it() {
+        let before = HashRing::new(&hosts(&["a", "b", "c"]));
+        let after = HashRing::new(&hosts(&["a", "b", "c", "d"]));
+
+        let mut moved = 0;
+        for key in keys() {
+            let old = before.hosts_for(&key)[0];
+            let new = after.hosts_for(&key)[0];
+            if old != new {
+                assert_eq!(new, "d");
+                moved += 1;
+            }
+        }
+
+        assert!(moved > 0);
+        assert!(moved < 500, "{} keys moved", moved);
+    }
+
+    #[test]
+    fn removing_a_host_falls_back_to_next() {
+        let before = HashRing::new(&hosts(&["a", "b", "c"]));
+        let after = HashRing::new(&hosts(&["a", "c"]));
+
+        for key in keys() {
+            let old = before.hosts_for(&key);
+            let expected: Vec<_> = old.into_iter().filter(|&h| h != "b").collect();
+            assert_eq!(after.hosts_for(&key), expected);
+        }
+    }
+}
@@ -63,18 +63,24 @@
     clippy::use_self
 )]
 
+pub mod grpc;
+mod hash_ring;
+pub mod matcher;
+mod merge;
+
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet, VecDeque},
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, Mutex,
     },
+    time::Duration,
 };
 
 use arrow_deps::arrow::record_batch::RecordBatch;
 use data_types::{
-    data::{lines_to_replicated_write, ReplicatedWrite},
-    database_rules::{DatabaseRules, HostGroup, HostGroupId, MatchTables},
+    data::{lines_to_replicated_write, ReplicatedWrite, WriteFilter},
+    database_rules::{DatabaseRules, HostGroup, HostGroupId, QueueFullPolicy},
 };
 use influxdb_line_protocol::ParsedLine;
 use object_store::ObjectStore;
@@ -83,9 +89,15 @@ use write_buffer::Db as WriteBufferDb;
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::stream::TryStreamExt;
+use chrono::Utc;
+use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use hash_ring::HashRing;
+use matcher::MatcherFilter;
 
 type DatabaseError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -110,6 +122,37 @@ pub enum Error {
     },
     #[snafu(display("error replicating to remote: {}", source))]
     ErrorReplicating { source: DatabaseError },
+    #[snafu(display("error querying remote server {}: {}", server, source))]
+    ErrorQuerying {
+        server: String,
+        source: DatabaseError,
+    },
+    #[snafu(display("no local buffer or query group to query for database: {}", db))]
+    NoQueryTarget { db: String },
+    #[snafu(display("error merging query results: {}", source))]
+    MergingResults { source: merge::Error },
+    #[snafu(display(
+        "replicated to {} of {} required host groups: {}",
+        replicated,
+        required,
+        source
+    ))]
+    ReplicationQuorumNotMet {
+        replicated: usize,
+        required: usize,
+        source: Box<Error>,
+    },
+    #[snafu(display("replication queue full for database: {}", db))]
+    ReplicationQueueFull { db: String },
+    #[snafu(display("error computing partition key: {}", source))]
+    ErrorComputingPartitionKey {
+        source: data_types::database_rules::Error,
+    },
+    #[snafu(display("invalid matcher for subscription {}: {}", name, source))]
+    InvalidSubscription {
+        name: String,
+        source: matcher::Error,
+    },
     #[snafu(display("unable to use server until id is set"))]
     IdNotSet,
     #[snafu(display("error serializing configuration {}", source))]
@@ -118,6 +161,17 @@ pub enum Error {
     ErrorDeserializing { source: serde_json::Error },
     #[snafu(display("store error: {}", source))]
     StoreError { source: object_store::Error },
+    #[snafu(display("invalid sequence stored at {}: {}", location, source))]
+    InvalidStoredSequence {
+        location: String,
+        source: std::num::ParseIntError,
+    },
+    #[snafu(display(
+        "write to database {} with timestamp {} is outside the retention period",
+        db,
+        timestamp
+    ))]
+    WriteOutsideRetention { db: String, timestamp: i64 },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -129,7 +183,9 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub struct Server<M: ConnectionManager> {
     config: Config,
     connection_manager: M,
-    store: ObjectStore,
+    store: Arc<ObjectStore>,
+    // hash rings over the hosts of each host group, kept in sync with `config.host_groups`
+    host_group_rings: BTreeMap<HostGroupId, HashRing>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
@@ -144,8 +200,9 @@ impl<M: ConnectionManager> Server<M> {
     pub fn new(connection_manager: M, store: ObjectStore) -> Self {
         Self {
             config: Config::default(),
-            store,
+            store: Arc::new(store),
             connection_manager,
+            host_group_rings: BTreeMap::new(),
         }
     }
 
@@ -165,21 +222,34 @@ impl<M: ConnectionManager> Server<M> {
         db_name: impl Into<String>,
         rules: DatabaseRules,
     ) -> Result<()> {
-        self.require_id()?;
+        let id = self.require_id()?;
 
         let db_name = db_name.into();
+        let subscription_filters = subscription_filters(&rules)?;
+        let sequence = Sequence::new(self.stored_sequence(id, &db_name).await?);
 
         let buffer = if rules.store_locally {
-            Some(WriteBufferDb::new(&db_name))
+            let buffer = WriteBufferDb::new_with_rules(&db_name, rules.clone())
+                .with_object_store(Arc::clone(&self.store), Some(id));
+            if !rules.read_only_partitions.is_empty() {
+                buffer
+                    .load_read_only_partitions(&self.store)
+                    .await
+                    .map_err(|e| Box::new(e) as DatabaseError)
+                    .context(UnknownDatabaseError {})?;
+            }
+            Some(buffer)
         } else {
             None
         };
 
-        let sequence = AtomicU64::new(STARTING_SEQUENCE);
         let db = Db {
             rules,
             buffer,
             sequence,
+            replication_queue: ReplicationQueue::default(),
+            unreplicated_writes: UnreplicatedWrites::default(),
+            subscription_filters,
         };
 
         self.config.databases.insert(db_name, db);
@@ -193,6 +263,8 @@ impl<M: ConnectionManager> Server<M> {
     pub async fn create_host_group(&mut self, id: HostGroupId, hosts: Vec<String>) -> Result<()> {
         self.require_id()?;
 
+        self.host_group_rings
+            .insert(id.clone(), HashRing::new(&hosts));
         self.config
             .host_groups
             .insert(id.clone(), HostGroup { id, hosts });
@@ -206,38 +278,24 @@ impl<M: ConnectionManager> Server<M> {
         let id = self.require_id()?;
 
         let data = Bytes::from(serde_json::to_vec(&self.config).context(ErrorSerializing)?);
-        let len = data.len();
-        let location = config_location(id);
-
-        let stream_data = std::io::Result::Ok(data);
-        self.store
-            .put(
-                &location,
-                futures::stream::once(async move { stream_data }),
-                len,
-            )
-            .await
-            .context(StoreError)?;
-
-        Ok(())
+        self.put_object(&config_location(id), data).await
     }
 
     /// Loads the configuration for this server from the configured store. This replaces
     /// any in-memory configuration that might already be set.
     pub async fn load_configuration(&mut self, id: u32) -> Result<()> {
-        let location = config_location(id);
-
-        let read_data = self
-            .store
-            .get(&location)
-            .await
-            .context(StoreError)?
-            .map_ok(|b| bytes::BytesMut::from(&b[..]))
-            .try_concat()
-            .await
-            .context(StoreError)?;
+        let read_data = self.get_object(&config_location(id)).await?;
 
-        let config: Config = serde_json::from_slice(&read_data).context(ErrorDeserializing)?;
+        let mut config: Config = serde_json::from_slice(&read_data).context(ErrorDeserializing)?;
+        for (db_name, db) in &mut config.databases {
+            db.subscription_filters = subscription_filters(&db.rules)?;
+            db.sequence = Sequence::new(self.stored_sequence(id, db_name).await?);
+        }
+        self.host_group_rings = config
+            .host_groups
+            .iter()
+            .map(|(id, group)| (id.clone(), HashRing::new(&group.hosts)))
+            .collect();
         self.config = config;
 
         Ok(())
@@ -255,12 +313,105 @@ impl<M: ConnectionManager> Server<M> {
             .get(db_name)
             .context(DatabaseNotFound { db: db_name })?;
 
-        let sequence = db.next_sequence();
-        let write = lines_to_replicated_write(id, sequence, lines, &db.rules);
+        if let Some(timestamp) = db.rules.expired_timestamp(lines, &Utc::now()) {
+            return WriteOutsideRetention {
+                db: db_name,
+                timestamp,
+            }
+            .fail();
+        }
+
+        let sequence = self.next_sequence(id, db_name, db).await?;
+        let mut write = lines_to_replicated_write(id, sequence, lines, &db.rules)
+            .context(ErrorComputingPartitionKey)?;
 
-        self.handle_replicated_write(db_name, db, write).await?;
+        // a retry of a write that missed the replication quorum keeps its sequence, so
+        // that the buffer and host groups it already reached don't apply it twice
+        if let Some(sequence) = db.unreplicated_writes.sequence_of(&write) {
+            write = lines_to_replicated_write(id, sequence, lines, &db.rules)
+                .context(ErrorComputingPartitionKey)?;
+        }
 
-        Ok(())
+        match self.handle_replicated_write(db_name, db, &write).await {
+            Err(e @ Error::ReplicationQuorumNotMet { .. }) => {
+                db.unreplicated_writes.insert(&write);
+                Err(e)
+            }
+            Err(e) => Err(e),
+            Ok(()) => {
+                db.unreplicated_writes.remove(&write);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the next sequence number for a write to the database. Sequence numbers are
+    /// reserved in blocks of `SEQUENCE_BLOCK_SIZE` and the end of each block is persisted
+    /// in the store before any number in it is used, so after a restart the server carries
+    /// on from there and the writer id and sequence of a write stay unique.
+    async fn next_sequence(&self, id: u32, db_name: &str, db: &Db) -> Result<u64> {
+        let mut state = db.sequence.state.lock().await;
+
+        if state.next >= state.reserved_until {
+            let reserved_until = state.next + SEQUENCE_BLOCK_SIZE;
+            let data = Bytes::from(reserved_until.to_string());
+            self.put_object(&sequence_location(id, db_name), data)
+                .await?;
+            state.reserved_until = reserved_until;
+        }
+
+        let sequence = state.next;
+        state.next += 1;
+
+        Ok(sequence)
+    }
+
+    /// Returns the sequence number the database continues from: the end of the last block
+    /// reserved by this server, or `STARTING_SEQUENCE` if it hasn't written to it before.
+    async fn stored_sequence(&self, id: u32, db_name: &str) -> Result<u64> {
+        let location = sequence_location(id, db_name);
+
+        let stored: Vec<String> = self
+            .store
+            .list(Some(&location))
+            .await
+            .context(StoreError)?
+            .try_concat()
+            .await
+            .context(StoreError)?;
+        if !stored.contains(&location) {
+            return Ok(STARTING_SEQUENCE);
+        }
+
+        let data = self.get_object(&location).await?;
+        String::from_utf8_lossy(&data)
+            .trim()
+            .parse()
+            .context(InvalidStoredSequence { location })
+    }
+
+    async fn put_object(&self, location: &str, data: Bytes) -> Result<()> {
+        let len = data.len();
+        let stream_data = std::io::Result::Ok(data);
+        self.store
+            .put(
+                location,
+                futures::stream::once(async move { stream_data }),
+                len,
+            )
+            .await
+            .context(StoreError)
+    }
+
+    async fn get_object(&self, location: &str) -> Result<bytes::BytesMut> {
+        self.store
+            .get(location)
+            .await
+            .context(StoreError)?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .context(StoreError)
     }
 
     /// Executes a query against the local write buffer database, if one exists.
@@ -278,70 +429,369 @@ impl<M: ConnectionManager> Server<M> {
             .context(UnknownDatabaseError {})
     }
 
+    /// Runs the query against the database as its rules describe: on the local write buffer
+    /// if `query_local` is set and, if there is a `primary_query_group`, on every host of that
+    /// group. A host that can't be queried is replaced by the host in the same position of the
+    /// first of the `secondary_query_groups` that can be.
+    ///
+    /// When more than one server answers, their results are merged as described in the
+    /// `merge` module: the rows of the local buffer come first, followed by those of each
+    /// position in the group, with their schemas unified. Queries whose partial results
+    /// can't be merged that way, such as aggregates, are rejected before being run.
+    pub async fn query(&self, db_name: &str, query: &str) -> Result<Vec<RecordBatch>> {
+        let db = self
+            .config
+            .databases
+            .get(db_name)
+            .context(DatabaseNotFound { db: db_name })?;
+        let rules = &db.rules;
+
+        ensure!(
+            rules.query_local || rules.primary_query_group.is_some(),
+            NoQueryTarget { db: db_name }
+        );
+
+        let primary_hosts = match &rules.primary_query_group {
+            Some(primary_id) => self.host_group(primary_id)?.hosts.len(),
+            None => 0,
+        };
+        let sources = primary_hosts + if rules.query_local { 1 } else { 0 };
+        if sources > 1 {
+            merge::check_mergeable(query).context(MergingResults)?;
+        }
+
+        let mut batches = if rules.query_local {
+            self.query_local(db_name, query).await?
+        } else {
+            vec![]
+        };
+
+        if let Some(primary_id) = &rules.primary_query_group {
+            let primary = self.host_group(primary_id)?;
+            let secondaries = rules
+                .secondary_query_groups
+                .iter()
+                .map(|id| self.host_group(id))
+                .collect::<Result<Vec<_>>>()?;
+
+            let positions = primary.hosts.iter().enumerate().map(|(position, host)| {
+                let hosts: Vec<_> = std::iter::once(host.as_str())
+                    .chain(
+                        secondaries
+                            .iter()
+                            .filter_map(|group| group.hosts.get(position))
+                            .map(String::as_str),
+                    )
+                    .collect();
+
+                async move { self.query_hosts(&hosts, db_name, query).await }
+            });
+
+            for results in futures::future::try_join_all(positions).await? {
+                batches.extend(results);
+            }
+        }
+
+        if sources > 1 {
+            batches = merge::merge_batches(batches).context(MergingResults)?;
+        }
+
+        Ok(batches)
+    }
+
+    /// Runs the query on the first of `hosts` that answers it.
+    async fn query_hosts(
+        &self,
+        hosts: &[&str],
+        db_name: &str,
+        query: &str,
+    ) -> Result<Vec<RecordBatch>> {
+        let mut last_error = None;
+
+        for &host in hosts {
+            let result = match self.connection_manager.remote_server(host).await {
+                Ok(connection) => connection
+                    .query(db_name, query)
+                    .await
+                    .map_err(|e| Box::new(e) as DatabaseError)
+                    .context(ErrorQuerying { server: host }),
+                Err(e) => Err(Box::new(e) as DatabaseError)
+                    .context(UnableToGetConnection { server: host }),
+            };
+
+            match result {
+                Ok(batches) => return Ok(batches),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.expect("hosts should not be empty"))
+    }
+
+    fn host_group(&self, id: &str) -> Result<&HostGroup> {
+        self.config
+            .host_groups
+            .get(id)
+            .context(HostGroupNotFound { id })
+    }
+
     pub async fn handle_replicated_write(
         &self,
         db_name: &str,
         db: &Db,
-        write: ReplicatedWrite,
+        write: &ReplicatedWrite,
     ) -> Result<()> {
+        // reject the write before anything commits it if the host groups it could miss
+        // wouldn't fit in the replication queue
+        db.replication_queue
+            .check_capacity(db_name, missable_host_groups(&db.rules), &db.rules)?;
+
         if let Some(buf) = &db.buffer {
-            buf.store_replicated_write(&write)
+            buf.store_replicated_write(write)
                 .await
                 .map_err(|e| Box::new(e) as DatabaseError)
                 .context(UnknownDatabaseError {})?;
         }
 
-        for host_group_id in &db.rules.replication {
-            self.replicate_to_host_group(host_group_id, db_name, &write)
-                .await?;
+        self.replicate(db_name, db, write).await?;
+
+        let subscriptions = db.rules.subscriptions.iter().zip(&db.subscription_filters);
+        for (subscription, filter) in subscriptions {
+            if let Some(write) = filter.filter(write) {
+                self.replicate_to_host_group(&subscription.host_group_id, db_name, &write)
+                    .await?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replicates the write to the host groups in the `replication` rules of the database.
+    /// Returns once `replication_count` host groups have acknowledged the write. The host
+    /// groups that failed or hadn't responded by then are added to the database's
+    /// replication queue, to be retried by `process_replication_queue`.
+    async fn replicate(&self, db_name: &str, db: &Db, write: &ReplicatedWrite) -> Result<()> {
+        let host_groups = &db.rules.replication;
+        if host_groups.is_empty() {
+            return Ok(());
         }
 
-        for subscription in &db.rules.subscriptions {
-            match subscription.matcher.tables {
-                MatchTables::All => {
-                    self.replicate_to_host_group(&subscription.host_group_id, db_name, &write)
-                        .await?
+        let required = required_host_groups(&db.rules);
+
+        let mut pending: FuturesUnordered<_> = host_groups
+            .iter()
+            .map(|id| async move { (id, self.replicate_to_host_group(id, db_name, write).await) })
+            .collect();
+
+        let mut replicated = BTreeSet::new();
+        let mut last_error = None;
+        while let Some((id, result)) = pending.next().await {
+            match result {
+                Ok(()) => {
+                    replicated.insert(id);
+                    if replicated.len() >= required {
+                        break;
+                    }
                 }
-                MatchTables::Table(_) => unimplemented!(),
-                MatchTables::Regex(_) => unimplemented!(),
+                Err(e) => last_error = Some(e),
             }
         }
+        // anything still in flight is retried from the queue instead
+        drop(pending);
+
+        if replicated.len() < required {
+            return Err(Error::ReplicationQuorumNotMet {
+                replicated: replicated.len(),
+                required,
+                source: Box::new(last_error.expect("a host group should have failed")),
+            });
+        }
+
+        let missed: Vec<_> = host_groups
+            .iter()
+            .filter(|id| !replicated.contains(id))
+            .cloned()
+            .collect();
+
+        if !missed.is_empty() {
+            db.replication_queue.push(missed, write, &db.rules);
+        }
 
         Ok(())
     }
 
+    /// Retries the writes waiting in the replication queue of the database. Writes that get
+    /// replicated are removed from the queue, the others are kept for the next call. This is
+    /// called periodically by the task started with `start_replication_queue_task`. Returns
+    /// the number of queued writes that were replicated.
+    pub async fn process_replication_queue(&self, db_name: &str) -> Result<usize> {
+        let db = self
+            .config
+            .databases
+            .get(db_name)
+            .context(DatabaseNotFound { db: db_name })?;
+
+        let mut replicated = 0;
+        for queued in db.replication_queue.entries() {
+            let result = self
+                .replicate_to_host_group(&queued.host_group_id, db_name, &queued.write)
+                .await;
+
+            if result.is_ok() {
+                db.replication_queue.remove(queued.id);
+                replicated += 1;
+            }
+        }
+
+        Ok(replicated)
+    }
+
+    /// Drops the partitions of the local write buffer of the database that hold only data
+    /// older than its retention period, and deletes the expired partitions it snapshotted to
+    /// the object store. This is meant to be called periodically from a background task.
+    /// Returns the number of partitions dropped or deleted.
+    pub async fn apply_retention(&self, db_name: &str) -> Result<usize> {
+        let id = self.require_id()?;
+
+        let db = self
+            .config
+            .databases
+            .get(db_name)
+            .context(DatabaseNotFound { db: db_name })?;
+
+        let buffer = match &db.buffer {
+            Some(buffer) => buffer,
+            None => return Ok(0),
+        };
+
+        let now = Utc::now();
+        let dropped = buffer
+            .drop_expired_partitions(&now)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(UnknownDatabaseError {})?;
+        let deleted = buffer
+            .delete_expired_snapshots(&self.store, id, &now)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(UnknownDatabaseError {})?;
+
+        Ok(dropped.len() + deleted.len())
+    }
+
+    /// Returns the counters of the replication queue of the database.
+    pub fn replication_queue_stats(&self, db_name: &str) -> Result<ReplicationQueueStats> {
+        let db = self
+            .config
+            .databases
+            .get(db_name)
+            .context(DatabaseNotFound { db: db_name })?;
+
+        Ok(db.replication_queue.stats())
+    }
+
+    /// Sends the write to the host group. Each partition key in the write is routed to a
+    /// host picked by consistent hashing of the key, so a write spanning several partitions
+    /// may be split across multiple hosts. If a connection to the chosen host can't be
+    /// established, the next host on the ring is tried.
     async fn replicate_to_host_group(
         &self,
         host_group_id: &str,
         db_name: &str,
         write: &ReplicatedWrite,
     ) -> Result<()> {
-        let group = self
-            .config
-            .host_groups
+        let ring = self
+            .host_group_rings
             .get(host_group_id)
             .context(HostGroupNotFound { id: host_group_id })?;
 
-        let host = group
-            .hosts
-            .get(0)
-            .context(NoHostInGroup { id: host_group_id })?;
+        // group the partition keys by the hosts that should receive them, in order
+        let mut routes: BTreeMap<Vec<&str>, BTreeSet<&str>> = BTreeMap::new();
+        for partition_key in write.partition_keys() {
+            let hosts = ring.hosts_for(partition_key);
+            ensure!(!hosts.is_empty(), NoHostInGroup { id: host_group_id });
 
-        let connection = self
-            .connection_manager
-            .remote_server(host)
-            .await
-            .map_err(|e| Box::new(e) as DatabaseError)
-            .context(UnableToGetConnection { server: host })?;
+            routes.entry(hosts).or_default().insert(partition_key);
+        }
 
-        connection
-            .replicate(db_name, write)
-            .await
-            .map_err(|e| Box::new(e) as DatabaseError)
-            .context(ErrorReplicating {})?;
+        if routes.len() == 1 {
+            let hosts = routes.keys().next().expect("checked length");
+            return self.replicate_to_hosts(hosts, db_name, write).await;
+        }
+
+        for (hosts, partition_keys) in &routes {
+            let write = write.filter(&PartitionKeys(partition_keys));
+
+            if let Some(write) = write {
+                self.replicate_to_hosts(hosts, db_name, &write).await?;
+            }
+        }
 
         Ok(())
     }
+
+    /// Sends the write to the first of `hosts` that a connection can be made to.
+    async fn replicate_to_hosts(
+        &self,
+        hosts: &[&str],
+        db_name: &str,
+        write: &ReplicatedWrite,
+    ) -> Result<()> {
+        let mut connection_error = None;
+
+        for &host in hosts {
+            let connection = match self.connection_manager.remote_server(host).await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    connection_error = Some(
+                        Err(Box::new(e) as DatabaseError)
+                            .context(UnableToGetConnection { server: host }),
+                    );
+                    continue;
+                }
+            };
+
+            return connection
+                .replicate(db_name, write)
+                .await
+                .map_err(|e| Box::new(e) as DatabaseError)
+                .context(ErrorReplicating {});
+        }
+
+        connection_error.expect("hosts should not be empty")
+    }
+}
+
+/// Starts a task that retries the writes in the replication queues of all the databases
+/// of the server every `interval`
+pub fn start_replication_queue_task<M>(server: Arc<Server<M>>, interval: Duration) -> JoinHandle<()>
+where
+    M: ConnectionManager + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+
+        loop {
+            ticks.tick().await;
+
+            for db_name in server.config.databases.keys() {
+                match server.process_replication_queue(db_name).await {
+                    Ok(0) => (),
+                    Ok(replicated) => info!("{} replicated {} queued writes", db_name, replicated),
+                    Err(e) => error!("Error processing replication queue of {}: {}", db_name, e),
+                }
+            }
+        }
+    })
+}
+
+/// Keeps only the entries of a `ReplicatedWrite` for a set of partition keys
+struct PartitionKeys<'a>(&'a BTreeSet<&'a str>);
+
+impl WriteFilter for PartitionKeys<'_> {
+    fn keep_partition(&self, partition_key: &str) -> bool {
+        self.0.contains(partition_key)
+    }
 }
 
 /// The `Server` will ask the `ConnectionManager` for connections to a specific remote server.
@@ -367,6 +817,9 @@ pub trait RemoteServer {
         db: &str,
         replicated_write: &ReplicatedWrite,
     ) -> Result<(), Self::Error>;
+
+    /// Runs a query against a database on the remote server, returning its results.
+    async fn query(&self, db: &str, query: &str) -> Result<Vec<RecordBatch>, Self::Error>;
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -376,7 +829,14 @@ pub struct Db {
     #[serde(skip)]
     pub buffer: Option<WriteBufferDb>,
     #[serde(skip)]
-    sequence: AtomicU64,
+    sequence: Sequence,
+    #[serde(skip)]
+    replication_queue: ReplicationQueue,
+    #[serde(skip)]
+    unreplicated_writes: UnreplicatedWrites,
+    // compiled matchers of `rules.subscriptions`, in the same order
+    #[serde(skip)]
+    subscription_filters: Vec<MatcherFilter>,
 }
 
 impl PartialEq for Db {
@@ -388,65 +848,296 @@ impl Eq for Db {}
 
 const STARTING_SEQUENCE: u64 = 1;
 
-impl Db {
-    fn next_sequence(&self) -> u64 {
-        self.sequence.fetch_add(1, Ordering::SeqCst)
+/// The number of sequence numbers reserved, and persisted, at a time
+const SEQUENCE_BLOCK_SIZE: u64 = 10_000;
+
+/// The sequence numbers of a database's writes
+#[derive(Debug)]
+struct Sequence {
+    state: tokio::sync::Mutex<SequenceState>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SequenceState {
+    next: u64,
+    // the sequence numbers below this have been persisted as used
+    reserved_until: u64,
+}
+
+impl Sequence {
+    fn new(next: u64) -> Self {
+        Self {
+            state: tokio::sync::Mutex::new(SequenceState {
+                next,
+                reserved_until: next,
+            }),
+        }
     }
 }
 
-// location in the store for the configuration file
-fn config_location(id: u32) -> String {
-    format!("{}/config.json", id)
+impl Default for Sequence {
+    fn default() -> Self {
+        Self::new(STARTING_SEQUENCE)
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use arrow_deps::arrow::{csv, util::string_writer::StringWriter};
-    use async_trait::async_trait;
-    use data_types::database_rules::{MatchTables, Matcher, Subscription};
-    use futures::TryStreamExt;
-    use influxdb_line_protocol::parse_lines;
-    use object_store::{InMemory, ObjectStoreIntegration};
-    use snafu::Snafu;
-    use std::sync::Mutex;
+/// Counters for the replication queue of a database. Writes are counted once for each
+/// host group they are queued for.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ReplicationQueueStats {
+    /// Number of writes currently waiting in the queue
+    pub len: usize,
+    /// Total number of writes added to the queue
+    pub queued: u64,
+    /// Total number of queued writes that were later replicated
+    pub replicated: u64,
+    /// Total number of queued writes dropped to make room for newer ones
+    pub dropped: u64,
+    /// Total number of incoming writes rejected because the queue was full
+    pub rejected: u64,
+}
 
-    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
-    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+/// In-memory queue of writes that still have to be sent to some of a database's
+/// replication host groups.
+#[derive(Debug, Default)]
+struct ReplicationQueue {
+    writes: Mutex<VecDeque<QueuedWrite>>,
+    next_id: AtomicU64,
+    queued: AtomicU64,
+    replicated: AtomicU64,
+    dropped: AtomicU64,
+    rejected: AtomicU64,
+}
 
-    #[tokio::test]
-    async fn server_api_calls_return_error_with_no_id_set() -> Result {
-        let manager = TestConnectionManager::new();
-        let store = ObjectStore::new_in_memory(InMemory::new());
-        let mut server = Server::new(manager, store);
+#[derive(Debug, Clone)]
+struct QueuedWrite {
+    id: u64,
+    host_group_id: HostGroupId,
+    write: Arc<ReplicatedWrite>,
+}
 
-        let rules = DatabaseRules::default();
-        let resp = server.create_database("foo", rules).await.unwrap_err();
-        assert!(matches!(resp, Error::IdNotSet));
+impl ReplicationQueue {
+    /// Returns an error if the rules reject writes when the queue is full and queueing
+    /// a write for `host_groups` more host groups would make it longer than
+    /// `replication_queue_max_size`. This is checked before a write is stored or
+    /// replicated anywhere, so a rejected write can be retried as is.
+    fn check_capacity(
+        &self,
+        db_name: &str,
+        host_groups: usize,
+        rules: &DatabaseRules,
+    ) -> Result<()> {
+        let max_size = rules.replication_queue_max_size;
 
-        let lines = parsed_lines("cpu foo=1 10");
-        let resp = server.write_lines("foo", &lines).await.unwrap_err();
-        assert!(matches!(resp, Error::IdNotSet));
+        // a queue size of zero means failures are always dropped
+        if host_groups == 0
+            || max_size == 0
+            || rules.replication_queue_full_policy != QueueFullPolicy::Reject
+        {
+            return Ok(());
+        }
 
-        let resp = server
-            .create_host_group("group1".to_string(), vec!["serverA".to_string()])
-            .await
-            .unwrap_err();
-        assert!(matches!(resp, Error::IdNotSet));
+        let len = self.writes.lock().expect("mutex poisoned").len();
+        if len + host_groups > max_size {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return ReplicationQueueFull { db: db_name }.fail();
+        }
 
         Ok(())
     }
 
-    #[tokio::test]
-    async fn writes_local() -> Result {
-        let manager = TestConnectionManager::new();
-        let store = ObjectStore::new_in_memory(InMemory::new());
-        let mut server = Server::new(manager, store);
-        server.set_id(1);
-        let rules = DatabaseRules {
-            store_locally: true,
-            ..Default::default()
-        };
+    /// Queues the write for each of the host groups. If the rules drop the oldest writes
+    /// when the queue is full, they are dropped until it is no longer than
+    /// `replication_queue_max_size`. Otherwise the room was checked by `check_capacity`
+    /// and writes racing it may make the queue slightly longer, rather than being lost.
+    fn push(
+        &self,
+        host_group_ids: Vec<HostGroupId>,
+        write: &ReplicatedWrite,
+        rules: &DatabaseRules,
+    ) {
+        let max_size = rules.replication_queue_max_size;
+        let drop_oldest =
+            max_size == 0 || rules.replication_queue_full_policy == QueueFullPolicy::DropOldest;
+        let mut writes = self.writes.lock().expect("mutex poisoned");
+
+        let write = Arc::new(write.clone());
+        for host_group_id in host_group_ids {
+            writes.push_back(QueuedWrite {
+                id: self.next_id.fetch_add(1, Ordering::Relaxed),
+                host_group_id,
+                write: Arc::clone(&write),
+            });
+            self.queued.fetch_add(1, Ordering::Relaxed);
+        }
+
+        while drop_oldest && writes.len() > max_size {
+            writes.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns a copy of the writes currently in the queue, oldest first
+    fn entries(&self) -> Vec<QueuedWrite> {
+        let writes = self.writes.lock().expect("mutex poisoned");
+        writes.iter().cloned().collect()
+    }
+
+    /// Removes a replicated write from the queue, unless it was dropped in the meantime
+    fn remove(&self, id: u64) {
+        let mut writes = self.writes.lock().expect("mutex poisoned");
+        if let Some(pos) = writes.iter().position(|w| w.id == id) {
+            writes.remove(pos);
+            self.replicated.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn stats(&self) -> ReplicationQueueStats {
+        let len = self.writes.lock().expect("mutex poisoned").len();
+
+        ReplicationQueueStats {
+            len,
+            queued: self.queued.load(Ordering::Relaxed),
+            replicated: self.replicated.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The number of writes that missed the replication quorum remembered for retries
+const MAX_UNREPLICATED_WRITES: usize = 1_000;
+
+/// The latest writes of this server to a database that missed the replication quorum,
+/// and so were rejected although some of the local buffer and host groups may have
+/// applied them. A client retrying one of them sends the same lines, so writes are
+/// matched by their payload, and the retry is given the sequence of the original to
+/// be recognized as a duplicate wherever it was applied. Identical lines written again
+/// on purpose are also deduplicated, which doesn't change the data. Lines without a
+/// timestamp are given the time they are written at, so their retries never match.
+#[derive(Debug, Default)]
+struct UnreplicatedWrites {
+    writes: Mutex<VecDeque<(u64, Vec<u8>)>>,
+}
+
+impl UnreplicatedWrites {
+    /// Returns the sequence of the unreplicated write with the same payload as `write`
+    fn sequence_of(&self, write: &ReplicatedWrite) -> Option<u64> {
+        let payload = write.to_fb().payload()?;
+        let writes = self.writes.lock().expect("mutex poisoned");
+        writes
+            .iter()
+            .find(|(_, p)| p.as_slice() == payload)
+            .map(|(sequence, _)| *sequence)
+    }
+
+    /// Remembers `write`, forgetting the oldest write if there are too many
+    fn insert(&self, write: &ReplicatedWrite) {
+        let fb = write.to_fb();
+        let payload = fb.payload().unwrap_or_default().to_vec();
+        let mut writes = self.writes.lock().expect("mutex poisoned");
+
+        if !writes
+            .iter()
+            .any(|(sequence, _)| *sequence == fb.sequence())
+        {
+            writes.push_back((fb.sequence(), payload));
+        }
+        while writes.len() > MAX_UNREPLICATED_WRITES {
+            writes.pop_front();
+        }
+    }
+
+    /// Forgets `write` once it has been replicated
+    fn remove(&self, write: &ReplicatedWrite) {
+        let sequence = write.to_fb().sequence();
+        let mut writes = self.writes.lock().expect("mutex poisoned");
+        writes.retain(|(s, _)| *s != sequence);
+    }
+}
+
+/// The number of replication host groups that must acknowledge a write
+fn required_host_groups(rules: &DatabaseRules) -> usize {
+    match rules.replication_count as usize {
+        0 => rules.replication.len(),
+        n => n.min(rules.replication.len()),
+    }
+}
+
+/// The number of replication host groups a write can miss and still be replicated,
+/// and so may have to be queued for
+fn missable_host_groups(rules: &DatabaseRules) -> usize {
+    rules.replication.len() - required_host_groups(rules)
+}
+
+fn subscription_filters(rules: &DatabaseRules) -> Result<Vec<MatcherFilter>> {
+    rules
+        .subscriptions
+        .iter()
+        .map(|s| MatcherFilter::new(&s.matcher).context(InvalidSubscription { name: &s.name }))
+        .collect()
+}
+
+// location in the store for the configuration file
+fn config_location(id: u32) -> String {
+    format!("{}/config.json", id)
+}
+
+// location in the store for the end of the last block of sequence numbers reserved for a database
+fn sequence_location(id: u32, db_name: &str) -> String {
+    format!("{}/{}/sequence", id, db_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{csv, util::string_writer::StringWriter};
+    use async_trait::async_trait;
+    use data_types::database_rules::{
+        MatchTables, Matcher, PartitionTemplate, Subscription, TemplatePart,
+    };
+    use futures::TryStreamExt;
+    use influxdb_line_protocol::parse_lines;
+    use object_store::{InMemory, ObjectStoreIntegration};
+    use snafu::Snafu;
+    use std::sync::{atomic::AtomicBool, Mutex};
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn server_api_calls_return_error_with_no_id_set() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut server = Server::new(manager, store);
+
+        let rules = DatabaseRules::default();
+        let resp = server.create_database("foo", rules).await.unwrap_err();
+        assert!(matches!(resp, Error::IdNotSet));
+
+        let lines = parsed_lines("cpu foo=1 10");
+        let resp = server.write_lines("foo", &lines).await.unwrap_err();
+        assert!(matches!(resp, Error::IdNotSet));
+
+        let resp = server
+            .create_host_group("group1".to_string(), vec!["serverA".to_string()])
+            .await
+            .unwrap_err();
+        assert!(matches!(resp, Error::IdNotSet));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_local() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
         server.create_database("foo", rules).await?;
 
         let line = "cpu bar=1 10";
@@ -470,6 +1161,37 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn retention_period() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            retention_period_seconds: Some(3600),
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        let lines = parsed_lines("cpu bar=1 10");
+        let err = server.write_lines("foo", &lines).await.unwrap_err();
+        assert!(
+            matches!(err, Error::WriteOutsideRetention { timestamp: 10, .. }),
+            "{}",
+            err
+        );
+
+        let lines = parsed_lines("cpu bar=2");
+        server.write_lines("foo", &lines).await?;
+        assert_eq!(server.apply_retention("foo").await?, 0);
+
+        let results = server.query_local("foo", "select bar from cpu").await?;
+        assert_eq!(to_csv(&results), "bar\n2\n");
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn replicate_to_single_group() -> Result {
         let mut manager = TestConnectionManager::new();
@@ -594,6 +1316,613 @@ partition_key:
         Ok(())
     }
 
+    #[tokio::test]
+    async fn replicate_partitions_across_group_hosts() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let hosts = vec!["serverA".to_string(), "serverB".to_string()];
+        for host in &hosts {
+            manager
+                .remotes
+                .insert(host.clone(), Arc::new(TestRemoteServer::default()));
+        }
+        let remotes = manager.remotes.clone();
+
+        let store = ObjectStore::new_in_memory(InMemory::new());
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let host_group_id = "az1".to_string();
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![TemplatePart::Table]),
+            replication: vec![host_group_id.clone()],
+            replication_count: 1,
+            ..Default::default()
+        };
+        server
+            .create_host_group(host_group_id.clone(), hosts.clone())
+            .await?;
+        let db_name = "foo";
+        server.create_database(db_name, rules).await?;
+
+        let tables = vec!["cpu", "mem", "disk", "net", "swap", "system"];
+        let lp = tables
+            .iter()
+            .map(|t| format!("{} val=1 10", t))
+            .collect::<Vec<_>>()
+            .join("\n");
+        server.write_lines(db_name, &parsed_lines(&lp)).await?;
+
+        let ring = HashRing::new(&hosts);
+        let mut received = 0;
+        for (host, remote) in &remotes {
+            let writes = remote.writes.lock().unwrap();
+            let writes = writes.get(db_name).map(Vec::as_slice).unwrap_or_default();
+            assert!(writes.len() <= 1);
+
+            let partition_keys: Vec<_> = writes
+                .iter()
+                .flat_map(|w| w.partition_keys())
+                .map(ToString::to_string)
+                .collect();
+            let mut expected: Vec<_> = tables
+                .iter()
+                .filter(|t| ring.hosts_for(t)[0] == host)
+                .map(ToString::to_string)
+                .collect();
+            expected.sort();
+            assert_eq!(partition_keys, expected);
+
+            for write in writes {
+                assert_eq!(write.to_fb().writer(), 1);
+                assert_eq!(write.to_fb().sequence(), 1);
+            }
+            received += partition_keys.len();
+        }
+        assert_eq!(received, tables.len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicate_falls_back_to_next_host() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        let remote_id = "serverA";
+        manager
+            .remotes
+            .insert(remote_id.to_string(), remote.clone());
+
+        let store = ObjectStore::new_in_memory(InMemory::new());
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let host_group_id = "az1".to_string();
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![TemplatePart::Table]),
+            replication: vec![host_group_id.clone()],
+            replication_count: 1,
+            ..Default::default()
+        };
+        server
+            .create_host_group(
+                host_group_id.clone(),
+                vec![remote_id.to_string(), "serverB".to_string()],
+            )
+            .await?;
+        let db_name = "foo";
+        server.create_database(db_name, rules).await?;
+
+        let lines = parsed_lines("cpu val=1 10\nmem val=1 10\ndisk val=1 10");
+        server.write_lines(db_name, &lines).await?;
+
+        let writes = remote.writes.lock().unwrap().get(db_name).unwrap().clone();
+        let partition_keys: Vec<_> = writes.iter().flat_map(|w| w.partition_keys()).collect();
+        assert_eq!(partition_keys.len(), 3);
+
+        // with no reachable hosts the connection error is returned
+        let host_group_id = "az2".to_string();
+        let rules = DatabaseRules {
+            replication: vec![host_group_id.clone()],
+            ..Default::default()
+        };
+        server
+            .create_host_group(host_group_id, vec!["serverC".to_string()])
+            .await?;
+        server.create_database("bar", rules).await?;
+
+        let err = server
+            .write_lines("bar", &parsed_lines("cpu val=1 10"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::UnableToGetConnection { server, .. } if server == "serverC"),
+            "{}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replication_quorum_queues_missed_groups() -> Result {
+        let (mut server, remote_a) = two_group_server(DatabaseRules {
+            replication_count: 1,
+            replication_queue_max_size: 10,
+            ..Default::default()
+        })
+        .await?;
+
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+        assert_eq!(remote_a.writes.lock().unwrap().get("foo").unwrap().len(), 1);
+
+        let stats = server.replication_queue_stats("foo")?;
+        assert_eq!(
+            stats,
+            ReplicationQueueStats {
+                len: 1,
+                queued: 1,
+                ..Default::default()
+            }
+        );
+
+        // still unreachable, so the write stays queued
+        assert_eq!(server.process_replication_queue("foo").await?, 0);
+        assert_eq!(server.replication_queue_stats("foo")?.len, 1);
+
+        let remote_b = Arc::new(TestRemoteServer::default());
+        server
+            .connection_manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        assert_eq!(server.process_replication_queue("foo").await?, 1);
+        let stats = server.replication_queue_stats("foo")?;
+        assert_eq!(
+            stats,
+            ReplicationQueueStats {
+                len: 0,
+                queued: 1,
+                replicated: 1,
+                ..Default::default()
+            }
+        );
+
+        let writes = remote_b.writes.lock().unwrap().get("foo").unwrap().clone();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].to_fb().sequence(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replication_quorum_not_met() -> Result {
+        let (server, _) = two_group_server(DatabaseRules {
+            replication_count: 2,
+            replication_queue_max_size: 10,
+            ..Default::default()
+        })
+        .await?;
+
+        let err = server
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::ReplicationQuorumNotMet {
+                    replicated: 1,
+                    required: 2,
+                    ..
+                }
+            ),
+            "{}",
+            err
+        );
+        assert_eq!(server.replication_queue_stats("foo")?.len, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replication_quorum_not_met_retry_keeps_sequence() -> Result {
+        let (mut server, remote_a) = two_group_server(DatabaseRules {
+            replication_count: 2,
+            ..Default::default()
+        })
+        .await?;
+
+        let lines = parsed_lines("cpu bar=1 10");
+        let err = server.write_lines("foo", &lines).await.unwrap_err();
+        assert!(
+            matches!(err, Error::ReplicationQuorumNotMet { .. }),
+            "{}",
+            err
+        );
+
+        let remote_b = Arc::new(TestRemoteServer::default());
+        server
+            .connection_manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        // the retry has the sequence of the write serverA already applied
+        server.write_lines("foo", &lines).await?;
+
+        let sequences = |remote: &Arc<TestRemoteServer>| -> Vec<_> {
+            remote.writes.lock().unwrap()["foo"]
+                .iter()
+                .map(|w| (w.to_fb().sequence(), w.to_fb().checksum()))
+                .collect()
+        };
+        let a = sequences(&remote_a);
+        assert_eq!(a.len(), 2);
+        assert_eq!(a[0], a[1]);
+        assert_eq!(sequences(&remote_b), vec![a[0]]);
+
+        // once replicated, the same lines are a new write
+        server.write_lines("foo", &lines).await?;
+        let b = sequences(&remote_b);
+        assert_eq!(b.len(), 2);
+        assert!(b[1].0 > b[0].0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replication_queue_full_drops_oldest() -> Result {
+        let (mut server, _) = two_group_server(DatabaseRules {
+            replication_count: 1,
+            replication_queue_max_size: 1,
+            replication_queue_full_policy: QueueFullPolicy::DropOldest,
+            ..Default::default()
+        })
+        .await?;
+
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=2 20"))
+            .await?;
+
+        let stats = server.replication_queue_stats("foo")?;
+        assert_eq!(
+            stats,
+            ReplicationQueueStats {
+                len: 1,
+                queued: 2,
+                dropped: 1,
+                ..Default::default()
+            }
+        );
+
+        let remote_b = Arc::new(TestRemoteServer::default());
+        server
+            .connection_manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+        server.process_replication_queue("foo").await?;
+
+        let writes = remote_b.writes.lock().unwrap().get("foo").unwrap().clone();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].to_fb().sequence(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replication_queue_full_rejects() -> Result {
+        let (server, remote_a) = two_group_server(DatabaseRules {
+            replication_count: 1,
+            replication_queue_max_size: 1,
+            replication_queue_full_policy: QueueFullPolicy::Reject,
+            ..Default::default()
+        })
+        .await?;
+
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+        let err = server
+            .write_lines("foo", &parsed_lines("cpu bar=2 20"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ReplicationQueueFull { .. }), "{}", err);
+
+        // the rejected write wasn't replicated, so it can be retried once there is room
+
+        let stats = server.replication_queue_stats("foo")?;
+        assert_eq!(
+            stats,
+            ReplicationQueueStats {
+                len: 1,
+                queued: 1,
+                rejected: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(remote_a.writes.lock().unwrap().get("foo").unwrap().len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replication_queue_task_retries_writes() -> Result {
+        let (mut server, _) = two_group_server(DatabaseRules {
+            replication_count: 1,
+            replication_queue_max_size: 10,
+            ..Default::default()
+        })
+        .await?;
+
+        let remote_b = Arc::new(TestRemoteServer::default());
+        remote_b.unavailable.store(true, Ordering::SeqCst);
+        server
+            .connection_manager
+            .remotes
+            .insert("serverB".to_string(), remote_b.clone());
+
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+        assert_eq!(server.replication_queue_stats("foo")?.len, 1);
+
+        let server = Arc::new(server);
+        start_replication_queue_task(Arc::clone(&server), Duration::from_millis(10));
+        remote_b.unavailable.store(false, Ordering::SeqCst);
+
+        let replicated = async {
+            while server.replication_queue_stats("foo").unwrap().len > 0 {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), replicated).await?;
+
+        let writes = remote_b.writes.lock().unwrap().get("foo").unwrap().clone();
+        assert_eq!(writes.len(), 1);
+
+        Ok(())
+    }
+
+    /// Creates a server with database "foo" replicating to host groups az1 (serverA) and
+    /// az2 (serverB). Only serverA is reachable, serverB can be added to the connection
+    /// manager by the test.
+    async fn two_group_server(
+        rules: DatabaseRules,
+    ) -> Result<(Server<TestConnectionManager>, Arc<TestRemoteServer>)> {
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), remote.clone());
+
+        let store = ObjectStore::new_in_memory(InMemory::new());
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        server
+            .create_host_group("az1".to_string(), vec!["serverA".to_string()])
+            .await?;
+        server
+            .create_host_group("az2".to_string(), vec!["serverB".to_string()])
+            .await?;
+
+        let rules = DatabaseRules {
+            replication: vec!["az1".to_string(), "az2".to_string()],
+            ..rules
+        };
+        server.create_database("foo", rules).await?;
+
+        Ok((server, remote))
+    }
+
+    #[tokio::test]
+    async fn federated_query_fails_over_to_secondary() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remotes: Vec<_> = (0..3)
+            .map(|_| Arc::new(TestRemoteServer::default()))
+            .collect();
+        // serverB is unreachable, so serverD answers for its position
+        for (id, remote) in ["serverA", "serverC", "serverD"].iter().zip(&remotes) {
+            manager.remotes.insert(id.to_string(), Arc::clone(remote));
+        }
+
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        server
+            .create_host_group(
+                "az1".to_string(),
+                vec!["serverA".to_string(), "serverB".to_string()],
+            )
+            .await?;
+        server
+            .create_host_group(
+                "az2".to_string(),
+                vec!["serverC".to_string(), "serverD".to_string()],
+            )
+            .await?;
+
+        let rules = DatabaseRules {
+            store_locally: true,
+            query_local: true,
+            primary_query_group: Some("az1".to_string()),
+            secondary_query_groups: vec!["az2".to_string()],
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+
+        for (remote, lp) in
+            remotes
+                .iter()
+                .zip(&["cpu bar=2 20", "cpu bar=4 40", "cpu bar=3,baz=5 30"])
+        {
+            let write =
+                lines_to_replicated_write(2, 1, &parsed_lines(lp), &DatabaseRules::default())?;
+            remote.replicate("foo", &write).await?;
+        }
+
+        // the column only serverD has is null in the rows of the other servers
+        let results = server.query("foo", "select * from cpu").await?;
+        assert_eq!(to_csv(&results), "bar,time,baz\n1,10,\n2,20,\n3,30,5\n");
+
+        let err = server
+            .query("foo", "select count(*) from cpu")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::MergingResults { .. }), "{}", err);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn federated_query_fails_without_host_for_position() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), Arc::clone(&remote));
+
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        server
+            .create_host_group(
+                "az1".to_string(),
+                vec!["serverA".to_string(), "serverB".to_string()],
+            )
+            .await?;
+        server
+            .create_host_group("az2".to_string(), vec!["serverC".to_string()])
+            .await?;
+
+        let lines = parsed_lines("cpu bar=1 10");
+        let write = lines_to_replicated_write(2, 1, &lines, &DatabaseRules::default())?;
+        remote.replicate("foo", &write).await?;
+
+        let rules = DatabaseRules {
+            primary_query_group: Some("az1".to_string()),
+            secondary_query_groups: vec!["az2".to_string()],
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        let err = server.query("foo", "select * from cpu").await.unwrap_err();
+        assert!(
+            matches!(&err, Error::UnableToGetConnection { server, .. } if server == "serverB"),
+            "{}",
+            err
+        );
+
+        server
+            .create_database("bar", DatabaseRules::default())
+            .await?;
+        let err = server.query("bar", "select * from cpu").await.unwrap_err();
+        assert!(matches!(err, Error::NoQueryTarget { .. }), "{}", err);
+
+        Ok(())
+    }
+
+    fn to_csv(batches: &[RecordBatch]) -> String {
+        let mut sw = StringWriter::new();
+        {
+            let mut writer = csv::Writer::new(&mut sw);
+            for batch in batches {
+                writer.write(batch).unwrap();
+            }
+        }
+        sw.to_string()
+    }
+
+    #[tokio::test]
+    async fn sends_matching_tables_to_subscriber() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        let remote_id = "serverA";
+        manager
+            .remotes
+            .insert(remote_id.to_string(), remote.clone());
+
+        let store = ObjectStore::new_in_memory(InMemory::new());
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let host_group_id = "az1".to_string();
+        let rules = DatabaseRules {
+            subscriptions: vec![Subscription {
+                name: "query_server_1".to_string(),
+                host_group_id: host_group_id.clone(),
+                matcher: Matcher {
+                    tables: MatchTables::Regex("^cpu".to_string()),
+                    predicate: None,
+                },
+            }],
+            ..Default::default()
+        };
+        server
+            .create_host_group(host_group_id.clone(), vec![remote_id.to_string()])
+            .await?;
+        let db_name = "foo";
+        server.create_database(db_name, rules).await?;
+
+        let lines = parsed_lines("cpu bar=1 10\nmem user=232 12\ncpu_load val=2 10");
+        server.write_lines(db_name, &lines).await?;
+
+        // nothing matches, so nothing gets sent
+        let lines = parsed_lines("mem user=232 14");
+        server.write_lines(db_name, &lines).await?;
+
+        let writes = remote.writes.lock().unwrap().get(db_name).unwrap().clone();
+        assert_eq!(writes.len(), 1);
+
+        let write_text = r#"
+writer:1, sequence:1, checksum:"#;
+        let text = writes[0].to_string();
+        assert!(text.starts_with(write_text), "{}", text);
+        assert!(text.ends_with(
+            r#"
+partition_key:
+  table:cpu
+    bar:1 time:10
+  table:cpu_load
+    val:2 time:10
+"#
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_subscription_regex() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = ObjectStore::new_in_memory(InMemory::new());
+
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            subscriptions: vec![Subscription {
+                name: "query_server_1".to_string(),
+                host_group_id: "az1".to_string(),
+                matcher: Matcher {
+                    tables: MatchTables::Regex("cpu(".to_string()),
+                    predicate: None,
+                },
+            }],
+            ..Default::default()
+        };
+
+        let err = server.create_database("foo", rules).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidSubscription { .. }), "{}", err);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn store_and_load_configuration() -> Result {
         let manager = TestConnectionManager::new();
@@ -628,12 +1957,12 @@ partition_key:
             .await
             .unwrap();
 
-        let config = r#"{"id":1,"databases":{"foo":{"partition_template":{"parts":[]},"store_locally":false,"replication":["az1"],"replication_count":1,"replication_queue_max_size":0,"subscriptions":[],"query_local":false,"primary_query_group":null,"secondary_query_groups":[],"read_only_partitions":[]}},"host_groups":{"az1":{"id":"az1","hosts":["serverA"]}}}"#;
+        let config = r#"{"id":1,"databases":{"foo":{"partition_template":{"parts":[]},"store_locally":false,"replication":["az1"],"replication_count":1,"replication_queue_max_size":0,"replication_queue_full_policy":"dropOldest","subscriptions":[],"query_local":false,"primary_query_group":null,"secondary_query_groups":[],"read_only_partitions":[],"lifecycle_rules":{"max_partition_age_seconds":null,"max_partition_bytes":null,"max_idle_seconds":null,"max_open_partitions":null,"buffer_size_soft":null,"buffer_size_hard":null},"retention_period_seconds":null}},"host_groups":{"az1":{"id":"az1","hosts":["serverA"]}}}"#;
         let read_data = std::str::from_utf8(&*read_data).unwrap();
         assert_eq!(read_data, config);
 
         let manager = TestConnectionManager::new();
-        let store = match server.store.0 {
+        let store = match &server.store.0 {
             ObjectStoreIntegration::InMemory(in_mem) => in_mem.clone().await,
             _ => panic!("wrong type"),
         };
@@ -647,6 +1976,76 @@ partition_key:
         Ok(())
     }
 
+    #[tokio::test]
+    async fn sequence_continues_after_restart() -> Result {
+        let remote = Arc::new(TestRemoteServer::default());
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let rules = DatabaseRules {
+            replication: vec!["az1".to_string()],
+            replication_count: 1,
+            ..Default::default()
+        };
+
+        let mut manager = TestConnectionManager::new();
+        manager
+            .remotes
+            .insert("serverA".to_string(), Arc::clone(&remote));
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        server
+            .create_host_group("az1".to_string(), vec!["serverA".to_string()])
+            .await?;
+        server.create_database("foo", rules.clone()).await?;
+        server.store_configuration().await?;
+
+        let lines = parsed_lines("cpu bar=1 10");
+        server.write_lines("foo", &lines).await?;
+        server.write_lines("foo", &lines).await?;
+
+        let stored = server.get_object("1/foo/sequence").await?;
+        assert_eq!(&stored[..], b"10001");
+
+        // restarting from the stored configuration continues after the reserved block
+        let store = match &server.store.0 {
+            ObjectStoreIntegration::InMemory(in_mem) => in_mem.clone().await,
+            _ => panic!("wrong type"),
+        };
+        let mut manager = TestConnectionManager::new();
+        manager
+            .remotes
+            .insert("serverA".to_string(), Arc::clone(&remote));
+        let mut recovered_server = Server::new(manager, ObjectStore::new_in_memory(store));
+        recovered_server.load_configuration(1).await?;
+        recovered_server.write_lines("foo", &lines).await?;
+
+        // as does creating the database again, after that restart
+        let store = match &recovered_server.store.0 {
+            ObjectStoreIntegration::InMemory(in_mem) => in_mem.clone().await,
+            _ => panic!("wrong type"),
+        };
+        let mut manager = TestConnectionManager::new();
+        manager
+            .remotes
+            .insert("serverA".to_string(), Arc::clone(&remote));
+        let mut recreated_server = Server::new(manager, ObjectStore::new_in_memory(store));
+        recreated_server.set_id(1);
+        recreated_server
+            .create_host_group("az1".to_string(), vec!["serverA".to_string()])
+            .await?;
+        recreated_server.create_database("foo", rules).await?;
+        recreated_server.write_lines("foo", &lines).await?;
+
+        let writes = remote.writes.lock().unwrap();
+        let sequences: Vec<_> = writes["foo"].iter().map(|w| w.to_fb().sequence()).collect();
+        assert_eq!(sequences, vec![1, 2, 10001, 20001]);
+
+        // restarts sharing a store never reuse a sequence
+        let distinct: BTreeSet<_> = sequences.iter().collect();
+        assert_eq!(distinct.len(), sequences.len());
+
+        Ok(())
+    }
+
     #[derive(Snafu, Debug, Clone)]
     enum TestClusterError {
         #[snafu(display("Test cluster error:  {}", message))]
@@ -671,13 +2070,17 @@ partition_key:
         type RemoteServer = TestRemoteServer;
 
         async fn remote_server(&self, id: &str) -> Result<Arc<TestRemoteServer>, Self::Error> {
-            Ok(self.remotes.get(id).unwrap().clone())
+            self.remotes.get(id).cloned().context(General {
+                message: format!("no remote server {}", id),
+            })
         }
     }
 
     #[derive(Default)]
     struct TestRemoteServer {
         writes: Mutex<BTreeMap<String, Vec<ReplicatedWrite>>>,
+        // if set, replicating to this server fails
+        unavailable: AtomicBool,
     }
 
     #[async_trait]
@@ -689,12 +2092,46 @@ partition_key:
             db: &str,
             replicated_write: &ReplicatedWrite,
         ) -> Result<(), Self::Error> {
+            ensure!(
+                !self.unavailable.load(Ordering::SeqCst),
+                General {
+                    message: "remote server unavailable"
+                }
+            );
+
             let mut writes = self.writes.lock().unwrap();
             let entries = writes.entry(db.to_string()).or_insert_with(Vec::new);
             entries.push(replicated_write.clone());
 
             Ok(())
         }
+
+        /// Answers queries from a write buffer holding the writes replicated to this server
+        async fn query(&self, db: &str, query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
+            let writes = self
+                .writes
+                .lock()
+                .unwrap()
+                .get(db)
+                .cloned()
+                .unwrap_or_default();
+
+            let buffer = WriteBufferDb::new(db);
+            for write in &writes {
+                buffer.store_replicated_write(write).await.map_err(|e| {
+                    TestClusterError::General {
+                        message: e.to_string(),
+                    }
+                })?;
+            }
+
+            buffer
+                .query(query)
+                .await
+                .map_err(|e| TestClusterError::General {
+                    message: e.to_string(),
+                })
+        }
     }
 
     fn parsed_lines(lp: &str) -> Vec<ParsedLine<'_>> {
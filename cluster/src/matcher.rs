@@ -0,0 +1,199 @@
+//! This module contains the compiled form of a subscription's `Matcher`, which
+//! decides the part of each `ReplicatedWrite` that is sent to the subscriber.
+
+use std::borrow::Cow;
+
+use data_types::{
+    data::{ReplicatedWrite, WriteFilter},
+    database_rules::{MatchTables, Matcher},
+    row_predicate::RowPredicate,
+};
+use generated_types::wal as wb;
+use regex::Regex;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("invalid table regex '{}': {}", regex, source))]
+    InvalidTableRegex { regex: String, source: regex::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub struct MatcherFilter {
+    tables: Tables,
+    predicate: Option<RowPredicate>,
+}
+
+#[derive(Debug)]
+enum Tables {
+    All,
+    Table(String),
+    Regex(Regex),
+}
+
+impl MatcherFilter {
+    pub fn new(matcher: &Matcher) -> Result<Self> {
+        let tables = match &matcher.tables {
+            MatchTables::All => Tables::All,
+            MatchTables::Table(name) => Tables::Table(name.clone()),
+            MatchTables::Regex(regex) => {
+                Tables::Regex(Regex::new(regex).context(InvalidTableRegex { regex })?)
+            }
+        };
+
+        Ok(Self {
+            tables,
+            predicate: matcher.predicate.clone(),
+        })
+    }
+
+    /// Returns the parts of `write` that match, keeping its writer id and sequence
+    /// number, or `None` if nothing in it matches.
+    pub fn filter<'a>(&self, write: &'a ReplicatedWrite) -> Option<Cow<'a, ReplicatedWrite>> {
+        match (&self.tables, &self.predicate) {
+            // nothing to filter out, so there's no need to rebuild the write
+            (Tables::All, None) => Some(Cow::Borrowed(write)),
+            _ => write.filter(self).map(Cow::Owned),
+        }
+    }
+}
+
+impl WriteFilter for MatcherFilter {
+    fn keep_table(&self, table_name: &str) -> bool {
+        match &self.tables {
+            Tables::All => true,
+            Tables::Table(name) => name == table_name,
+            Tables::Regex(regex) => regex.is_match(table_name),
+        }
+    }
+
+    fn keep_row(&self, _table_name: &str, row: &wb::Row<'_>) -> bool {
+        self.predicate
+            .as_ref()
+            .map(|predicate| predicate.matches(row))
+            .unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::data::lines_to_replicated_write;
+    use data_types::database_rules::DatabaseRules;
+    use influxdb_line_protocol::parse_lines;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn matcher(tables: MatchTables) -> Matcher {
+        Matcher {
+            tables,
+            predicate: None,
+        }
+    }
+
+    fn write(lp: &str) -> Result<ReplicatedWrite> {
+        let lines: Vec<_> = parse_lines(lp).collect::<Result<_, _>>()?;
+        Ok(lines_to_replicated_write(
+            1,
+            5,
+            &lines,
+            &DatabaseRules::default(),
+        )?)
+    }
+
+    fn tables(write: &ReplicatedWrite) -> Vec<String> {
+        write
+            .write_buffer_batch()
+            .and_then(|b| b.entries())
+            .into_iter()
+            .flatten()
+            .flat_map(|e| e.table_batches().into_iter().flatten())
+            .map(|t| t.name().unwrap_or("").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn match_all() -> Result {
+        let write = write("cpu val=1 10\nmem val=2 10")?;
+        let filter = MatcherFilter::new(&matcher(MatchTables::All))?;
+
+        let filtered = filter.filter(&write).unwrap();
+        assert_eq!(filtered.data, write.data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn match_table() -> Result {
+        let write = write("cpu val=1 10\nmem val=2 10\ncpu val=3 20")?;
+        let filter = MatcherFilter::new(&matcher(MatchTables::Table("cpu".to_string())))?;
+
+        let filtered = filter.filter(&write).unwrap();
+        assert_eq!(tables(&filtered), vec!["cpu"]);
+        assert_eq!(filtered.to_fb().writer(), 1);
+        assert_eq!(filtered.to_fb().sequence(), 5);
+
+        let filter = MatcherFilter::new(&matcher(MatchTables::Table("disk".to_string())))?;
+        assert!(filter.filter(&write).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn match_regex() -> Result {
+        let write = write("cpu val=1 10\nmem val=2 10\ncpu_load val=3 20")?;
+        let filter = MatcherFilter::new(&matcher(MatchTables::Regex("^cpu".to_string())))?;
+
+        let filtered = filter.filter(&write).unwrap();
+        assert_eq!(tables(&filtered), vec!["cpu", "cpu_load"]);
+
+        let filter = MatcherFilter::new(&matcher(MatchTables::Regex("^disk".to_string())))?;
+        assert!(filter.filter(&write).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn match_predicate() -> Result {
+        let write = write(
+            "cpu,region=us-west val=1 10\n\
+             cpu,region=us-east val=2 10\n\
+             mem,region=us-west val=3 10\n\
+             disk,region=us-east val=4 10",
+        )?;
+
+        let filter = MatcherFilter::new(&Matcher {
+            tables: MatchTables::All,
+            predicate: Some(RowPredicate::parse("region = 'us-west'")?),
+        })?;
+        let filtered = filter.filter(&write).unwrap();
+        assert_eq!(tables(&filtered), vec!["cpu", "mem"]);
+        assert!(filtered.to_string().contains("region:us-west val:1"));
+        assert!(!filtered.to_string().contains("us-east"));
+
+        let filter = MatcherFilter::new(&Matcher {
+            tables: MatchTables::Table("cpu".to_string()),
+            predicate: Some(RowPredicate::parse("region = 'us-east' AND val > 1")?),
+        })?;
+        let filtered = filter.filter(&write).unwrap();
+        assert_eq!(tables(&filtered), vec!["cpu"]);
+        assert!(filtered.to_string().contains("region:us-east val:2"));
+
+        let filter = MatcherFilter::new(&Matcher {
+            tables: MatchTables::All,
+            predicate: Some(RowPredicate::parse("region = 'eu-central'")?),
+        })?;
+        assert!(filter.filter(&write).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_regex() {
+        let err = MatcherFilter::new(&matcher(MatchTables::Regex("cpu(".to_string()))).unwrap_err();
+        assert!(matches!(err, Error::InvalidTableRegex { .. }));
+    }
+}
@@ -0,0 +1,448 @@
+//! Combines the results of a query run on several servers, each holding part of
+//! a database's data, as `Server::query` does.
+//!
+//! Results are merged by concatenating the rows of every server, which is only
+//! correct for queries that select rows without combining them: queries with
+//! aggregates, `GROUP BY`, `DISTINCT`, `HAVING`, `ORDER BY`, `LIMIT`, joins or
+//! subqueries in expressions would return a partial answer per server, so they
+//! are rejected instead. Servers may not have data for every column, so their
+//! schemas are unified first: columns a server didn't return are null in its
+//! rows.
+
+use std::sync::Arc;
+
+use arrow_deps::{
+    arrow::{
+        array::{
+            ArrayRef, BooleanBuilder, Float64Builder, Int64Builder, StringBuilder, UInt64Builder,
+        },
+        datatypes::{DataType, Field, Schema},
+        error::ArrowError,
+        record_batch::RecordBatch,
+    },
+    datafusion::sql::parser::{DFParser, Statement as DFStatement},
+};
+use snafu::{ensure, ResultExt, Snafu};
+use sqlparser::{
+    ast::{
+        Expr, Query, Select, SelectItem, SetExpr, SetOperator, Statement, TableFactor,
+        TableWithJoins,
+    },
+    parser::ParserError,
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("error parsing query {}: {}", query, source))]
+    ParsingQuery { query: String, source: ParserError },
+
+    #[snafu(display(
+        "query can't be merged across servers, as it uses {}: {}",
+        reason,
+        query
+    ))]
+    Unmergeable { query: String, reason: String },
+
+    #[snafu(display(
+        "servers returned column {} as both {:?} and {:?}",
+        column,
+        first,
+        second
+    ))]
+    ConflictingTypes {
+        column: String,
+        first: DataType,
+        second: DataType,
+    },
+
+    #[snafu(display("can't fill missing column {} of type {:?}", column, data_type))]
+    UnsupportedMissingColumn { column: String, data_type: DataType },
+
+    #[snafu(display("error merging results: {}", source))]
+    MergingBatches { source: ArrowError },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Functions that combine the values of several rows
+const AGGREGATE_FUNCTIONS: &[&str] = &[
+    "AVG", "COUNT", "FIRST", "LAST", "MAX", "MEAN", "MIN", "STDDEV", "SUM", "VARIANCE",
+];
+
+/// Returns an error if the results of running `query` on several servers can't
+/// be merged by concatenating them. This is conservative: an aggregate or an
+/// unmergeable clause anywhere in the query, even in a subquery, makes the
+/// query unmergeable.
+pub fn check_mergeable(query: &str) -> Result<()> {
+    let statements = DFParser::parse_sql(query).context(ParsingQuery { query })?;
+
+    let parsed = match statements.front() {
+        Some(DFStatement::Statement(Statement::Query(parsed))) if statements.len() == 1 => parsed,
+        _ => {
+            return Unmergeable {
+                query,
+                reason: "anything but a single SELECT",
+            }
+            .fail()
+        }
+    };
+
+    match query_unmergeable(parsed) {
+        Some(reason) => Unmergeable { query, reason }.fail(),
+        None => Ok(()),
+    }
+}
+
+/// Returns what makes the results of `query` unmergeable, if anything
+fn query_unmergeable(query: &Query) -> Option<String> {
+    if !query.order_by.is_empty() {
+        return Some("ORDER BY".into());
+    }
+    if query.limit.is_some() {
+        return Some("LIMIT".into());
+    }
+    if query.offset.is_some() {
+        return Some("OFFSET".into());
+    }
+    if query.fetch.is_some() {
+        return Some("FETCH".into());
+    }
+
+    query
+        .ctes
+        .iter()
+        .find_map(|cte| query_unmergeable(&cte.query))
+        .or_else(|| set_expr_unmergeable(&query.body))
+}
+
+fn set_expr_unmergeable(body: &SetExpr) -> Option<String> {
+    match body {
+        SetExpr::Select(select) => select_unmergeable(select),
+        SetExpr::Query(query) => query_unmergeable(query),
+        SetExpr::SetOperation {
+            op: SetOperator::Union,
+            all: true,
+            left,
+            right,
+        } => set_expr_unmergeable(left).or_else(|| set_expr_unmergeable(right)),
+        SetExpr::SetOperation {
+            op: SetOperator::Union,
+            ..
+        } => Some("UNION without ALL".into()),
+        SetExpr::SetOperation { op, .. } => Some(op.to_string()),
+        SetExpr::Values(_) => None,
+    }
+}
+
+fn select_unmergeable(select: &Select) -> Option<String> {
+    if select.distinct {
+        return Some("DISTINCT".into());
+    }
+    if !select.group_by.is_empty() {
+        return Some("GROUP BY".into());
+    }
+    if select.having.is_some() {
+        return Some("HAVING".into());
+    }
+    if select.from.len() > 1 {
+        return Some("a join".into());
+    }
+
+    select
+        .projection
+        .iter()
+        .find_map(|item| match item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                expr_unmergeable(expr)
+            }
+            _ => None,
+        })
+        .or_else(|| select.from.iter().find_map(table_unmergeable))
+        .or_else(|| select.selection.as_ref().and_then(expr_unmergeable))
+}
+
+fn table_unmergeable(table: &TableWithJoins) -> Option<String> {
+    if !table.joins.is_empty() {
+        return Some("a join".into());
+    }
+
+    match &table.relation {
+        TableFactor::Table { .. } => None,
+        TableFactor::Derived { subquery, .. } => query_unmergeable(subquery),
+        TableFactor::NestedJoin(table) => table_unmergeable(table),
+    }
+}
+
+fn expr_unmergeable(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Function(function) => {
+            let name = function.name.to_string().to_uppercase();
+            if AGGREGATE_FUNCTIONS.contains(&name.as_str()) {
+                return Some(format!("the aggregate {}", name));
+            }
+            if function.over.is_some() {
+                return Some(format!("the window function {}", name));
+            }
+            function.args.iter().find_map(expr_unmergeable)
+        }
+        // a subquery would only see the rows of the server running it
+        Expr::Subquery(_) | Expr::Exists(_) | Expr::InSubquery { .. } => {
+            Some("a subquery in an expression".into())
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            expr_unmergeable(left).or_else(|| expr_unmergeable(right))
+        }
+        Expr::UnaryOp { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::Extract { expr, .. }
+        | Expr::Collate { expr, .. }
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr) => expr_unmergeable(expr),
+        Expr::Between {
+            expr, low, high, ..
+        } => expr_unmergeable(expr)
+            .or_else(|| expr_unmergeable(low))
+            .or_else(|| expr_unmergeable(high)),
+        Expr::InList { expr, list, .. } => {
+            expr_unmergeable(expr).or_else(|| list.iter().find_map(expr_unmergeable))
+        }
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => operand
+            .iter()
+            .chain(else_result)
+            .map(|expr| &**expr)
+            .chain(conditions)
+            .chain(results)
+            .find_map(expr_unmergeable),
+        _ => None,
+    }
+}
+
+/// Merges the batches returned by several servers into batches sharing one
+/// schema, with the columns of all of them, in the order they first appear.
+/// A column missing from a batch is null in its rows.
+pub fn merge_batches(batches: Vec<RecordBatch>) -> Result<Vec<RecordBatch>> {
+    let mut fields: Vec<Field> = vec![];
+    for batch in &batches {
+        for field in batch.schema().fields() {
+            match fields.iter().find(|f| f.name() == field.name()) {
+                Some(existing) => ensure!(
+                    existing.data_type() == field.data_type(),
+                    ConflictingTypes {
+                        column: field.name(),
+                        first: existing.data_type().clone(),
+                        second: field.data_type().clone(),
+                    }
+                ),
+                None => fields.push(field.clone()),
+            }
+        }
+    }
+
+    // columns that are missing from, or nullable in, any batch are nullable
+    let fields = fields
+        .into_iter()
+        .map(|field| {
+            let nullable = batches.iter().any(|batch| {
+                let schema = batch.schema();
+                schema
+                    .index_of(field.name())
+                    .map_or(true, |i| schema.field(i).is_nullable())
+            });
+            Field::new(field.name(), field.data_type().clone(), nullable)
+        })
+        .collect();
+    let schema = Arc::new(Schema::new(fields));
+
+    batches
+        .iter()
+        .map(|batch| {
+            let batch_schema = batch.schema();
+            let columns = schema
+                .fields()
+                .iter()
+                .map(|field| match batch_schema.index_of(field.name()) {
+                    Ok(i) => Ok(Arc::clone(batch.column(i))),
+                    Err(_) => null_array(field, batch.num_rows()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            RecordBatch::try_new(Arc::clone(&schema), columns).context(MergingBatches)
+        })
+        .collect()
+}
+
+/// Returns an array of `len` nulls for the column `field`
+fn null_array(field: &Field, len: usize) -> Result<ArrayRef> {
+    let array: ArrayRef = match field.data_type() {
+        DataType::Utf8 => {
+            let mut builder = StringBuilder::new(len);
+            for _ in 0..len {
+                builder.append_null().context(MergingBatches)?;
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Float64 => {
+            let mut builder = Float64Builder::new(len);
+            for _ in 0..len {
+                builder.append_null().context(MergingBatches)?;
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Int64 => {
+            let mut builder = Int64Builder::new(len);
+            for _ in 0..len {
+                builder.append_null().context(MergingBatches)?;
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::UInt64 => {
+            let mut builder = UInt64Builder::new(len);
+            for _ in 0..len {
+                builder.append_null().context(MergingBatches)?;
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Boolean => {
+            let mut builder = BooleanBuilder::new(len);
+            for _ in 0..len {
+                builder.append_null().context(MergingBatches)?;
+            }
+            Arc::new(builder.finish())
+        }
+        data_type => {
+            return UnsupportedMissingColumn {
+                column: field.name(),
+                data_type: data_type.clone(),
+            }
+            .fail()
+        }
+    };
+
+    Ok(array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::array::{Array, Float64Array, Int64Array};
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[test]
+    fn mergeable_queries() {
+        for query in &[
+            "select * from cpu",
+            "select usage, time from cpu where host = 'a' and time > 10",
+            "select counter, max_usage from cpu",
+            // keywords and function names in literals and column names
+            "select count, order_id from cpu where host = 'limit' or host = 'sum(1)'",
+            "select * from (select host, abs(usage) as u from cpu where u > 1) as t",
+            "with t as (select host from cpu) select host from t",
+            "select host from cpu union all select host from mem",
+        ] {
+            check_mergeable(query).unwrap();
+        }
+
+        let cases = vec![
+            ("select count(*) from cpu", "the aggregate COUNT"),
+            ("select host, max(usage) from cpu group by host", "GROUP BY"),
+            ("select distinct host from cpu", "DISTINCT"),
+            ("select * from cpu order by time", "ORDER BY"),
+            ("select * from cpu limit 10", "LIMIT"),
+            (
+                "select * from (select sum (usage) as s from cpu) as t",
+                "the aggregate SUM",
+            ),
+            (
+                "with t as (select max(usage) as m from cpu) select m from t",
+                "the aggregate MAX",
+            ),
+            (
+                "select host from cpu where usage > (select avg(usage) from cpu)",
+                "a subquery in an expression",
+            ),
+            (
+                "select * from cpu join mem on cpu.host = mem.host",
+                "a join",
+            ),
+            (
+                "select host from cpu union select host from mem",
+                "UNION without ALL",
+            ),
+            ("select 1; select 2", "anything but a single SELECT"),
+        ];
+        for (query, reason) in cases {
+            let err = check_mergeable(query).unwrap_err();
+            assert!(
+                matches!(&err, Error::Unmergeable { reason: r, .. } if r == reason),
+                "query: {} error: {}",
+                query,
+                err
+            );
+        }
+
+        let err = check_mergeable("selec * from cpu").unwrap_err();
+        assert!(matches!(err, Error::ParsingQuery { .. }), "{}", err);
+    }
+
+    #[test]
+    fn merge_different_schemas() -> Result {
+        let cpu = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![
+                Field::new("usage", DataType::Float64, false),
+                Field::new("time", DataType::Int64, false),
+            ])),
+            vec![
+                Arc::new(Float64Array::from(vec![1.0])),
+                Arc::new(Int64Array::from(vec![10])),
+            ],
+        )?;
+        let cpu_with_count = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![
+                Field::new("time", DataType::Int64, false),
+                Field::new("count", DataType::Int64, true),
+            ])),
+            vec![
+                Arc::new(Int64Array::from(vec![20, 30])),
+                Arc::new(Int64Array::from(vec![Some(1), None])),
+            ],
+        )?;
+
+        let merged = merge_batches(vec![cpu, cpu_with_count])?;
+        assert_eq!(merged.len(), 2);
+
+        let schema = merged[0].schema();
+        let fields: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|f| (f.name().as_str(), f.is_nullable()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![("usage", true), ("time", false), ("count", true)]
+        );
+        assert_eq!(merged[1].schema(), schema);
+
+        assert_eq!(merged[0].column(2).null_count(), 1);
+        assert_eq!(merged[1].column(0).null_count(), 2);
+        assert_eq!(merged[1].num_rows(), 2);
+
+        let conflicting = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![Field::new("time", DataType::Utf8, false)])),
+            vec![Arc::new(arrow_deps::arrow::array::StringArray::from(vec![
+                "10",
+            ]))],
+        )?;
+        let err = merge_batches(merged.into_iter().chain(Some(conflicting)).collect()).unwrap_err();
+        assert!(matches!(err, Error::ConflictingTypes { .. }), "{}", err);
+
+        Ok(())
+    }
+}
@@ -1,14 +1,17 @@
 //! This module contains helper methods for constructing replicated writes
 //! based on `DatabaseRules`.
 
-use crate::database_rules::DatabaseRules;
+use crate::database_rules::{DatabaseRules, Result as RulesResult};
 use crate::TIME_COLUMN_NAME;
 use generated_types::wal as wb;
 use influxdb_line_protocol::{FieldValue, ParsedLine};
 
-use std::{collections::BTreeMap, fmt};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use crc32fast::Hasher;
 use flatbuffers::FlatBufferBuilder;
 
@@ -42,6 +45,286 @@ impl ReplicatedWrite {
             None => None,
         }
     }
+
+    /// Computes the crc32 checksum of the payload, which should match the
+    /// `checksum` of the write unless it was corrupted
+    pub fn payload_checksum(&self) -> u32 {
+        let mut hasher = Hasher::new();
+        if let Some(payload) = self.to_fb().payload() {
+            hasher.update(payload);
+        }
+        hasher.finalize()
+    }
+
+    /// Returns the distinct partition keys of the entries in this write
+    pub fn partition_keys(&self) -> BTreeSet<&str> {
+        self.write_buffer_batch()
+            .and_then(|batch| batch.entries())
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|entry| entry.partition_key().unwrap_or(""))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the oldest value of the time column of the rows in this write
+    pub fn min_timestamp(&self) -> Option<i64> {
+        let entries = self.write_buffer_batch()?.entries()?;
+
+        let mut min: Option<i64> = None;
+        for entry in entries {
+            let tables = entry.table_batches().into_iter().flatten();
+            let rows = tables.flat_map(|table| table.rows().into_iter().flatten());
+            for row in rows {
+                let times = row
+                    .values()
+                    .into_iter()
+                    .flatten()
+                    .filter(|value| value.column() == Some(TIME_COLUMN_NAME))
+                    .filter_map(|value| value.value_as_i64value())
+                    .map(|value| value.value());
+                for time in times {
+                    min = Some(min.map_or(time, |min| min.min(time)));
+                }
+            }
+        }
+
+        min
+    }
+
+    /// Creates a new `ReplicatedWrite` with the same writer and
+    /// sequence number as this one, containing only the parts of
+    /// the payload accepted by `filter`. Entries and table batches
+    /// that end up empty are dropped. Returns `None` if nothing is
+    /// left.
+    pub fn filter(&self, filter: &impl WriteFilter) -> Option<Self> {
+        let entries = self.write_buffer_batch()?.entries()?;
+
+        let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+
+        let entries = entries
+            .iter()
+            .filter_map(|entry| filter_entry(&mut fbb, &entry, filter))
+            .collect::<Vec<_>>();
+
+        if entries.is_empty() {
+            return None;
+        }
+
+        let entries_vec = fbb.create_vector(&entries);
+        let batch = wb::WriteBufferBatch::create(
+            &mut fbb,
+            &wb::WriteBufferBatchArgs {
+                entries: Some(entries_vec),
+            },
+        );
+        fbb.finish(batch, None);
+
+        let (mut data, idx) = fbb.collapse();
+        let entry_bytes = data.split_off(idx);
+
+        let fb = self.to_fb();
+        Some(Self::new(fb.writer(), fb.sequence(), &entry_bytes))
+    }
+
+    /// Creates a new `ReplicatedWrite` with `entry_bytes`, the
+    /// serialized `WriteBufferBatch`, as its payload
+    fn new(writer: u32, sequence: u64, entry_bytes: &[u8]) -> Self {
+        let mut hasher = Hasher::new();
+        hasher.update(entry_bytes);
+        let checksum = hasher.finalize();
+
+        let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+        let payload = fbb.create_vector_direct(entry_bytes);
+
+        let write = wb::ReplicatedWrite::create(
+            &mut fbb,
+            &wb::ReplicatedWriteArgs {
+                writer,
+                sequence,
+                checksum,
+                payload: Some(payload),
+            },
+        );
+
+        fbb.finish(write, None);
+
+        let (mut data, idx) = fbb.collapse();
+        Self {
+            data: data.split_off(idx),
+        }
+    }
+}
+
+/// Decides which parts of a `ReplicatedWrite` are kept by
+/// `ReplicatedWrite::filter`. Everything is kept by default.
+pub trait WriteFilter {
+    /// Returns true if the entry for `partition_key` should be kept
+    fn keep_partition(&self, _partition_key: &str) -> bool {
+        true
+    }
+
+    /// Returns true if the rows and deletes of `table_name` should be kept
+    fn keep_table(&self, _table_name: &str) -> bool {
+        true
+    }
+
+    /// Returns true if `row` of `table_name` should be kept
+    fn keep_row(&self, _table_name: &str, _row: &wb::Row<'_>) -> bool {
+        true
+    }
+}
+
+fn filter_entry<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    entry: &wb::WriteBufferEntry<'_>,
+    filter: &impl WriteFilter,
+) -> Option<flatbuffers::WIPOffset<wb::WriteBufferEntry<'a>>> {
+    let partition_key = entry.partition_key();
+    if !filter.keep_partition(partition_key.unwrap_or("")) {
+        return None;
+    }
+
+    let table_batches = entry
+        .table_batches()
+        .map(|batches| {
+            batches
+                .iter()
+                .filter_map(|batch| filter_table_batch(fbb, &batch, filter))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let delete = entry
+        .delete()
+        .filter(|delete| filter.keep_table(delete.table_name().unwrap_or("")))
+        .map(|delete| {
+            let table_name = delete.table_name().map(|n| fbb.create_string(n));
+            let predicate = delete.predicate().map(|p| fbb.create_string(p));
+            wb::WriteBufferDelete::create(
+                fbb,
+                &wb::WriteBufferDeleteArgs {
+                    table_name,
+                    predicate,
+                },
+            )
+        });
+
+    if table_batches.is_empty() && delete.is_none() {
+        return None;
+    }
+
+    let partition_key = partition_key.map(|key| fbb.create_string(key));
+    let table_batches = if table_batches.is_empty() {
+        None
+    } else {
+        Some(fbb.create_vector(&table_batches))
+    };
+
+    Some(wb::WriteBufferEntry::create(
+        fbb,
+        &wb::WriteBufferEntryArgs {
+            partition_key,
+            table_batches,
+            delete,
+        },
+    ))
+}
+
+fn filter_table_batch<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    batch: &wb::TableWriteBatch<'_>,
+    filter: &impl WriteFilter,
+) -> Option<flatbuffers::WIPOffset<wb::TableWriteBatch<'a>>> {
+    let name = batch.name().unwrap_or("");
+    if !filter.keep_table(name) {
+        return None;
+    }
+
+    let rows = batch
+        .rows()?
+        .iter()
+        .filter(|row| filter.keep_row(name, row))
+        .map(|row| copy_row(fbb, &row))
+        .collect::<Vec<_>>();
+
+    if rows.is_empty() {
+        return None;
+    }
+
+    let name = fbb.create_string(name);
+    let rows = fbb.create_vector(&rows);
+
+    Some(wb::TableWriteBatch::create(
+        fbb,
+        &wb::TableWriteBatchArgs {
+            name: Some(name),
+            rows: Some(rows),
+        },
+    ))
+}
+
+fn copy_row<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    row: &wb::Row<'_>,
+) -> flatbuffers::WIPOffset<wb::Row<'a>> {
+    let values = row
+        .values()
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|value| copy_value(fbb, &value))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let values = fbb.create_vector(&values);
+
+    wb::Row::create(
+        fbb,
+        &wb::RowArgs {
+            values: Some(values),
+        },
+    )
+}
+
+fn copy_value<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    value: &wb::Value<'_>,
+) -> Option<flatbuffers::WIPOffset<wb::Value<'a>>> {
+    let column = value.column().unwrap_or("");
+
+    let value = match value.value_type() {
+        wb::ColumnValue::TagValue => {
+            let value = value.value_as_tag_value()?.value()?;
+            add_tag_value(fbb, column, value)
+        }
+        wb::ColumnValue::StringValue => {
+            let value = value.value_as_string_value()?.value()?;
+            add_string_value(fbb, column, value)
+        }
+        wb::ColumnValue::I64Value => {
+            let value = value.value_as_i64value()?.value();
+            add_i64_value(fbb, column, value)
+        }
+        wb::ColumnValue::U64Value => {
+            let value = value.value_as_u64value()?.value();
+            add_u64_value(fbb, column, value)
+        }
+        wb::ColumnValue::F64Value => {
+            let value = value.value_as_f64value()?.value();
+            add_f64_value(fbb, column, value)
+        }
+        wb::ColumnValue::BoolValue => {
+            let value = value.value_as_bool_value()?.value();
+            add_bool_value(fbb, column, value)
+        }
+        wb::ColumnValue::NONE => return None,
+    };
+
+    Some(value)
 }
 
 impl fmt::Display for ReplicatedWrite {
@@ -125,49 +408,34 @@ pub fn lines_to_replicated_write(
     sequence: u64,
     lines: &[ParsedLine<'_>],
     rules: &DatabaseRules,
-) -> ReplicatedWrite {
-    let default_time = Utc::now();
+) -> RulesResult<ReplicatedWrite> {
     let entry_bytes = split_lines_into_write_entry_partitions(
-        |line| rules.partition_key(line, &default_time).unwrap(),
+        |line, default_time| rules.partition_key(line, default_time),
         lines,
-    );
-
-    let mut hasher = Hasher::new();
-    hasher.update(&entry_bytes);
-    let checksum = hasher.finalize();
-
-    let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
-    let payload = fbb.create_vector_direct(&entry_bytes);
+    )?;
 
-    let write = wb::ReplicatedWrite::create(
-        &mut fbb,
-        &wb::ReplicatedWriteArgs {
-            writer,
-            sequence,
-            checksum,
-            payload: Some(payload),
-        },
-    );
-
-    fbb.finish(write, None);
-
-    let (mut data, idx) = fbb.collapse();
-    ReplicatedWrite {
-        data: data.split_off(idx),
-    }
+    Ok(ReplicatedWrite::new(writer, sequence, &entry_bytes))
 }
 
-pub fn split_lines_into_write_entry_partitions(
-    partition_key: impl Fn(&ParsedLine<'_>) -> String,
+/// Splits `lines` into one `WriteBufferEntry` per partition key, as
+/// computed by `partition_key`, and returns the serialized
+/// `WriteBufferBatch`.
+///
+/// Lines without a timestamp are all assigned the same default time
+/// (the time this function is called), which is passed to
+/// `partition_key` and also used as the value of their time column.
+pub fn split_lines_into_write_entry_partitions<E>(
+    partition_key: impl Fn(&ParsedLine<'_>, &DateTime<Utc>) -> Result<String, E>,
     lines: &[ParsedLine<'_>],
-) -> Vec<u8> {
+) -> Result<Vec<u8>, E> {
+    let default_time = Utc::now();
     let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
 
     // split the lines into collections that go into partitions
     let mut partition_writes = BTreeMap::new();
 
     for line in lines {
-        let key = partition_key(line);
+        let key = partition_key(line, &default_time)?;
 
         partition_writes
             .entry(key)
@@ -178,7 +446,9 @@ pub fn split_lines_into_write_entry_partitions(
     // create a WALEntry for each batch of lines going to a partition (one WALEntry per partition)
     let entries = partition_writes
         .into_iter()
-        .map(|(key, lines)| add_write_entry(&mut fbb, Some(&key), &lines))
+        .map(|(key, lines)| {
+            add_write_entry(&mut fbb, Some(&key), &lines, default_time.timestamp_nanos())
+        })
         .collect::<Vec<_>>();
 
     let entries_vec = fbb.create_vector(&entries);
@@ -192,6 +462,91 @@ pub fn split_lines_into_write_entry_partitions(
 
     fbb.finish(batch, None);
 
+    let (mut data, idx) = fbb.collapse();
+    Ok(data.split_off(idx))
+}
+
+/// A column value of a row given to `rows_to_write_buffer_batch`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowValue<'a> {
+    Tag(&'a str),
+    String(&'a str),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+}
+
+/// The rows of a table given to `rows_to_write_buffer_batch`. Each row is
+/// a list of column names and their values.
+#[derive(Debug, Default)]
+pub struct TableRows<'a> {
+    pub name: &'a str,
+    pub rows: Vec<Vec<(&'a str, RowValue<'a>)>>,
+}
+
+/// Returns a serialized `WriteBufferBatch` with a single entry, for
+/// `partition_key`, holding the rows of `tables`.
+pub fn rows_to_write_buffer_batch(partition_key: &str, tables: &[TableRows<'_>]) -> Vec<u8> {
+    let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+
+    let mut table_batches = Vec::with_capacity(tables.len());
+    for table in tables {
+        let mut rows = Vec::with_capacity(table.rows.len());
+        for row in &table.rows {
+            let values = row
+                .iter()
+                .map(|&(column, value)| match value {
+                    RowValue::Tag(v) => add_tag_value(&mut fbb, column, v),
+                    RowValue::String(v) => add_string_value(&mut fbb, column, v),
+                    RowValue::I64(v) => add_i64_value(&mut fbb, column, v),
+                    RowValue::U64(v) => add_u64_value(&mut fbb, column, v),
+                    RowValue::F64(v) => add_f64_value(&mut fbb, column, v),
+                    RowValue::Bool(v) => add_bool_value(&mut fbb, column, v),
+                })
+                .collect::<Vec<_>>();
+            let values = fbb.create_vector(&values);
+
+            rows.push(wb::Row::create(
+                &mut fbb,
+                &wb::RowArgs {
+                    values: Some(values),
+                },
+            ));
+        }
+
+        let name = fbb.create_string(table.name);
+        let rows = fbb.create_vector(&rows);
+        table_batches.push(wb::TableWriteBatch::create(
+            &mut fbb,
+            &wb::TableWriteBatchArgs {
+                name: Some(name),
+                rows: Some(rows),
+            },
+        ));
+    }
+
+    let partition_key = fbb.create_string(partition_key);
+    let table_batches = fbb.create_vector(&table_batches);
+    let entry = wb::WriteBufferEntry::create(
+        &mut fbb,
+        &wb::WriteBufferEntryArgs {
+            partition_key: Some(partition_key),
+            table_batches: Some(table_batches),
+            ..Default::default()
+        },
+    );
+
+    let entries = fbb.create_vector(&[entry]);
+    let batch = wb::WriteBufferBatch::create(
+        &mut fbb,
+        &wb::WriteBufferBatchArgs {
+            entries: Some(entries),
+        },
+    );
+
+    fbb.finish(batch, None);
+
     let (mut data, idx) = fbb.collapse();
     data.split_off(idx)
 }
@@ -200,6 +555,7 @@ fn add_write_entry<'a>(
     fbb: &mut FlatBufferBuilder<'a>,
     partition_key: Option<&str>,
     lines: &[&ParsedLine<'_>],
+    default_time: i64,
 ) -> flatbuffers::WIPOffset<wb::WriteBufferEntry<'a>> {
     // split into tables
     let mut table_batches = BTreeMap::new();
@@ -214,7 +570,7 @@ fn add_write_entry<'a>(
     // create TableWriteBatch for each table
     let table_batches = table_batches
         .into_iter()
-        .map(|(name, lines)| add_table_batch(fbb, name, &lines))
+        .map(|(name, lines)| add_table_batch(fbb, name, &lines, default_time))
         .collect::<Vec<_>>();
 
     // create write entry
@@ -242,11 +598,12 @@ fn add_table_batch<'a>(
     fbb: &mut FlatBufferBuilder<'a>,
     name: &str,
     lines: &[&ParsedLine<'_>],
+    default_time: i64,
 ) -> flatbuffers::WIPOffset<wb::TableWriteBatch<'a>> {
     // create Row
     let rows = lines
         .iter()
-        .map(|line| add_line(fbb, line))
+        .map(|line| add_line(fbb, line, default_time))
         .collect::<Vec<_>>();
 
     let table_name = fbb.create_string(name);
@@ -264,6 +621,7 @@ fn add_table_batch<'a>(
 fn add_line<'a>(
     fbb: &mut FlatBufferBuilder<'a>,
     line: &ParsedLine<'_>,
+    default_time: i64,
 ) -> flatbuffers::WIPOffset<wb::Row<'a>> {
     let mut row_values = Vec::new();
 
@@ -276,6 +634,7 @@ fn add_line<'a>(
     for (column, value) in &line.field_set {
         let val = match value {
             FieldValue::I64(v) => add_i64_value(fbb, column.as_str(), *v),
+            FieldValue::U64(v) => add_u64_value(fbb, column.as_str(), *v),
             FieldValue::F64(v) => add_f64_value(fbb, column.as_str(), *v),
             FieldValue::Boolean(v) => add_bool_value(fbb, column.as_str(), *v),
             FieldValue::String(v) => add_string_value(fbb, column.as_str(), v.as_str()),
@@ -284,9 +643,7 @@ fn add_line<'a>(
         row_values.push(val);
     }
 
-    let time = line
-        .timestamp
-        .unwrap_or_else(|| Utc::now().timestamp_nanos());
+    let time = line.timestamp.unwrap_or(default_time);
     row_values.push(add_i64_value(fbb, TIME_COLUMN_NAME, time));
 
     let row_values = fbb.create_vector(&row_values);
@@ -352,6 +709,16 @@ fn add_i64_value<'a>(
     add_value(fbb, column, wb::ColumnValue::I64Value, iv.as_union_value())
 }
 
+fn add_u64_value<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    column: &str,
+    value: u64,
+) -> flatbuffers::WIPOffset<wb::Value<'a>> {
+    let iv = wb::U64Value::create(fbb, &wb::U64ValueArgs { value });
+
+    add_value(fbb, column, wb::ColumnValue::U64Value, iv.as_union_value())
+}
+
 fn add_bool_value<'a>(
     fbb: &mut FlatBufferBuilder<'a>,
     column: &str,
@@ -379,3 +746,118 @@ fn add_value<'a>(
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_rules::{PartitionTemplate, TemplatePart};
+    use influxdb_line_protocol::parse_lines;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    struct TableFilter(&'static str);
+
+    impl WriteFilter for TableFilter {
+        fn keep_table(&self, table_name: &str) -> bool {
+            table_name == self.0
+        }
+    }
+
+    struct PartitionFilter(&'static str);
+
+    impl WriteFilter for PartitionFilter {
+        fn keep_partition(&self, partition_key: &str) -> bool {
+            partition_key == self.0
+        }
+    }
+
+    fn table_partitioned_write(lp: &str) -> Result<ReplicatedWrite> {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![TemplatePart::Table]),
+            ..Default::default()
+        };
+        let lines: Vec<_> = parse_lines(lp).collect::<Result<_, _>>()?;
+
+        Ok(lines_to_replicated_write(3, 7, &lines, &rules)?)
+    }
+
+    #[test]
+    fn partition_keys() -> Result {
+        let write = table_partitioned_write("cpu val=1 10\nmem val=2 10\ncpu val=3 20")?;
+
+        let keys: Vec<_> = write.partition_keys().into_iter().collect();
+        assert_eq!(keys, vec!["cpu", "mem"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_keeps_writer_and_sequence() -> Result {
+        let write =
+            table_partitioned_write("cpu,host=a val=1i,u=2u,s=\"x\",b=true 10\nmem val=2 10")?;
+
+        let filtered = write.filter(&TableFilter("cpu")).unwrap();
+        let fb = filtered.to_fb();
+        assert_eq!(fb.writer(), 3);
+        assert_eq!(fb.sequence(), 7);
+
+        let mut hasher = Hasher::new();
+        hasher.update(fb.payload().unwrap());
+        assert_eq!(fb.checksum(), hasher.finalize());
+
+        let keys: Vec<_> = filtered.partition_keys().into_iter().collect();
+        assert_eq!(keys, vec!["cpu"]);
+
+        let expected = "\nwriter:3, sequence:7, checksum:";
+        let display = filtered.to_string();
+        assert!(display.starts_with(expected));
+        assert!(display.contains(" host:a val:1 u:2 s:x b:true time:10"));
+        assert!(!display.contains("mem"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn min_timestamp() -> Result {
+        let write = table_partitioned_write("cpu val=1 20\nmem val=2 -5\ncpu val=3 10")?;
+        assert_eq!(write.min_timestamp(), Some(-5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_drops_everything() -> Result {
+        let write = table_partitioned_write("cpu val=1 10\nmem val=2 10")?;
+
+        assert!(write.filter(&PartitionFilter("disk")).is_none());
+        assert!(write.filter(&PartitionFilter("mem")).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rows_to_batch() -> Result {
+        let expected = table_partitioned_write("cpu,host=a val=1,ok=true,msg=\"hi\" 10")?;
+
+        let table = TableRows {
+            name: "cpu",
+            rows: vec![vec![
+                ("host", RowValue::Tag("a")),
+                ("val", RowValue::F64(1.0)),
+                ("ok", RowValue::Bool(true)),
+                ("msg", RowValue::String("hi")),
+                ("time", RowValue::I64(10)),
+            ]],
+        };
+        let batch = rows_to_write_buffer_batch("cpu", &[table]);
+        let write = ReplicatedWrite::new(3, 7, &batch);
+
+        // the checksum on the first line differs as the flatbuffers are laid out differently
+        let body =
+            |w: &ReplicatedWrite| w.to_string().lines().skip(2).collect::<Vec<_>>().join("\n");
+        assert_eq!(body(&write), body(&expected));
+
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
-use influxdb_line_protocol::ParsedLine;
+use crate::data::ReplicatedWrite;
+use crate::row_predicate::RowPredicate;
+use influxdb_line_protocol::{FieldValue, ParsedLine};
 
 use chrono::{DateTime, TimeZone, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
+
+use std::{convert::TryFrom, fmt::Write};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -11,13 +16,33 @@ pub enum Error {
         source_module: &'static str,
         source: Box<dyn std::error::Error + Send + Sync + 'static>,
     },
+
+    #[snafu(display("Invalid regex '{}' in partition template: {}", regex, source))]
+    InvalidRegex { regex: String, source: regex::Error },
+
+    #[snafu(display("Invalid time format '{}' in partition template", format))]
+    InvalidTimeFormat { format: String },
+
+    #[snafu(display(
+        "Regex capture on column '{}' requires a tag or string field, but got {}",
+        column,
+        value
+    ))]
+    RegexCaptureOnNonString { column: String, value: String },
+
+    #[snafu(display(
+        "Strftime on column '{}' requires a nanosecond timestamp, but got {}",
+        column,
+        value
+    ))]
+    StrftimeOnNonTimestamp { column: String, value: String },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// DatabaseRules contains the rules for replicating data, sending data to subscribers, and
 /// querying data for a single database.
-#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
 pub struct DatabaseRules {
     /// Template that generates a partition key for each row inserted into the db
     pub partition_template: PartitionTemplate,
@@ -34,12 +59,17 @@ pub struct DatabaseRules {
     pub replication: Vec<HostGroupId>,
     /// The minimum number of host groups to replicate a write to before success is returned. This
     /// can be overridden on a per request basis. Replication will continue to write to the other
-    /// host groups in the background.
+    /// host groups in the background. A count of zero means every host group in `replication`
+    /// must receive the write.
     pub replication_count: u8,
     /// How long the replication queue can get before either rejecting writes or dropping missed
     /// writes. The queue is kept in memory on a per-database basis. A queue size of zero means it
     /// will only try to replicate synchronously and drop any failures.
     pub replication_queue_max_size: usize,
+    /// What to do when a write would grow the replication queue beyond
+    /// `replication_queue_max_size`.
+    #[serde(default)]
+    pub replication_queue_full_policy: QueueFullPolicy,
     /// `subscriptions` are used for query servers to get data via either push or pull as it
     /// arrives. They are separate from replication as they have a different purpose. They're for
     /// query servers or other clients that want to subscribe to some subset of data being written
@@ -68,6 +98,17 @@ pub struct DatabaseRules {
     /// queries by pointing it at a collection of partitions and then telling it to also pull
     /// data from the replication servers (writes that haven't been snapshotted into a partition).
     pub read_only_partitions: Vec<PartitionId>,
+
+    /// When the open partitions of the local write buffer are closed and persisted to
+    /// object storage, freeing their memory.
+    #[serde(default)]
+    pub lifecycle_rules: LifecycleRules,
+
+    /// How many seconds data is kept for. Writes with older timestamps are rejected, and
+    /// partitions holding only older data are dropped from the write buffer and deleted from
+    /// object storage. Without a retention period, data is kept forever.
+    #[serde(default)]
+    pub retention_period_seconds: Option<u64>,
 }
 
 impl DatabaseRules {
@@ -78,6 +119,59 @@ impl DatabaseRules {
     ) -> Result<String> {
         self.partition_template.partition_key(line, default_time)
     }
+
+    /// Returns the oldest timestamp, in nanoseconds, inside the retention period as of `now`
+    pub fn retention_cutoff(&self, now: &DateTime<Utc>) -> Option<i64> {
+        self.retention_period_seconds.map(|seconds| {
+            let period = i64::try_from(seconds)
+                .unwrap_or(i64::MAX)
+                .saturating_mul(1_000_000_000);
+            now.timestamp_nanos().saturating_sub(period)
+        })
+    }
+
+    /// Returns the timestamp of the first of `lines` that falls outside the retention
+    /// period as of `now`. Lines without a timestamp are written at `now`, so they never do.
+    pub fn expired_timestamp(&self, lines: &[ParsedLine<'_>], now: &DateTime<Utc>) -> Option<i64> {
+        let cutoff = self.retention_cutoff(now)?;
+        lines
+            .iter()
+            .filter_map(|line| line.timestamp)
+            .find(|&timestamp| timestamp < cutoff)
+    }
+
+    /// Returns the oldest timestamp of the rows of `write` if it falls outside the
+    /// retention period as of `now`
+    pub fn expired_write_timestamp(
+        &self,
+        write: &ReplicatedWrite,
+        now: &DateTime<Utc>,
+    ) -> Option<i64> {
+        let cutoff = self.retention_cutoff(now)?;
+        write
+            .min_timestamp()
+            .filter(|&timestamp| timestamp < cutoff)
+    }
+}
+
+/// `LifecycleRules` decide when an open partition of the write buffer is closed, snapshotted
+/// to object storage and dropped from memory. A partition is closed as soon as any of the
+/// rules that are set applies to it; with none set, partitions are never closed.
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone, Copy)]
+pub struct LifecycleRules {
+    /// Close a partition once this many seconds have passed since it was created
+    pub max_partition_age_seconds: Option<u64>,
+    /// Close a partition once its data takes up more than this many bytes of memory
+    pub max_partition_bytes: Option<usize>,
+    /// Close a partition once this many seconds have passed since it was last written to
+    pub max_idle_seconds: Option<u64>,
+    /// Close the least recently written partitions while more than this many are open
+    pub max_open_partitions: Option<usize>,
+    /// Close the least recently written partitions while the write buffer takes up more
+    /// than this many bytes of memory
+    pub buffer_size_soft: Option<usize>,
+    /// Reject writes while the write buffer takes up more than this many bytes of memory
+    pub buffer_size_hard: Option<usize>,
 }
 
 /// `PartitionTemplate` is used to compute the partition key of each row that gets written. It
@@ -87,43 +181,60 @@ impl DatabaseRules {
 ///
 /// The key is constructed in order of the template parts; thus ordering changes what partition
 /// key is generated.
-#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq, Clone)]
 pub struct PartitionTemplate {
     parts: Vec<TemplatePart>,
 }
 
 impl PartitionTemplate {
+    /// Create a new template that builds partition keys from `parts`
+    pub fn new(parts: Vec<TemplatePart>) -> Self {
+        Self { parts }
+    }
+
     pub fn partition_key(
         &self,
         line: &ParsedLine<'_>,
         default_time: &DateTime<Utc>,
     ) -> Result<String> {
-        let parts: Vec<_> = self
+        let parts = self
             .parts
             .iter()
             .map(|p| match p {
-                TemplatePart::Table => line.series.measurement.to_string(),
-                TemplatePart::Column(column) => match line.tag_value(&column) {
+                TemplatePart::Table => Ok(line.series.measurement.to_string()),
+                TemplatePart::Column(column) => Ok(match line.tag_value(&column) {
                     Some(v) => format!("{}_{}", column, v),
                     None => match line.field_value(&column) {
                         Some(v) => format!("{}_{}", column, v),
                         None => "".to_string(),
                     },
-                },
+                }),
                 TemplatePart::TimeFormat(format) => match line.timestamp {
-                    Some(t) => Utc.timestamp_nanos(t).format(&format).to_string(),
-                    None => default_time.format(&format).to_string(),
+                    Some(t) => format_time(&Utc.timestamp_nanos(t), format),
+                    None => format_time(default_time, format),
                 },
-                _ => unimplemented!(),
+                TemplatePart::RegexCapture(capture) => capture.partition_key_part(line),
+                TemplatePart::StrftimeColumn(strftime) => strftime.partition_key_part(line),
             })
-            .collect();
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(parts.join("-"))
     }
 }
 
+/// Formats `time` using the strftime style `format`, returning an
+/// error rather than panicking if `format` is invalid
+fn format_time(time: &DateTime<Utc>, format: &str) -> Result<String> {
+    let mut formatted = String::new();
+
+    match write!(formatted, "{}", time.format(format)) {
+        Ok(()) => Ok(formatted),
+        Err(_) => InvalidTimeFormat { format }.fail(),
+    }
+}
+
 /// `TemplatePart` specifies what part of a row should be used to compute this part of a partition key.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub enum TemplatePart {
     Table,
     Column(String),
@@ -132,24 +243,146 @@ pub enum TemplatePart {
     StrftimeColumn(StrftimeColumn),
 }
 
-/// `RegexCapture` is for pulling parts of a string column into the partition key.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+/// `RegexCapture` is for pulling parts of a string column into the partition key. The
+/// capture groups of `regex` (or the whole match, if it has no groups) are joined with
+/// `_` and output as `<column>_<captures>`. If the column is missing or the regex doesn't
+/// match, a blank value is output.
+///
+/// The regex is compiled when the template part is created or deserialized, so rules
+/// with an invalid regex are rejected before they are used.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(try_from = "SerializedRegexCapture", into = "SerializedRegexCapture")]
 pub struct RegexCapture {
+    column: String,
+    regex: Regex,
+}
+
+impl RegexCapture {
+    pub fn new(column: impl Into<String>, regex: impl AsRef<str>) -> Result<Self> {
+        let regex = regex.as_ref();
+
+        Ok(Self {
+            column: column.into(),
+            regex: Regex::new(regex).context(InvalidRegex { regex })?,
+        })
+    }
+
+    fn partition_key_part(&self, line: &ParsedLine<'_>) -> Result<String> {
+        let value = match line.tag_value(&self.column) {
+            Some(v) => v.as_str(),
+            None => match line.field_value(&self.column) {
+                Some(FieldValue::String(v)) => v.as_str(),
+                Some(v) => {
+                    return RegexCaptureOnNonString {
+                        column: &self.column,
+                        value: v.to_string(),
+                    }
+                    .fail()
+                }
+                None => return Ok("".to_string()),
+            },
+        };
+
+        let captures = match self.regex.captures(value) {
+            Some(captures) => captures,
+            None => return Ok("".to_string()),
+        };
+
+        // use the capture groups if there are any, otherwise the whole match
+        let skip = if captures.len() > 1 { 1 } else { 0 };
+        let matched: Vec<_> = captures
+            .iter()
+            .skip(skip)
+            .filter_map(|m| m.map(|m| m.as_str()))
+            .collect();
+
+        Ok(format!("{}_{}", self.column, matched.join("_")))
+    }
+}
+
+impl PartialEq for RegexCapture {
+    fn eq(&self, other: &Self) -> bool {
+        self.column == other.column && self.regex.as_str() == other.regex.as_str()
+    }
+}
+impl Eq for RegexCapture {}
+
+/// The serialized form of a `RegexCapture`, with its regex as a string
+#[derive(Serialize, Deserialize)]
+struct SerializedRegexCapture {
     column: String,
     regex: String,
 }
 
+impl TryFrom<SerializedRegexCapture> for RegexCapture {
+    type Error = Error;
+
+    fn try_from(capture: SerializedRegexCapture) -> Result<Self> {
+        Self::new(capture.column, capture.regex)
+    }
+}
+
+impl From<RegexCapture> for SerializedRegexCapture {
+    fn from(capture: RegexCapture) -> Self {
+        Self {
+            column: capture.column,
+            regex: capture.regex.as_str().to_string(),
+        }
+    }
+}
+
 /// `StrftimeColumn` can be used to create a time based partition key off some column other than
-/// the builtin `time` column.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+/// the builtin `time` column. The column must be an integer field holding nanoseconds since
+/// the epoch. If the column is missing, a blank value is output.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct StrftimeColumn {
     column: String,
     format: String,
 }
 
+impl StrftimeColumn {
+    pub fn new(column: impl Into<String>, format: impl Into<String>) -> Self {
+        Self {
+            column: column.into(),
+            format: format.into(),
+        }
+    }
+
+    fn partition_key_part(&self, line: &ParsedLine<'_>) -> Result<String> {
+        let nanos = match line.field_value(&self.column) {
+            Some(FieldValue::I64(v)) => Some(*v),
+            Some(FieldValue::U64(v)) if *v <= i64::MAX as u64 => Some(*v as i64),
+            Some(v) => {
+                return StrftimeOnNonTimestamp {
+                    column: &self.column,
+                    value: v.to_string(),
+                }
+                .fail()
+            }
+            None => match line.tag_value(&self.column) {
+                Some(v) => {
+                    return StrftimeOnNonTimestamp {
+                        column: &self.column,
+                        value: v.to_string(),
+                    }
+                    .fail()
+                }
+                None => None,
+            },
+        };
+
+        match nanos {
+            Some(nanos) => format_time(&Utc.timestamp_nanos(nanos), &self.format),
+            None => Ok("".to_string()),
+        }
+    }
+}
+
 /// `PartitionId` is the object storage identifier for a specific partition. It should be a
 /// path that can be used against an object store to locate all the files and subdirectories
-/// for a partition. It takes the form of `/<writer ID>/<database>/<partition key>/`.
+/// for a partition. It takes the form of `/<writer ID>/<database>/<partition key>/`, followed
+/// by a generation for the snapshots of the write buffer, as a partition key can be
+/// snapshotted more than once.
 pub type PartitionId = String;
 pub type WriterId = String;
 
@@ -162,7 +395,7 @@ pub type WriterId = String;
 ///
 /// For pull based subscriptions, the requester will send a matcher, which the receiver
 /// will execute against its in-memory WAL.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Subscription {
     pub name: String,
     pub host_group_id: HostGroupId,
@@ -171,18 +404,19 @@ pub struct Subscription {
 
 /// `Matcher` specifies the rule against the table name and/or a predicate
 /// against the row to determine if it matches the write rule.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Matcher {
     #[serde(flatten)]
     pub tables: MatchTables,
-    // TODO: make this work with storage::Predicate
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub predicate: Option<String>,
+    /// Only rows matching the predicate are sent. It's serialized as text, for
+    /// example `"region = 'us-west'"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub predicate: Option<RowPredicate>,
 }
 
 /// `MatchTables` looks at the table name of a row to determine if it should
 /// match the rule.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum MatchTables {
     #[serde(rename = "*")]
@@ -191,9 +425,27 @@ pub enum MatchTables {
     Regex(String),
 }
 
+/// `QueueFullPolicy` decides what happens to a write when the replication queue has
+/// no room left for the host groups it missed.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum QueueFullPolicy {
+    /// Drop the oldest queued writes to make room for the new ones.
+    DropOldest,
+    /// Reject the incoming write with an error, before it is stored or replicated
+    /// anywhere, if the host groups it could miss wouldn't fit in the queue.
+    Reject,
+}
+
+impl Default for QueueFullPolicy {
+    fn default() -> Self {
+        Self::DropOldest
+    }
+}
+
 pub type HostGroupId = String;
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct HostGroup {
     pub id: HostGroupId,
     /// `hosts` is a vector of connection strings for remote hosts.
@@ -210,6 +462,62 @@ mod tests {
     #[allow(dead_code)]
     type Result<T = (), E = TestError> = std::result::Result<T, E>;
 
+    #[test]
+    fn retention() -> Result {
+        let rules = DatabaseRules {
+            retention_period_seconds: Some(10),
+            ..Default::default()
+        };
+        let now = Utc.timestamp_nanos(100_000_000_000);
+        assert_eq!(rules.retention_cutoff(&now), Some(90_000_000_000));
+
+        let lines: Vec<_> = parse_lines("cpu foo=1 95000000000\ncpu foo=2\ncpu foo=3 1")
+            .map(|l| l.unwrap())
+            .collect();
+        assert_eq!(rules.expired_timestamp(&lines, &now), Some(1));
+        assert_eq!(rules.expired_timestamp(&lines[..2], &now), None);
+
+        let write = crate::data::lines_to_replicated_write(1, 1, &lines, &rules)?;
+        assert_eq!(rules.expired_write_timestamp(&write, &now), Some(1));
+        let write = crate::data::lines_to_replicated_write(1, 1, &lines[..2], &rules)?;
+        assert_eq!(rules.expired_write_timestamp(&write, &now), None);
+
+        let rules = DatabaseRules::default();
+        assert_eq!(rules.retention_cutoff(&now), None);
+        assert_eq!(rules.expired_timestamp(&lines, &now), None);
+
+        // periods too long to represent in nanoseconds keep everything
+        let rules = DatabaseRules {
+            retention_period_seconds: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert_eq!(
+            rules.retention_cutoff(&now),
+            Some(100_000_000_000 - i64::MAX)
+        );
+        assert_eq!(rules.expired_timestamp(&lines, &now), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rules_without_defaulted_fields() -> Result {
+        let mut json = serde_json::to_value(DatabaseRules::default())?;
+        let fields = json.as_object_mut().expect("rules are an object");
+        for field in &[
+            "replication_queue_full_policy",
+            "lifecycle_rules",
+            "retention_period_seconds",
+        ] {
+            assert!(fields.remove(*field).is_some(), "no field {}", field);
+        }
+
+        let rules: DatabaseRules = serde_json::from_value(json)?;
+        assert_eq!(rules, DatabaseRules::default());
+
+        Ok(())
+    }
+
     #[test]
     fn partition_key_with_table() -> Result {
         let template = PartitionTemplate {
@@ -360,6 +668,204 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn partition_key_with_invalid_time_format() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::TimeFormat("%Y-%Q".to_string())],
+        };
+
+        let line = parse_line("cpu,foo=asdf bar=true 1602338097000000000");
+        let err = template.partition_key(&line, &Utc::now()).unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidTimeFormat { .. }),
+            "Wrong error: {:?}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_regex_capture_tag() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::RegexCapture(RegexCapture::new(
+                "host",
+                r"^([a-z]+)-\d+$",
+            )?)],
+        };
+
+        let line = parse_line("cpu,host=west-123 usage_user=23.2 10");
+        assert_eq!(
+            "host_west",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_regex_capture_string_field() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::RegexCapture(RegexCapture::new(
+                "path",
+                r"^/([a-z]+)/(v\d+)/",
+            )?)],
+        };
+
+        let line = parse_line("requests path=\"/api/v2/write\" 10");
+        assert_eq!(
+            "path_api_v2",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_regex_capture_no_groups() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::RegexCapture(RegexCapture::new(
+                "host", r"[a-z]+",
+            )?)],
+        };
+
+        let line = parse_line("cpu,host=west-123 usage_user=23.2 10");
+        assert_eq!(
+            "host_west",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_regex_capture_no_match() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![
+                TemplatePart::Table,
+                TemplatePart::RegexCapture(RegexCapture::new("host", r"^(\d+)$")?),
+                TemplatePart::RegexCapture(RegexCapture::new("not_here", r"(.*)")?),
+            ],
+        };
+
+        let line = parse_line("cpu,host=west-123 usage_user=23.2 10");
+        assert_eq!("cpu--", template.partition_key(&line, &Utc::now()).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_regex_capture_non_string() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::RegexCapture(RegexCapture::new(
+                "usage_user",
+                r"(.*)",
+            )?)],
+        };
+
+        let line = parse_line("cpu,host=west-123 usage_user=23.2 10");
+        let err = template.partition_key(&line, &Utc::now()).unwrap_err();
+        assert!(
+            matches!(err, Error::RegexCaptureOnNonString { .. }),
+            "Wrong error: {:?}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_capture_with_invalid_regex() -> Result {
+        let err = RegexCapture::new("host", r"(unclosed").unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidRegex { .. }),
+            "Wrong error: {:?}",
+            err
+        );
+
+        // the regex is serialized as a string, and compiled when the rules are loaded
+        let capture = RegexCapture::new("host", r"^([a-z]+)")?;
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::RegexCapture(capture)],
+            },
+            ..Default::default()
+        };
+        let mut json = serde_json::to_value(&rules)?;
+        assert_eq!(
+            json["partition_template"]["parts"][0]["RegexCapture"],
+            serde_json::json!({"column": "host", "regex": "^([a-z]+)"})
+        );
+        let loaded: DatabaseRules = serde_json::from_value(json.clone())?;
+        assert_eq!(loaded, rules);
+
+        // rules with an invalid regex can't be loaded
+        json["partition_template"]["parts"][0]["RegexCapture"]["regex"] = "(unclosed".into();
+        let err = serde_json::from_value::<DatabaseRules>(json).unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid regex '(unclosed'"),
+            "Wrong error: {}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_strftime_column() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::StrftimeColumn(StrftimeColumn::new(
+                "event_time",
+                "%Y-%m-%d",
+            ))],
+        };
+
+        let line = parse_line("events,host=a event_time=1602338097000000000i 10");
+        assert_eq!(
+            "2020-10-10",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        let line = parse_line("events,host=a event_time=1602338097000000000u 10");
+        assert_eq!(
+            "2020-10-10",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        let line = parse_line("events,host=a other=1i 10");
+        assert_eq!("", template.partition_key(&line, &Utc::now()).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_key_with_strftime_non_timestamp_column() -> Result {
+        let template = PartitionTemplate {
+            parts: vec![TemplatePart::StrftimeColumn(StrftimeColumn::new(
+                "event_time",
+                "%Y-%m-%d",
+            ))],
+        };
+
+        for lp in &[
+            "events,host=a event_time=1.5 10",
+            "events,host=a event_time=\"yesterday\" 10",
+            "events,event_time=today value=1 10",
+            "events,host=a event_time=18446744073709551615u 10",
+        ] {
+            let line = parse_line(lp);
+            let err = template.partition_key(&line, &Utc::now()).unwrap_err();
+            assert!(
+                matches!(err, Error::StrftimeOnNonTimestamp { .. }),
+                "Wrong error for {}: {:?}",
+                lp,
+                err
+            );
+        }
+
+        Ok(())
+    }
+
     fn parsed_lines(lp: &str) -> Vec<ParsedLine<'_>> {
         parse_lines(lp).map(|l| l.unwrap()).collect()
     }
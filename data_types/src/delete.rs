@@ -0,0 +1,428 @@
+//! This module contains `Delete`, which describes the rows to remove from a
+//! database. Deletes are stored in the WAL as the `delete` of a
+//! `WriteBufferEntry` without a partition key, and apply to every partition.
+//!
+//! Deletes can be given in the form used by the InfluxDB 2.x delete API: a time
+//! range and a predicate comparing tags to double quoted strings, combined with
+//! `AND`. The `_measurement` key selects the table to delete from:
+//!
+//! ```text
+//! _measurement="cpu" AND host="a" AND region!="us-west"
+//! ```
+
+use std::convert::TryFrom;
+
+use flatbuffers::FlatBufferBuilder;
+use generated_types::wal as wb;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt, Snafu};
+
+use crate::{
+    row_predicate::{self, CompareOp, Literal, RowPredicate},
+    TIME_COLUMN_NAME,
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "invalid delete predicate: expected {} at position {}",
+        expected,
+        position
+    ))]
+    InvalidPredicate {
+        expected: &'static str,
+        position: usize,
+    },
+
+    #[snafu(display("deleting by {} is not supported", key))]
+    UnsupportedKey { key: String },
+
+    #[snafu(display("a delete predicate can only select one measurement, with ="))]
+    InvalidMeasurement,
+
+    #[snafu(display("delete start {} is after its stop {}", start, stop))]
+    InvalidTimeRange { start: i64, stop: i64 },
+
+    #[snafu(display("invalid predicate in delete entry: {}", source))]
+    ParsingEntry { source: row_predicate::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A delete of the rows of a table, or of every table, that match a predicate.
+/// It's serialized with its predicate in the form stored in the WAL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "SerializedDelete", into = "SerializedDelete")]
+pub struct Delete {
+    /// The table to delete from, or every table if `None`
+    pub table_name: Option<String>,
+    /// The rows to delete, or every row if `None`
+    pub predicate: Option<RowPredicate>,
+}
+
+impl Delete {
+    /// Creates the delete of the InfluxDB 2.x delete API: the rows with timestamps
+    /// from `start` to `stop`, inclusive, that match `predicate`.
+    pub fn from_influx(start: i64, stop: i64, predicate: &str) -> Result<Self> {
+        ensure!(start <= stop, InvalidTimeRange { start, stop });
+
+        let mut table_name = None;
+        let mut row_predicate = and(
+            time_comparison(CompareOp::GtEq, start),
+            time_comparison(CompareOp::LtEq, stop),
+        );
+
+        let mut scanner = Scanner {
+            input: predicate,
+            pos: 0,
+        };
+        let mut first = true;
+        while !scanner.at_end() {
+            if !first {
+                scanner.and()?;
+            }
+            first = false;
+
+            let key = scanner.key()?;
+            let op = scanner.op()?;
+            let value = scanner.quoted("a double quoted value")?;
+
+            match key.as_str() {
+                "_measurement" => {
+                    ensure!(
+                        op == CompareOp::Eq && table_name.is_none(),
+                        InvalidMeasurement
+                    );
+                    table_name = Some(value);
+                }
+                "_field" => return UnsupportedKey { key }.fail(),
+                _ => {
+                    let comparison = RowPredicate::Compare {
+                        column: key,
+                        op,
+                        value: Literal::String(value),
+                    };
+                    row_predicate = and(row_predicate, comparison);
+                }
+            }
+        }
+
+        Ok(Self {
+            table_name,
+            predicate: Some(row_predicate),
+        })
+    }
+
+    /// Reads a delete stored in a `WriteBufferEntry`
+    pub fn from_fb(delete: &wb::WriteBufferDelete<'_>) -> Result<Self> {
+        let predicate = delete
+            .predicate()
+            .map(RowPredicate::parse)
+            .transpose()
+            .context(ParsingEntry)?;
+
+        Ok(Self {
+            table_name: delete.table_name().map(ToString::to_string),
+            predicate,
+        })
+    }
+
+    /// Returns a serialized `WriteBufferBatch` with a single entry holding
+    /// this delete
+    pub fn to_write_buffer_batch(&self) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new_with_capacity(1024);
+
+        let table_name = self.table_name.as_deref().map(|n| fbb.create_string(n));
+        let predicate = self
+            .predicate
+            .as_ref()
+            .map(|p| fbb.create_string(&p.to_string()));
+        let delete = wb::WriteBufferDelete::create(
+            &mut fbb,
+            &wb::WriteBufferDeleteArgs {
+                table_name,
+                predicate,
+            },
+        );
+
+        let entry = wb::WriteBufferEntry::create(
+            &mut fbb,
+            &wb::WriteBufferEntryArgs {
+                delete: Some(delete),
+                ..Default::default()
+            },
+        );
+
+        let entries = fbb.create_vector(&[entry]);
+        let batch = wb::WriteBufferBatch::create(
+            &mut fbb,
+            &wb::WriteBufferBatchArgs {
+                entries: Some(entries),
+            },
+        );
+
+        fbb.finish(batch, None);
+
+        let (mut data, idx) = fbb.collapse();
+        data.split_off(idx)
+    }
+}
+
+/// The serialized form of a `Delete`, with its predicate as a string
+#[derive(Serialize, Deserialize)]
+struct SerializedDelete {
+    table_name: Option<String>,
+    predicate: Option<String>,
+}
+
+impl TryFrom<SerializedDelete> for Delete {
+    type Error = Error;
+
+    fn try_from(delete: SerializedDelete) -> Result<Self> {
+        let predicate = delete
+            .predicate
+            .as_deref()
+            .map(RowPredicate::parse)
+            .transpose()
+            .context(ParsingEntry)?;
+
+        Ok(Self {
+            table_name: delete.table_name,
+            predicate,
+        })
+    }
+}
+
+impl From<Delete> for SerializedDelete {
+    fn from(delete: Delete) -> Self {
+        Self {
+            table_name: delete.table_name,
+            predicate: delete.predicate.map(|p| p.to_string()),
+        }
+    }
+}
+
+fn time_comparison(op: CompareOp, time: i64) -> RowPredicate {
+    RowPredicate::Compare {
+        column: TIME_COLUMN_NAME.to_string(),
+        op,
+        value: Literal::I64(time),
+    }
+}
+
+fn and(left: RowPredicate, right: RowPredicate) -> RowPredicate {
+    RowPredicate::And(Box::new(left), Box::new(right))
+}
+
+/// Reads the parts of an InfluxDB 2.x delete predicate
+#[derive(Debug)]
+struct Scanner<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        self.pos == self.input.len()
+    }
+
+    fn fail<T>(&self, expected: &'static str) -> Result<T> {
+        InvalidPredicate {
+            expected,
+            position: self.pos,
+        }
+        .fail()
+    }
+
+    /// Reads a bare word: letters, digits and `_`, `-` and `.`
+    fn word(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let start = self.pos;
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-' || c == '.'))
+            .unwrap_or_else(|| self.rest().len());
+        self.pos += len;
+        &self.input[start..self.pos]
+    }
+
+    fn and(&mut self) -> Result<()> {
+        let start = self.pos;
+        if self.word().eq_ignore_ascii_case("and") {
+            Ok(())
+        } else {
+            self.pos = start;
+            self.skip_whitespace();
+            self.fail("AND")
+        }
+    }
+
+    /// Reads a bare or double quoted key
+    fn key(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        if self.rest().starts_with('"') {
+            return self.quoted("a key");
+        }
+
+        match self.word() {
+            "" => self.fail("a key"),
+            word => Ok(word.to_string()),
+        }
+    }
+
+    fn op(&mut self) -> Result<CompareOp> {
+        self.skip_whitespace();
+        if self.rest().starts_with("!=") {
+            self.pos += 2;
+            Ok(CompareOp::NotEq)
+        } else if self.rest().starts_with('=') {
+            self.pos += 1;
+            Ok(CompareOp::Eq)
+        } else {
+            self.fail("= or !=")
+        }
+    }
+
+    /// Reads a double quoted string, in which `\` escapes the next character
+    fn quoted(&mut self, expected: &'static str) -> Result<String> {
+        self.skip_whitespace();
+        if !self.rest().starts_with('"') {
+            return self.fail(expected);
+        }
+
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+
+        self.pos = self.input.len();
+        self.fail("a closing \"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[test]
+    fn serialized_deletes() -> Result {
+        let delete = Delete::from_influx(10, 20, r#"_measurement="cpu" and host="a""#)?;
+        let json = serde_json::to_string(&delete)?;
+        assert_eq!(
+            json,
+            r#"{"table_name":"cpu","predicate":"(time >= 10 AND time <= 20) AND host = 'a'"}"#
+        );
+        assert_eq!(serde_json::from_str::<Delete>(&json)?, delete);
+
+        let json = r#"{"table_name":null,"predicate":"time >="}"#;
+        assert!(serde_json::from_str::<Delete>(json).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn influx_predicates() -> Result {
+        let delete = Delete::from_influx(
+            10,
+            20,
+            r#"_measurement="cpu" and host="a" AND "host name"!="b \"c\"""#,
+        )?;
+        assert_eq!(delete.table_name.as_deref(), Some("cpu"));
+        assert_eq!(
+            delete.predicate.unwrap().to_string(),
+            r#"((time >= 10 AND time <= 20) AND host = 'a') AND "host name" != 'b "c"'"#
+        );
+
+        let delete = Delete::from_influx(10, 20, "  ")?;
+        assert_eq!(delete.table_name, None);
+        assert_eq!(
+            delete.predicate.unwrap().to_string(),
+            "time >= 10 AND time <= 20"
+        );
+
+        let cases = vec![
+            (
+                r#"host="a" host="b""#,
+                "invalid delete predicate: expected AND at position 9",
+            ),
+            (
+                r#"host=a"#,
+                "invalid delete predicate: expected a double quoted value at position 5",
+            ),
+            (
+                r#"host<"a""#,
+                "invalid delete predicate: expected = or != at position 4",
+            ),
+            (
+                r#"host="a"#,
+                "invalid delete predicate: expected a closing \" at position 7",
+            ),
+            (r#"_field="usage""#, "deleting by _field is not supported"),
+            (
+                r#"_measurement="a" AND _measurement="b""#,
+                "a delete predicate can only select one measurement, with =",
+            ),
+            (
+                r#"_measurement!="a""#,
+                "a delete predicate can only select one measurement, with =",
+            ),
+        ];
+        for (predicate, expected) in cases {
+            let err = Delete::from_influx(10, 20, predicate).unwrap_err();
+            assert_eq!(err.to_string(), expected, "predicate: {}", predicate);
+        }
+
+        let err = Delete::from_influx(20, 10, "").unwrap_err();
+        assert_eq!(err.to_string(), "delete start 20 is after its stop 10");
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_buffer_batch_roundtrip() -> Result {
+        let deletes = vec![
+            Delete::from_influx(1, 2, r#"_measurement="cpu" AND host="a""#)?,
+            Delete {
+                table_name: None,
+                predicate: None,
+            },
+        ];
+
+        for delete in deletes {
+            let data = delete.to_write_buffer_batch();
+            let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+            let entries = batch.entries().unwrap();
+            assert_eq!(entries.len(), 1);
+
+            let entry = entries.get(0);
+            assert_eq!(entry.partition_key(), None);
+            assert_eq!(Delete::from_fb(&entry.delete().unwrap())?, delete);
+        }
+
+        Ok(())
+    }
+}
@@ -14,6 +14,8 @@ pub const TIME_COLUMN_NAME: &str = "time";
 
 pub mod data;
 pub mod database_rules;
+pub mod delete;
 pub mod error;
 pub mod partition_metadata;
+pub mod row_predicate;
 pub mod table_schema;
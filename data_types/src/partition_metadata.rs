@@ -1,39 +1,101 @@
 //! This module contains structs that describe the metadata for a partition including schema,
 //! summary statistics, and file locations in storage.
+//!
+//! A partition is stored in object storage under its `PartitionId` path, as a
+//! `METADATA_FILE_NAME` file holding its `Partition` as JSON and a Parquet file for each of
+//! its tables, named by `table_file_name`.
 
 use std::fmt::{Debug, Display};
 
 use serde::{Deserialize, Serialize};
 
+use crate::{delete::Delete, TIME_COLUMN_NAME};
+
+/// Name of the file holding the JSON `Partition` metadata in the directory of a partition
+pub const METADATA_FILE_NAME: &str = "meta.json";
+
+/// Returns the name of the Parquet file holding the data of a table in the directory of a
+/// partition
+pub fn table_file_name(table_name: &str) -> String {
+    format!("{}.parquet", table_name)
+}
+
+/// Returns the location in object storage of a file in the directory of the partition
+/// with the `PartitionId` path `partition_id`
+pub fn partition_file_location(partition_id: &str, file_name: &str) -> String {
+    let dir = partition_id.trim_start_matches('/').trim_end_matches('/');
+    format!("{}/{}", dir, file_name)
+}
+
 /// Describes the schema, summary statistics for each column in each table and the location of
 /// the partition in storage.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Partition {
     /// The identifier for the partition, the partition key computed from PartitionRules
     pub key: String,
     /// The tables in this partition
     pub tables: Vec<Table>,
+    /// The deletes made after the partition was stored, which are applied to its data
+    /// whenever it's loaded
+    #[serde(default)]
+    pub deletes: Vec<Delete>,
+}
+
+impl Partition {
+    /// Returns the newest timestamp of the data in the partition, if it has any
+    pub fn max_time(&self) -> Option<i64> {
+        self.tables
+            .iter()
+            .filter_map(|table| match table.column(TIME_COLUMN_NAME) {
+                Some(Column::I64(stats)) => Some(stats.max),
+                _ => None,
+            })
+            .max()
+    }
 }
 
 /// Metadata and statistics information for a table.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Table {
     pub name: String,
-    pub columns: Vec<Column>,
+    pub columns: Vec<ColumnSummary>,
+}
+
+impl Table {
+    /// Returns the type and statistics of the column named `name`
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| &c.stats)
+    }
+
+    /// Returns true if the column named `name` is a tag column
+    pub fn is_tag(&self, name: &str) -> bool {
+        matches!(self.column(name), Some(Column::Tag(_)))
+    }
+}
+
+/// The name of a column, with its type and statistics.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ColumnSummary {
+    pub name: String,
+    pub stats: Column,
 }
 
 /// Statistics and type information for a column.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub enum Column {
     I64(Statistics<i64>),
     U64(Statistics<u64>),
     F64(Statistics<f64>),
     Bool(Statistics<bool>),
     String(Statistics<String>),
+    Tag(Statistics<String>),
 }
 
 /// Summary statistics for a column.
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Statistics<T: PartialEq + PartialOrd + Debug + Display + Clone> {
     pub min: T,
     pub max: T,
@@ -118,6 +180,15 @@ mod tests {
         assert_eq!(stat.count, 4);
     }
 
+    #[test]
+    fn file_locations() {
+        let location = partition_file_location("/1/mydb/2020-10-01/", METADATA_FILE_NAME);
+        assert_eq!(location, "1/mydb/2020-10-01/meta.json");
+
+        let location = partition_file_location("1/mydb/2020-10-01", &table_file_name("cpu"));
+        assert_eq!(location, "1/mydb/2020-10-01/cpu.parquet");
+    }
+
     #[test]
     fn update_string() {
         let mut stat = Statistics::new("bbb".to_string());
@@ -0,0 +1,760 @@
+//! This module contains `RowPredicate`, a predicate that is evaluated against the
+//! individual rows of a `ReplicatedWrite`. It is used by subscriptions to only send
+//! the matching rows of a write to a subscriber.
+//!
+//! Predicates are written as comparisons of a column against a literal, combined
+//! with `AND` and `OR` (`AND` binds tighter) and grouped with parentheses:
+//!
+//! ```text
+//! region = 'us-west' AND (usage_user > 90.0 OR "host name" != 'a')
+//! ```
+//!
+//! Column names that aren't made of letters, digits and underscores must be double
+//! quoted. Literals are single quoted strings, integers, floats and `true`/`false`.
+//! A comparison is false for rows that don't have the column or where the column's
+//! type can't be compared to the literal.
+
+use std::{cmp::Ordering, convert::TryFrom, fmt};
+
+use generated_types::wal as wb;
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+
+use crate::data::RowValue;
+
+#[derive(Debug, Snafu, Clone, PartialEq)]
+pub enum Error {
+    #[snafu(display("unexpected end of predicate"))]
+    UnexpectedEnd,
+
+    #[snafu(display("unexpected {} at position {} of predicate", found, position))]
+    Unexpected { found: String, position: usize },
+
+    #[snafu(display("unterminated quote starting at position {} of predicate", position))]
+    UnterminatedQuote { position: usize },
+
+    #[snafu(display("invalid number '{}' in predicate", value))]
+    InvalidNumber { value: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A predicate on the columns of a row. It is serialized as its text form.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub enum RowPredicate {
+    And(Box<RowPredicate>, Box<RowPredicate>),
+    Or(Box<RowPredicate>, Box<RowPredicate>),
+    Compare {
+        column: String,
+        op: CompareOp,
+        value: Literal,
+    },
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CompareOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+#[derive(Debug, Clone)]
+pub enum Literal {
+    String(String),
+    I64(i64),
+    F64(f64),
+    Bool(bool),
+}
+
+impl RowPredicate {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+
+        let predicate = parser.or_expr()?;
+        match parser.next() {
+            None => Ok(predicate),
+            Some((token, position)) => Unexpected {
+                found: token.to_string(),
+                position,
+            }
+            .fail(),
+        }
+    }
+
+    /// Returns true if the row matches the predicate.
+    pub fn matches(&self, row: &wb::Row<'_>) -> bool {
+        self.matches_values(&|column| {
+            row.values()?
+                .iter()
+                .find(|v| v.column() == Some(column))
+                .and_then(|v| row_value(&v))
+        })
+    }
+
+    /// Returns true if the row whose column values are returned by `value`
+    /// matches the predicate. `value` returns `None` for columns the row
+    /// doesn't have.
+    pub fn matches_values<'a>(&self, value: &dyn Fn(&str) -> Option<RowValue<'a>>) -> bool {
+        self.evaluate(value, false)
+    }
+
+    /// Like `matches_values`, except that a comparison is true when the column's
+    /// value can't be compared to the literal, such as a NaN or a value of another
+    /// type. A row this returns false for can't match the same comparisons made in
+    /// SQL, so it can be used to skip rows before filtering them exactly.
+    pub fn might_match_values<'a>(&self, value: &dyn Fn(&str) -> Option<RowValue<'a>>) -> bool {
+        self.evaluate(value, true)
+    }
+
+    fn evaluate<'a>(
+        &self,
+        value: &dyn Fn(&str) -> Option<RowValue<'a>>,
+        uncomparable_matches: bool,
+    ) -> bool {
+        match self {
+            Self::And(left, right) => {
+                left.evaluate(value, uncomparable_matches)
+                    && right.evaluate(value, uncomparable_matches)
+            }
+            Self::Or(left, right) => {
+                left.evaluate(value, uncomparable_matches)
+                    || right.evaluate(value, uncomparable_matches)
+            }
+            Self::Compare {
+                column,
+                op,
+                value: literal,
+            } => match value(column) {
+                Some(v) => literal
+                    .compare_to(v)
+                    .map_or(uncomparable_matches, |ordering| op.matches(ordering)),
+                None => false,
+            },
+        }
+    }
+}
+
+/// Returns the value of a column of a `wb::Row`
+fn row_value<'a>(value: &wb::Value<'a>) -> Option<RowValue<'a>> {
+    Some(match value.value_type() {
+        wb::ColumnValue::TagValue => RowValue::Tag(value.value_as_tag_value()?.value()?),
+        wb::ColumnValue::StringValue => RowValue::String(value.value_as_string_value()?.value()?),
+        wb::ColumnValue::I64Value => RowValue::I64(value.value_as_i64value()?.value()),
+        wb::ColumnValue::U64Value => RowValue::U64(value.value_as_u64value()?.value()),
+        wb::ColumnValue::F64Value => RowValue::F64(value.value_as_f64value()?.value()),
+        wb::ColumnValue::BoolValue => RowValue::Bool(value.value_as_bool_value()?.value()),
+        wb::ColumnValue::NONE => return None,
+    })
+}
+
+impl CompareOp {
+    /// Returns true if a column value comparing `ordering` to the literal matches
+    fn matches(self, ordering: Ordering) -> bool {
+        match self {
+            Self::Eq => ordering == Ordering::Equal,
+            Self::NotEq => ordering != Ordering::Equal,
+            Self::Lt => ordering == Ordering::Less,
+            Self::LtEq => ordering != Ordering::Greater,
+            Self::Gt => ordering == Ordering::Greater,
+            Self::GtEq => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl Literal {
+    /// Compares the column value to this literal, returning `None` if their types
+    /// can't be compared.
+    fn compare_to(&self, value: RowValue<'_>) -> Option<Ordering> {
+        match (self, value) {
+            (Self::String(s), RowValue::Tag(v)) => Some(v.cmp(s.as_str())),
+            (Self::String(s), RowValue::String(v)) => Some(v.cmp(s.as_str())),
+            (Self::I64(i), RowValue::I64(v)) => Some(v.cmp(i)),
+            (Self::I64(i), RowValue::U64(v)) => Some(i128::from(v).cmp(&i128::from(*i))),
+            (Self::I64(i), RowValue::F64(v)) => v.partial_cmp(&(*i as f64)),
+            (Self::F64(f), RowValue::I64(v)) => (v as f64).partial_cmp(f),
+            (Self::F64(f), RowValue::U64(v)) => (v as f64).partial_cmp(f),
+            (Self::F64(f), RowValue::F64(v)) => v.partial_cmp(f),
+            (Self::Bool(b), RowValue::Bool(v)) => Some(v.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::I64(a), Self::I64(b)) => a == b,
+            (Self::F64(a), Self::F64(b)) => a.to_bits() == b.to_bits(),
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Literal {}
+
+impl fmt::Display for RowPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // nested AND/OR are always parenthesized, so the output parses back to
+        // the same predicate
+        fn operand(f: &mut fmt::Formatter<'_>, p: &RowPredicate) -> fmt::Result {
+            match p {
+                RowPredicate::Compare { .. } => write!(f, "{}", p),
+                _ => write!(f, "({})", p),
+            }
+        }
+
+        match self {
+            Self::And(left, right) => {
+                operand(f, left)?;
+                write!(f, " AND ")?;
+                operand(f, right)
+            }
+            Self::Or(left, right) => {
+                operand(f, left)?;
+                write!(f, " OR ")?;
+                operand(f, right)
+            }
+            Self::Compare { column, op, value } => {
+                if is_bare_identifier(column) {
+                    write!(f, "{}", column)?;
+                } else {
+                    write!(f, "\"{}\"", escape(column, '"'))?;
+                }
+                write!(f, " {} {}", op, value)
+            }
+        }
+    }
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Self::Eq => "=",
+            Self::NotEq => "!=",
+            Self::Lt => "<",
+            Self::LtEq => "<=",
+            Self::Gt => ">",
+            Self::GtEq => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "'{}'", escape(s, '\'')),
+            Self::I64(i) => write!(f, "{}", i),
+            // Debug always includes a decimal point or exponent, so it parses back as a float
+            Self::F64(v) => write!(f, "{:?}", v),
+            Self::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+impl TryFrom<String> for RowPredicate {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        Self::parse(&s)
+    }
+}
+
+impl From<RowPredicate> for String {
+    fn from(p: RowPredicate) -> Self {
+        p.to_string()
+    }
+}
+
+fn is_bare_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !is_keyword(s)
+        }
+        _ => false,
+    }
+}
+
+fn is_keyword(s: &str) -> bool {
+    ["and", "or", "true", "false"]
+        .iter()
+        .any(|k| s.eq_ignore_ascii_case(k))
+}
+
+fn escape(s: &str, quote: char) -> String {
+    s.replace('\\', "\\\\")
+        .replace(quote, &format!("\\{}", quote))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    String(String),
+    Number(String),
+    Op(CompareOp),
+    And,
+    Or,
+    True,
+    False,
+    LeftParen,
+    RightParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identifier(s) => write!(f, "identifier {}", s),
+            Self::String(s) => write!(f, "string '{}'", s),
+            Self::Number(n) => write!(f, "number {}", n),
+            Self::Op(op) => write!(f, "operator {}", op),
+            Self::And => write!(f, "AND"),
+            Self::Or => write!(f, "OR"),
+            Self::True => write!(f, "true"),
+            Self::False => write!(f, "false"),
+            Self::LeftParen => write!(f, "'('"),
+            Self::RightParen => write!(f, "')'"),
+        }
+    }
+}
+
+/// Splits the input into tokens, paired with their byte offset in the input
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => {
+                chars.next();
+                Token::LeftParen
+            }
+            ')' => {
+                chars.next();
+                Token::RightParen
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let followed_by_eq = chars.peek().map(|&(_, c)| c == '=').unwrap_or(false);
+                if followed_by_eq {
+                    chars.next();
+                }
+
+                let op = match (c, followed_by_eq) {
+                    // "==" is accepted as well as "="
+                    ('=', _) => CompareOp::Eq,
+                    ('!', true) => CompareOp::NotEq,
+                    ('<', false) => CompareOp::Lt,
+                    ('<', true) => CompareOp::LtEq,
+                    ('>', false) => CompareOp::Gt,
+                    ('>', true) => CompareOp::GtEq,
+                    _ => {
+                        return Unexpected {
+                            found: format!("'{}'", c),
+                            position: start,
+                        }
+                        .fail()
+                    }
+                };
+                Token::Op(op)
+            }
+            '\'' | '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => value.push(escaped),
+                            None => return UnterminatedQuote { position: start }.fail(),
+                        },
+                        Some((_, q)) if q == c => break,
+                        Some((_, other)) => value.push(other),
+                        None => return UnterminatedQuote { position: start }.fail(),
+                    }
+                }
+
+                if c == '\'' {
+                    Token::String(value)
+                } else {
+                    Token::Identifier(value)
+                }
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let mut number = String::new();
+                number.push(c);
+                chars.next();
+                while let Some(&(_, c)) = chars.peek() {
+                    let exponent_sign =
+                        (c == '-' || c == '+') && number.ends_with(|e| e == 'e' || e == 'E');
+                    if c.is_ascii_alphanumeric() || c == '.' || exponent_sign {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Number(number)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' {
+                        word.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                match word.to_ascii_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "true" => Token::True,
+                    "false" => Token::False,
+                    _ => Token::Identifier(word),
+                }
+            }
+            c => {
+                return Unexpected {
+                    found: format!("'{}'", c),
+                    position: start,
+                }
+                .fail()
+            }
+        };
+
+        tokens.push((token, start));
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn or_expr(&mut self) -> Result<RowPredicate> {
+        let mut predicate = self.and_expr()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            let right = self.and_expr()?;
+            predicate = RowPredicate::Or(Box::new(predicate), Box::new(right));
+        }
+        Ok(predicate)
+    }
+
+    fn and_expr(&mut self) -> Result<RowPredicate> {
+        let mut predicate = self.term()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            let right = self.term()?;
+            predicate = RowPredicate::And(Box::new(predicate), Box::new(right));
+        }
+        Ok(predicate)
+    }
+
+    fn term(&mut self) -> Result<RowPredicate> {
+        match self.next() {
+            Some((Token::LeftParen, _)) => {
+                let predicate = self.or_expr()?;
+                match self.next() {
+                    Some((Token::RightParen, _)) => Ok(predicate),
+                    other => self.unexpected(other),
+                }
+            }
+            Some((Token::Identifier(column), _)) => {
+                let op = match self.next() {
+                    Some((Token::Op(op), _)) => op,
+                    other => return self.unexpected(other),
+                };
+                let value = self.literal()?;
+
+                Ok(RowPredicate::Compare { column, op, value })
+            }
+            other => self.unexpected(other),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Literal> {
+        match self.next() {
+            Some((Token::String(s), _)) => Ok(Literal::String(s)),
+            Some((Token::True, _)) => Ok(Literal::Bool(true)),
+            Some((Token::False, _)) => Ok(Literal::Bool(false)),
+            Some((Token::Number(n), _)) => {
+                if let Ok(i) = n.parse() {
+                    Ok(Literal::I64(i))
+                } else if n.contains(|c| c == '.' || c == 'e' || c == 'E') {
+                    n.parse()
+                        .map(Literal::F64)
+                        .map_err(|_| Error::InvalidNumber { value: n })
+                } else {
+                    InvalidNumber { value: n }.fail()
+                }
+            }
+            other => self.unexpected(other),
+        }
+    }
+
+    fn unexpected<T>(&self, found: Option<(Token, usize)>) -> Result<T> {
+        match found {
+            Some((token, position)) => Unexpected {
+                found: token.to_string(),
+                position,
+            }
+            .fail(),
+            None => UnexpectedEnd.fail(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flatbuffers::FlatBufferBuilder;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[test]
+    fn parse_and_display() -> Result {
+        let cases = vec![
+            ("region = 'us-west'", "region = 'us-west'"),
+            (
+                "a=1 AND b>=2.5 or c != true",
+                "(a = 1 AND b >= 2.5) OR c != true",
+            ),
+            (
+                "a < -3 and (b <= 1e3 OR \"host name\" > 'it\\'s')",
+                "a < -3 AND (b <= 1000.0 OR \"host name\" > 'it\\'s')",
+            ),
+            ("(x = 1)", "x = 1"),
+        ];
+
+        for (input, expected) in cases {
+            let predicate = RowPredicate::parse(input)?;
+            assert_eq!(predicate.to_string(), expected, "input: {}", input);
+            assert_eq!(RowPredicate::parse(expected)?, predicate);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() -> Result {
+        let predicate = RowPredicate::parse("a = 1 OR b = 2 AND c = 3")?;
+        assert!(matches!(predicate, RowPredicate::Or(..)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = vec![
+            ("", Error::UnexpectedEnd),
+            ("region =", Error::UnexpectedEnd),
+            (
+                "region 'a'",
+                Error::Unexpected {
+                    found: "string 'a'".to_string(),
+                    position: 7,
+                },
+            ),
+            (
+                "a = 1 b = 2",
+                Error::Unexpected {
+                    found: "identifier b".to_string(),
+                    position: 6,
+                },
+            ),
+            ("a = 'foo", Error::UnterminatedQuote { position: 4 }),
+            (
+                "a = 12x",
+                Error::InvalidNumber {
+                    value: "12x".to_string(),
+                },
+            ),
+            (
+                "a ! 1",
+                Error::Unexpected {
+                    found: "'!'".to_string(),
+                    position: 2,
+                },
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                RowPredicate::parse(input).unwrap_err(),
+                expected,
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn serde_roundtrip() -> Result {
+        let predicate = RowPredicate::parse("region = 'us-west' and usage > 1.5")?;
+
+        let json = serde_json::to_string(&predicate)?;
+        assert_eq!(json, r#""region = 'us-west' AND usage > 1.5""#);
+
+        let parsed: RowPredicate = serde_json::from_str(&json)?;
+        assert_eq!(parsed, predicate);
+
+        let err = serde_json::from_str::<RowPredicate>(r#""region =""#).unwrap_err();
+        assert!(err.to_string().contains("unexpected end of predicate"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn matches_rows() -> Result {
+        let row = row_bytes();
+        let row = flatbuffers::get_root::<wb::Row<'_>>(&row);
+
+        let cases = vec![
+            ("region = 'us-west'", true),
+            ("region != 'us-west'", false),
+            ("region > 'us-east'", true),
+            ("host = 'a'", false),
+            ("missing = 'a'", false),
+            ("missing != 'a'", false),
+            ("usage > 90", true),
+            ("usage > 90.6", false),
+            ("count = 3", true),
+            ("count >= 3.5", false),
+            ("big > 9223372036854775807 OR big = 9", true),
+            ("big > -1", true),
+            ("active = true", true),
+            ("active = 1", false),
+            ("region = 'us-west' AND (usage < 10 OR active = true)", true),
+            ("region = 'us-west' AND usage < 10", false),
+            ("msg = 'hello'", true),
+        ];
+
+        for (input, expected) in cases {
+            let predicate = RowPredicate::parse(input)?;
+            assert_eq!(predicate.matches(&row), expected, "predicate: {}", input);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn might_match_uncomparable_values() -> Result {
+        let value = |column: &str| match column {
+            "usage" => Some(RowValue::F64(f64::NAN)),
+            "active" => Some(RowValue::Bool(true)),
+            _ => None,
+        };
+
+        let cases = vec![
+            ("usage != 1.5", false, true),
+            ("usage = 1.5", false, true),
+            ("active = 1", false, true),
+            ("active = 1 AND active = false", false, false),
+            ("missing != 'a'", false, false),
+        ];
+
+        for (input, matches, might_match) in cases {
+            let predicate = RowPredicate::parse(input)?;
+            assert_eq!(
+                predicate.matches_values(&value),
+                matches,
+                "predicate: {}",
+                input
+            );
+            assert_eq!(
+                predicate.might_match_values(&value),
+                might_match,
+                "predicate: {}",
+                input
+            );
+        }
+
+        Ok(())
+    }
+
+    fn row_bytes() -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let region = fbb.create_string("us-west");
+        let region = wb::TagValue::create(
+            &mut fbb,
+            &wb::TagValueArgs {
+                value: Some(region),
+            },
+        );
+        let msg = fbb.create_string("hello");
+        let msg = wb::StringValue::create(&mut fbb, &wb::StringValueArgs { value: Some(msg) });
+        let usage = wb::F64Value::create(&mut fbb, &wb::F64ValueArgs { value: 90.5 });
+        let count = wb::I64Value::create(&mut fbb, &wb::I64ValueArgs { value: 3 });
+        let big = wb::U64Value::create(&mut fbb, &wb::U64ValueArgs { value: 9 });
+        let active = wb::BoolValue::create(&mut fbb, &wb::BoolValueArgs { value: true });
+
+        let values = vec![
+            ("region", wb::ColumnValue::TagValue, region.as_union_value()),
+            ("msg", wb::ColumnValue::StringValue, msg.as_union_value()),
+            ("usage", wb::ColumnValue::F64Value, usage.as_union_value()),
+            ("count", wb::ColumnValue::I64Value, count.as_union_value()),
+            ("big", wb::ColumnValue::U64Value, big.as_union_value()),
+            (
+                "active",
+                wb::ColumnValue::BoolValue,
+                active.as_union_value(),
+            ),
+        ];
+
+        let values = values
+            .into_iter()
+            .map(|(column, value_type, value)| {
+                let column = fbb.create_string(column);
+                wb::Value::create(
+                    &mut fbb,
+                    &wb::ValueArgs {
+                        column: Some(column),
+                        value_type,
+                        value: Some(value),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let values = fbb.create_vector(&values);
+        let row = wb::Row::create(
+            &mut fbb,
+            &wb::RowArgs {
+                values: Some(values),
+            },
+        );
+        fbb.finish(row, None);
+
+        fbb.finished_data().to_vec()
+    }
+}
@@ -49,6 +49,12 @@ pub enum Error {
         value: String,
     },
 
+    #[snafu(display(r#"Unable to parse unsigned integer value '{}'"#, value))]
+    UIntegerValueInvalid {
+        source: std::num::ParseIntError,
+        value: String,
+    },
+
     #[snafu(display(r#"Unable to parse floating-point value '{}'"#, value))]
     FloatValueInvalid {
         source: std::num::ParseFloatError,
@@ -336,6 +342,7 @@ pub type TagSet<'a> = SmallVec<[(EscapedStr<'a>, EscapedStr<'a>); 8]>;
 #[derive(Debug, Clone, PartialEq)]
 pub enum FieldValue<'a> {
     I64(i64),
+    U64(u64),
     F64(f64),
     String(EscapedStr<'a>),
     Boolean(bool),
@@ -348,6 +355,7 @@ impl<'a> Display for FieldValue<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::I64(v) => write!(f, "{}i", v),
+            Self::U64(v) => write!(f, "{}u", v),
             Self::F64(v) => write!(f, "{}", v),
             Self::String(v) => escape_and_write_value(f, v, FIELD_VALUE_STRING_DELIMITERS),
             Self::Boolean(v) => write!(f, "{}", v),
@@ -644,11 +652,12 @@ fn field_key(i: &str) -> IResult<&str, EscapedStr<'_>> {
 
 fn field_value(i: &str) -> IResult<&str, FieldValue<'_>> {
     let int = map(field_integer_value, FieldValue::I64);
+    let uint = map(field_uinteger_value, FieldValue::U64);
     let float = map(field_float_value, FieldValue::F64);
     let string = map(field_string_value, FieldValue::String);
     let boolv = map(field_bool_value, FieldValue::Boolean);
 
-    alt((int, float, string, boolv))(i)
+    alt((int, uint, float, string, boolv))(i)
 }
 
 fn field_integer_value(i: &str) -> IResult<&str, i64> {
@@ -658,6 +667,15 @@ fn field_integer_value(i: &str) -> IResult<&str, i64> {
     })(i)
 }
 
+fn field_uinteger_value(i: &str) -> IResult<&str, u64> {
+    // Accept a leading minus sign here so that negative values are
+    // reported as invalid rather than falling through to other types
+    let tagged_value = terminated(integral_value_common, tag("u"));
+    map_fail(tagged_value, |value| {
+        value.parse().context(UIntegerValueInvalid { value })
+    })(i)
+}
+
 fn field_float_value(i: &str) -> IResult<&str, f64> {
     let value = alt((field_float_value_with_decimal, field_float_value_no_decimal));
     map_fail(value, |value| {
@@ -1034,6 +1052,13 @@ mod test {
             }
         }
 
+        fn unwrap_u64(&self) -> u64 {
+            match self {
+                Self::U64(v) => *v,
+                _ => panic!("field was not an u64"),
+            }
+        }
+
         fn unwrap_f64(&self) -> f64 {
             match self {
                 Self::F64(v) => *v,
@@ -1199,6 +1224,19 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn parse_single_field_unsigned_integer() -> Result {
+        let input = "foo asdf=23u 1234";
+        let vals = parse(input)?;
+
+        assert_eq!(vals[0].series.measurement, "foo");
+        assert_eq!(vals[0].timestamp, Some(1234));
+        assert_eq!(vals[0].field_set[0].0, "asdf");
+        assert_eq!(vals[0].field_set[0].1.unwrap_u64(), 23);
+
+        Ok(())
+    }
+
     #[test]
     fn parse_single_field_float_no_decimal() -> Result {
         let input = "foo asdf=44 546";
@@ -1425,6 +1463,34 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn parse_out_of_range_uinteger() -> Result {
+        let input = "m0 field=99999999999999999999999999999999u 99";
+        let parsed = parse(input);
+
+        assert!(
+            matches!(parsed, Err(super::Error::UIntegerValueInvalid { .. })),
+            "Wrong error: {:?}",
+            parsed,
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_negative_uinteger() -> Result {
+        let input = "m0 field=-1u 99";
+        let parsed = parse(input);
+
+        assert!(
+            matches!(parsed, Err(super::Error::UIntegerValueInvalid { .. })),
+            "Wrong error: {:?}",
+            parsed,
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn parse_out_of_range_float() -> Result {
         let input = format!("m0 field={val}.{val} 99", val = "9".repeat(200));
@@ -1911,6 +1977,7 @@ her"#,
     #[test]
     fn field_value_display() -> Result {
         assert_eq!(FieldValue::I64(42).to_string(), "42i");
+        assert_eq!(FieldValue::U64(42).to_string(), "42u");
         assert_eq!(FieldValue::F64(42.11).to_string(), "42.11");
         assert_eq!(
             FieldValue::String(EscapedStr::from("foo")).to_string(),
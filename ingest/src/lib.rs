@@ -23,6 +23,7 @@ use packers::{
 use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use std::{
     collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
     io::{Read, Seek},
 };
 use tracing::debug;
@@ -89,6 +90,13 @@ pub enum Error {
     // TODO clean this error up
     #[snafu(display(r#"could not find column"#))]
     CouldNotFindColumn,
+
+    #[snafu(display(
+        r#"Unsigned value {} of field {} is too large to store as an integer"#,
+        value,
+        field
+    ))]
+    UnsignedOutOfRange { field: String, value: u64 },
 }
 
 /// Handles buffering `ParsedLine` objects and deducing a schema from that sample
@@ -306,6 +314,10 @@ impl<'a> MeasurementSampler<'a> {
                 let field_type = match field_value {
                     FieldValue::F64(_) => DataType::Float,
                     FieldValue::I64(_) => DataType::Integer,
+                    // There is no unsigned type in the schema (yet), so
+                    // unsigned values are stored as integers, and values
+                    // above i64::MAX are rejected when they are packed
+                    FieldValue::U64(_) => DataType::Integer,
                     FieldValue::String(_) => DataType::String,
                     FieldValue::Boolean(_) => DataType::Boolean,
                 };
@@ -352,7 +364,7 @@ impl<'a> MeasurementWriter<'a> {
     /// table writer in a single chunk
     fn flush_buffer(&mut self) -> Result<(), Error> {
         debug!("Flushing buffer {} rows", self.write_buffer.len());
-        let packers = pack_lines(&self.schema, &self.write_buffer);
+        let packers = pack_lines(&self.schema, &self.write_buffer)?;
         self.table_writer.write_batch(&packers).context(Writing)?;
         self.write_buffer.clear();
         Ok(())
@@ -400,6 +412,9 @@ impl<'a> PackersForRow<'a> {
 /// Internal implementation: packs the `ParsedLine` structures for a
 /// single measurement into a format suitable for writing
 ///
+/// Returns an error if an unsigned field value is too large to be
+/// stored as an integer.
+///
 /// # Panics
 ///
 /// The caller is responsible for ensuring that all `ParsedLines` come
@@ -409,7 +424,7 @@ impl<'a> PackersForRow<'a> {
 ///
 /// TODO: improve performance by reusing the the Vec<Packer> rather
 /// than always making new ones
-fn pack_lines<'a>(schema: &Schema, lines: &[ParsedLine<'a>]) -> Vec<Packers> {
+fn pack_lines<'a>(schema: &Schema, lines: &[ParsedLine<'a>]) -> Result<Vec<Packers>, Error> {
     let col_defs = schema.get_col_defs();
     let mut packers: Vec<_> = col_defs
         .iter()
@@ -472,6 +487,13 @@ fn pack_lines<'a>(schema: &Schema, lines: &[ParsedLine<'a>]) -> Vec<Packers> {
                     FieldValue::I64(i) => {
                         packer.i64_packer_mut().push(i);
                     }
+                    FieldValue::U64(u) => {
+                        let i = i64::try_from(u).ok().context(UnsignedOutOfRange {
+                            field: field_name.as_str(),
+                            value: u,
+                        })?;
+                        packer.i64_packer_mut().push(i);
+                    }
                     FieldValue::String(ref s) => {
                         packer.str_packer_mut().push(ByteArray::from(s.as_str()));
                     }
@@ -507,7 +529,7 @@ fn pack_lines<'a>(schema: &Schema, lines: &[ParsedLine<'a>]) -> Vec<Packers> {
             packer_for_row.finish_row();
         }
     }
-    packers
+    Ok(packers)
 }
 
 // use arrow::array;
@@ -1568,12 +1590,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn pack_unsigned_values() -> Result<(), Error> {
+        let mut sampler = MeasurementSampler::new(get_sampler_settings());
+        for line in only_good_lines("cpu u=9223372036854775807u 10\ncpu u=1u 20") {
+            sampler.add_sample(line);
+        }
+        let schema = sampler.deduce_schema_from_sample()?;
+
+        let packers = pack_lines(&schema, &sampler.schema_sample)?;
+        let unsigned_packer = packers[0].i64_packer();
+        assert_eq!(unsigned_packer.get(0).unwrap(), &i64::MAX);
+        assert_eq!(unsigned_packer.get(1).unwrap(), &1);
+
+        // values that don't fit in an integer are rejected, not wrapped
+        let lines = only_good_lines("cpu u=18446744073709551615u 30");
+        let err = pack_lines(&schema, &lines).unwrap_err();
+        assert!(
+            matches!(err, Error::UnsignedOutOfRange { value: u64::MAX, .. }),
+            "Wrong error: {:?}",
+            err
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn pack_data_value() -> Result<(), Error> {
         let mut sampler = parse_data_into_sampler()?;
         let schema = sampler.deduce_schema_from_sample()?;
 
-        let packers = pack_lines(&schema, &sampler.schema_sample);
+        let packers = pack_lines(&schema, &sampler.schema_sample)?;
 
         // 6 columns so 6 packers
         assert_eq!(packers.len(), 6);
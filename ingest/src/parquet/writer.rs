@@ -76,6 +76,19 @@ impl FromStr for CompressionLevel {
 
 /// A `IOxParquetTableWriter` is used for writing batches of rows
 /// parquet files.
+/// How the timestamp column, which holds nanoseconds since the epoch in
+/// IOx, is stored in the parquet file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampPrecision {
+    /// As `TIMESTAMP_MICROS`, which other tools read as timestamps. The
+    /// packed values must be microseconds.
+    Microsecond,
+
+    /// As plain `INT64` nanoseconds, which keeps every timestamp exactly but
+    /// which other tools read as integers. See `convert_to_parquet_schema`.
+    Nanosecond,
+}
+
 pub struct IOxParquetTableWriter<W>
 where
     W: ParquetWriter,
@@ -138,9 +151,25 @@ where
         schema: &data_types::table_schema::Schema,
         compression_level: CompressionLevel,
         writer: W,
+    ) -> Result<Self, Error> {
+        Self::new_with_timestamp_precision(
+            schema,
+            compression_level,
+            TimestampPrecision::Microsecond,
+            writer,
+        )
+    }
+
+    /// Create a new TableWriter like `new`, storing timestamps with
+    /// `timestamp_precision`
+    pub fn new_with_timestamp_precision(
+        schema: &data_types::table_schema::Schema,
+        compression_level: CompressionLevel,
+        timestamp_precision: TimestampPrecision,
+        writer: W,
     ) -> Result<Self, Error> {
         let writer_props = create_writer_props(&schema, compression_level);
-        let parquet_schema = convert_to_parquet_schema(&schema)?;
+        let parquet_schema = convert_to_parquet_schema(&schema, timestamp_precision)?;
 
         let file_writer = SerializedFileWriter::new(writer, parquet_schema.clone(), writer_props)
             .context(ParquetLibraryError {
@@ -282,6 +311,7 @@ where
 // Converts from line protocol `Schema` to the equivalent parquet schema `Type`.
 fn convert_to_parquet_schema(
     schema: &data_types::table_schema::Schema,
+    timestamp_precision: TimestampPrecision,
 ) -> Result<Rc<parquet::schema::types::Type>, Error> {
     let mut parquet_columns = Vec::new();
 
@@ -297,6 +327,11 @@ fn convert_to_parquet_schema(
             data_types::table_schema::DataType::String => {
                 (PhysicalType::BYTE_ARRAY, Some(LogicalType::UTF8))
             }
+            data_types::table_schema::DataType::Timestamp
+                if timestamp_precision == TimestampPrecision::Nanosecond =>
+            {
+                (PhysicalType::INT64, None)
+            }
             data_types::table_schema::DataType::Timestamp => {
                 // At the time of writing, the underlying rust parquet
                 // library doesn't support nanosecond timestamp
@@ -493,7 +528,8 @@ mod tests {
             .field("bool_field", data_types::table_schema::DataType::Boolean)
             .build();
 
-        let parquet_schema = convert_to_parquet_schema(&schema).expect("conversion successful");
+        let parquet_schema = convert_to_parquet_schema(&schema, TimestampPrecision::Microsecond)
+            .expect("conversion successful");
         let parquet_schema_string = normalize_spaces(&parquet_schema_as_string(&parquet_schema));
         let expected_schema_string = normalize_spaces(
             r#"message measurement_name {
@@ -507,6 +543,15 @@ mod tests {
         );
 
         assert_eq!(parquet_schema_string, expected_schema_string);
+
+        let parquet_schema = convert_to_parquet_schema(&schema, TimestampPrecision::Nanosecond)
+            .expect("conversion successful");
+        let parquet_schema_string = normalize_spaces(&parquet_schema_as_string(&parquet_schema));
+        assert!(
+            parquet_schema_string.contains("OPTIONAL INT64 time;"),
+            "{}",
+            parquet_schema_string
+        );
     }
 
     fn make_test_schema() -> data_types::table_schema::Schema {
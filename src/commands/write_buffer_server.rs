@@ -6,6 +6,7 @@ use std::env::VarError;
 use std::fs;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::server::http_routes;
 use crate::server::rpc::storage;
@@ -13,7 +14,14 @@ use crate::server::rpc::storage;
 use ::storage::exec::Executor as StorageExecutor;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
-use write_buffer::{Db, WriteBufferDatabases};
+use object_store::{File, ObjectStore};
+use write_buffer::{start_lifecycle_task, start_retention_task, Db, WriteBufferDatabases};
+
+/// How often the lifecycle rules of the databases are applied
+const LIFECYCLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often partitions outside the retention periods of the databases are dropped
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60);
 
 pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     dotenv::dotenv().ok();
@@ -31,15 +39,100 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     debug!("InfluxDB IOx Server using database directory: {:?}", db_dir);
 
-    let storage = Arc::new(WriteBufferDatabases::new(&db_dir));
+    let mut storage = WriteBufferDatabases::new(&db_dir);
+
+    // writes are rejected while the write buffers of all databases take up more than this
+    match std::env::var("INFLUXDB_IOX_WRITE_BUFFER_MEMORY_LIMIT") {
+        Ok(limit) => {
+            let limit = limit.parse().expect(
+                "INFLUXDB_IOX_WRITE_BUFFER_MEMORY_LIMIT environment variable not a valid number of bytes",
+            );
+            storage = storage.with_memory_limit(limit);
+        }
+        Err(VarError::NotPresent) => (),
+        Err(VarError::NotUnicode(_)) => {
+            panic!("INFLUXDB_IOX_WRITE_BUFFER_MEMORY_LIMIT environment variable not a valid unicode string")
+        }
+    }
+
+    // read-only partitions are loaded from object storage in this directory, if set
+    let object_store = match std::env::var("INFLUXDB_IOX_OBJECT_STORE_DIR") {
+        Ok(dir) => Some(Arc::new(ObjectStore::new_file(File::new(dir)))),
+        Err(VarError::NotPresent) => None,
+        Err(VarError::NotUnicode(_)) => {
+            panic!("INFLUXDB_IOX_OBJECT_STORE_DIR environment variable not a valid unicode string")
+        }
+    };
+
+    // partitions are persisted to the object store as this writer
+    let snapshots = match (&object_store, std::env::var("INFLUXDB_IOX_ID")) {
+        (Some(object_store), Ok(id)) => {
+            let id = id
+                .parse()
+                .expect("INFLUXDB_IOX_ID environment variable not a valid writer id");
+            Some((Arc::clone(object_store), id))
+        }
+        (_, Err(VarError::NotUnicode(_))) => {
+            panic!("INFLUXDB_IOX_ID environment variable not a valid unicode string")
+        }
+        _ => None,
+    };
+
+    // deletes are recorded in the partitions persisted to the object store
+    if let Some(object_store) = &object_store {
+        let writer = snapshots.as_ref().map(|(_, id)| *id);
+        storage = storage.with_object_store(Arc::clone(object_store), writer);
+    }
+
+    let storage = Arc::new(storage);
     let dirs = storage.wal_dirs()?;
 
-    // TODO: make recovery of multiple databases multi-threaded
+    // the databases are recovered concurrently, each replaying its WAL on a blocking
+    // thread, while the servers start up. Until it's recovered a database can't be used
+    // and the server isn't ready.
+    let recovery_start = Instant::now();
+    let mut restores = Vec::with_capacity(dirs.len());
     for dir in dirs {
-        let db = Db::restore_from_wal(dir).await?;
-        storage.add_db(db).await;
+        let wal_entries = storage.start_recovery(&dir).await?;
+        let storage = Arc::clone(&storage);
+        let object_store = object_store.clone();
+        restores.push(tokio::spawn(async move {
+            let db = Db::restore_from_wal_with_progress(dir, wal_entries).await?;
+            if let Some(object_store) = &object_store {
+                let loaded = db.load_read_only_partitions(object_store).await?;
+                debug!("Loaded {} read-only partitions for {}", loaded, db.name);
+            }
+            storage.add_db(db).await;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        }));
     }
 
+    let recovery = async move {
+        let restored = restores.len();
+        for result in futures::future::join_all(restores).await {
+            result.expect("database recovery task panicked")?;
+        }
+        info!(
+            "Recovered {} databases in {:?}",
+            restored,
+            recovery_start.elapsed()
+        );
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+    };
+
+    match &snapshots {
+        Some((object_store, id)) => {
+            start_lifecycle_task(
+                Arc::clone(&storage),
+                Arc::clone(object_store),
+                *id,
+                LIFECYCLE_INTERVAL,
+            );
+        }
+        None => info!("Partition lifecycle rules are not applied without an object store and id"),
+    }
+    start_retention_task(Arc::clone(&storage), snapshots, RETENTION_INTERVAL);
+
     // Fire up the query executor
     let executor = Arc::new(StorageExecutor::default());
 
@@ -84,11 +177,16 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let server = Server::bind(&bind_addr).serve(make_svc);
     info!("Listening on http://{}", bind_addr);
 
-    // Wait for both the servers to complete
-    let (grpc_server, server) = futures::future::join(grpc_server, server).await;
+    // Wait for both the servers to complete, stopping if a database can't be recovered
+    let servers = async {
+        let (grpc_server, server) = futures::future::join(grpc_server, server).await;
+
+        grpc_server?;
+        server?;
 
-    grpc_server?;
-    server?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+    };
+    futures::future::try_join(recovery, servers).await?;
 
     Ok(())
 }
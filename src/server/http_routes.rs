@@ -18,8 +18,9 @@ use http::header::CONTENT_ENCODING;
 use tracing::{debug, error, info};
 
 use arrow_deps::arrow;
+use data_types::delete::Delete;
 use influxdb_line_protocol::parse_lines;
-use storage::{org_and_bucket_to_database, Database, DatabaseStore};
+use storage::{org_and_bucket_to_database, Database, DatabaseError, DatabaseStore};
 
 use bytes::{Bytes, BytesMut};
 use futures::{self, StreamExt};
@@ -67,9 +68,31 @@ pub enum ApplicationError {
     },
 
     // Application level errors
+    #[snafu(display(
+        "Write buffer full for org {}, bucket {}, retry later:  {}",
+        org,
+        bucket_name,
+        source
+    ))]
+    WriteBufferFull {
+        org: String,
+        bucket_name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
     #[snafu(display("Bucket {} not found in org {}", bucket, org))]
     BucketNotFound { org: String, bucket: String },
 
+    #[snafu(display(
+        "Database {} is still being recovered: {} WAL entries replayed",
+        db_name,
+        wal_entries
+    ))]
+    DatabaseRecovering { db_name: String, wal_entries: usize },
+
+    #[snafu(display("Recovering databases: {}", databases))]
+    NotReady { databases: String },
+
     #[snafu(display("Body exceeds limit of {} bytes", max_body_size))]
     RequestSizeExceeded { max_body_size: usize },
 
@@ -121,6 +144,27 @@ pub enum ApplicationError {
 
     #[snafu(display("Internal error creating gzip decoder: {:?}", source))]
     CreatingGzipDecoder { source: std::io::Error },
+
+    #[snafu(display(
+        "Internal error deleting points from org {}, bucket {}:  {}",
+        org,
+        bucket_name,
+        source
+    ))]
+    DeletingPoints {
+        org: String,
+        bucket_name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Invalid delete time '{}': {}", time, source))]
+    InvalidDeleteTime {
+        time: String,
+        source: chrono::ParseError,
+    },
+
+    #[snafu(display("Invalid delete: {}", source))]
+    InvalidDelete { source: data_types::delete::Error },
 }
 
 impl ApplicationError {
@@ -130,7 +174,10 @@ impl ApplicationError {
             Self::WritingPoints { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Query { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Self::QueryError { .. } => StatusCode::BAD_REQUEST,
+            Self::WriteBufferFull { .. } => StatusCode::TOO_MANY_REQUESTS,
             Self::BucketNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::DatabaseRecovering { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::NotReady { .. } => StatusCode::SERVICE_UNAVAILABLE,
             Self::RequestSizeExceeded { .. } => StatusCode::BAD_REQUEST,
             Self::ExpectedQueryString { .. } => StatusCode::BAD_REQUEST,
             Self::InvalidQueryString { .. } => StatusCode::BAD_REQUEST,
@@ -143,6 +190,9 @@ impl ApplicationError {
             Self::ReadingBodyAsGzip { .. } => StatusCode::BAD_REQUEST,
             Self::RouteNotFound { .. } => StatusCode::NOT_FOUND,
             Self::CreatingGzipDecoder { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::DeletingPoints { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidDeleteTime { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidDelete { .. } => StatusCode::BAD_REQUEST,
         }
     }
 }
@@ -208,6 +258,21 @@ async fn parse_body(req: hyper::Request<Body>) -> Result<Bytes, ApplicationError
     }
 }
 
+/// Returns an error if the database `db_name` is still being recovered
+async fn ensure_recovered<T: DatabaseStore>(
+    storage: &T,
+    db_name: &str,
+) -> Result<(), ApplicationError> {
+    match storage.recovery_progress(db_name).await {
+        Some(progress) => DatabaseRecovering {
+            db_name,
+            wal_entries: progress.wal_entries,
+        }
+        .fail(),
+        None => Ok(()),
+    }
+}
+
 #[tracing::instrument(level = "debug")]
 async fn write<T: DatabaseStore>(
     req: hyper::Request<Body>,
@@ -220,6 +285,7 @@ async fn write<T: DatabaseStore>(
     })?;
 
     let db_name = org_and_bucket_to_database(&write_info.org, &write_info.bucket);
+    ensure_recovered(&*storage, &db_name).await?;
 
     let db = storage
         .db_or_create(&db_name)
@@ -246,13 +312,23 @@ async fn write<T: DatabaseStore>(
         write_info.bucket
     );
 
-    db.write_lines(&lines)
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(WritingPoints {
-            org: write_info.org.clone(),
-            bucket_name: write_info.bucket.clone(),
-        })?;
+    db.write_lines(&lines).await.map_err(|e| {
+        let org = write_info.org.clone();
+        let bucket_name = write_info.bucket.clone();
+        if e.is_resource_exhausted() {
+            ApplicationError::WriteBufferFull {
+                org,
+                bucket_name,
+                source: Box::new(e),
+            }
+        } else {
+            ApplicationError::WritingPoints {
+                org,
+                bucket_name,
+                source: Box::new(e),
+            }
+        }
+    })?;
 
     Ok(None)
 }
@@ -280,6 +356,7 @@ async fn read<T: DatabaseStore>(
     })?;
 
     let db_name = org_and_bucket_to_database(&read_info.org, &read_info.bucket);
+    ensure_recovered(&*storage, &db_name).await?;
 
     let db = storage.db(&db_name).await.context(BucketNotFound {
         org: read_info.org.clone(),
@@ -296,6 +373,77 @@ async fn read<T: DatabaseStore>(
     Ok(Some(results.into_bytes().into()))
 }
 
+#[derive(Deserialize, Debug)]
+/// Query string of the request to the /delete endpoint
+struct DeleteInfo {
+    org: String,
+    bucket: String,
+}
+
+#[derive(Deserialize, Debug)]
+/// Body of the request to the /delete endpoint
+struct DeleteRequest {
+    /// RFC3339 timestamp of the oldest points to delete
+    start: String,
+    /// RFC3339 timestamp of the newest points to delete
+    stop: String,
+    /// Which points in the time range to delete, e.g.
+    /// `_measurement="cpu" AND host="a"`
+    #[serde(default)]
+    predicate: Option<String>,
+}
+
+fn parse_delete_time(time: &str) -> Result<i64, ApplicationError> {
+    let time = chrono::DateTime::parse_from_rfc3339(time).context(InvalidDeleteTime { time })?;
+    Ok(time.timestamp_nanos())
+}
+
+#[tracing::instrument(level = "debug")]
+async fn delete<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    storage: Arc<T>,
+) -> Result<Option<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let delete_info: DeleteInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: String::from(query),
+        })?;
+
+    let db_name = org_and_bucket_to_database(&delete_info.org, &delete_info.bucket);
+    ensure_recovered(&*storage, &db_name).await?;
+
+    let db = storage.db(&db_name).await.context(BucketNotFound {
+        org: delete_info.org.clone(),
+        bucket: delete_info.bucket.clone(),
+    })?;
+
+    let body = parse_body(req).await?;
+    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+
+    let request: DeleteRequest =
+        serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+    let delete = Delete::from_influx(
+        parse_delete_time(&request.start)?,
+        parse_delete_time(&request.stop)?,
+        request.predicate.as_deref().unwrap_or(""),
+    )
+    .context(InvalidDelete)?;
+
+    debug!("Deleting {:?} from database {}", delete, db_name);
+
+    db.delete(&delete)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(DeletingPoints {
+            org: delete_info.org.clone(),
+            bucket_name: delete_info.bucket.clone(),
+        })?;
+
+    Ok(None)
+}
+
 // Route to test that the server is alive
 #[tracing::instrument(level = "debug")]
 async fn ping(req: hyper::Request<Body>) -> Result<Option<Body>, ApplicationError> {
@@ -303,6 +451,33 @@ async fn ping(req: hyper::Request<Body>) -> Result<Option<Body>, ApplicationErro
     Ok(Some(response_body.into()))
 }
 
+// Route to check that the server is up, even while it is recovering databases
+#[tracing::instrument(level = "debug")]
+async fn health(req: hyper::Request<Body>) -> Result<Option<Body>, ApplicationError> {
+    let response_body = r#"{"status":"pass"}"#;
+    Ok(Some(response_body.into()))
+}
+
+// Route to check that the server has recovered all its databases and can
+// serve requests for them
+#[tracing::instrument(level = "debug")]
+async fn ready<T: DatabaseStore>(storage: Arc<T>) -> Result<Option<Body>, ApplicationError> {
+    let recovering = storage.recovering().await;
+    if !recovering.is_empty() {
+        let databases = recovering
+            .iter()
+            .map(|(name, progress)| {
+                format!("{} ({} WAL entries replayed)", name, progress.wal_entries)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        return NotReady { databases }.fail();
+    }
+
+    let response_body = r#"{"status":"ready"}"#;
+    Ok(Some(response_body.into()))
+}
+
 fn no_op(name: &str) -> Result<Option<Body>, ApplicationError> {
     info!("NOOP: {}", name);
     Ok(None)
@@ -317,8 +492,11 @@ pub async fn service<T: DatabaseStore>(
 
     let response = match (req.method(), req.uri().path()) {
         (&Method::POST, "/api/v2/write") => write(req, storage).await,
+        (&Method::POST, "/api/v2/delete") => delete(req, storage).await,
         (&Method::POST, "/api/v2/buckets") => no_op("create bucket"),
         (&Method::GET, "/ping") => ping(req).await,
+        (&Method::GET, "/health") => health(req).await,
+        (&Method::GET, "/ready") => ready(storage).await,
         (&Method::GET, "/api/v2/read") => read(req, storage).await,
         _ => Err(ApplicationError::RouteNotFound {
             method: method.clone(),
@@ -358,7 +536,7 @@ mod tests {
     use hyper::service::{make_service_fn, service_fn};
     use hyper::Server;
 
-    use storage::{test::TestDatabaseStore, DatabaseStore};
+    use storage::{test::TestDatabaseStore, DatabaseStore, RecoveryProgress};
 
     type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
     type Result<T, E = Error> = std::result::Result<T, E>;
@@ -410,6 +588,111 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_delete() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+        test_storage.db_or_create("MyOrg_MyBucket").await?;
+
+        let client = Client::new();
+        let url = format!("{}/api/v2/delete?bucket=MyBucket&org=MyOrg", server_url);
+
+        let response = client
+            .post(&url)
+            .body(
+                r#"{"start": "1970-01-01T00:00:00Z", "stop": "1970-01-01T00:00:01Z",
+                    "predicate": "_measurement=\"cpu\" AND host=\"a\""}"#,
+            )
+            .send()
+            .await;
+        check_response("delete", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client
+            .post(&url)
+            .body(r#"{"start": "1970-01-01T00:00:01Z", "stop": "1970-01-01T00:00:00Z"}"#)
+            .send()
+            .await;
+        check_response(
+            "delete",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Invalid delete: delete start 1000000000 is after its stop 0"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!(
+                "{}/api/v2/delete?bucket=Missing&org=MyOrg",
+                server_url
+            ))
+            .body(r#"{"start": "1970-01-01T00:00:00Z", "stop": "1970-01-01T00:00:01Z"}"#)
+            .send()
+            .await;
+        check_response(
+            "delete",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Bucket Missing not found in org MyOrg"}"#,
+        )
+        .await;
+
+        let test_db = test_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(
+            test_db.get_deletes().await,
+            vec![Delete::from_influx(
+                0,
+                1_000_000_000,
+                r#"_measurement="cpu" AND host="a""#
+            )?]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_health_and_ready() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+        test_storage
+            .set_recovering("MyOrg_MyBucket", Some(RecoveryProgress { wal_entries: 3 }))
+            .await;
+
+        let client = Client::new();
+        let response = client.get(&format!("{}/health", server_url)).send().await;
+        check_response("health", response, StatusCode::OK, r#"{"status":"pass"}"#).await;
+
+        let response = client.get(&format!("{}/ready", server_url)).send().await;
+        check_response(
+            "ready",
+            response,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"error":"Recovering databases: MyOrg_MyBucket (3 WAL entries replayed)"}"#,
+        )
+        .await;
+
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+        let response = client.post(&write_url).body("cpu bar=1 10").send().await;
+        check_response(
+            "write",
+            response,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"error":"Database MyOrg_MyBucket is still being recovered: 3 WAL entries replayed"}"#,
+        )
+        .await;
+        assert!(test_storage.db("MyOrg_MyBucket").await.is_none());
+
+        test_storage.set_recovering("MyOrg_MyBucket", None).await;
+
+        let response = client.get(&format!("{}/ready", server_url)).send().await;
+        check_response("ready", response, StatusCode::OK, r#"{"status":"ready"}"#).await;
+
+        let response = client.post(&write_url).body("cpu bar=1 10").send().await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        Ok(())
+    }
+
     fn gzip_str(s: &str) -> Vec<u8> {
         use libflate::gzip::Encoder;
         use std::io::Write;
@@ -4,7 +4,7 @@
 use std::{collections::BTreeSet, sync::Arc};
 
 use arrow_deps::arrow::{
-    array::{ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray},
+    array::{ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, UInt64Array},
     datatypes::DataType as ArrowDataType,
 };
 
@@ -17,7 +17,7 @@ use generated_types::{
     measurement_fields_response::{FieldType, MessageField},
     read_response::{
         frame::Data, BooleanPointsFrame, DataType, FloatPointsFrame, Frame, GroupFrame,
-        IntegerPointsFrame, SeriesFrame, StringPointsFrame,
+        IntegerPointsFrame, SeriesFrame, StringPointsFrame, UnsignedPointsFrame,
     },
     MeasurementFieldsResponse, ReadResponse, Tag,
 };
@@ -116,12 +116,17 @@ pub fn grouped_series_set_item_to_read_response(
 }
 
 fn group_description_to_frames(group_description: GroupDescription) -> Result<Vec<Frame>> {
-    // split key=value pairs into two separate vectors
-    let (tag_keys, partition_key_vals): (Vec<Vec<u8>>, Vec<Vec<u8>>) = group_description
+    let tag_keys = group_description
+        .tag_keys
+        .iter()
+        .map(|k| k.bytes().collect())
+        .collect();
+
+    let partition_key_vals = group_description
         .tags
-        .into_iter()
-        .map(|(k, v)| (k.bytes().collect(), v.bytes().collect()))
-        .unzip();
+        .iter()
+        .map(|(_, v)| v.bytes().collect())
+        .collect();
 
     let group_frame = GroupFrame {
         tag_keys,
@@ -138,6 +143,7 @@ fn data_type(array: &ArrayRef) -> Result<DataType> {
         ArrowDataType::Utf8 => Ok(DataType::String),
         ArrowDataType::Float64 => Ok(DataType::Float),
         ArrowDataType::Int64 => Ok(DataType::Integer),
+        ArrowDataType::UInt64 => Ok(DataType::Unsigned),
         ArrowDataType::Boolean => Ok(DataType::Boolean),
         _ => UnsupportedDataType {
             type_name: format!("{:?}", array.data_type()),
@@ -198,6 +204,14 @@ fn field_to_data(frames: &mut Vec<Data>, series_set: &SeriesSet, field_index: us
                 .extract_values(start_row, num_rows);
             Data::IntegerPoints(IntegerPointsFrame { timestamps, values })
         }
+        ArrowDataType::UInt64 => {
+            let values = array
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .unwrap()
+                .extract_values(start_row, num_rows);
+            Data::UnsignedPoints(UnsignedPointsFrame { timestamps, values })
+        }
         ArrowDataType::Boolean => {
             let values = array
                 .as_any()
@@ -268,6 +282,13 @@ impl ExtractValues<i64> for Int64Array {
     }
 }
 
+impl ExtractValues<u64> for UInt64Array {
+    fn extract_values(&self, start_row: usize, num_rows: usize) -> Vec<u64> {
+        let end_row = start_row + num_rows;
+        (start_row..end_row).map(|row| self.value(row)).collect()
+    }
+}
+
 impl ExtractValues<f64> for Float64Array {
     fn extract_values(&self, start_row: usize, num_rows: usize) -> Vec<f64> {
         let end_row = start_row + num_rows;
@@ -367,6 +388,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_series_set_conversion_unsigned() {
+        let schema = Arc::new(Schema::new(vec![
+            ArrowField::new("uint_field", ArrowDataType::UInt64, true),
+            ArrowField::new("time", ArrowDataType::Int64, true),
+        ]));
+
+        let uint_array: ArrayRef = Arc::new(UInt64Array::from(vec![1, 2, u64::MAX]));
+        let timestamp_array: ArrayRef = Arc::new(Int64Array::from(vec![1000, 2000, 3000]));
+
+        let batch = RecordBatch::try_new(schema, vec![uint_array, timestamp_array])
+            .expect("created new record batch");
+
+        let series_set = SeriesSet {
+            table_name: Arc::new("the_table".into()),
+            tags: vec![(Arc::new("tag1".into()), Arc::new("val1".into()))],
+            timestamp_index: 1,
+            field_indices: Arc::new(vec![0]),
+            start_row: 1,
+            num_rows: 2,
+            batch,
+        };
+
+        let response =
+            series_set_to_read_response(series_set).expect("Correctly converted series set");
+
+        let dumped_frames = response
+            .frames
+            .iter()
+            .map(|f| dump_frame(f))
+            .collect::<Vec<_>>();
+
+        let expected_frames = vec![
+            "SeriesFrame, tags: _field=uint_field,_measurement=the_table,tag1=val1, type: 2",
+            "UnsignedPointsFrame, timestamps: [2000, 3000], values: \"2,18446744073709551615\"",
+        ];
+
+        assert_eq!(
+            dumped_frames, expected_frames,
+            "Expected:\n{:#?}\nActual:\n{:#?}",
+            expected_frames, dumped_frames
+        );
+    }
+
     #[test]
     fn test_group_group_conversion() {
         let group_description = GroupDescription {
@@ -374,6 +439,7 @@ mod tests {
                 (Arc::new("tag1".into()), Arc::new("val1".into())),
                 (Arc::new("tag2".into()), Arc::new("val2".into())),
             ],
+            tag_keys: vec![Arc::new("tag1".into()), Arc::new("tag2".into())],
         };
 
         let grouped_series_set_item = GroupedSeriesSetItem::GroupStart(group_description);
@@ -559,6 +625,11 @@ mod tests {
                 timestamps,
                 dump_values(values)
             ),
+            Some(Data::UnsignedPoints(UnsignedPointsFrame { timestamps, values })) => format!(
+                "UnsignedPointsFrame, timestamps: {:?}, values: {:?}",
+                timestamps,
+                dump_values(values)
+            ),
             Some(Data::BooleanPoints(BooleanPointsFrame { timestamps, values })) => format!(
                 "BooleanPointsFrame, timestamps: {:?}, values: {}",
                 timestamps,
@@ -1,5 +1,6 @@
 //! This module has logic to translate gRPC `Predicate` nodes into the
-//! native storage system predicate form,  `storage::Predicates`
+//! native storage system predicate form,  `storage::Predicates`, and
+//! gRPC `Aggregate`s into `storage::aggregate::Aggregate`
 
 use std::convert::TryFrom;
 
@@ -8,11 +9,12 @@ use arrow_deps::datafusion::{
     scalar::ScalarValue,
 };
 use generated_types::{
-    node::Comparison as RPCComparison, node::Logical as RPCLogical, node::Value as RPCValue,
+    aggregate::AggregateType as RPCAggregateType, node::Comparison as RPCComparison,
+    node::Logical as RPCLogical, node::Value as RPCValue, Aggregate as RPCAggregate,
     Node as RPCNode, Predicate as RPCPredicate,
 };
-use snafu::{ResultExt, Snafu};
-use storage::predicate::PredicateBuilder;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use storage::{aggregate::Aggregate, predicate::PredicateBuilder, regex_match::RegexMatch};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -29,21 +31,17 @@ pub enum Error {
     InternalInvalidFieldReference {},
 
     #[snafu(display(
-        "Error creating predicate: Regular expression predicates are not supported: {}",
-        regexp
+        "Error creating predicate: {} comparisons must be between a column and a literal pattern, not {:?}",
+        comparison,
+        inputs
     ))]
-    RegExpLiteralNotSupported { regexp: String },
+    UnsupportedPatternOperands {
+        comparison: &'static str,
+        inputs: Vec<Expr>,
+    },
 
-    #[snafu(display("Error creating predicate: Regular expression predicates are not supported"))]
-    RegExpNotSupported {},
-
-    #[snafu(display(
-        "Error creating predicate: Not Regular expression predicates are not supported"
-    ))]
-    NotRegExpNotSupported {},
-
-    #[snafu(display("Error creating predicate: StartsWith comparisons not supported"))]
-    StartsWithNotSupported {},
+    #[snafu(display("Error creating predicate: {}", source))]
+    CreatingRegexMatch { source: storage::regex_match::Error },
 
     #[snafu(display(
         "Error creating predicate: Unexpected children for predicate: {:?}",
@@ -72,10 +70,56 @@ pub enum Error {
 
     #[snafu(display("Error converting field_name to utf8: {}", source))]
     ConvertingFieldName { source: std::string::FromUtf8Error },
+
+    #[snafu(display("Error creating aggregate: Unknown aggregate type: {}", aggregate_type))]
+    UnknownAggregate { aggregate_type: i32 },
+
+    #[snafu(display(
+        "Error creating aggregate: Expected one window aggregate, got {}",
+        count
+    ))]
+    WindowAggregateCount { count: usize },
+
+    #[snafu(display("Error creating aggregate: Window aggregates can not be NONE"))]
+    NoWindowAggregate {},
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Converts the aggregate of a gRPC request, if any. Both a missing
+/// aggregate and one of type `NONE` mean the points are not aggregated.
+pub fn make_aggregate(aggregate: Option<RPCAggregate>) -> Result<Option<Aggregate>> {
+    let aggregate_type = match aggregate {
+        Some(aggregate) => aggregate.r#type,
+        None => return Ok(None),
+    };
+
+    match RPCAggregateType::from_i32(aggregate_type) {
+        Some(RPCAggregateType::None) => Ok(None),
+        Some(RPCAggregateType::Sum) => Ok(Some(Aggregate::Sum)),
+        Some(RPCAggregateType::Count) => Ok(Some(Aggregate::Count)),
+        Some(RPCAggregateType::Min) => Ok(Some(Aggregate::Min)),
+        Some(RPCAggregateType::Max) => Ok(Some(Aggregate::Max)),
+        Some(RPCAggregateType::First) => Ok(Some(Aggregate::First)),
+        Some(RPCAggregateType::Last) => Ok(Some(Aggregate::Last)),
+        Some(RPCAggregateType::Mean) => Ok(Some(Aggregate::Mean)),
+        None => UnknownAggregate { aggregate_type }.fail(),
+    }
+}
+
+/// Converts the aggregates of a gRPC `ReadWindowAggregate` request, which
+/// must be exactly one aggregate other than `NONE`
+pub fn make_window_aggregate(aggregates: Vec<RPCAggregate>) -> Result<Aggregate> {
+    ensure!(
+        aggregates.len() == 1,
+        WindowAggregateCount {
+            count: aggregates.len()
+        }
+    );
+
+    make_aggregate(aggregates.into_iter().next())?.context(NoWindowAggregate)
+}
+
 /// A trait for adding gRPC specific nodes to the generic predicate builder
 pub trait AddRPCNode
 where
@@ -373,7 +417,7 @@ fn build_node(value: RPCValue, inputs: Vec<Expr>) -> Result<Expr> {
         RPCValue::IntValue(v) => Ok(Expr::Literal(ScalarValue::Int64(Some(v)))),
         RPCValue::UintValue(v) => Ok(Expr::Literal(ScalarValue::UInt64(Some(v)))),
         RPCValue::FloatValue(f) => Ok(Expr::Literal(ScalarValue::Float64(Some(f)))),
-        RPCValue::RegexValue(regexp) => RegExpLiteralNotSupported { regexp }.fail(),
+        RPCValue::RegexValue(regexp) => Ok(Expr::Literal(ScalarValue::Utf8(Some(regexp)))),
         RPCValue::TagRefValue(tag_name) => Ok(Expr::Column(make_tag_name(tag_name)?)),
         RPCValue::FieldRefValue(field_name) => Ok(Expr::Column(field_name)),
         RPCValue::Logical(logical) => build_logical_node(logical, inputs),
@@ -405,11 +449,17 @@ fn build_comparison_node(comparison: i32, inputs: Vec<Expr>) -> Result<Expr> {
     } else if comparison == RPCComparison::NotEqual as i32 {
         build_binary_expr(Operator::NotEq, inputs)
     } else if comparison == RPCComparison::StartsWith as i32 {
-        StartsWithNotSupported {}.fail()
+        build_pattern_expr("StartsWith", inputs, |column, prefix| {
+            Ok(RegexMatch::starts_with(column, prefix))
+        })
     } else if comparison == RPCComparison::Regex as i32 {
-        RegExpNotSupported {}.fail()
+        build_pattern_expr("Regex", inputs, |column, pattern| {
+            RegexMatch::new(column, pattern, false)
+        })
     } else if comparison == RPCComparison::NotRegex as i32 {
-        NotRegExpNotSupported {}.fail()
+        build_pattern_expr("NotRegex", inputs, |column, pattern| {
+            RegexMatch::new(column, pattern, true)
+        })
     } else if comparison == RPCComparison::Lt as i32 {
         build_binary_expr(Operator::Lt, inputs)
     } else if comparison == RPCComparison::Lte as i32 {
@@ -439,6 +489,22 @@ fn build_binary_expr(op: Operator, inputs: Vec<Expr>) -> Result<Expr> {
     }
 }
 
+/// Creates a datafusion expression matching the values of a column
+/// against a pattern (a regular expression or a prefix) with `make`
+fn build_pattern_expr<F>(comparison: &'static str, inputs: Vec<Expr>, make: F) -> Result<Expr>
+where
+    F: FnOnce(&str, &str) -> Result<RegexMatch, storage::regex_match::Error>,
+{
+    match inputs.as_slice() {
+        [Expr::Column(column), Expr::Literal(ScalarValue::Utf8(Some(pattern)))] => {
+            let regex_match =
+                make(column.as_str(), pattern.as_str()).context(CreatingRegexMatch)?;
+            Ok(regex_match.to_datafusion_expr())
+        }
+        _ => UnsupportedPatternOperands { comparison, inputs }.fail(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -729,6 +795,126 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_convert_predicate_regex() {
+        let regex_value = |pattern: &str| RPCValue::RegexValue(pattern.into());
+        let string_value = |value: &str| RPCValue::StringValue(value.into());
+
+        let cases = vec![
+            (RPCComparison::Regex, regex_value("^web"), "^web", false),
+            (RPCComparison::NotRegex, regex_value("^web"), "^web", true),
+            (
+                RPCComparison::StartsWith,
+                string_value("web.1"),
+                r"^web\.1",
+                false,
+            ),
+        ];
+
+        for (comparison, value, expected_pattern, expected_negated) in cases {
+            let rpc_predicate = RPCPredicate {
+                root: Some(make_pattern_node(comparison, value)),
+            };
+
+            let predicate = PredicateBuilder::default()
+                .rpc_predicate(Some(rpc_predicate))
+                .expect("successfully converting predicate")
+                .build();
+
+            assert_eq!(predicate.exprs.len(), 1);
+            let regex_match = RegexMatch::from_datafusion_expr(&predicate.exprs[0])
+                .expect("converted to a regex match");
+            assert_eq!(regex_match.column, "host");
+            assert_eq!(regex_match.regex.as_str(), expected_pattern);
+            assert_eq!(regex_match.negated, expected_negated);
+        }
+
+        let cases = vec![
+            (
+                make_pattern_node(RPCComparison::Regex, regex_value("web(")),
+                "Error creating predicate: Invalid regular expression /web(/",
+            ),
+            (
+                make_pattern_node(RPCComparison::StartsWith, RPCValue::IntValue(5)),
+                "Error creating predicate: StartsWith comparisons must be between a column and a literal pattern",
+            ),
+        ];
+
+        for (node, expected_error) in cases {
+            let rpc_predicate = RPCPredicate { root: Some(node) };
+            let res = PredicateBuilder::default().rpc_predicate(Some(rpc_predicate));
+
+            let actual_error = error_result_to_string(res);
+            assert!(
+                actual_error.contains(expected_error),
+                "expected '{}' not found in '{}'",
+                expected_error,
+                actual_error
+            );
+        }
+    }
+
+    #[test]
+    fn test_convert_aggregate() {
+        let cases = vec![
+            (None, None),
+            (Some(RPCAggregateType::None), None),
+            (Some(RPCAggregateType::Sum), Some(Aggregate::Sum)),
+            (Some(RPCAggregateType::Count), Some(Aggregate::Count)),
+            (Some(RPCAggregateType::Min), Some(Aggregate::Min)),
+            (Some(RPCAggregateType::Max), Some(Aggregate::Max)),
+            (Some(RPCAggregateType::First), Some(Aggregate::First)),
+            (Some(RPCAggregateType::Last), Some(Aggregate::Last)),
+            (Some(RPCAggregateType::Mean), Some(Aggregate::Mean)),
+        ];
+        for (aggregate_type, expected) in cases {
+            let aggregate = aggregate_type.map(|t| RPCAggregate { r#type: t as i32 });
+            assert_eq!(make_aggregate(aggregate).unwrap(), expected);
+        }
+
+        let res = make_aggregate(Some(RPCAggregate { r#type: 100 }));
+        let expected_error = "Unknown aggregate type: 100";
+        let actual_error = error_result_to_string(res);
+        assert!(
+            actual_error.contains(expected_error),
+            "expected '{}' not found in '{}'",
+            expected_error,
+            actual_error
+        );
+    }
+
+    #[test]
+    fn test_convert_window_aggregate() {
+        let mean = RPCAggregate {
+            r#type: RPCAggregateType::Mean as i32,
+        };
+        let none = RPCAggregate {
+            r#type: RPCAggregateType::None as i32,
+        };
+        assert_eq!(
+            make_window_aggregate(vec![mean.clone()]).unwrap(),
+            Aggregate::Mean
+        );
+
+        let cases = vec![
+            (vec![], "Expected one window aggregate, got 0"),
+            (
+                vec![mean.clone(), mean],
+                "Expected one window aggregate, got 2",
+            ),
+            (vec![none], "Window aggregates can not be NONE"),
+        ];
+        for (aggregates, expected_error) in cases {
+            let actual_error = error_result_to_string(make_window_aggregate(aggregates));
+            assert!(
+                actual_error.contains(expected_error),
+                "expected '{}' not found in '{}'",
+                expected_error,
+                actual_error
+            );
+        }
+    }
+
     /// make a _f = 'field_name' type node
     fn make_field_ref_node(field_name: impl Into<String>) -> RPCNode {
         make_tag_ref_node(&[255], field_name)
@@ -781,6 +967,24 @@ mod tests {
         }
     }
 
+    /// make a host <comparison> <value> type node
+    fn make_pattern_node(comparison: RPCComparison, value: RPCValue) -> RPCNode {
+        let tag_ref_node = RPCNode {
+            children: vec![],
+            value: Some(RPCValue::TagRefValue(b"host".to_vec())),
+        };
+
+        let value_node = RPCNode {
+            children: vec![],
+            value: Some(value),
+        };
+
+        RPCNode {
+            children: vec![tag_ref_node, value_node],
+            value: Some(RPCValue::Comparison(comparison as i32)),
+        }
+    }
+
     /// make n1 OR n2
     fn make_or_node(n1: RPCNode, n2: RPCNode) -> RPCNode {
         RPCNode {
@@ -2,8 +2,8 @@ use tonic::Status;
 
 use generated_types::{
     MeasurementFieldsRequest, MeasurementNamesRequest, MeasurementTagKeysRequest,
-    MeasurementTagValuesRequest, ReadFilterRequest, ReadGroupRequest, ReadSource, TagKeysRequest,
-    TagValuesRequest,
+    MeasurementTagValuesRequest, ReadFilterRequest, ReadGroupRequest, ReadSource,
+    ReadWindowAggregateRequest, TagKeysRequest, TagValuesRequest,
 };
 use storage::id::Id;
 
@@ -59,6 +59,12 @@ impl GrpcInputs for ReadGroupRequest {
     }
 }
 
+impl GrpcInputs for ReadWindowAggregateRequest {
+    fn read_source_field(&self) -> Option<&prost_types::Any> {
+        self.read_source.as_ref()
+    }
+}
+
 impl GrpcInputs for TagKeysRequest {
     fn read_source_field(&self) -> Option<&prost_types::Any> {
         self.tags_source.as_ref()
@@ -6,14 +6,17 @@ use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use generated_types::{
     i_ox_server::{IOx, IOxServer},
+    read_group_request::Group as ReadGroup,
     storage_server::{Storage, StorageServer},
     CapabilitiesResponse, CreateBucketRequest, CreateBucketResponse, DeleteBucketRequest,
     DeleteBucketResponse, GetBucketsResponse, MeasurementFieldsRequest, MeasurementFieldsResponse,
     MeasurementNamesRequest, MeasurementTagKeysRequest, MeasurementTagValuesRequest, Organization,
-    Predicate, ReadFilterRequest, ReadGroupRequest, ReadResponse, StringValuesResponse,
-    TagKeysRequest, TagValuesRequest, TestErrorRequest, TestErrorResponse, TimestampRange,
+    Predicate, ReadFilterRequest, ReadGroupRequest, ReadResponse, ReadWindowAggregateRequest,
+    StringValuesResponse, TagKeysRequest, TagValuesRequest, TestErrorRequest, TestErrorResponse,
+    TimestampRange,
 };
 
+use cluster::grpc::{RemoteQueryService, ReplicationService};
 use data_types::error::ErrorLogger;
 
 #[allow(unused_imports)]
@@ -21,10 +24,11 @@ use data_types::error::ErrorLogger;
 // complains of unresolved imports if they are not imported.
 use generated_types::{node, Node};
 
-use crate::server::rpc::expr::{AddRPCNode, SpecialTagKeys};
+use crate::server::rpc::expr::{make_aggregate, make_window_aggregate, AddRPCNode, SpecialTagKeys};
 use crate::server::rpc::input::GrpcInputs;
 
 use storage::{
+    aggregate::{Aggregate, Window},
     exec::{
         seriesset::{Error as SeriesSetError, GroupedSeriesSetItem, SeriesSet},
         Executor as StorageExecutor,
@@ -53,6 +57,13 @@ pub enum Error {
     #[snafu(display("Database not found: {}", db_name))]
     DatabaseNotFound { db_name: String },
 
+    #[snafu(display(
+        "Database {} is still being recovered: {} WAL entries replayed",
+        db_name,
+        wal_entries
+    ))]
+    DatabaseRecovering { db_name: String, wal_entries: usize },
+
     #[snafu(display("Error listing tables in database '{}': {}", db_name, source))]
     ListingTables {
         db_name: String,
@@ -83,6 +94,16 @@ pub enum Error {
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
+    #[snafu(display(
+        "Error creating window aggregate plans for database '{}': {}",
+        db_name,
+        source
+    ))]
+    PlanningWindowAggregate {
+        db_name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
     #[snafu(display("Error running series plans for database '{}': {}", db_name, source))]
     FilteringSeries {
         db_name: String,
@@ -113,6 +134,17 @@ pub enum Error {
         source: crate::server::rpc::expr::Error,
     },
 
+    #[snafu(display("Converting Aggregate:  {}", source))]
+    ConvertingAggregate {
+        source: crate::server::rpc::expr::Error,
+    },
+
+    #[snafu(display("Unknown group type: {}", group))]
+    UnknownGroup { group: i32 },
+
+    #[snafu(display("Converting Window:  {}", source))]
+    ConvertingWindow { source: storage::aggregate::Error },
+
     #[snafu(display("Computing series: {}", source))]
     ComputingSeriesSet { source: SeriesSetError },
 
@@ -146,6 +178,7 @@ impl Error {
         match &self {
             Self::ServerError { .. } => Status::internal(self.to_string()),
             Self::DatabaseNotFound { .. } => Status::not_found(self.to_string()),
+            Self::DatabaseRecovering { .. } => Status::unavailable(self.to_string()),
             Self::ListingTables { .. } => Status::internal(self.to_string()),
             Self::ListingColumns { .. } => {
                 // TODO: distinguish between input errors and internal errors
@@ -157,10 +190,14 @@ impl Error {
             }
             Self::PlanningFilteringSeries { .. } => Status::invalid_argument(self.to_string()),
             Self::PlanningGroupSeries { .. } => Status::invalid_argument(self.to_string()),
+            Self::PlanningWindowAggregate { .. } => Status::invalid_argument(self.to_string()),
             Self::FilteringSeries { .. } => Status::invalid_argument(self.to_string()),
             Self::GroupingSeries { .. } => Status::invalid_argument(self.to_string()),
             Self::ListingTagValues { .. } => Status::invalid_argument(self.to_string()),
             Self::ConvertingPredicate { .. } => Status::invalid_argument(self.to_string()),
+            Self::ConvertingAggregate { .. } => Status::invalid_argument(self.to_string()),
+            Self::UnknownGroup { .. } => Status::invalid_argument(self.to_string()),
+            Self::ConvertingWindow { .. } => Status::invalid_argument(self.to_string()),
             Self::ComputingSeriesSet { .. } => Status::invalid_argument(self.to_string()),
             Self::ComputingGroupedSeriesSet { .. } => Status::invalid_argument(self.to_string()),
             Self::ConvertingSeriesSet { .. } => Status::invalid_argument(self.to_string()),
@@ -281,18 +318,23 @@ where
             range,
             predicate,
             group_keys,
-            // TODO: handle Group::None
-            group: _group,
-            // TODO: handle aggregate values, especially whether None is the same as
-            // Some(AggregateType::None) or not
-            aggregate: _aggregate,
+            group,
+            aggregate,
         } = read_group_request;
 
         info!(
-            "read_group for database {}, range: {:?}, group_keys: {:?}",
-            db_name, range, group_keys
+            "read_group for database {}, range: {:?}, group_keys: {:?}, group: {:?}, aggregate: {:?}",
+            db_name, range, group_keys, group, aggregate
         );
 
+        let group = ReadGroup::from_i32(group)
+            .context(UnknownGroup { group })
+            .map_err(|e| e.to_status())?;
+
+        let aggregate = make_aggregate(aggregate)
+            .context(ConvertingAggregate)
+            .map_err(|e| e.to_status())?;
+
         read_group_impl(
             tx.clone(),
             self.db_store.clone(),
@@ -301,6 +343,58 @@ where
             range,
             predicate,
             group_keys,
+            group,
+            aggregate,
+        )
+        .await
+        .map_err(|e| e.to_status())?;
+
+        Ok(tonic::Response::new(rx))
+    }
+
+    type ReadWindowAggregateStream = mpsc::Receiver<Result<ReadResponse, Status>>;
+
+    async fn read_window_aggregate(
+        &self,
+        req: tonic::Request<ReadWindowAggregateRequest>,
+    ) -> Result<tonic::Response<Self::ReadWindowAggregateStream>, Status> {
+        let (tx, rx) = mpsc::channel(4);
+
+        let read_window_aggregate_request = req.into_inner();
+
+        let db_name = get_database_name(&read_window_aggregate_request)?;
+
+        let ReadWindowAggregateRequest {
+            read_source: _read_source,
+            range,
+            predicate,
+            window_every,
+            offset,
+            aggregate,
+        } = read_window_aggregate_request;
+
+        info!(
+            "read_window_aggregate for database {}, range: {:?}, window_every: {}, offset: {}, aggregate: {:?}",
+            db_name, range, window_every, offset, aggregate
+        );
+
+        let aggregate = make_window_aggregate(aggregate)
+            .context(ConvertingAggregate)
+            .map_err(|e| e.to_status())?;
+
+        let window = Window::new(window_every, offset)
+            .context(ConvertingWindow)
+            .map_err(|e| e.to_status())?;
+
+        read_window_aggregate_impl(
+            tx.clone(),
+            self.db_store.clone(),
+            self.executor.clone(),
+            db_name,
+            range,
+            predicate,
+            window,
+            aggregate,
         )
         .await
         .map_err(|e| e.to_status())?;
@@ -372,16 +466,18 @@ where
         // Special case a request for 'tag_key=_measurement" means to list all measurements
         let response = if tag_key.is_measurement() {
             info!(
-                "tag_values with tag_key=[x00] for database {}, range: {:?} --> returning measurement_names",
-                db_name, range
+                "tag_values with tag_key=[x00] for database {}, range: {:?}, predicate: {:?} --> returning measurement_names",
+                db_name, range, predicate
             );
 
-            if predicate.is_some() {
-                unimplemented!("tag_value for a measurement, with general predicate");
-            }
-
-            measurement_name_impl(self.db_store.clone(), self.executor.clone(), db_name, range)
-                .await
+            measurement_name_impl(
+                self.db_store.clone(),
+                self.executor.clone(),
+                db_name,
+                range,
+                predicate,
+            )
+            .await
         } else {
             info!(
                 "tag_values for database {}, range: {:?}, tag_key: {}",
@@ -417,9 +513,9 @@ where
         // idpe/storage/read/capabilities.go (aka window aggregate /
         // pushdown)
         //
-        // For now, do not claim to support any capabilities
+        // For now, only claim to support pushing down window aggregates
         let caps = CapabilitiesResponse {
-            caps: HashMap::new(),
+            caps: capabilities(),
         };
         Ok(tonic::Response::new(caps))
     }
@@ -446,10 +542,15 @@ where
             db_name, range
         );
 
-        let response =
-            measurement_name_impl(self.db_store.clone(), self.executor.clone(), db_name, range)
-                .await
-                .map_err(|e| e.to_status());
+        let response = measurement_name_impl(
+            self.db_store.clone(),
+            self.executor.clone(),
+            db_name,
+            range,
+            None,
+        )
+        .await
+        .map_err(|e| e.to_status());
 
         tx.send(response)
             .await
@@ -607,6 +708,17 @@ impl SetRange for PredicateBuilder {
     }
 }
 
+/// Returns the capabilities of the storage gRPC service: the aggregates
+/// `ReadWindowAggregate` computes
+fn capabilities() -> HashMap<String, String> {
+    let mut caps = HashMap::new();
+    caps.insert(
+        "WindowAggregate".to_string(),
+        "Count,Sum,Min,Max,Mean,First,Last".to_string(),
+    );
+    caps
+}
+
 fn get_database_name(input: &impl GrpcInputs) -> Result<String, Status> {
     Ok(org_and_bucket_to_database(
         input.org_id()?,
@@ -619,23 +731,50 @@ fn get_database_name(input: &impl GrpcInputs) -> Result<String, Status> {
 // can use ?, etc). The trait implemententations then handle mapping
 // to the appropriate tonic Status
 
+/// Returns the database `db_name`, unless it doesn't exist or is still being
+/// recovered
+async fn get_db<T>(db_store: &T, db_name: &str) -> Result<Arc<T::Database>>
+where
+    T: DatabaseStore,
+{
+    if let Some(progress) = db_store.recovery_progress(db_name).await {
+        return DatabaseRecovering {
+            db_name,
+            wal_entries: progress.wal_entries,
+        }
+        .fail();
+    }
+
+    db_store
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { db_name })
+}
+
 /// Gathers all measurement names that have data in the specified
-/// (optional) range
+/// (optional) range and that match the (optional) predicate
 async fn measurement_name_impl<T>(
     db_store: Arc<T>,
     executor: Arc<StorageExecutor>,
     db_name: String,
     range: Option<TimestampRange>,
+    rpc_predicate: Option<Predicate>,
 ) -> Result<StringValuesResponse>
 where
     T: DatabaseStore,
 {
-    let predicate = PredicateBuilder::default().set_range(range).build();
+    let rpc_predicate_string = format!("{:?}", rpc_predicate);
 
-    let plan = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &db_name })?
+    let predicate = PredicateBuilder::default()
+        .set_range(range)
+        .rpc_predicate(rpc_predicate)
+        .context(ConvertingPredicate {
+            rpc_predicate_string,
+        })?
+        .build();
+
+    let plan = get_db(&*db_store, &db_name)
+        .await?
         .table_names(predicate)
         .await
         .map_err(|e| Error::ListingTables {
@@ -683,10 +822,7 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &db_name })?;
+    let db = get_db(&*db_store, &db_name).await?;
 
     let tag_key_plan = db
         .tag_column_names(predicate)
@@ -735,10 +871,7 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &db_name })?;
+    let db = get_db(&*db_store, &db_name).await?;
 
     let tag_value_plan =
         db.column_values(&tag_name, predicate)
@@ -790,10 +923,7 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &db_name })?;
+    let db = get_db(&*db_store, &db_name).await?;
 
     let series_plan =
         db.query_series(predicate)
@@ -850,7 +980,68 @@ async fn convert_series_set(
     Ok(())
 }
 
+/// Launch async tasks that send the result of executing read_window_aggregate to `tx`
+#[allow(clippy::too_many_arguments)]
+async fn read_window_aggregate_impl<T>(
+    tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    db_store: Arc<T>,
+    executor: Arc<StorageExecutor>,
+    db_name: String,
+    range: Option<TimestampRange>,
+    rpc_predicate: Option<Predicate>,
+    window: Window,
+    aggregate: Aggregate,
+) -> Result<()>
+where
+    T: DatabaseStore,
+{
+    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+
+    let predicate = PredicateBuilder::default()
+        .set_range(range)
+        .rpc_predicate(rpc_predicate)
+        .context(ConvertingPredicate {
+            rpc_predicate_string,
+        })?
+        .build();
+
+    let db = get_db(&*db_store, &db_name).await?;
+
+    let series_plan = db
+        .query_window_aggregate(predicate, window, aggregate)
+        .await
+        .map_err(|e| Error::PlanningWindowAggregate {
+            db_name: db_name.clone(),
+            source: Box::new(e),
+        })?;
+
+    // Spawn task to convert between series sets and the gRPC results
+    // and to run the actual plans (so we can return a result to the
+    // client before we start sending result)
+    let (tx_series, rx_series) = mpsc::channel(4);
+    tokio::spawn(async move {
+        convert_series_set(rx_series, tx)
+            .await
+            .log_if_error("Converting series set")
+    });
+
+    // fire up the plans and start the pipeline flowing
+    tokio::spawn(async move {
+        executor
+            .to_series_set(series_plan, tx_series)
+            .await
+            .map_err(|e| Error::FilteringSeries {
+                db_name: db_name.clone(),
+                source: Box::new(e),
+            })
+            .log_if_error("Running window aggregate series set plan")
+    });
+
+    Ok(())
+}
+
 /// Launch async tasks that send the result of executing read_group to `tx`
+#[allow(clippy::too_many_arguments)]
 async fn read_group_impl<T>(
     tx: mpsc::Sender<Result<ReadResponse, Status>>,
     db_store: Arc<T>,
@@ -859,6 +1050,8 @@ async fn read_group_impl<T>(
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
     group_keys: Vec<String>,
+    group: ReadGroup,
+    aggregate: Option<Aggregate>,
 ) -> Result<()>
 where
     T: DatabaseStore,
@@ -873,17 +1066,22 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &db_name })?;
+    let db = get_db(&*db_store, &db_name).await?;
 
-    let grouped_series_set_plan = db.query_groups(predicate, group_keys).await.map_err(|e| {
-        Error::PlanningFilteringSeries {
+    // With GROUP_NONE all series are in a single group, so the group
+    // keys only matter for GROUP_BY
+    let group_keys = match group {
+        ReadGroup::None => vec![],
+        ReadGroup::By => group_keys,
+    };
+
+    let grouped_series_set_plan = db
+        .query_groups(predicate, group_keys, aggregate)
+        .await
+        .map_err(|e| Error::PlanningFilteringSeries {
             db_name: db_name.clone(),
             source: Box::new(e),
-        }
-    })?;
+        })?;
 
     // Spawn task to convert between series sets and the gRPC results
     // and to run the actual plans (so we can return a result to the
@@ -897,9 +1095,20 @@ where
 
     // fire up the plans and start the pipeline flowing
     tokio::spawn(async move {
-        executor
-            .to_grouped_series_set(grouped_series_set_plan, tx_series)
-            .await
+        let result = match group {
+            ReadGroup::None => {
+                executor
+                    .to_single_group_series_set(grouped_series_set_plan, tx_series)
+                    .await
+            }
+            ReadGroup::By => {
+                executor
+                    .to_grouped_series_set(grouped_series_set_plan, tx_series)
+                    .await
+            }
+        };
+
+        result
             .map_err(|e| Error::GroupingSeries {
                 db_name: db_name.clone(),
                 source: Box::new(e),
@@ -956,10 +1165,7 @@ where
         })?
         .build();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name: &db_name })?;
+    let db = get_db(&*db_store, &db_name).await?;
 
     let fieldlist_plan = db
         .field_columns(predicate)
@@ -1003,6 +1209,8 @@ where
             storage.clone(),
             executor.clone(),
         )))
+        .add_service(ReplicationService::new(storage.clone()).into_server())
+        .add_service(RemoteQueryService::new(storage.clone()).into_server())
         .serve(bind_addr)
         .await
         .context(ServerError {})
@@ -1028,8 +1236,10 @@ mod tests {
         test::ColumnNamesRequest,
         test::FieldColumnsRequest,
         test::QueryGroupsRequest,
+        test::QueryWindowAggregateRequest,
         test::TestDatabaseStore,
         test::{ColumnValuesRequest, QuerySeriesRequest},
+        RecoveryProgress,
     };
     use test_helpers::tracing::TracingCapture;
     use tonic::Code;
@@ -1078,7 +1288,12 @@ mod tests {
             .expect("Connecting to test server");
 
         // Test response from storage server
-        assert_eq!(HashMap::new(), fixture.storage_client.capabilities().await?);
+        let mut expected_caps = HashMap::new();
+        expected_caps.insert(
+            "WindowAggregate".to_string(),
+            "Count,Sum,Min,Max,Mean,First,Last".to_string(),
+        );
+        assert_eq!(expected_caps, fixture.storage_client.capabilities().await?);
 
         Ok(())
     }
@@ -1133,6 +1348,59 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_storage_rpc_database_recovering() -> Result<(), tonic::Status> {
+        // Note we use a unique port. TODO: let the OS pick the port
+        let mut fixture = Fixture::new(11814)
+            .await
+            .expect("Connecting to test server");
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let partition_id = 1;
+
+        fixture
+            .test_storage
+            .add_lp_string(&db_info.db_name, "h2o,state=CA temp=50.4 100")
+            .await;
+        fixture
+            .test_storage
+            .set_recovering(&db_info.db_name, Some(RecoveryProgress { wal_entries: 7 }))
+            .await;
+
+        let source = Some(StorageClientWrapper::read_source(
+            db_info.org_id,
+            db_info.bucket_id,
+            partition_id,
+        ));
+        let request = MeasurementNamesRequest {
+            source,
+            range: None,
+        };
+
+        let status = fixture
+            .storage_client
+            .measurement_names(request.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(
+            status.message(),
+            format!(
+                "Database {} is still being recovered: 7 WAL entries replayed",
+                db_info.db_name
+            )
+        );
+
+        fixture
+            .test_storage
+            .set_recovering(&db_info.db_name, None)
+            .await;
+        let actual_measurements = fixture.storage_client.measurement_names(request).await?;
+        assert_eq!(actual_measurements, to_string_vec(&["h2o"]));
+
+        Ok(())
+    }
+
     /// test the plumbing of the RPC layer for tag_keys -- specifically that
     /// the right parameters are passed into the Database interface
     /// and that the returned values are sent back via gRPC.
@@ -1385,6 +1653,23 @@ mod tests {
             "unexpected tag values while getting tag values for measurement names"
         );
 
+        // ---
+        // test tag_key = _measurement, with a predicate
+        // ---
+        let request = TagValuesRequest {
+            tags_source: source.clone(),
+            range: make_timestamp_range(1000, 2500),
+            predicate: make_measurement_predicate("o2"),
+            tag_key: "\x00".into(),
+        };
+
+        let tag_values = vec!["o2"];
+        let actual_tag_values = fixture.storage_client.tag_values(request).await?;
+        assert_eq!(
+            actual_tag_values, tag_values,
+            "unexpected tag values while getting tag values for measurement names with a predicate"
+        );
+
         // ---
         // test error
         // ---
@@ -1569,7 +1854,7 @@ mod tests {
         }
 
         // Ensure there are still threads to answer actual client queries
-        assert_eq!(HashMap::new(), fixture.storage_client.capabilities().await?);
+        assert_eq!(capabilities(), fixture.storage_client.capabilities().await?);
 
         Ok(())
     }
@@ -1653,6 +1938,103 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_read_window_aggregate() -> Result<(), tonic::Status> {
+        // Note we use a unique port. TODO: let the OS pick the port
+        let mut fixture = Fixture::new(11904)
+            .await
+            .expect("Connecting to test server");
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let partition_id = 1;
+
+        let test_db = fixture
+            .test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let source = Some(StorageClientWrapper::read_source(
+            db_info.org_id,
+            db_info.bucket_id,
+            partition_id,
+        ));
+
+        let mean = generated_types::Aggregate {
+            r#type: generated_types::aggregate::AggregateType::Mean as i32,
+        };
+
+        let request = ReadWindowAggregateRequest {
+            read_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+            window_every: 1122,
+            offset: 15,
+            aggregate: vec![mean.clone()],
+        };
+
+        let expected_request = QueryWindowAggregateRequest {
+            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into(),
+            window: Window::new(1122, 15).unwrap(),
+            aggregate: Aggregate::Mean,
+        };
+
+        let dummy_series_set_plan = SeriesSetPlans::from(vec![]);
+        test_db
+            .set_query_window_aggregate_values(dummy_series_set_plan)
+            .await;
+
+        let actual_frames = fixture
+            .storage_client
+            .read_window_aggregate(request)
+            .await?;
+        let expected_frames: Vec<String> = vec!["0 frames".into()];
+
+        assert_eq!(
+            actual_frames, expected_frames,
+            "unexpected frames returned by query_window_aggregate",
+        );
+        assert_eq!(
+            test_db.get_query_window_aggregate_request().await,
+            Some(expected_request),
+            "unexpected request to query_window_aggregate",
+        );
+
+        // ---
+        // test invalid windows and aggregates
+        // ---
+        let cases = vec![
+            (
+                0,
+                vec![mean.clone()],
+                "Windows must last a positive duration, not 0ns",
+            ),
+            (10, vec![], "Expected one window aggregate, got 0"),
+        ];
+        for (window_every, aggregate, expected_error) in cases {
+            let request = ReadWindowAggregateRequest {
+                read_source: source.clone(),
+                range: None,
+                predicate: None,
+                window_every,
+                offset: 0,
+                aggregate,
+            };
+
+            let response = fixture.storage_client.read_window_aggregate(request).await;
+            let response_string = format!("{:?}", response);
+            assert!(
+                response_string.contains(expected_error),
+                "'{}' did not contain expected content '{}'",
+                response_string,
+                expected_error
+            );
+        }
+        assert_eq!(test_db.get_query_window_aggregate_request().await, None);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_read_group() -> Result<(), tonic::Status> {
         // Note we use a unique port. TODO: let the OS pick the port
@@ -1675,7 +2057,7 @@ mod tests {
             partition_id,
         ));
 
-        let group = generated_types::read_group_request::Group::None as i32;
+        let group = generated_types::read_group_request::Group::By as i32;
 
         let request = ReadGroupRequest {
             read_source: source.clone(),
@@ -1689,6 +2071,7 @@ mod tests {
         let expected_request = QueryGroupsRequest {
             predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into(),
             group_columns: vec![String::from("tag1")],
+            aggregate: None,
         };
 
         // TODO setup any expected results
@@ -1708,6 +2091,97 @@ mod tests {
             "unexpected request to query_groups"
         );
 
+        // ---
+        // test aggregate
+        // ---
+        let request = ReadGroupRequest {
+            read_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: None,
+            group_keys: vec![String::from("tag1")],
+            group,
+            aggregate: Some(generated_types::Aggregate {
+                r#type: generated_types::aggregate::AggregateType::Sum as i32,
+            }),
+        };
+
+        let expected_request = Some(QueryGroupsRequest {
+            predicate: "Predicate { range: TimestampRange { start: 150, end: 200 }}".into(),
+            group_columns: vec![String::from("tag1")],
+            aggregate: Some(Aggregate::Sum),
+        });
+
+        test_db
+            .set_query_groups_values(GroupedSeriesSetPlans::from(vec![]))
+            .await;
+        fixture.storage_client.read_group(request).await?;
+        assert_eq!(test_db.get_query_groups_request().await, expected_request);
+
+        // ---
+        // test GROUP_NONE, which ignores the group keys
+        // ---
+        let request = ReadGroupRequest {
+            read_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: None,
+            group_keys: vec![String::from("tag1")],
+            group: generated_types::read_group_request::Group::None as i32,
+            aggregate: None,
+        };
+
+        let expected_request = Some(QueryGroupsRequest {
+            predicate: "Predicate { range: TimestampRange { start: 150, end: 200 }}".into(),
+            group_columns: vec![],
+            aggregate: None,
+        });
+
+        test_db
+            .set_query_groups_values(GroupedSeriesSetPlans::from(vec![]))
+            .await;
+        let actual_frames = fixture.storage_client.read_group(request).await?;
+        assert_eq!(actual_frames, vec!["0 group frames"]);
+        assert_eq!(test_db.get_query_groups_request().await, expected_request);
+
+        // an unknown group is rejected before querying the database
+        let request = ReadGroupRequest {
+            read_source: source.clone(),
+            range: None,
+            predicate: None,
+            group_keys: vec![],
+            group: 42,
+            aggregate: None,
+        };
+
+        let response = fixture.storage_client.read_group(request).await;
+        let response_string = format!("{:?}", response);
+        let expected_error = "Unknown group type: 42";
+        assert!(
+            response_string.contains(expected_error),
+            "'{}' did not contain expected content '{}'",
+            response_string,
+            expected_error
+        );
+
+        // an unknown aggregate is rejected before querying the database
+        let request = ReadGroupRequest {
+            read_source: source.clone(),
+            range: None,
+            predicate: None,
+            group_keys: vec![],
+            group,
+            aggregate: Some(generated_types::Aggregate { r#type: 42 }),
+        };
+
+        let response = fixture.storage_client.read_group(request).await;
+        let response_string = format!("{:?}", response);
+        let expected_error = "Unknown aggregate type: 42";
+        assert!(
+            response_string.contains(expected_error),
+            "'{}' did not contain expected content '{}'",
+            response_string,
+            expected_error
+        );
+
         // ---
         // test error
         // ---
@@ -1735,6 +2209,7 @@ mod tests {
         let expected_request = Some(QueryGroupsRequest {
             predicate: "Predicate {}".into(),
             group_columns: vec![],
+            aggregate: None,
         });
         assert_eq!(test_db.get_query_groups_request().await, expected_request);
 
@@ -1853,6 +2328,27 @@ mod tests {
         Some(Predicate { root: Some(root) })
     }
 
+    /// return a predicate like
+    ///
+    /// _measurement="<measurement>"
+    fn make_measurement_predicate(measurement: &str) -> Option<Predicate> {
+        use node::{Comparison, Value};
+        let root = Node {
+            value: Some(Value::Comparison(Comparison::Equal as i32)),
+            children: vec![
+                Node {
+                    value: Some(Value::TagRefValue(vec![0])),
+                    children: vec![],
+                },
+                Node {
+                    value: Some(Value::StringValue(measurement.to_string())),
+                    children: vec![],
+                },
+            ],
+        };
+        Some(Predicate { root: Some(root) })
+    }
+
     /// Convert to a Vec<String> to facilitate comparison with results of client
     fn to_string_vec(v: &[&str]) -> Vec<String> {
         v.iter().map(|s| s.to_string()).collect()
@@ -2034,6 +2530,31 @@ mod tests {
             Ok(vec![s])
         }
 
+        /// Make a request to Storage::read_window_aggregate and do the
+        /// required async dance to flatten the resulting stream
+        async fn read_window_aggregate(
+            &mut self,
+            request: ReadWindowAggregateRequest,
+        ) -> Result<Vec<String>, tonic::Status> {
+            let responses: Vec<_> = self
+                .inner
+                .read_window_aggregate(request)
+                .await?
+                .into_inner()
+                .try_collect()
+                .await?;
+
+            let data_frames: Vec<frame::Data> = responses
+                .into_iter()
+                .flat_map(|r| r.frames)
+                .flat_map(|f| f.data)
+                .collect();
+
+            let s = format!("{} frames", data_frames.len());
+
+            Ok(vec![s])
+        }
+
         /// Make a request to Storage::query_groups and do the
         /// required async dance to flatten the resulting stream
         async fn read_group(
@@ -0,0 +1,349 @@
+//! This module contains `Aggregate`, which reduces the points of each series
+//! returned by a query, e.g. a storage gRPC `ReadGroup` call, to a single
+//! point per field, and `Window`, which splits series into fixed windows of
+//! time to aggregate separately, as a `ReadWindowAggregate` call does.
+//!
+//! Both are computed by DataFusion, using the expressions built here.
+
+use std::{convert::TryFrom, sync::Arc};
+
+use arrow_deps::{
+    arrow::{
+        array::{ArrayRef, Int64Array, Int64Builder},
+        datatypes::DataType,
+    },
+    datafusion::{
+        error::{DataFusionError, Result as DataFusionResult},
+        logical_plan::{self, Expr},
+        physical_plan::{
+            aggregates::{AccumulatorFunctionImplementation, StateTypeFunction},
+            functions::{ReturnTypeFunction, ScalarFunctionImplementation, Signature},
+            udaf::AggregateUDF,
+            udf::ScalarUDF,
+            Accumulator,
+        },
+        scalar::ScalarValue,
+    },
+};
+use snafu::{ensure, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Windows must last a positive duration, not {}ns", every))]
+    InvalidWindow { every: i64 },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// An aggregate of the values of a field over all the points of a series
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    /// The sum of the values
+    Sum,
+    /// The number of non-null values
+    Count,
+    /// The smallest value
+    Min,
+    /// The largest value
+    Max,
+    /// The average of the values
+    Mean,
+    /// The value with the earliest timestamp
+    First,
+    /// The value with the latest timestamp
+    Last,
+}
+
+impl Aggregate {
+    /// Returns the DataFusion expression aggregating the values of the
+    /// field column `field`, of type `data_type`, or `None` if the values of
+    /// that type can't be aggregated this way. Only numbers can be summed,
+    /// averaged or have a minimum or maximum.
+    ///
+    /// `time` is the column of the timestamps of the values.
+    pub fn to_datafusion_expr(self, field: &str, data_type: &DataType, time: &str) -> Option<Expr> {
+        let is_numeric = matches!(
+            data_type,
+            DataType::Float64 | DataType::Int64 | DataType::UInt64
+        );
+        let value = Expr::Column(field.to_string());
+
+        match self {
+            Self::Count => Some(logical_plan::count(value)),
+            Self::First | Self::Last => {
+                let selector = selector_udaf(self, data_type);
+                Some(selector.call(vec![value, Expr::Column(time.to_string())]))
+            }
+            _ if !is_numeric => None,
+            Self::Sum => Some(logical_plan::sum(value)),
+            Self::Min => Some(logical_plan::min(value)),
+            Self::Max => Some(logical_plan::max(value)),
+            Self::Mean => Some(logical_plan::avg(value)),
+        }
+    }
+
+    /// Returns the expression converting the output of
+    /// `to_datafusion_expr` for `field` to the type returned to clients:
+    /// counts are integers rather than unsigned.
+    pub fn to_output_expr(self, field: &str) -> Expr {
+        let value = Expr::Column(field.to_string());
+
+        match self {
+            Self::Count => Expr::Alias(
+                Box::new(Expr::Cast {
+                    expr: Box::new(value),
+                    data_type: DataType::Int64,
+                }),
+                field.to_string(),
+            ),
+            _ => value,
+        }
+    }
+}
+
+/// Consecutive windows of time that each last `every` nanoseconds. The
+/// windows start at `offset` nanoseconds past each multiple of `every`, and
+/// end no later than `stop`, if set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    every: i64,
+    offset: i64,
+    stop: Option<i64>,
+}
+
+impl Window {
+    pub fn new(every: i64, offset: i64) -> Result<Self> {
+        ensure!(every > 0, InvalidWindow { every });
+
+        Ok(Self {
+            every,
+            offset: offset.rem_euclid(every),
+            stop: None,
+        })
+    }
+
+    /// Returns the same windows, ending no later than `stop`, such as the
+    /// (exclusive) end of the range of time a query reads
+    pub fn with_stop(self, stop: i64) -> Self {
+        Self {
+            stop: Some(stop),
+            ..self
+        }
+    }
+
+    pub fn every(&self) -> i64 {
+        self.every
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Returns the (exclusive) end of the window `time` is in, saturating
+    /// at `i64::MAX` and clamped to the stop of the windows
+    pub fn end(&self, time: i64) -> i64 {
+        // windows near the ends of the range of i64 may start or end outside
+        // of it, so this is computed with wider integers
+        let (time, offset, every) = (
+            i128::from(time),
+            i128::from(self.offset),
+            i128::from(self.every),
+        );
+        let start = time - (time - offset).rem_euclid(every);
+        let end = i64::try_from(start + every).unwrap_or(i64::MAX);
+
+        match self.stop {
+            Some(stop) => end.min(stop),
+            None => end,
+        }
+    }
+
+    /// Returns the DataFusion expression computing the end of the
+    /// window each of the timestamps of the column `time` is in
+    pub fn to_end_expr(self, time: &str) -> Expr {
+        let fun: ScalarFunctionImplementation = Arc::new(move |args: &[ArrayRef]| {
+            let times = args[0]
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .ok_or_else(|| {
+                    DataFusionError::Internal("window timestamps must be Int64".to_string())
+                })?;
+
+            let mut ends = Int64Builder::new(times.len());
+            for i in 0..times.len() {
+                if times.is_null(i) {
+                    ends.append_null()?;
+                } else {
+                    ends.append_value(self.end(times.value(i)))?;
+                }
+            }
+            Ok(Arc::new(ends.finish()) as ArrayRef)
+        });
+        let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Int64)));
+
+        let udf = ScalarUDF::new(
+            "window_end",
+            &Signature::Exact(vec![DataType::Int64]),
+            &return_type,
+            &fun,
+        );
+        udf.call(vec![Expr::Column(time.to_string())])
+    }
+}
+
+/// Returns an aggregate function of a value, of type `data_type`, and its
+/// timestamp that selects the value with the earliest timestamp for
+/// `Aggregate::First` or the latest for `Aggregate::Last`
+fn selector_udaf(aggregate: Aggregate, data_type: &DataType) -> AggregateUDF {
+    let name = match aggregate {
+        Aggregate::First => "first",
+        _ => "last",
+    };
+
+    let value_type = data_type.clone();
+    let return_type: ReturnTypeFunction = Arc::new(move |_| Ok(Arc::new(value_type.clone())));
+
+    let value_type = data_type.clone();
+    let accumulator: AccumulatorFunctionImplementation = Arc::new(move || {
+        Ok(Box::new(SelectorAccumulator {
+            first: aggregate == Aggregate::First,
+            value: ScalarValue::try_from(&value_type)?,
+            time: None,
+        }))
+    });
+
+    let value_type = data_type.clone();
+    let state_type: StateTypeFunction =
+        Arc::new(move |_| Ok(Arc::new(vec![value_type.clone(), DataType::Int64])));
+
+    AggregateUDF::new(
+        name,
+        &Signature::Exact(vec![data_type.clone(), DataType::Int64]),
+        &return_type,
+        &accumulator,
+        &state_type,
+    )
+}
+
+/// Keeps the non-null value with the earliest, or latest, timestamp. Its
+/// state is the same as its input: the value and its timestamp.
+#[derive(Debug)]
+struct SelectorAccumulator {
+    first: bool,
+    value: ScalarValue,
+    time: Option<i64>,
+}
+
+impl Accumulator for SelectorAccumulator {
+    fn state(&self) -> DataFusionResult<Vec<ScalarValue>> {
+        Ok(vec![self.value.clone(), ScalarValue::Int64(self.time)])
+    }
+
+    fn update(&mut self, values: &Vec<ScalarValue>) -> DataFusionResult<()> {
+        let time = match &values[1] {
+            ScalarValue::Int64(Some(time)) => *time,
+            ScalarValue::Int64(None) => return Ok(()),
+            value => {
+                return Err(DataFusionError::Internal(format!(
+                    "selector timestamps must be Int64, not {:?}",
+                    value
+                )))
+            }
+        };
+        if is_null(&values[0]) {
+            return Ok(());
+        }
+
+        let selected = match self.time {
+            None => true,
+            Some(selected_time) if self.first => time < selected_time,
+            Some(selected_time) => time > selected_time,
+        };
+        if selected {
+            self.value = values[0].clone();
+            self.time = Some(time);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, states: &Vec<ScalarValue>) -> DataFusionResult<()> {
+        self.update(states)
+    }
+
+    fn evaluate(&self) -> DataFusionResult<ScalarValue> {
+        Ok(self.value.clone())
+    }
+}
+
+fn is_null(value: &ScalarValue) -> bool {
+    matches!(
+        value,
+        ScalarValue::Float64(None)
+            | ScalarValue::Int64(None)
+            | ScalarValue::UInt64(None)
+            | ScalarValue::Boolean(None)
+            | ScalarValue::Utf8(None)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_end() {
+        let window = Window::new(10, 3).unwrap();
+        assert_eq!(window.end(3), 13);
+        assert_eq!(window.end(12), 13);
+        assert_eq!(window.end(13), 23);
+        assert_eq!(window.end(0), 3);
+        assert_eq!(window.end(-8), -7);
+
+        // windows at the ends of the range of timestamps
+        assert_eq!(window.end(i64::MIN), i64::MIN + 1);
+        assert_eq!(window.end(i64::MAX - 1), i64::MAX);
+        assert_eq!(Window::new(i64::MAX, 0).unwrap().end(i64::MIN), -i64::MAX);
+
+        // windows end no later than the stop
+        let stopped = window.with_stop(20);
+        assert_eq!(stopped.end(12), 13);
+        assert_eq!(stopped.end(15), 20);
+
+        // offsets are relative to the window's duration
+        assert_eq!(Window::new(10, -7).unwrap(), window);
+        assert_eq!(Window::new(10, 23).unwrap(), window);
+
+        let err = Window::new(0, 0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Windows must last a positive duration, not 0ns"
+        );
+    }
+
+    #[test]
+    fn selector_accumulator() {
+        for &(first, expected) in &[(true, 2.0), (false, 3.0)] {
+            let mut accumulator = SelectorAccumulator {
+                first,
+                value: ScalarValue::Float64(None),
+                time: None,
+            };
+
+            let points = vec![(1.0, 20), (2.0, 10), (3.0, 30), (4.0, 5)];
+            for (value, time) in points {
+                let value = if time == 5 { None } else { Some(value) };
+                accumulator
+                    .update(&vec![
+                        ScalarValue::Float64(value),
+                        ScalarValue::Int64(Some(time)),
+                    ])
+                    .unwrap();
+            }
+
+            assert_eq!(
+                accumulator.evaluate().unwrap(),
+                ScalarValue::Float64(Some(expected))
+            );
+        }
+    }
+}
@@ -405,6 +405,39 @@ impl Executor {
         Ok(())
     }
 
+    /// Executes the Grouped plans, sending all the resulting series
+    /// to the `tx` channel as a single group, regardless of how the
+    /// plans group them. The series are ordered by table_name.
+    ///
+    /// As the group starts with the union of the tag keys of all the
+    /// series, nothing is sent until all the plans have run.
+    pub async fn to_single_group_series_set(
+        &self,
+        grouped_series_set_plans: GroupedSeriesSetPlans,
+        tx: mpsc::Sender<Result<GroupedSeriesSetItem, SeriesSetError>>,
+    ) -> Result<()> {
+        let GroupedSeriesSetPlans { grouped_plans } = grouped_series_set_plans;
+        let series_set_plans: SeriesSetPlans = grouped_plans
+            .into_iter()
+            .map(|plan| plan.series_set_plan)
+            .collect::<Vec<_>>()
+            .into();
+
+        let (series_tx, series_rx) = mpsc::channel(1);
+        let converter = tokio::task::spawn(async move {
+            GroupedSeriesSetConverter::new(tx)
+                .convert_single_group(series_rx)
+                .await
+        });
+
+        self.to_series_set(series_set_plans, series_tx).await?;
+
+        converter
+            .await
+            .context(JoinError)?
+            .context(SeriesSetConversion)
+    }
+
     /// Executes `plan` and return the resulting FieldList
     pub async fn to_fieldlist(&self, plan: FieldListPlan) -> Result<FieldList> {
         match plan {
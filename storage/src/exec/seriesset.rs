@@ -17,7 +17,7 @@
 //! the columns would be ordered `host`, `region`, and `service` as
 //! well.
 
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc};
 
 use arrow::{
     array::StringArray, datatypes::DataType, datatypes::SchemaRef, record_batch::RecordBatch,
@@ -109,6 +109,11 @@ pub struct SeriesSet {
 pub struct GroupDescription {
     /// key = value  pairs that define the group
     pub tags: Vec<(Arc<String>, Arc<String>)>,
+
+    /// The tag keys of the group: the keys of `tags`, or, when all
+    /// the series are in a single group, the union of the tag keys
+    /// of all the series
+    pub tag_keys: Vec<Arc<String>>,
     // TODO: maybe also include the resulting aggregate value (per group) here
 }
 
@@ -437,6 +442,7 @@ impl GroupedSeriesSetConverter {
                     let group_tags = series_set.tags[0..num_prefix_tag_group_columns].to_vec();
 
                     let group_desc = GroupDescription {
+                        tag_keys: group_tags.iter().map(|(key, _)| key.clone()).collect(),
                         tags: group_tags.clone(),
                     };
 
@@ -474,6 +480,57 @@ impl GroupedSeriesSetConverter {
 
         Ok(())
     }
+
+    /// Sends all the series received from `rx`, which may come from
+    /// several tables, as a single group. The group's tag keys are
+    /// the union of the tag keys of all the series, so the series are
+    /// buffered until `rx` is closed. Nothing is sent if there are no
+    /// series.
+    ///
+    /// Errors received from `rx` are passed along as they arrive.
+    pub async fn convert_single_group(
+        &mut self,
+        mut rx: mpsc::Receiver<Result<SeriesSet>>,
+    ) -> Result<()> {
+        let mut tag_keys = BTreeSet::new();
+        let mut series_sets = Vec::new();
+
+        while let Some(series_set) = rx.recv().await {
+            match series_set {
+                Ok(series_set) => {
+                    tag_keys.extend(series_set.tags.iter().map(|(key, _)| key.clone()));
+                    series_sets.push(series_set);
+                }
+                Err(e) => self.send(Err(e)).await?,
+            }
+        }
+
+        if series_sets.is_empty() {
+            return Ok(());
+        }
+
+        let group_desc = GroupDescription {
+            tags: vec![],
+            tag_keys: tag_keys.into_iter().collect(),
+        };
+        self.send(Ok(GroupedSeriesSetItem::GroupStart(group_desc)))
+            .await?;
+
+        for series_set in series_sets {
+            self.send(Ok(GroupedSeriesSetItem::GroupData(series_set)))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, item: Result<GroupedSeriesSetItem>) -> Result<()> {
+        self.tx
+            .send(item)
+            .await
+            .map_err(|e| Error::SendingDuringGroupedConversion {
+                source: Box::new(e),
+            })
+    }
 }
 
 #[cfg(test)]
@@ -743,6 +800,7 @@ mod tests {
         let series_set3 = extract_series_set(results[4].as_ref().expect("Correctly converted"));
 
         assert_eq!(group_1.tags, str_pair_vec_to_vec(&[("tag_a", "one")]));
+        assert_eq!(group_1.tag_keys, *str_vec_to_arc_vec(&["tag_a"]));
 
         assert_eq!(*series_set1.table_name, "foo");
         assert_eq!(
@@ -806,6 +864,7 @@ mod tests {
         let series_set2 = extract_series_set(results[2].as_ref().expect("Correctly converted"));
 
         assert_eq!(group_1.tags, &[]);
+        assert_eq!(group_1.tag_keys, &[]);
 
         assert_eq!(*series_set1.table_name, "foo");
         assert_eq!(
@@ -826,6 +885,74 @@ mod tests {
         Ok(())
     }
 
+    // test all the series of several tables in a single group
+    #[tokio::test]
+    async fn test_convert_single_group() -> Result<()> {
+        let cpu_schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("region", DataType::Utf8, true),
+            Field::new("usage", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let cpu_input = parse_to_iterator(
+            cpu_schema,
+            "a,east,10.0,1000\n\
+             b,west,10.1,2000\n",
+        );
+        let cpu_results = convert("cpu", &["host", "region"], &["usage"], cpu_input).await;
+
+        let mem_schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("rack", DataType::Utf8, true),
+            Field::new("free", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let mem_input = parse_to_iterator(mem_schema, "a,r1,50.0,1000\n");
+        let mem_results = convert("mem", &["host", "rack"], &["free"], mem_input).await;
+
+        let results = convert_single_group(cpu_results.into_iter().chain(mem_results)).await;
+
+        // expect the output to be
+        // Group1 (tag keys host, rack, region)
+        // Series1 (cpu host = a, region = east)
+        // Series2 (cpu host = b, region = west)
+        // Series3 (mem host = a, rack = r1)
+        assert_eq!(results.len(), 4, "results were\n{:#?}", results);
+
+        let group = extract_group(results[0].as_ref().expect("correctly made group"));
+        let series_set1 = extract_series_set(results[1].as_ref().expect("Correctly converted"));
+        let series_set2 = extract_series_set(results[2].as_ref().expect("Correctly converted"));
+        let series_set3 = extract_series_set(results[3].as_ref().expect("Correctly converted"));
+
+        assert_eq!(group.tags, &[]);
+        assert_eq!(
+            group.tag_keys,
+            *str_vec_to_arc_vec(&["host", "rack", "region"])
+        );
+
+        assert_eq!(*series_set1.table_name, "cpu");
+        assert_eq!(
+            series_set1.tags,
+            str_pair_vec_to_vec(&[("host", "a"), ("region", "east")])
+        );
+        assert_eq!(*series_set2.table_name, "cpu");
+        assert_eq!(
+            series_set2.tags,
+            str_pair_vec_to_vec(&[("host", "b"), ("region", "west")])
+        );
+        assert_eq!(*series_set3.table_name, "mem");
+        assert_eq!(
+            series_set3.tags,
+            str_pair_vec_to_vec(&[("host", "a"), ("rack", "r1")])
+        );
+
+        // no series, no group
+        let results = convert_single_group(Vec::<Result<SeriesSet>>::new()).await;
+        assert_eq!(results.len(), 0, "results were\n{:#?}", results);
+
+        Ok(())
+    }
+
     fn extract_group(item: &GroupedSeriesSetItem) -> &GroupDescription {
         match item {
             GroupedSeriesSetItem::GroupStart(group) => group,
@@ -903,6 +1030,38 @@ mod tests {
         results
     }
 
+    /// Test helper: convert `series_sets` to a single group and return a Vec
+    pub async fn convert_single_group(
+        series_sets: impl IntoIterator<Item = Result<SeriesSet>>,
+    ) -> Vec<Result<GroupedSeriesSetItem>> {
+        let (mut series_tx, series_rx) = mpsc::channel(1);
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut converter = GroupedSeriesSetConverter::new(tx);
+
+        tokio::task::spawn(async move {
+            converter
+                .convert_single_group(series_rx)
+                .await
+                .expect("Conversion happened without error")
+        });
+
+        let series_sets = series_sets.into_iter().collect::<Vec<_>>();
+        tokio::task::spawn(async move {
+            for series_set in series_sets {
+                series_tx
+                    .send(series_set)
+                    .await
+                    .expect("Sending series set to convert");
+            }
+        });
+
+        let mut results = Vec::new();
+        while let Some(r) = rx.recv().await {
+            results.push(r)
+        }
+        results
+    }
+
     /// Test helper: parses the csv content into a single record batch arrow arrays
     /// columnar ArrayRef according to the schema
     fn parse_to_record_batch(schema: SchemaRef, data: &str) -> RecordBatch {
@@ -8,17 +8,20 @@
 
 use arrow_deps::arrow::record_batch::RecordBatch;
 use async_trait::async_trait;
-use data_types::data::ReplicatedWrite;
+use data_types::{data::ReplicatedWrite, delete::Delete};
 use exec::{FieldListPlan, GroupedSeriesSetPlans, SeriesSetPlans, StringSetPlan};
 use influxdb_line_protocol::ParsedLine;
 
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
 
+pub mod aggregate;
 pub mod exec;
 pub mod id;
 pub mod predicate;
+pub mod regex_match;
 pub mod util;
 
+use self::aggregate::{Aggregate, Window};
 use self::predicate::{Predicate, TimestampRange};
 
 #[async_trait]
@@ -36,7 +39,7 @@ use self::predicate::{Predicate, TimestampRange};
 /// can retrieved as Arrow columns, the field type is used to select
 /// certain columns in some query types.
 pub trait Database: Debug + Send + Sync {
-    type Error: std::error::Error + Send + Sync + 'static;
+    type Error: DatabaseError;
 
     /// writes parsed lines into this database
     async fn write_lines(&self, lines: &[ParsedLine<'_>]) -> Result<(), Self::Error>;
@@ -44,6 +47,10 @@ pub trait Database: Debug + Send + Sync {
     /// Stores the replicated write in the write buffer and, if enabled, the write ahead log.
     async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error>;
 
+    /// Removes the rows matching `delete` from this database and, if
+    /// enabled, records the delete in the write ahead log.
+    async fn delete(&self, delete: &Delete) -> Result<(), Self::Error>;
+
     /// Execute the specified query and return arrow record batches with the result
     async fn query(&self, query: &str) -> Result<Vec<RecordBatch>, Self::Error>;
 
@@ -66,7 +73,8 @@ pub trait Database: Debug + Send + Sync {
 
     /// Returns a plan which finds the distinct values in the
     /// `column_name` column of this database which pass the
-    /// conditions specified by `predicate`.
+    /// conditions specified by `predicate`. Tables without the
+    /// column, and rows without a value in it, contribute no values.
     async fn column_values(
         &self,
         column_name: &str,
@@ -90,12 +98,30 @@ pub trait Database: Debug + Send + Sync {
     /// "tag_columns" for each field in the "field_columns". Each
     /// group is is defined by unique combinations of the columns
     /// in `group_columns`
+    ///
+    /// If `aggregate` is specified, each time series has a single
+    /// row holding the aggregate of each of its fields, at the time
+    /// of its last row
     async fn query_groups(
         &self,
         predicate: Predicate,
         group_columns: Vec<String>,
+        aggregate: Option<Aggregate>,
     ) -> Result<GroupedSeriesSetPlans, Self::Error>;
 
+    /// Returns a plan that finds the rows which pass the conditions
+    /// specified by `predicate` in the form of logical time series, as
+    /// `query_series` does, but with a single row for each `window` a
+    /// time series has rows in. The row holds the `aggregate` of each of
+    /// the fields in the window, at the end of the window, or of the range
+    /// of time of `predicate` if that is earlier.
+    async fn query_window_aggregate(
+        &self,
+        predicate: Predicate,
+        window: Window,
+        aggregate: Aggregate,
+    ) -> Result<SeriesSetPlans, Self::Error>;
+
     /// Fetch the specified table names and columns as Arrow
     /// RecordBatches. Columns are returned in the order specified.
     async fn table_to_arrow(
@@ -105,6 +131,15 @@ pub trait Database: Debug + Send + Sync {
     ) -> Result<Vec<RecordBatch>, Self::Error>;
 }
 
+/// An error returned by a `Database`
+pub trait DatabaseError: std::error::Error + Send + Sync + 'static {
+    /// Returns true if the request failed because the database ran out of a
+    /// resource, such as memory, and may succeed if retried later
+    fn is_resource_exhausted(&self) -> bool {
+        false
+    }
+}
+
 #[async_trait]
 /// Storage for `Databases` which can be retrieved by name
 pub trait DatabaseStore: Debug + Send + Sync {
@@ -121,6 +156,26 @@ pub trait DatabaseStore: Debug + Send + Sync {
     /// Retrieve the database specified by `name`, creating it if it
     /// doesn't exist.
     async fn db_or_create(&self, name: &str) -> Result<Arc<Self::Database>, Self::Error>;
+
+    /// Returns the progress of recovering the database specified by
+    /// `name` if it is still being recovered, e.g. from its write ahead
+    /// log at startup, in which case it can't be used yet
+    async fn recovery_progress(&self, _name: &str) -> Option<RecoveryProgress> {
+        None
+    }
+
+    /// Returns the progress of all the databases still being recovered,
+    /// by name. The store is ready to serve requests once there are none.
+    async fn recovering(&self) -> BTreeMap<String, RecoveryProgress> {
+        BTreeMap::new()
+    }
+}
+
+/// How far the recovery of a database has got
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    /// The number of write ahead log entries replayed so far
+    pub wal_entries: usize,
 }
 
 /// Compatibility: return the database name to use for the specified
@@ -112,16 +112,15 @@ impl PredicateBuilder {
         self.tables(vec![table.into()])
     }
 
-    /// Sets table name restrictions
+    /// Sets table name restrictions. If there already are table name
+    /// restrictions, only the tables in both sets are selected (as with
+    /// `table_name IN (foo, bar) AND table_name IN (bar, baz)`)
     pub fn tables(mut self, tables: Vec<String>) -> Self {
-        // We need to distinguish predicates like `table_name In
-        // (foo, bar)` and `table_name = foo and table_name = bar` in order to handle this
-        assert!(
-            self.inner.table_names.is_none(),
-            "Multiple table predicate specification not yet supported"
-        );
+        let mut table_names = tables.into_iter().collect::<BTreeSet<_>>();
+        if let Some(existing) = self.inner.table_names.take() {
+            table_names = table_names.intersection(&existing).cloned().collect();
+        }
 
-        let table_names = tables.into_iter().collect::<BTreeSet<_>>();
         self.inner.table_names = Some(table_names);
         self
     }
@@ -145,3 +144,24 @@ impl PredicateBuilder {
         self.inner
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiple_table_restrictions() {
+        let predicate = PredicateBuilder::default()
+            .tables(vec!["cpu".into(), "mem".into()])
+            .table("mem")
+            .build();
+        let expected = vec!["mem".to_string()].into_iter().collect();
+        assert_eq!(predicate.table_names, Some(expected));
+
+        let predicate = PredicateBuilder::default()
+            .table("cpu")
+            .table("mem")
+            .build();
+        assert_eq!(predicate.table_names, Some(BTreeSet::new()));
+    }
+}
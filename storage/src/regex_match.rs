@@ -0,0 +1,197 @@
+//! This module contains `RegexMatch`, a regular expression predicate on the
+//! values of a column, e.g. `host =~ /^web/` or `host !~ /^web/`, which is
+//! also how prefix predicates are evaluated.
+//!
+//! It is evaluated by DataFusion as a call to a scalar function whose
+//! arguments are the column and the pattern, so stores that prune data
+//! without DataFusion can recognize it in a predicate's expressions.
+
+use std::sync::Arc;
+
+use arrow_deps::{
+    arrow::{
+        array::{ArrayRef, BooleanBuilder, StringArray},
+        datatypes::DataType,
+    },
+    datafusion::{
+        error::DataFusionError,
+        logical_plan::Expr,
+        physical_plan::{
+            functions::{ReturnTypeFunction, ScalarFunctionImplementation, Signature},
+            udf::ScalarUDF,
+        },
+        scalar::ScalarValue,
+    },
+};
+use regex::Regex;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid regular expression /{}/: {}", pattern, source))]
+    InvalidRegex {
+        pattern: String,
+        source: regex::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The name of the function selecting the values that match a pattern
+pub const REGEX_MATCH_UDF_NAME: &str = "RegexMatch";
+
+/// The name of the function selecting the values that don't match a pattern
+pub const REGEX_NOT_MATCH_UDF_NAME: &str = "RegexNotMatch";
+
+/// Selects the rows whose value of `column` matches `regex`, or, if
+/// `negated`, doesn't. Rows without a value are never selected.
+#[derive(Debug, Clone)]
+pub struct RegexMatch {
+    pub column: String,
+    pub regex: Regex,
+    pub negated: bool,
+}
+
+impl RegexMatch {
+    pub fn new(column: impl Into<String>, pattern: &str, negated: bool) -> Result<Self> {
+        let regex = Regex::new(pattern).context(InvalidRegex { pattern })?;
+
+        Ok(Self {
+            column: column.into(),
+            regex,
+            negated,
+        })
+    }
+
+    /// Selects the rows whose value of `column` starts with `prefix`
+    pub fn starts_with(column: impl Into<String>, prefix: &str) -> Self {
+        let pattern = format!("^{}", regex::escape(prefix));
+        Self::new(column, &pattern, false).expect("escaped prefixes are valid patterns")
+    }
+
+    /// Returns true if rows with `value` are selected
+    pub fn matches(&self, value: &str) -> bool {
+        self.regex.is_match(value) != self.negated
+    }
+
+    /// Returns the DataFusion expression evaluating this predicate
+    pub fn to_datafusion_expr(&self) -> Expr {
+        let regex_match = self.clone();
+        let fun: ScalarFunctionImplementation = Arc::new(move |args: &[ArrayRef]| {
+            let values = args[0]
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .ok_or_else(|| {
+                    DataFusionError::Internal(
+                        "regular expressions can only match strings".to_string(),
+                    )
+                })?;
+
+            let mut selected = BooleanBuilder::new(values.len());
+            for i in 0..values.len() {
+                if values.is_null(i) {
+                    selected.append_null()?;
+                } else {
+                    selected.append_value(regex_match.matches(values.value(i)))?;
+                }
+            }
+            Ok(Arc::new(selected.finish()) as ArrayRef)
+        });
+        let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Boolean)));
+
+        let name = if self.negated {
+            REGEX_NOT_MATCH_UDF_NAME
+        } else {
+            REGEX_MATCH_UDF_NAME
+        };
+        let udf = ScalarUDF::new(
+            name,
+            &Signature::Exact(vec![DataType::Utf8, DataType::Utf8]),
+            &return_type,
+            &fun,
+        );
+
+        let pattern = self.regex.as_str().to_string();
+        udf.call(vec![
+            Expr::Column(self.column.clone()),
+            Expr::Literal(ScalarValue::Utf8(Some(pattern))),
+        ])
+    }
+
+    /// Returns the predicate `expr` evaluates, if it was created by
+    /// `to_datafusion_expr`
+    pub fn from_datafusion_expr(expr: &Expr) -> Option<Self> {
+        match expr {
+            Expr::ScalarUDF { fun, args } => {
+                let negated = match fun.name.as_str() {
+                    REGEX_MATCH_UDF_NAME => false,
+                    REGEX_NOT_MATCH_UDF_NAME => true,
+                    _ => return None,
+                };
+
+                match args.as_slice() {
+                    [Expr::Column(column), Expr::Literal(ScalarValue::Utf8(Some(pattern)))] => {
+                        Self::new(column.as_str(), pattern, negated).ok()
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[test]
+    fn regex_match() -> Result {
+        let web = RegexMatch::new("host", "^web", false)?;
+        assert!(web.matches("web01"));
+        assert!(!web.matches("db01"));
+
+        let not_web = RegexMatch::new("host", "^web", true)?;
+        assert!(!not_web.matches("web01"));
+        assert!(not_web.matches("db01"));
+
+        // regular expression characters in prefixes match themselves
+        let prefix = RegexMatch::starts_with("host", "web.1");
+        assert!(prefix.matches("web.1.example.com"));
+        assert!(!prefix.matches("web01"));
+        assert!(!prefix.matches("my-web.1"));
+
+        let err = RegexMatch::new("host", "web(", false).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Invalid regular expression /web(/"),
+            "unexpected error: {}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn datafusion_expr_roundtrip() -> Result {
+        for regex_match in vec![
+            RegexMatch::new("host", "^web", false)?,
+            RegexMatch::new("region", "east|west", true)?,
+        ] {
+            let expr = regex_match.to_datafusion_expr();
+            let parsed = RegexMatch::from_datafusion_expr(&expr).expect("recognized regex match");
+
+            assert_eq!(parsed.column, regex_match.column);
+            assert_eq!(parsed.regex.as_str(), regex_match.regex.as_str());
+            assert_eq!(parsed.negated, regex_match.negated);
+        }
+
+        let expr = Expr::Column("host".into());
+        assert!(RegexMatch::from_datafusion_expr(&expr).is_none());
+
+        Ok(())
+    }
+}
@@ -4,15 +4,16 @@
 use arrow_deps::arrow::record_batch::RecordBatch;
 
 use crate::{
+    aggregate::{Aggregate, Window},
     exec::FieldListPlan,
     exec::{
         stringset::{StringSet, StringSetRef},
         GroupedSeriesSetPlans, SeriesSetPlans, StringSetPlan,
     },
-    Database, DatabaseStore, Predicate, TimestampRange,
+    Database, DatabaseError, DatabaseStore, Predicate, RecoveryProgress, TimestampRange,
 };
 
-use data_types::data::ReplicatedWrite;
+use data_types::{data::ReplicatedWrite, delete::Delete};
 use influxdb_line_protocol::{parse_lines, ParsedLine};
 
 use async_trait::async_trait;
@@ -31,6 +32,9 @@ pub struct TestDatabase {
     /// Replicated writes which have been written to this database, in order
     replicated_writes: Mutex<Vec<ReplicatedWrite>>,
 
+    /// Deletes which have been applied to this database, in order
+    deletes: Mutex<Vec<Delete>>,
+
     /// `column_names` to return upon next request
     column_names: Arc<Mutex<Option<StringSetRef>>>,
 
@@ -55,6 +59,12 @@ pub struct TestDatabase {
     /// The last request for `query_series`
     query_groups_request: Arc<Mutex<Option<QueryGroupsRequest>>>,
 
+    /// Responses to return on the next request to `query_window_aggregate`
+    query_window_aggregate_values: Arc<Mutex<Option<SeriesSetPlans>>>,
+
+    /// The last request for `query_window_aggregate`
+    query_window_aggregate_request: Arc<Mutex<Option<QueryWindowAggregateRequest>>>,
+
     /// Responses to return on the next request to `field_column_values`
     field_columns_value: Arc<Mutex<Option<FieldListPlan>>>,
 
@@ -92,6 +102,16 @@ pub struct QueryGroupsRequest {
     /// Stringified '{:?}' version of the predicate
     pub predicate: String,
     pub group_columns: Vec<String>,
+    pub aggregate: Option<Aggregate>,
+}
+
+/// Records the parameters passed to a `query_window_aggregate` request
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueryWindowAggregateRequest {
+    /// Stringified '{:?}' version of the predicate
+    pub predicate: String,
+    pub window: Window,
+    pub aggregate: Aggregate,
 }
 
 /// Records the parameters passed to a `field_columns` request
@@ -110,6 +130,8 @@ pub enum TestError {
     Execution { source: crate::exec::Error },
 }
 
+impl DatabaseError for TestError {}
+
 impl TestDatabase {
     pub fn new() -> Self {
         Self::default()
@@ -125,6 +147,11 @@ impl TestDatabase {
         self.replicated_writes.lock().await.clone()
     }
 
+    /// Get all deletes applied to this database
+    pub async fn get_deletes(&self) -> Vec<Delete> {
+        self.deletes.lock().await.clone()
+    }
+
     /// Parse line protocol and add it as new lines to this
     /// database
     pub async fn add_lp_string(&self, lp_data: &str) {
@@ -183,6 +210,20 @@ impl TestDatabase {
         self.query_groups_request.clone().lock().await.take()
     }
 
+    /// Set the series that will be returned on a call to query_window_aggregate
+    pub async fn set_query_window_aggregate_values(&self, plan: SeriesSetPlans) {
+        *(self.query_window_aggregate_values.clone().lock().await) = Some(plan);
+    }
+
+    /// Get the parameters from the last query_window_aggregate request
+    pub async fn get_query_window_aggregate_request(&self) -> Option<QueryWindowAggregateRequest> {
+        self.query_window_aggregate_request
+            .clone()
+            .lock()
+            .await
+            .take()
+    }
+
     /// Set the FieldSet plan that will be returned
     pub async fn set_field_colum_names_values(&self, plan: FieldListPlan) {
         *(self.field_columns_value.clone().lock().await) = Some(plan);
@@ -261,20 +302,34 @@ impl Database for TestDatabase {
         Ok(())
     }
 
+    /// Records the delete
+    async fn delete(&self, delete: &Delete) -> Result<(), Self::Error> {
+        self.deletes.lock().await.push(delete.clone());
+        Ok(())
+    }
+
     /// Execute the specified query and return arrow record batches with the result
     async fn query(&self, _query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
         unimplemented!("query Not yet implemented");
     }
 
-    /// Return all table names that are saved in this database
+    /// Return all table names that are saved in this database, applying
+    /// the range and table name restrictions of `predicate` (but not its
+    /// general purpose expressions)
     async fn table_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
         let saved_lines = self.saved_lines.lock().await;
 
         let names = parse_lines(&saved_lines.join("\n"))
             .filter_map(|line| {
                 let line = line.expect("Correctly parsed saved line");
-                if line_in_range(&line, predicate.range.as_ref()) {
-                    Some(line.series.measurement.to_string())
+                let table_name = line.series.measurement.to_string();
+                let table_selected = predicate
+                    .table_names
+                    .as_ref()
+                    .map_or(true, |table_names| table_names.contains(&table_name));
+
+                if table_selected && line_in_range(&line, predicate.range.as_ref()) {
+                    Some(table_name)
                 } else {
                     None
                 }
@@ -381,12 +436,14 @@ impl Database for TestDatabase {
         &self,
         predicate: Predicate,
         group_columns: Vec<String>,
+        aggregate: Option<Aggregate>,
     ) -> Result<GroupedSeriesSetPlans, Self::Error> {
         let predicate = predicate_to_test_string(&predicate);
 
         let new_queries_groups_request = Some(QueryGroupsRequest {
             predicate,
             group_columns,
+            aggregate,
         });
 
         *self.query_groups_request.clone().lock().await = new_queries_groups_request;
@@ -402,6 +459,34 @@ impl Database for TestDatabase {
             })
     }
 
+    async fn query_window_aggregate(
+        &self,
+        predicate: Predicate,
+        window: Window,
+        aggregate: Aggregate,
+    ) -> Result<SeriesSetPlans, Self::Error> {
+        let predicate = predicate_to_test_string(&predicate);
+
+        let new_query_window_aggregate_request = Some(QueryWindowAggregateRequest {
+            predicate,
+            window,
+            aggregate,
+        });
+
+        *self.query_window_aggregate_request.clone().lock().await =
+            new_query_window_aggregate_request;
+
+        self.query_window_aggregate_values
+            .clone()
+            .lock()
+            .await
+            .take()
+            // Turn None into an error
+            .context(General {
+                message: "No saved query_window_aggregate in TestDatabase",
+            })
+    }
+
     /// Fetch the specified table names and columns as Arrow RecordBatches
     async fn table_to_arrow(
         &self,
@@ -415,6 +500,9 @@ impl Database for TestDatabase {
 #[derive(Debug)]
 pub struct TestDatabaseStore {
     databases: Mutex<BTreeMap<String, Arc<TestDatabase>>>,
+
+    /// Databases to report as still being recovered
+    recovering: Mutex<BTreeMap<String, RecoveryProgress>>,
 }
 
 impl TestDatabaseStore {
@@ -430,12 +518,23 @@ impl TestDatabaseStore {
             .add_lp_string(lp_data)
             .await
     }
+
+    /// Reports the `db_name` database as being recovered with `progress`,
+    /// or as recovered if `progress` is `None`
+    pub async fn set_recovering(&self, db_name: &str, progress: Option<RecoveryProgress>) {
+        let mut recovering = self.recovering.lock().await;
+        match progress {
+            Some(progress) => recovering.insert(db_name.to_string(), progress),
+            None => recovering.remove(db_name),
+        };
+    }
 }
 
 impl Default for TestDatabaseStore {
     fn default() -> Self {
         Self {
             databases: Mutex::new(BTreeMap::new()),
+            recovering: Mutex::new(BTreeMap::new()),
         }
     }
 }
@@ -464,4 +563,12 @@ impl DatabaseStore for TestDatabaseStore {
             Ok(new_db)
         }
     }
+
+    async fn recovery_progress(&self, name: &str) -> Option<RecoveryProgress> {
+        self.recovering.lock().await.get(name).cloned()
+    }
+
+    async fn recovering(&self) -> BTreeMap<String, RecoveryProgress> {
+        self.recovering.lock().await.clone()
+    }
 }
@@ -45,10 +45,17 @@ pub struct WalDetails {
 
 #[derive(Debug)]
 pub struct WalWrite {
-    payload: WritePayload,
+    request: WalRequest,
     notify_tx: mpsc::Sender<Result<SequenceNumber, WalError>>,
 }
 
+#[derive(Debug)]
+enum WalRequest {
+    Append(WritePayload),
+    /// Delete the WAL files holding only entries before this sequence number
+    DeleteUpTo(SequenceNumber),
+}
+
 impl WalDetails {
     pub async fn write_metadata(&self) -> Result<()> {
         Ok(tokio::fs::write(
@@ -61,25 +68,37 @@ impl WalDetails {
         })?)
     }
 
-    pub async fn write_and_sync(&self, data: Vec<u8>) -> Result<()> {
+    /// Appends `data` to the WAL and syncs it to disk, returning the sequence number of the
+    /// new entry
+    pub async fn write_and_sync(&self, data: Vec<u8>) -> Result<SequenceNumber> {
         let payload = WritePayload::new(data).context(UnderlyingWalError {})?;
 
+        self.send(WalRequest::Append(payload)).await
+    }
+
+    /// Deletes the WAL files that only hold entries before `sequence_number`. The file holding
+    /// `sequence_number`, and any entries before it in that file, are kept.
+    pub async fn delete_up_to(&self, sequence_number: SequenceNumber) -> Result<()> {
+        self.send(WalRequest::DeleteUpTo(sequence_number)).await?;
+
+        Ok(())
+    }
+
+    async fn send(&self, request: WalRequest) -> Result<SequenceNumber> {
         let (notify_tx, mut notify_rx) = mpsc::channel(1);
 
-        let write = WalWrite { payload, notify_tx };
+        let write = WalWrite { request, notify_tx };
 
         let mut tx = self.write_tx.clone();
         tx.send(write)
             .await
             .expect("The WAL thread should always be running to receive a write");
 
-        let _ = notify_rx
+        notify_rx
             .next()
             .await
             .expect("The WAL thread should always be running to send a response.")
-            .context(UnderlyingWalError {})?;
-
-        Ok(())
+            .context(UnderlyingWalError {})
     }
 }
 
@@ -123,13 +142,15 @@ pub async fn start_wal_sync_task(wal_builder: WalBuilder) -> Result<WalDetails>
             loop {
                 match write_rx.next().await {
                     Some(write) => {
-                        let payload = write.payload;
                         let mut tx = write.notify_tx;
 
-                        let result = wal.append(payload).and_then(|seq| {
-                            wal.sync_all()?;
-                            Ok(seq)
-                        });
+                        let result = match write.request {
+                            WalRequest::Append(payload) => wal.append(payload).and_then(|seq| {
+                                wal.sync_all()?;
+                                Ok(seq)
+                            }),
+                            WalRequest::DeleteUpTo(seq) => wal.delete_up_to_entry(seq).map(|_| seq),
+                        };
 
                         if let Err(e) = tx.send(result).await {
                             error!("error sending result back to writer {:?}", e);
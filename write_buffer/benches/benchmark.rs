@@ -23,7 +23,8 @@ fn benchmark_restore_single_entry_single_partition(common_create_entries: &mut C
     group.bench_function("restore_single_entry_single_partition", |b| {
         b.iter(|| {
             let entries = entries.clone().into_iter().map(Ok);
-            let (partitions, _stats) = restore_partitions_from_wal(entries).unwrap();
+            let (partitions, _stats) =
+                restore_partitions_from_wal(entries, &Default::default()).unwrap();
             assert_eq!(partitions.len(), 1);
         })
     });
@@ -49,7 +50,8 @@ fn benchmark_restore_multiple_entry_multiple_partition(common_create_entries: &m
     group.bench_function("restore_multiple_entry_multiple_partition", |b| {
         b.iter(|| {
             let entries = entries.clone().into_iter().map(Ok);
-            let (partitions, _stats) = restore_partitions_from_wal(entries).unwrap();
+            let (partitions, _stats) =
+                restore_partitions_from_wal(entries, &Default::default()).unwrap();
             assert_eq!(partitions.len(), 3);
         })
     });
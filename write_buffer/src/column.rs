@@ -1,8 +1,16 @@
 use generated_types::wal as wb;
 use snafu::Snafu;
 
+use std::{
+    fmt::{Debug, Display},
+    mem,
+};
+
 use crate::dictionary::Dictionary;
-use data_types::{data::type_description, partition_metadata::Statistics};
+use data_types::{
+    data::{type_description, RowValue},
+    partition_metadata::Statistics,
+};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -29,7 +37,9 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub enum Column {
     F64(Vec<Option<f64>>, Statistics<f64>),
     I64(Vec<Option<i64>>, Statistics<i64>),
-    String(Vec<Option<String>>, Statistics<String>),
+    U64(Vec<Option<u64>>, Statistics<u64>),
+    /// String values, their statistics and their total length in bytes
+    String(Vec<Option<String>>, Statistics<String>, usize),
     Bool(Vec<Option<bool>>, Statistics<bool>),
     Tag(Vec<Option<u32>>, Statistics<String>),
 }
@@ -61,6 +71,15 @@ impl Column {
                 vals.push(Some(val));
                 Self::I64(vals, Statistics::new(val))
             }
+            U64Value => {
+                let val = value
+                    .value_as_u64value()
+                    .expect("u64 value should be present")
+                    .value();
+                let mut vals = vec![None; capacity];
+                vals.push(Some(val));
+                Self::U64(vals, Statistics::new(val))
+            }
             StringValue => {
                 let val = value
                     .value_as_string_value()
@@ -69,7 +88,7 @@ impl Column {
                     .expect("string must be present");
                 let mut vals = vec![None; capacity];
                 vals.push(Some(val.to_string()));
-                Self::String(vals, Statistics::new(val.to_string()))
+                Self::String(vals, Statistics::new(val.to_string()), val.len())
             }
             BoolValue => {
                 let val = value
@@ -104,7 +123,8 @@ impl Column {
         match self {
             Self::F64(v, _) => v.len(),
             Self::I64(v, _) => v.len(),
-            Self::String(v, _) => v.len(),
+            Self::U64(v, _) => v.len(),
+            Self::String(v, _, _) => v.len(),
             Self::Bool(v, _) => v.len(),
             Self::Tag(v, _) => v.len(),
         }
@@ -114,11 +134,77 @@ impl Column {
         self.len() == 0
     }
 
+    /// Returns an estimate of the memory, in bytes, taken up by the values
+    /// of this column. Tag values are counted by their dictionary ids.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::F64(v, _) => v.len() * mem::size_of::<Option<f64>>(),
+            Self::I64(v, _) => v.len() * mem::size_of::<Option<i64>>(),
+            Self::U64(v, _) => v.len() * mem::size_of::<Option<u64>>(),
+            Self::String(v, _, bytes) => v.len() * mem::size_of::<Option<String>>() + bytes,
+            Self::Bool(v, _) => v.len() * mem::size_of::<Option<bool>>(),
+            Self::Tag(v, _) => v.len() * mem::size_of::<Option<u32>>(),
+        }
+    }
+
+    /// Returns the value of `row`, if it has one, with tags looked up in
+    /// `dictionary`
+    pub fn row_value<'a>(&'a self, row: usize, dictionary: &'a Dictionary) -> Option<RowValue<'a>> {
+        match self {
+            Self::F64(v, _) => v[row].map(RowValue::F64),
+            Self::I64(v, _) => v[row].map(RowValue::I64),
+            Self::U64(v, _) => v[row].map(RowValue::U64),
+            Self::String(v, _, _) => v[row].as_deref().map(RowValue::String),
+            Self::Bool(v, _) => v[row].map(RowValue::Bool),
+            Self::Tag(v, _) => v[row].map(|id| RowValue::Tag(lookup_tag(dictionary, id))),
+        }
+    }
+
+    /// Returns this column with only the rows for which `keep` is true and its
+    /// statistics recomputed, or `None` if none of those rows have a value
+    pub fn retain_rows(self, keep: &[bool], dictionary: &Dictionary) -> Option<Self> {
+        Some(match self {
+            Self::F64(v, _) => {
+                let v = retain(v, keep);
+                let stats = statistics(v.iter().flatten().cloned())?;
+                Self::F64(v, stats)
+            }
+            Self::I64(v, _) => {
+                let v = retain(v, keep);
+                let stats = statistics(v.iter().flatten().cloned())?;
+                Self::I64(v, stats)
+            }
+            Self::U64(v, _) => {
+                let v = retain(v, keep);
+                let stats = statistics(v.iter().flatten().cloned())?;
+                Self::U64(v, stats)
+            }
+            Self::String(v, _, _) => {
+                let v = retain(v, keep);
+                let stats = string_statistics(v.iter().flatten().map(String::as_str))?;
+                let bytes = v.iter().flatten().map(String::len).sum();
+                Self::String(v, stats, bytes)
+            }
+            Self::Bool(v, _) => {
+                let v = retain(v, keep);
+                let stats = statistics(v.iter().flatten().cloned())?;
+                Self::Bool(v, stats)
+            }
+            Self::Tag(v, _) => {
+                let v = retain(v, keep);
+                let tags = v.iter().flatten().map(|&id| lookup_tag(dictionary, id));
+                let stats = string_statistics(tags)?;
+                Self::Tag(v, stats)
+            }
+        })
+    }
+
     pub fn type_description(&self) -> &'static str {
         match self {
             Self::F64(_, _) => "f64",
             Self::I64(_, _) => "i64",
-            Self::String(_, _) => "String",
+            Self::U64(_, _) => "u64",
+            Self::String(_, _, _) => "String",
             Self::Bool(_, _) => "bool",
             Self::Tag(_, _) => "tag",
         }
@@ -136,11 +222,12 @@ impl Column {
                 }
                 None => false,
             },
-            Self::String(vals, stats) => match value.value_as_string_value() {
+            Self::String(vals, stats, bytes) => match value.value_as_string_value() {
                 Some(str_val) => {
                     let str_val = str_val.value().expect("string must have value");
                     vals.push(Some(str_val.to_string()));
                     Statistics::update_string(stats, str_val);
+                    *bytes += str_val.len();
                     true
                 }
                 None => false,
@@ -163,6 +250,15 @@ impl Column {
                 }
                 None => false,
             },
+            Self::U64(vals, stats) => match value.value_as_u64value() {
+                Some(u64_val) => {
+                    let u64_val = u64_val.value();
+                    vals.push(Some(u64_val));
+                    stats.update(u64_val);
+                    true
+                }
+                None => false,
+            },
             Self::F64(vals, stats) => match value.value_as_f64value() {
                 Some(f64_val) => {
                     let f64_val = f64_val.value();
@@ -199,7 +295,12 @@ impl Column {
                     v.push(None);
                 }
             }
-            Self::String(v, _) => {
+            Self::U64(v, _) => {
+                if v.len() == len {
+                    v.push(None);
+                }
+            }
+            Self::String(v, _, _) => {
                 if v.len() == len {
                     v.push(None);
                 }
@@ -302,6 +403,19 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn string_size_tracks_retained_values() {
+        let mut stats = Statistics::new("a".to_string());
+        Statistics::update_string(&mut stats, "bcd");
+        let col = Column::String(vec![Some("a".into()), None, Some("bcd".into())], stats, 4);
+        let slot = mem::size_of::<Option<String>>();
+        assert_eq!(col.size(), 3 * slot + 4);
+
+        let dictionary = Dictionary::new();
+        let col = col.retain_rows(&[false, true, true], &dictionary).unwrap();
+        assert_eq!(col.size(), 2 * slot + 3);
+    }
+
     #[test]
     fn test_has_non_null_i64_range_() -> Result {
         let none_col: Vec<Option<u32>> = vec![None, None, None];
@@ -326,3 +440,37 @@ mod tests {
         Ok(())
     }
 }
+
+fn lookup_tag(dictionary: &Dictionary, id: u32) -> &str {
+    dictionary
+        .lookup_id(id)
+        .expect("tag value id should be in the dictionary")
+}
+
+fn retain<T>(values: Vec<Option<T>>, keep: &[bool]) -> Vec<Option<T>> {
+    values
+        .into_iter()
+        .zip(keep)
+        .filter(|(_, &keep)| keep)
+        .map(|(value, _)| value)
+        .collect()
+}
+
+fn statistics<T>(mut values: impl Iterator<Item = T>) -> Option<Statistics<T>>
+where
+    T: PartialEq + PartialOrd + Debug + Display + Clone,
+{
+    let mut stats = Statistics::new(values.next()?);
+    for value in values {
+        stats.update(value);
+    }
+    Some(stats)
+}
+
+fn string_statistics<'a>(mut values: impl Iterator<Item = &'a str>) -> Option<Statistics<String>> {
+    let mut stats = Statistics::new(values.next()?.to_string());
+    for value in values {
+        Statistics::update_string(&mut stats, value);
+    }
+    Some(stats)
+}
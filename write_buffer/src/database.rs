@@ -1,51 +1,65 @@
 use generated_types::wal as wb;
 use influxdb_line_protocol::ParsedLine;
+use object_store::ObjectStore;
 use storage::{
+    aggregate::{Aggregate, Window},
     exec::{
         stringset::StringSet, FieldListPlan, GroupedSeriesSetPlan, GroupedSeriesSetPlans,
         SeriesSetPlan, SeriesSetPlans, StringSetPlan,
     },
     predicate::Predicate,
-    Database,
+    Database, DatabaseError,
 };
 use wal::{
     writer::{start_wal_sync_task, Error as WalWriterError, WalDetails},
-    WalBuilder,
+    SequenceNumber, WalBuilder,
 };
 
 use crate::column::Column;
+use crate::memory::{MemoryTracker, MemoryUsage};
 use crate::partition::Partition;
-use crate::{partition::PartitionPredicate, table::Table};
+use crate::{
+    partition::PartitionPredicate,
+    table::{aggregate_series_set_plan, Table},
+};
 
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::io::ErrorKind;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Instant;
 
 use arrow_deps::{
     arrow,
-    arrow::{datatypes::Schema as ArrowSchema, record_batch::RecordBatch},
-    datafusion::logical_plan::LogicalPlan,
+    arrow::record_batch::RecordBatch,
+    datafusion::logical_plan::{Expr, LogicalPlan},
     datafusion::prelude::ExecutionConfig,
-    datafusion::{
-        datasource::MemTable, error::DataFusionError, execution::context::ExecutionContext,
-    },
+    datafusion::{error::DataFusionError, execution::context::ExecutionContext},
+};
+use data_types::{
+    data::{split_lines_into_write_entry_partitions, ReplicatedWrite},
+    database_rules::{DatabaseRules, PartitionId, PartitionTemplate, TemplatePart},
+    delete::Delete,
+    partition_metadata::{self, partition_file_location, table_file_name, METADATA_FILE_NAME},
 };
-use data_types::data::{split_lines_into_write_entry_partitions, ReplicatedWrite};
 
 use crate::dictionary::Error as DictionaryError;
+use crate::lifecycle::partitions_to_close;
 use crate::partition::restore_partitions_from_wal;
+use crate::provider::{register_tables, union_batches};
+use crate::read_only::{load_metadata, load_partition};
+use crate::replicated_write::{encode_wal_entry, AppliedWrites};
+use crate::snapshot::{write_metadata, Snapshot};
 
 use async_trait::async_trait;
-use chrono::{offset::TimeZone, Utc};
-use snafu::{OptionExt, ResultExt, Snafu};
-use sqlparser::{
-    ast::{SetExpr, Statement, TableFactor},
-    dialect::GenericDialect,
-    parser::Parser,
-};
-use tokio::sync::RwLock;
-use tracing::info;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info, warn};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -91,8 +105,82 @@ pub enum Error {
     #[snafu(display("Database {} doesn't exist", database))]
     DatabaseNotFound { database: String },
 
-    #[snafu(display("Partition {} is full", partition))]
-    PartitionFull { partition: String },
+    #[snafu(display("Error computing partition key for database {}: {}", database, source))]
+    PartitionKeyError {
+        database: String,
+        source: data_types::database_rules::Error,
+    },
+
+    #[snafu(display("Error serializing rules for database {}: {}", database, source))]
+    SerializingRules {
+        database: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error deserializing rules for database {}: {}", database, source))]
+    DeserializingRules {
+        database: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error writing rules to '{:?}': {}", rules_path, source))]
+    WritingRules {
+        rules_path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error reading rules from '{:?}': {}", rules_path, source))]
+    ReadingRules {
+        rules_path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Error serializing snapshot sequences for database {}: {}",
+        database,
+        source
+    ))]
+    SerializingSnapshotSequences {
+        database: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Error deserializing snapshot sequences for database {}: {}",
+        database,
+        source
+    ))]
+    DeserializingSnapshotSequences {
+        database: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error writing snapshot sequences to '{:?}': {}", path, source))]
+    WritingSnapshotSequences {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error reading snapshot sequences from '{:?}': {}", path, source))]
+    ReadingSnapshotSequences {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Write buffer of database {} is full: {} bytes used of {}",
+        database,
+        size,
+        limit
+    ))]
+    BufferFull {
+        database: String,
+        size: usize,
+        limit: usize,
+    },
+
+    #[snafu(display("Server write buffer is full: {} bytes used of {}", used, limit))]
+    ServerBufferFull { used: usize, limit: usize },
 
     #[snafu(display("Error in {}: {}", source_module, source))]
     PassThrough {
@@ -122,17 +210,6 @@ pub enum Error {
         source: DictionaryError,
     },
 
-    #[snafu(display(
-        "Column name {} not found in dictionary of partition {}",
-        column_name,
-        partition
-    ))]
-    ColumnNameNotFoundInDictionary {
-        column_name: String,
-        partition: String,
-        source: DictionaryError,
-    },
-
     #[snafu(display(
         "Column ID {} not found in dictionary of partition {}",
         column_id,
@@ -161,6 +238,9 @@ pub enum Error {
     ))]
     UnsupportedColumnTypeForListingValues { column_name: String },
 
+    #[snafu(display("Table {} not found in database {}", table, database))]
+    TableNotFound { table: String, database: String },
+
     #[snafu(display("Table {} not found in partition {}", table, partition))]
     TableNotFoundInPartition { table: u32, partition: String },
 
@@ -176,22 +256,22 @@ pub enum Error {
     #[snafu(display("id conversion error"))]
     IdConversionError { source: std::num::TryFromIntError },
 
-    #[snafu(display("Invalid sql query: {} : {}", query, source))]
-    InvalidSqlQuery {
-        query: String,
-        source: sqlparser::parser::ParserError,
-    },
-
     #[snafu(display("error executing query {}: {}", query, source))]
     QueryError {
         query: String,
         source: DataFusionError,
     },
 
-    #[snafu(display("Unsupported SQL statement in query {}: {}", query, statement))]
-    UnsupportedStatement {
+    #[snafu(display("error registering tables for query {}: {}", query, source))]
+    RegisteringTables {
         query: String,
-        statement: Box<Statement>,
+        source: crate::provider::Error,
+    },
+
+    #[snafu(display("error reading table {} to aggregate: {}", table, source))]
+    ReadingAggregateTable {
+        table: String,
+        source: crate::provider::Error,
     },
 
     #[snafu(display("query error {} on query {}", message, query))]
@@ -199,6 +279,88 @@ pub enum Error {
 
     #[snafu(display("replicated write from writer {} missing payload", writer))]
     MissingPayload { writer: u32 },
+
+    #[snafu(display(
+        "replicated write {} from writer {} has checksum {} but its payload has checksum {}",
+        sequence,
+        writer,
+        expected,
+        actual
+    ))]
+    ChecksumMismatch {
+        writer: u32,
+        sequence: u64,
+        expected: u32,
+        actual: u32,
+    },
+
+    #[snafu(display(
+        "No partition with key {} to snapshot in database {}",
+        partition_key,
+        database
+    ))]
+    SnapshotPartitionNotFound {
+        database: String,
+        partition_key: String,
+    },
+
+    #[snafu(display("Error snapshotting partition of database {}: {}", database, source))]
+    SnapshottingPartition {
+        database: String,
+        source: crate::snapshot::Error,
+    },
+
+    #[snafu(display("Error truncating WAL for database {}: {}", database, source))]
+    TruncatingWal {
+        database: String,
+        source: WalWriterError,
+    },
+
+    #[snafu(display(
+        "Write to database {} with timestamp {} is outside the retention period",
+        database,
+        timestamp
+    ))]
+    WriteOutsideRetention { database: String, timestamp: i64 },
+
+    #[snafu(display("Error listing snapshots of database {}: {}", database, source))]
+    ListingSnapshots {
+        database: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error loading snapshot metadata: {}", source))]
+    LoadingSnapshotMetadata { source: crate::read_only::Error },
+
+    #[snafu(display("Error deleting {} from object storage: {}", location, source))]
+    DeletingSnapshot {
+        location: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Invalid delete for database {}: {}", database, source))]
+    InvalidDelete {
+        database: String,
+        source: data_types::delete::Error,
+    },
+
+    #[snafu(display(
+        "Error recording delete in partition {} of database {}: {}",
+        partition_id,
+        database,
+        source
+    ))]
+    PersistingDelete {
+        database: String,
+        partition_id: String,
+        source: crate::snapshot::Error,
+    },
+}
+
+impl DatabaseError for Error {
+    fn is_resource_exhausted(&self) -> bool {
+        matches!(self, Self::BufferFull { .. } | Self::ServerBufferFull { .. })
+    }
 }
 
 impl From<crate::table::Error> for Error {
@@ -221,26 +383,122 @@ impl From<crate::partition::Error> for Error {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Name of the file, stored in the WAL directory, that holds the
+/// serialized `DatabaseRules` of a database
+const RULES_FILE_NAME: &str = "rules.json";
+
+/// Name of the file, stored in the WAL directory, that holds the sequence
+/// number of the last WAL entry snapshotted for each partition key
+const SNAPSHOT_SEQUENCES_FILE_NAME: &str = "snapshots.json";
+
+/// Time format used to partition data when a database is created
+/// without explicit rules: one partition per hour
+const DEFAULT_PARTITION_TIME_FORMAT: &str = "%Y-%m-%dT%H";
+
+/// Returns the bytes of `partition` counted against the memory limits of the
+/// write buffer. Read-only partitions are backed by object storage, so they
+/// aren't counted.
+fn buffered_size(partition: &Partition) -> usize {
+    match partition.read_only_id {
+        Some(_) => 0,
+        None => partition.size(),
+    }
+}
+
+/// Returns the name of the database whose WAL is in `wal_dir`
+pub(crate) fn wal_dir_database_name(wal_dir: &Path) -> Result<String> {
+    Ok(wal_dir
+        .iter()
+        .last()
+        .with_context(|| OpenDb { dir: wal_dir })?
+        .to_str()
+        .with_context(|| OpenDb { dir: wal_dir })?
+        .to_string())
+}
+
+/// Returns the rules used for databases created without explicit rules
+fn default_rules() -> DatabaseRules {
+    DatabaseRules {
+        partition_template: PartitionTemplate::new(vec![TemplatePart::TimeFormat(
+            DEFAULT_PARTITION_TIME_FORMAT.to_string(),
+        )]),
+        ..Default::default()
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Db {
     pub name: String,
-    // TODO: partitions need to be wrapped in an Arc if they're going to be used without this lock
-    partitions: RwLock<Vec<Partition>>,
+    /// Rules for this database. The partition template is used to
+    /// compute the partition key of every line written
+    rules: RwLock<DatabaseRules>,
+    /// Shared with the `TableProvider`s of queries, which read the
+    /// partitions when they are executed
+    partitions: Arc<RwLock<Vec<Partition>>>,
+    /// The replicated writes applied from each writer, used to ignore
+    /// duplicates. The lock is held while a replicated write is applied.
+    applied_writes: RwLock<AppliedWrites>,
     wal_details: Option<WalDetails>,
+    /// A lower bound for the sequence number of the next WAL entry, which
+    /// is recorded in the partitions an entry is written to. With no writes
+    /// in progress, it's one after the sequence number of the last entry.
+    next_wal_sequence: AtomicU64,
+    /// Held for reading by writes from when they are applied to the partitions
+    /// until they are in the WAL, so that a snapshot can wait for the writes to
+    /// the partition it closes to get their sequence numbers
+    wal_writes: RwLock<()>,
+    /// The sequence number of the last WAL entry snapshotted for each partition
+    /// key, persisted alongside the WAL. The writes to a partition key in
+    /// entries up to it aren't replayed, as their data is in object storage.
+    snapshot_sequences: Mutex<BTreeMap<String, SequenceNumber>>,
+    /// The estimated bytes taken up by the partitions, which are counted
+    /// along with those of the other databases of the server it belongs to
+    memory: MemoryUsage,
+    /// The lowest generation the next snapshot of a partition can have. The
+    /// lock is held while a snapshot id is picked.
+    next_snapshot_generation: Mutex<u64>,
+    /// The object store the partitions of this database are persisted to, in
+    /// which deletes are recorded, and the writer id it snapshots them as
+    object_store: Option<Arc<ObjectStore>>,
+    writer: Option<u32>,
+    /// Held while a partition is snapshotted, while snapshots are deleted and
+    /// while a delete is applied, so that a delete applies either to the data
+    /// of a partition before it's snapshotted or to its snapshot
+    persistence: Mutex<()>,
 }
 
 impl Db {
-    /// New creates a new in-memory only write buffer database
+    /// New creates a new in-memory only write buffer database that
+    /// partitions data using `default_rules`
     pub fn new(name: impl Into<String>) -> Self {
+        Self::new_with_rules(name, default_rules())
+    }
+
+    /// Creates a new in-memory only write buffer database with the
+    /// specified rules
+    pub fn new_with_rules(name: impl Into<String>, rules: DatabaseRules) -> Self {
         Self {
             name: name.into(),
+            rules: RwLock::new(rules),
             ..Default::default()
         }
     }
 
     /// Create a new DB that will create and use the Write Ahead Log
-    /// (WAL) directory `wal_dir`
+    /// (WAL) directory `wal_dir`, partitioning data using `default_rules`
     pub async fn try_with_wal(name: impl Into<String>, wal_dir: &mut PathBuf) -> Result<Self> {
+        Self::try_with_rules_and_wal(name, default_rules(), wal_dir).await
+    }
+
+    /// Create a new DB with the specified rules that will create and
+    /// use the Write Ahead Log (WAL) directory `wal_dir`. The rules
+    /// are persisted in the WAL directory so they are used again when
+    /// the database is restored.
+    pub async fn try_with_rules_and_wal(
+        name: impl Into<String>,
+        rules: DatabaseRules,
+        wal_dir: &mut PathBuf,
+    ) -> Result<Self> {
         let name = name.into();
         wal_dir.push(&name);
         if let Err(e) = std::fs::create_dir(wal_dir.clone()) {
@@ -263,1233 +521,3163 @@ impl Db {
             .write_metadata()
             .await
             .context(OpeningWal { database: &name })?;
+        write_rules(&name, &rules, wal_dir).await?;
 
         Ok(Self {
             name,
+            rules: RwLock::new(rules),
             wal_details: Some(wal_details),
             ..Default::default()
         })
     }
 
     /// Create a new DB and initially restore pre-existing data in the
-    /// Write Ahead Log (WAL) directory `wal_dir`
+    /// Write Ahead Log (WAL) directory `wal_dir`. If no rules were
+    /// persisted in `wal_dir`, `default_rules` are used.
     pub async fn restore_from_wal(wal_dir: PathBuf) -> Result<Self> {
+        Self::restore_from_wal_with_progress(wal_dir, Default::default()).await
+    }
+
+    /// Like `restore_from_wal`, counting the WAL entries replayed in
+    /// `wal_entries` as it goes
+    pub async fn restore_from_wal_with_progress(
+        wal_dir: PathBuf,
+        wal_entries: Arc<AtomicUsize>,
+    ) -> Result<Self> {
         let now = std::time::Instant::now();
-        let name = wal_dir
-            .iter()
-            .last()
-            .with_context(|| OpenDb { dir: &wal_dir })?
-            .to_str()
-            .with_context(|| OpenDb { dir: &wal_dir })?
-            .to_string();
+        let name = wal_dir_database_name(&wal_dir)?;
+
+        let rules = read_rules(&name, &wal_dir).await?;
+        let snapshot_sequences = read_snapshot_sequences(&name, &wal_dir).await?;
 
         let wal_builder = WalBuilder::new(wal_dir.clone());
         let wal_details = start_wal_sync_task(wal_builder.clone())
             .await
             .context(OpeningWal { database: &name })?;
 
-        // TODO: check wal metadata format
-        let entries = wal_builder
-            .entries()
-            .context(LoadingWal { database: &name })?;
-
-        let (partitions, stats) =
-            restore_partitions_from_wal(entries).context(WalRecoverError { database: &name })?;
+        // replaying the WAL is CPU bound, so it's kept off the async runtime's threads
+        let (partitions, stats) = {
+            let name = name.clone();
+            let snapshot_sequences = snapshot_sequences.clone();
+            tokio::task::spawn_blocking(move || {
+                // TODO: check wal metadata format
+                let entries = wal_builder
+                    .entries()
+                    .context(LoadingWal { database: &name })?
+                    .inspect(|_| {
+                        wal_entries.fetch_add(1, Ordering::SeqCst);
+                    });
+
+                restore_partitions_from_wal(entries, &snapshot_sequences)
+                    .context(WalRecoverError { database: &name })
+            })
+            .await
+            .expect("WAL restore task panicked")?
+        };
 
-        let elapsed = now.elapsed();
         info!(
-            "{} database loaded {} rows in {:?} in {} tables",
+            "{} database loaded {} rows in {} tables and {} partitions from {} WAL entries in {:?} ({:?} replaying the WAL)",
             &name,
             stats.row_count,
-            elapsed,
             stats.tables.len(),
+            partitions.len(),
+            stats.wal_entries,
+            now.elapsed(),
+            stats.elapsed,
         );
 
-        info!("{} database partition count: {}", &name, partitions.len(),);
+        // the sequences before the first entry left in the WAL are no longer needed
+        let needed: BTreeMap<_, _> = snapshot_sequences
+            .iter()
+            .filter(|(_, sequence)| {
+                stats
+                    .first_wal_sequence
+                    .map_or(false, |first| **sequence >= first)
+            })
+            .map(|(key, sequence)| (key.clone(), *sequence))
+            .collect();
+        if needed.len() < snapshot_sequences.len() {
+            write_snapshot_sequences(&name, &needed, &wal_dir).await?;
+        }
+
+        let memory = MemoryUsage::default();
+        memory.resize(0, partitions.iter().map(|p| p.size()).sum());
 
         Ok(Self {
             name,
-            partitions: RwLock::new(partitions),
+            rules: RwLock::new(rules),
+            partitions: Arc::new(RwLock::new(partitions)),
+            applied_writes: RwLock::new(stats.applied_writes),
             wal_details: Some(wal_details),
+            next_wal_sequence: AtomicU64::new(stats.last_wal_sequence.map_or(0, |s| s + 1)),
+            memory,
+            snapshot_sequences: Mutex::new(needed),
+            ..Default::default()
         })
     }
 
-    async fn write_entries_to_partitions(&self, batch: &wb::WriteBufferBatch<'_>) -> Result<()> {
-        if let Some(entries) = batch.entries() {
-            let mut partitions = self.partitions.write().await;
+    /// Tracks the memory used by this database with `memory`, which is
+    /// usually shared with the other databases of the server
+    pub fn with_memory_tracker(mut self, memory: Arc<MemoryTracker>) -> Self {
+        self.memory.set_tracker(memory);
+        self
+    }
 
-            for entry in entries {
-                let key = entry
-                    .partition_key()
-                    .expect("partition key should have been inserted");
+    /// Records the deletes made to this database in the metadata of its
+    /// read-only partitions in `store`, and of the partitions it snapshotted
+    /// there as `writer`, so the rows deleted stay deleted when they are loaded
+    pub fn with_object_store(mut self, store: Arc<ObjectStore>, writer: Option<u32>) -> Self {
+        self.object_store = Some(store);
+        self.writer = writer;
+        self
+    }
 
-                match partitions.iter_mut().find(|p| p.should_write(key)) {
-                    Some(p) => p.write_entry(&entry)?,
-                    None => {
-                        let mut p = Partition::new(key);
-                        p.write_entry(&entry)?;
-                        partitions.push(p)
-                    }
+    /// Returns an estimate of the memory, in bytes, taken up by the data
+    /// written to this database. Read-only partitions, whose data is
+    /// persisted in object storage, aren't counted.
+    pub fn size(&self) -> usize {
+        self.memory.used()
+    }
+
+    /// Returns an error if the write buffer of this database, or of the
+    /// server, is over its memory limit
+    fn check_memory(&self, rules: &DatabaseRules) -> Result<()> {
+        if let Some(limit) = rules.lifecycle_rules.buffer_size_hard {
+            let size = self.size();
+            ensure!(
+                size <= limit,
+                BufferFull {
+                    database: &self.name,
+                    size,
+                    limit,
                 }
-            }
+            );
+        }
+
+        if let Some(limit) = self.memory.tracker().limit() {
+            let used = self.memory.tracker().used();
+            ensure!(used <= limit, ServerBufferFull { used, limit });
         }
 
         Ok(())
     }
-}
 
-#[async_trait]
-impl Database for Db {
-    type Error = Error;
+    /// Returns a copy of the current rules of this database
+    pub async fn rules(&self) -> DatabaseRules {
+        self.rules.read().await.clone()
+    }
 
-    // TODO: writes lines creates a column named "time" for the timestmap data. If
-    //       we keep this we need to validate that no tag or field has the same name.
-    async fn write_lines(&self, lines: &[ParsedLine<'_>]) -> Result<(), Self::Error> {
-        let data = split_lines_into_write_entry_partitions(partition_key, lines);
-        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+    /// Replaces the rules of this database, persisting them alongside
+    /// the WAL if there is one. Only subsequent writes use the new
+    /// partition template; data already written stays in the
+    /// partitions it was originally assigned to.
+    pub async fn set_rules(&self, rules: DatabaseRules) -> Result<()> {
+        let mut current_rules = self.rules.write().await;
+        self.persist_rules(&rules).await?;
+        *current_rules = rules;
 
-        self.write_entries_to_partitions(&batch).await?;
+        Ok(())
+    }
 
+    /// Writes `rules` alongside the WAL, if there is one
+    async fn persist_rules(&self, rules: &DatabaseRules) -> Result<()> {
         if let Some(wal) = &self.wal_details {
-            wal.write_and_sync(data).await.context(WritingWal {
-                database: &self.name,
-            })?;
+            if let Some(wal_dir) = wal.metadata_path.parent() {
+                write_rules(&self.name, rules, wal_dir).await?;
+            }
         }
 
         Ok(())
     }
 
-    async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
-        match write.write_buffer_batch() {
-            Some(b) => self.write_entries_to_partitions(&b).await?,
-            None => {
-                return MissingPayload {
-                    writer: write.to_fb().writer(),
-                }
-                .fail()
+    /// Loads the `read_only_partitions` of the rules of this database from
+    /// object storage, replacing any loaded before, so their data is
+    /// queried along with the data written to this database. Their memory
+    /// isn't counted against the limits of the write buffer. Partitions that
+    /// can't be loaded, such as ones deleted once their data expired, are
+    /// skipped with a warning. Returns the number of partitions loaded.
+    pub async fn load_read_only_partitions(&self, store: &ObjectStore) -> Result<usize> {
+        let partition_ids = self.rules.read().await.read_only_partitions.clone();
+
+        let mut loaded = Vec::with_capacity(partition_ids.len());
+        for partition_id in &partition_ids {
+            match load_partition(store, partition_id).await {
+                Ok(partition) => loaded.push(partition),
+                Err(e) => warn!(
+                    "{} skipping read-only partition {}: {}",
+                    self.name, partition_id, e
+                ),
             }
+        }
+        let loaded_count = loaded.len();
+
+        let mut partitions = self.partitions.write().await;
+        partitions.retain(|p| p.read_only_id.is_none());
+        partitions.extend(loaded);
+
+        Ok(loaded_count)
+    }
+
+    /// Returns the sequence of the newest replicated write applied from
+    /// `writer`, if any
+    pub async fn high_water_mark(&self, writer: u32) -> Option<u64> {
+        self.applied_writes.read().await.high_water_mark(writer)
+    }
+
+    /// Closes the open partition with `partition_key` and writes a snapshot of it to
+    /// `store`, under `<writer>/<database name>/<partition key>/<generation>`, returning
+    /// the id and metadata of the snapshot. Once the snapshot is written, the partition
+    /// is dropped from memory, leaving its data in object storage only, and the WAL is
+    /// truncated, dropping the files only needed to restore it. The WAL entries left
+    /// that wrote to the partition aren't replayed when the database is restored.
+    pub async fn snapshot_partition(
+        &self,
+        store: &ObjectStore,
+        writer: u32,
+        partition_key: &str,
+    ) -> Result<(PartitionId, partition_metadata::Partition)> {
+        let _persistence = self.persistence.lock().await;
+        let partition_id = self.new_snapshot_id(store, writer, partition_key).await?;
+
+        // writes to the partition key go to a new partition once this one is closed, and
+        // the writes to this one are all in the WAL once those in progress are done
+        let (snapshot, wal_sequence) = {
+            let _wal_writes = self.wal_writes.write().await;
+            let mut partitions = self.partitions.write().await;
+            let partition = partitions
+                .iter_mut()
+                .find(|p| p.key == partition_key && p.is_open)
+                .context(SnapshotPartitionNotFound {
+                    database: &self.name,
+                    partition_key,
+                })?;
+            partition.is_open = false;
+            partition.snapshot_id = Some(partition_id.clone());
+
+            let snapshot = Snapshot::new(partition).context(SnapshottingPartition {
+                database: &self.name,
+            })?;
+            let wal_sequence = self
+                .wal_details
+                .as_ref()
+                .and_then(|_| self.next_wal_sequence.load(Ordering::SeqCst).checked_sub(1));
+            (snapshot, wal_sequence)
         };
 
-        if let Some(wal) = &self.wal_details {
-            // TODO(paul): refactor this so we're not cloning. Although replicated writes shouldn't
-            //  be using a WAL and how the WAL is used at all is likely to have a larger refactor soon.
-            wal.write_and_sync(write.data.clone())
+        let metadata =
+            snapshot
+                .write(store, &partition_id)
                 .await
-                .context(WritingWal {
+                .context(SnapshottingPartition {
                     database: &self.name,
                 })?;
+
+        if let Some(wal_sequence) = wal_sequence {
+            self.record_snapshot_sequence(partition_key, wal_sequence)
+                .await?;
         }
 
-        Ok(())
-    }
+        {
+            let mut partitions = self.partitions.write().await;
+            let mut dropped_size = 0;
+            partitions.retain(|p| {
+                let snapshotted = p.snapshot_id.as_ref() == Some(&partition_id);
+                if snapshotted {
+                    dropped_size += p.size();
+                }
+                !snapshotted
+            });
+            self.memory.resize(dropped_size, 0);
+        }
 
-    async fn table_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
-        // TODO: Cache this information to avoid creating this each time
-        let partitions = self.partitions.read().await;
+        self.truncate_wal().await?;
 
-        let mut table_names: BTreeSet<String> = BTreeSet::new();
-        for partition in partitions.iter() {
-            let partition_predicate = partition.compile_predicate(&predicate)?;
-            // this doesn't seem to make any sense
-            assert!(
-                partition_predicate.field_restriction.is_none(),
-                "Column selection for table names not supported"
-            );
+        Ok((partition_id, metadata))
+    }
 
-            // It might make sense to ask for all table names that
-            // have rows that pass a general purpose predicate. I am
-            // not sure if it is needed now, so panic
-            assert!(
-                partition_predicate.partition_exprs.is_empty(),
-                "General partition exprs on table name list are not supported"
-            );
+    /// Records that the writes to `partition_key` in the WAL entries up to
+    /// `wal_sequence` are snapshotted, persisting it alongside the WAL
+    async fn record_snapshot_sequence(
+        &self,
+        partition_key: &str,
+        wal_sequence: SequenceNumber,
+    ) -> Result<()> {
+        let wal_dir = match self
+            .wal_details
+            .as_ref()
+            .and_then(|wal| wal.metadata_path.parent())
+        {
+            Some(wal_dir) => wal_dir,
+            None => return Ok(()),
+        };
 
-            for (table_name_symbol, table) in &partition.tables {
-                if table.could_match_predicate(&partition_predicate)? {
-                    let table_name = partition.dictionary.lookup_id(*table_name_symbol).unwrap();
+        let mut sequences = self.snapshot_sequences.lock().await;
+        let sequence = sequences
+            .entry(partition_key.to_string())
+            .or_insert(wal_sequence);
+        *sequence = wal_sequence.max(*sequence);
 
-                    if !table_names.contains(table_name) {
-                        table_names.insert(table_name.to_string());
-                    }
-                }
-            }
-        }
-        Ok(table_names.into())
+        write_snapshot_sequences(&self.name, &sequences, wal_dir).await
     }
 
-    // return all column names in this database, while applying optional predicates
-    async fn tag_column_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
-        let has_exprs = predicate.has_exprs();
-        let mut filter = PartitionTableFilter::new(predicate);
+    /// Returns the id of a new snapshot of the partition with `partition_key`. A
+    /// partition key is closed and snapshotted again when more data is written to
+    /// it, so each snapshot gets the generation after the highest one in `store`
+    /// or given out before by this database.
+    async fn new_snapshot_id(
+        &self,
+        store: &ObjectStore,
+        writer: u32,
+        partition_key: &str,
+    ) -> Result<PartitionId> {
+        let mut next_generation = self.next_snapshot_generation.lock().await;
+
+        let prefix = format!("{}/", self.partition_id(writer, partition_key));
+        let locations: Vec<String> = store
+            .list(Some(prefix.as_str()))
+            .await
+            .context(ListingSnapshots {
+                database: &self.name,
+            })?
+            .try_concat()
+            .await
+            .context(ListingSnapshots {
+                database: &self.name,
+            })?;
 
-        if has_exprs {
-            let mut visitor = NamePredVisitor::new();
-            self.visit_tables(&mut filter, &mut visitor).await?;
-            Ok(visitor.plans.into())
-        } else {
-            let mut visitor = NameVisitor::new();
-            self.visit_tables(&mut filter, &mut visitor).await?;
-            Ok(visitor.column_names.into())
-        }
-    }
+        let stored = locations
+            .iter()
+            .filter_map(|location| location.get(prefix.len()..)?.split('/').next())
+            .filter_map(|generation| generation.parse::<u64>().ok())
+            .max();
+        let generation = match stored {
+            Some(stored) => (stored + 1).max(*next_generation),
+            None => *next_generation,
+        };
+        *next_generation = generation + 1;
 
-    /// return all field names in this database, while applying optional predicates
-    async fn field_columns(&self, predicate: Predicate) -> Result<FieldListPlan, Self::Error> {
-        let mut filter = PartitionTableFilter::new(predicate);
-        let mut visitor = TableFieldPredVisitor::new();
-        self.visit_tables(&mut filter, &mut visitor).await?;
-        Ok(visitor.into_fieldlist_plan())
+        Ok(format!("{}{}", prefix, generation))
     }
 
-    /// return all column values in this database, while applying optional predicates
-    async fn column_values(
+    /// Closes the partitions selected by the lifecycle rules of this database as of
+    /// `now` and snapshots them to `store`, which frees the memory they took up.
+    /// Returns the keys of the partitions closed.
+    pub async fn apply_lifecycle_rules(
         &self,
-        column_name: &str,
-        predicate: Predicate,
-    ) -> Result<StringSetPlan, Self::Error> {
-        let has_exprs = predicate.has_exprs();
-        let mut filter = PartitionTableFilter::new(predicate);
-
-        if has_exprs {
-            let mut visitor = ValuePredVisitor::new(column_name);
-            self.visit_tables(&mut filter, &mut visitor).await?;
-            Ok(visitor.plans.into())
-        } else {
-            let mut visitor = ValueVisitor::new(column_name);
-            self.visit_tables(&mut filter, &mut visitor).await?;
-            Ok(visitor.column_values.into())
+        store: &ObjectStore,
+        writer: u32,
+        now: Instant,
+    ) -> Result<Vec<String>> {
+        let rules = self.rules.read().await.lifecycle_rules;
+        let keys = partitions_to_close(&rules, &self.partitions.read().await, now);
+
+        for key in &keys {
+            self.snapshot_partition(store, writer, key).await?;
         }
-    }
 
-    async fn query_series(&self, predicate: Predicate) -> Result<SeriesSetPlans, Self::Error> {
-        let mut filter = PartitionTableFilter::new(predicate);
-        let mut visitor = SeriesVisitor::new();
-        self.visit_tables(&mut filter, &mut visitor).await?;
-        Ok(visitor.plans.into())
+        Ok(keys)
     }
 
-    async fn query_groups(
-        &self,
-        predicate: Predicate,
-        group_columns: Vec<String>,
-    ) -> Result<GroupedSeriesSetPlans, Self::Error> {
-        let mut filter = PartitionTableFilter::new(predicate)
-            // Add any specified groups as predicate columns (so we can skip tables without those tags)
-            .add_required_columns(&group_columns);
+    /// Drops the partitions holding only data older than the retention period of this
+    /// database as of `now`, and truncates the WAL. Returns the keys of the partitions
+    /// dropped.
+    pub async fn drop_expired_partitions(&self, now: &DateTime<Utc>) -> Result<Vec<String>> {
+        let cutoff = match self.rules.read().await.retention_cutoff(now) {
+            Some(cutoff) => cutoff,
+            None => return Ok(vec![]),
+        };
 
-        let mut visitor = GroupsVisitor::new(group_columns);
-        self.visit_tables(&mut filter, &mut visitor).await?;
-        Ok(visitor.plans.into())
+        let mut dropped = vec![];
+        {
+            let mut partitions = self.partitions.write().await;
+            let mut dropped_size = 0;
+            partitions.retain(|p| {
+                let expired = p.max_time().map_or(false, |time| time < cutoff);
+                if expired {
+                    dropped.push(p.key.clone());
+                    dropped_size += buffered_size(p);
+                }
+                !expired
+            });
+            self.memory.resize(dropped_size, 0);
+        }
+
+        if !dropped.is_empty() {
+            self.truncate_wal().await?;
+        }
+
+        Ok(dropped)
     }
 
-    async fn table_to_arrow(
+    /// Deletes the partitions of this database snapshotted to `store` as `writer` that
+    /// hold only data older than the retention period as of `now`, and removes them
+    /// from the `read_only_partitions` of the rules. Returns the ids of the partitions
+    /// deleted.
+    pub async fn delete_expired_snapshots(
         &self,
-        table_name: &str,
-        columns: &[&str],
-    ) -> Result<Vec<RecordBatch>, Self::Error> {
-        let partitions = self.partitions.read().await;
+        store: &ObjectStore,
+        writer: u32,
+        now: &DateTime<Utc>,
+    ) -> Result<Vec<PartitionId>> {
+        let cutoff = match self.rules.read().await.retention_cutoff(now) {
+            Some(cutoff) => cutoff,
+            None => return Ok(vec![]),
+        };
 
-        let batches = partitions
-            .iter()
-            .map(|p| p.table_to_arrow(table_name, columns))
-            .collect::<Result<Vec<_>, crate::partition::Error>>()?;
+        let _persistence = self.persistence.lock().await;
+        let partition_ids = self.snapshot_ids(store, writer).await?;
 
-        Ok(batches)
-    }
+        let mut deleted = vec![];
+        for partition_id in &partition_ids {
+            let metadata = load_metadata(store, partition_id)
+                .await
+                .context(LoadingSnapshotMetadata)?;
+            if !metadata.max_time().map_or(false, |time| time < cutoff) {
+                continue;
+            }
 
-    async fn query(&self, query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
-        let mut tables = vec![];
-
-        let dialect = GenericDialect {};
-        let ast = Parser::parse_sql(&dialect, query).context(InvalidSqlQuery { query })?;
-
-        for statement in ast {
-            match statement {
-                Statement::Query(q) => {
-                    if let SetExpr::Select(q) = q.body {
-                        for item in q.from {
-                            if let TableFactor::Table { name, .. } = item.relation {
-                                let name = name.to_string();
-                                let data = self.table_to_arrow(&name, &[]).await?;
-                                tables.push(ArrowTable {
-                                    name,
-                                    schema: data[0].schema().clone(),
-                                    data,
-                                });
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    return UnsupportedStatement {
-                        query: query.to_string(),
-                        statement,
-                    }
-                    .fail()
-                }
+            // the metadata goes first, so a partition partly deleted isn't loaded
+            let files = std::iter::once(METADATA_FILE_NAME.to_string())
+                .chain(metadata.tables.iter().map(|t| table_file_name(&t.name)));
+            for file in files {
+                let location = partition_file_location(partition_id, &file);
+                store
+                    .delete(&location)
+                    .await
+                    .context(DeletingSnapshot { location })?;
             }
+
+            deleted.push(partition_id.to_string());
         }
 
-        let config = ExecutionConfig::new().with_batch_size(1024 * 1024);
-        let mut ctx = ExecutionContext::with_config(config);
+        // were the rules not updated, loading them would fail on the partitions deleted
+        if !deleted.is_empty() {
+            let mut rules = self.rules.write().await;
+            if rules
+                .read_only_partitions
+                .iter()
+                .any(|id| deleted.contains(id))
+            {
+                let mut updated = rules.clone();
+                updated
+                    .read_only_partitions
+                    .retain(|id| !deleted.contains(id));
+                self.persist_rules(&updated).await?;
+                *rules = updated;
+            }
 
-        for table in tables {
-            let provider =
-                MemTable::new(table.schema, vec![table.data]).context(QueryError { query })?;
-            ctx.register_table(&table.name, Box::new(provider));
+            self.partitions.write().await.retain(|p| {
+                p.read_only_id
+                    .as_ref()
+                    .map_or(true, |id| !deleted.contains(id))
+            });
         }
 
-        let plan = ctx
-            .create_logical_plan(&query)
-            .context(QueryError { query })?;
-        let plan = ctx.optimize(&plan).context(QueryError { query })?;
-        let plan = ctx
-            .create_physical_plan(&plan)
-            .context(QueryError { query })?;
-
-        ctx.collect(plan).await.context(QueryError { query })
+        Ok(deleted)
     }
-}
 
-/// This trait is used to implement a "Visitor" pattern for Database
-/// which can be used to define logic that shares a common Depth First
-/// Search (DFS) traversal of the Database --> Partition --> Table -->
-/// Column datastructure heirarchy.
-///
-/// Specifically, if we had a database like the following:
-///
-/// YesterdayPartition
-///   CPU Table1
-///    Col1
-/// TodayPartition
-///   CPU Table2
-///    Col2
-///
-/// Then the methods would be invoked in the following order
-///
-///  visitor.pre_visit_partition(YesterdayPartition)
-///  visitor.pre_visit_table(CPU Table1)
-///  visitor.visit_column(Col1)
-///  visitor.post_visit_table(CPU Table1)
-///  visitor.post_visit_partition(YesterdayPartition)
-///  visitor.pre_visit_partition(TodayPartition)
-///  visitor.pre_visit_table(CPU Table2)
-///  visitor.visit_column(Col2)
-///  visitor.post_visit_table(CPU Table2)
-///  visitor.post_visit_partition(TodayPartition)
-trait Visitor {
-    // called once before any column in a partition is visisted
-    fn pre_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
-        Ok(())
-    }
+    /// Returns the ids of the partitions of this database snapshotted to `store` as
+    /// `writer`
+    async fn snapshot_ids(&self, store: &ObjectStore, writer: u32) -> Result<Vec<PartitionId>> {
+        let prefix = self.partition_id(writer, "");
+        let locations: Vec<String> = store
+            .list(Some(prefix.as_str()))
+            .await
+            .context(ListingSnapshots {
+                database: &self.name,
+            })?
+            .try_concat()
+            .await
+            .context(ListingSnapshots {
+                database: &self.name,
+            })?;
 
-    // called once before any column in a Table is visited
-    fn pre_visit_table(
-        &mut self,
-        _table: &Table,
-        _partition: &Partition,
-        _filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        Ok(())
+        let metadata_suffix = format!("/{}", METADATA_FILE_NAME);
+        Ok(locations
+            .iter()
+            .filter(|location| location.ends_with(&metadata_suffix))
+            .map(|location| location[..location.len() - metadata_suffix.len()].to_string())
+            .collect())
     }
 
-    // called every time a column is visited
-    fn visit_column(
-        &mut self,
-        _table: &Table,
-        _column_id: u32,
-        _column: &Column,
-        _filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        Ok(())
-    }
+    /// Records `delete` in the metadata of the partitions of this database persisted
+    /// to its object store that have data it could remove, so it's applied again
+    /// whenever they are loaded. Partitions that can't be read, such as read-only
+    /// partitions since deleted, are skipped with a warning.
+    async fn persist_delete(&self, delete: &Delete) -> Result<()> {
+        let store = match &self.object_store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
 
-    // called once after all columns in a Table are visited
-    fn post_visit_table(&mut self, _table: &Table, _partition: &Partition) -> Result<()> {
-        Ok(())
-    }
+        let mut partition_ids: BTreeSet<PartitionId> = self
+            .rules
+            .read()
+            .await
+            .read_only_partitions
+            .iter()
+            .cloned()
+            .collect();
+        if let Some(writer) = self.writer {
+            partition_ids.extend(self.snapshot_ids(store, writer).await?);
+        }
+
+        for partition_id in &partition_ids {
+            let mut metadata = match load_metadata(store, partition_id).await {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    warn!(
+                        "{} not recording delete in partition {}: {}",
+                        self.name, partition_id, e
+                    );
+                    continue;
+                }
+            };
+
+            let has_data = match &delete.table_name {
+                Some(table_name) => metadata.tables.iter().any(|t| &t.name == table_name),
+                None => !metadata.tables.is_empty(),
+            };
+            if has_data {
+                metadata.deletes.push(delete.clone());
+                write_metadata(store, partition_id, &metadata)
+                    .await
+                    .context(PersistingDelete {
+                        database: &self.name,
+                        partition_id,
+                    })?;
+            }
+        }
 
-    // called once after all columns in a partition is visited
-    fn post_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
         Ok(())
     }
-}
 
-impl Db {
-    /// returns the number of partitions in this database
-    pub async fn len(&self) -> usize {
-        self.partitions.read().await.len()
+    fn partition_id(&self, writer: u32, partition_key: &str) -> PartitionId {
+        format!("{}/{}/{}", writer, self.name, partition_key)
     }
 
-    /// returns true if the database has no partititons
-    pub async fn is_empty(&self) -> bool {
-        self.partitions.read().await.is_empty()
-    }
+    /// Deletes the WAL files holding only entries of partitions that have been
+    /// snapshotted. The WAL keeps every entry from the oldest one that a partition
+    /// which hasn't been snapshotted could need, so those can be restored.
+    pub async fn truncate_wal(&self) -> Result<()> {
+        let wal = match &self.wal_details {
+            Some(wal) => wal,
+            None => return Ok(()),
+        };
 
-    /// Traverse this database's tables, calling the relevant
-    /// functions, in order, of `visitor`, as described on the Visitor
-    /// trait.
-    ///
-    /// Skips visiting any table or columns of `filter.should_visit_table` returns false
-    async fn visit_tables<V: Visitor>(
-        &self,
-        filter: &mut PartitionTableFilter,
-        visitor: &mut V,
-    ) -> Result<()> {
-        let partitions = self.partitions.read().await;
+        // partitions are dropped once they've been snapshotted
+        let oldest_needed = self
+            .partitions
+            .read()
+            .await
+            .iter()
+            .filter_map(|p| p.oldest_wal_sequence)
+            .min();
 
-        for partition in partitions.iter() {
-            visitor.pre_visit_partition(partition)?;
-            filter.pre_visit_partition(partition)?;
+        // the file being written to is always kept
+        wal.delete_up_to(oldest_needed.unwrap_or(SequenceNumber::MAX))
+            .await
+            .context(TruncatingWal {
+                database: &self.name,
+            })
+    }
 
-            for table in partition.tables.values() {
-                if filter.should_visit_table(table)? {
-                    visitor.pre_visit_table(table, partition, filter)?;
+    async fn write_entries_to_partitions(&self, batch: &wb::WriteBufferBatch<'_>) -> Result<()> {
+        if let Some(entries) = batch.entries() {
+            let mut partitions = self.partitions.write().await;
 
-                    for (column_id, column_index) in &table.column_id_to_index {
-                        visitor.visit_column(
-                            table,
-                            *column_id,
-                            &table.columns[*column_index],
-                            filter,
-                        )?
+            // the entry is written to the WAL after this, so it gets this sequence or later
+            let wal_sequence = self
+                .wal_details
+                .as_ref()
+                .map(|_| self.next_wal_sequence.load(Ordering::SeqCst));
+
+            for entry in entries {
+                if let Some(delete) = entry.delete() {
+                    let delete = Delete::from_fb(&delete).context(InvalidDelete {
+                        database: &self.name,
+                    })?;
+                    let mut deleted = 0;
+                    for p in partitions.iter_mut() {
+                        let size = buffered_size(p);
+                        deleted += p.delete(&delete);
+                        self.memory.resize(size, buffered_size(p));
                     }
+                    debug!("{} deleted {} rows", self.name, deleted);
+                    continue;
+                }
 
-                    visitor.post_visit_table(table, partition)?;
+                let key = entry
+                    .partition_key()
+                    .expect("partition key should have been inserted");
+
+                let p = match partitions.iter().position(|p| p.should_write(key)) {
+                    Some(index) => &mut partitions[index],
+                    None => {
+                        partitions.push(Partition::new(key));
+                        partitions.last_mut().expect("partition was just added")
+                    }
+                };
+                // only the partition written to changes size
+                let size = p.size();
+                let written = p.write_entry(&entry);
+                self.memory.resize(size, p.size());
+                written?;
+
+                if let Some(wal_sequence) = wal_sequence {
+                    p.oldest_wal_sequence.get_or_insert(wal_sequence);
                 }
             }
-            visitor.post_visit_partition(partition)?;
-        } // next partition
+        }
 
         Ok(())
     }
-}
-
-/// Common logic for processing and filtering tables in the write buffer
-///
-/// Note that since each partition has its own dictionary, mappings
-/// between Strings --> we cache the String->id mappings per partition
-///
-/// b) the table doesn't have a column range that overlaps the
-/// predicate values, e.g., if you have env = "us-west" and a
-/// table's env column has the range ["eu-south", "us-north"].
-#[derive(Debug)]
-struct PartitionTableFilter {
-    predicate: Predicate,
 
-    /// If specififed, only tables with all specified columns will be
-    /// visited. Note that just becuase a table has all these columns,
-    /// it might not be visited for other reasons (e.g. it is filted
-    /// out by a table_name predicate)
-    additional_required_columns: Option<HashSet<String>>,
+    async fn write_to_wal(&self, wal: &WalDetails, data: Vec<u8>) -> Result<()> {
+        let sequence = wal.write_and_sync(data).await.context(WritingWal {
+            database: &self.name,
+        })?;
+
+        // entries may complete out of order, so the bound only ever goes up
+        let mut current = self.next_wal_sequence.load(Ordering::SeqCst);
+        while current <= sequence {
+            match self.next_wal_sequence.compare_exchange(
+                current,
+                sequence + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
 
-    /// A 'compiled' version of the predicate to evaluate on tables /
-    /// columns in a particular partition during the walk
-    partition_predicate: Option<PartitionPredicate>,
+        Ok(())
+    }
 }
 
-impl PartitionTableFilter {
-    fn new(predicate: Predicate) -> Self {
-        Self {
-            predicate,
-            additional_required_columns: None,
-            partition_predicate: None,
-        }
-    }
+#[async_trait]
+impl Database for Db {
+    type Error = Error;
 
-    /// adds the specified columns to a list of columns that must be
-    /// present in a table.
-    fn add_required_columns(mut self, column_names: &[String]) -> Self {
-        let mut required_columns = self
-            .additional_required_columns
-            .take()
-            .unwrap_or_else(HashSet::new);
+    // TODO: writes lines creates a column named "time" for the timestmap data. If
+    //       we keep this we need to validate that no tag or field has the same name.
+    async fn write_lines(&self, lines: &[ParsedLine<'_>]) -> Result<(), Self::Error> {
+        let data = {
+            let rules = self.rules.read().await;
+            self.check_memory(&rules)?;
 
-        for c in column_names {
-            if !required_columns.contains(c) {
-                required_columns.insert(c.clone());
+            if let Some(timestamp) = rules.expired_timestamp(lines, &Utc::now()) {
+                return WriteOutsideRetention {
+                    database: &self.name,
+                    timestamp,
+                }
+                .fail();
             }
-        }
 
-        self.additional_required_columns = Some(required_columns);
-        self
-    }
+            split_lines_into_write_entry_partitions(
+                |line, default_time| rules.partition_key(line, default_time),
+                lines,
+            )
+            .context(PartitionKeyError {
+                database: &self.name,
+            })?
+        };
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
 
-    /// Called when each partition gets visited. Since ids are
-    /// specific to each partitition, the predicates much get
-    /// translated each time.
-    fn pre_visit_partition(&mut self, partition: &Partition) -> Result<()> {
-        let mut partition_predicate = partition.compile_predicate(&self.predicate)?;
+        let _wal_writes = self.wal_writes.read().await;
+        self.write_entries_to_partitions(&batch).await?;
 
-        // add any additional column needs
-        if let Some(additional_required_columns) = &self.additional_required_columns {
-            partition.add_required_columns_to_predicate(
-                additional_required_columns,
-                &mut partition_predicate,
-            );
+        if let Some(wal) = &self.wal_details {
+            self.write_to_wal(wal, data).await?;
         }
 
-        self.partition_predicate = Some(partition_predicate);
-
         Ok(())
     }
 
-    /// If returns false, skips visiting _table and all its columns
-    fn should_visit_table(&mut self, table: &Table) -> Result<bool> {
-        Ok(table.could_match_predicate(self.partition_predicate())?)
-    }
+    /// Applies a write from another server, unless it is a duplicate of a
+    /// write already applied from the same writer. Like `write_lines`, writes
+    /// with data outside the retention period are rejected.
+    async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
+        let fb = write.to_fb();
+        let (writer, sequence, checksum) = (fb.writer(), fb.sequence(), fb.checksum());
+
+        let batch = write
+            .write_buffer_batch()
+            .context(MissingPayload { writer })?;
+
+        let actual = write.payload_checksum();
+        ensure!(
+            actual == checksum,
+            ChecksumMismatch {
+                writer,
+                sequence,
+                expected: checksum,
+                actual,
+            }
+        );
 
-    pub fn partition_predicate(&self) -> &PartitionPredicate {
-        self.partition_predicate
-            .as_ref()
-            .expect("Visited partition to compile predicate")
-    }
-}
+        {
+            let rules = self.rules.read().await;
+            self.check_memory(&rules)?;
 
-/// return all column names in this database, while applying only the
-/// timestamp range (has no general purpose predicates)
-struct NameVisitor {
-    column_names: StringSet,
-    partition_column_ids: BTreeSet<u32>,
-}
+            if let Some(timestamp) = rules.expired_write_timestamp(write, &Utc::now()) {
+                return WriteOutsideRetention {
+                    database: &self.name,
+                    timestamp,
+                }
+                .fail();
+            }
+        }
 
-impl NameVisitor {
-    fn new() -> Self {
-        Self {
-            column_names: StringSet::new(),
-            partition_column_ids: BTreeSet::new(),
+        // hold the applied writes until the write is applied so that copies arriving at the
+        // same time aren't applied twice
+        let mut applied_writes = self.applied_writes.write().await;
+        if applied_writes.is_duplicate(writer, sequence, checksum) {
+            debug!(
+                "{} ignoring duplicate write {} from writer {}",
+                self.name, sequence, writer
+            );
+            return Ok(());
         }
-    }
-}
 
-impl Visitor for NameVisitor {
-    fn visit_column(
-        &mut self,
-        table: &Table,
-        column_id: u32,
-        column: &Column,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        if let Column::Tag(column, _) = column {
-            if table.column_matches_predicate(column, filter.partition_predicate())? {
-                self.partition_column_ids.insert(column_id);
-            }
+        let _wal_writes = self.wal_writes.read().await;
+        self.write_entries_to_partitions(&batch).await?;
+
+        if let Some(wal) = &self.wal_details {
+            self.write_to_wal(wal, encode_wal_entry(write)).await?;
         }
+
+        applied_writes.insert(writer, sequence, checksum);
+
         Ok(())
     }
 
-    fn pre_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
-        self.partition_column_ids.clear();
-        Ok(())
+    /// Removes the rows matching `delete` from the partitions in memory, and records
+    /// it in the WAL and in the metadata of the partitions persisted to object storage
+    async fn delete(&self, delete: &Delete) -> Result<(), Self::Error> {
+        let data = delete.to_write_buffer_batch();
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+        let _persistence = self.persistence.lock().await;
+        {
+            let _wal_writes = self.wal_writes.read().await;
+            self.write_entries_to_partitions(&batch).await?;
+
+            if let Some(wal) = &self.wal_details {
+                self.write_to_wal(wal, data).await?;
+            }
+        }
+
+        self.persist_delete(delete).await
     }
 
-    fn post_visit_partition(&mut self, partition: &Partition) -> Result<()> {
-        // convert all the partition's column_ids to Strings
-        for &column_id in &self.partition_column_ids {
-            let column_name = partition.dictionary.lookup_id(column_id).context(
-                ColumnIdNotFoundInDictionary {
-                    column_id,
-                    partition: &partition.key,
-                },
-            )?;
+    async fn table_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
+        if predicate.has_exprs() {
+            let mut filter = PartitionTableFilter::new(predicate);
+            let mut visitor = TableNamePredVisitor::new();
+            self.visit_tables(&mut filter, &mut visitor).await?;
+            return Ok(visitor.plans.into());
+        }
 
-            if !self.column_names.contains(column_name) {
-                self.column_names.insert(column_name.to_string());
+        // TODO: Cache this information to avoid creating this each time
+        let partitions = self.partitions.read().await;
+
+        let mut table_names: BTreeSet<String> = BTreeSet::new();
+        for partition in partitions.iter() {
+            let partition_predicate = partition.compile_predicate(&predicate)?;
+            // this doesn't seem to make any sense
+            assert!(
+                partition_predicate.field_restriction.is_none(),
+                "Column selection for table names not supported"
+            );
+
+            for (table_name_symbol, table) in &partition.tables {
+                if table.could_match_predicate(&partition_predicate)? {
+                    let table_name = partition.dictionary.lookup_id(*table_name_symbol).unwrap();
+
+                    if !table_names.contains(table_name) {
+                        table_names.insert(table_name.to_string());
+                    }
+                }
             }
         }
-        Ok(())
+        Ok(table_names.into())
     }
-}
 
-/// Return all column names in this database, while applying a
-/// general purpose predicates
-struct NamePredVisitor {
-    plans: Vec<LogicalPlan>,
-}
+    // return all column names in this database, while applying optional predicates
+    async fn tag_column_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
+        let has_exprs = predicate.has_exprs();
+        let mut filter = PartitionTableFilter::new(predicate);
 
-impl NamePredVisitor {
-    fn new() -> Self {
-        Self { plans: Vec::new() }
+        if has_exprs {
+            let mut visitor = NamePredVisitor::new();
+            self.visit_tables(&mut filter, &mut visitor).await?;
+            Ok(visitor.plans.into())
+        } else {
+            let mut visitor = NameVisitor::new();
+            self.visit_tables(&mut filter, &mut visitor).await?;
+            Ok(visitor.column_names.into())
+        }
     }
-}
 
-impl Visitor for NamePredVisitor {
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        self.plans
-            .push(table.tag_column_names_plan(filter.partition_predicate(), partition)?);
-        Ok(())
+    /// return all field names in this database, while applying optional predicates
+    async fn field_columns(&self, predicate: Predicate) -> Result<FieldListPlan, Self::Error> {
+        let mut filter = PartitionTableFilter::new(predicate);
+        let mut visitor = TableFieldPredVisitor::new();
+        self.visit_tables(&mut filter, &mut visitor).await?;
+        Ok(visitor.into_fieldlist_plan())
     }
-}
 
-/// return a plan that selects all values from field columns after
-/// applying timestamp and other predicates
-#[derive(Debug)]
-struct TableFieldPredVisitor {
-    // As Each table can be spread across multiple Partitions, we
-    // collect all the relevant plans and Union them together.
-    plans: Vec<LogicalPlan>,
-}
+    /// return all column values in this database, while applying optional predicates
+    async fn column_values(
+        &self,
+        column_name: &str,
+        predicate: Predicate,
+    ) -> Result<StringSetPlan, Self::Error> {
+        let has_exprs = predicate.has_exprs();
+        // Tables without the column have no values for it
+        let mut filter =
+            PartitionTableFilter::new(predicate).add_required_columns(&[column_name.to_string()]);
 
-impl Visitor for TableFieldPredVisitor {
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        self.plans
-            .push(table.field_names_plan(filter.partition_predicate(), partition)?);
-        Ok(())
+        if has_exprs {
+            let mut visitor = ValuePredVisitor::new(column_name);
+            self.visit_tables(&mut filter, &mut visitor).await?;
+            Ok(visitor.plans.into())
+        } else {
+            let mut visitor = ValueVisitor::new(column_name);
+            self.visit_tables(&mut filter, &mut visitor).await?;
+            Ok(visitor.column_values.into())
+        }
     }
-}
 
-impl TableFieldPredVisitor {
-    fn new() -> Self {
-        let plans = Vec::new();
-        Self { plans }
+    async fn query_series(&self, predicate: Predicate) -> Result<SeriesSetPlans, Self::Error> {
+        let mut filter = PartitionTableFilter::new(predicate);
+        let mut visitor = SeriesVisitor::new();
+        self.visit_tables(&mut filter, &mut visitor).await?;
+        Ok(visitor.plans.into())
     }
 
-    fn into_fieldlist_plan(self) -> FieldListPlan {
-        FieldListPlan::Plans(self.plans)
+    async fn query_groups(
+        &self,
+        predicate: Predicate,
+        group_columns: Vec<String>,
+        aggregate: Option<Aggregate>,
+    ) -> Result<GroupedSeriesSetPlans, Self::Error> {
+        let mut filter = PartitionTableFilter::new(predicate)
+            // Add any specified groups as predicate columns (so we can skip tables without those tags)
+            .add_required_columns(&group_columns);
+
+        match aggregate {
+            Some(aggregate) => {
+                let mut visitor = AggregateVisitor::new();
+                self.visit_tables(&mut filter, &mut visitor).await?;
+
+                let num_prefix_tag_group_columns = group_columns.len();
+                let plans = visitor
+                    .plans(&group_columns, aggregate, None)?
+                    .into_iter()
+                    .map(|series_set_plan| GroupedSeriesSetPlan {
+                        series_set_plan,
+                        num_prefix_tag_group_columns,
+                    })
+                    .collect::<Vec<_>>();
+                Ok(plans.into())
+            }
+            None => {
+                let mut visitor = GroupsVisitor::new(group_columns);
+                self.visit_tables(&mut filter, &mut visitor).await?;
+                Ok(visitor.plans.into())
+            }
+        }
     }
-}
 
-/// return all values in the `column_name` column
-/// in this database, while applying the timestamp range
-///
-/// Potential optimizations: Run this in parallel (in different
-/// futures) for each partition / table, rather than a single one
-/// -- but that will require building up parallel hash tables.
-struct ValueVisitor<'a> {
-    column_name: &'a str,
-    // what column id we are looking for
-    column_id: Option<u32>,
-    partition_value_ids: BTreeSet<u32>,
-    column_values: StringSet,
-}
+    async fn query_window_aggregate(
+        &self,
+        predicate: Predicate,
+        window: Window,
+        aggregate: Aggregate,
+    ) -> Result<SeriesSetPlans, Self::Error> {
+        // the last window ends with the range of time read
+        let window = match &predicate.range {
+            Some(range) => window.with_stop(range.end),
+            None => window,
+        };
 
-impl<'a> ValueVisitor<'a> {
-    fn new(column_name: &'a str) -> Self {
-        Self {
-            column_name,
-            column_id: None,
-            column_values: StringSet::new(),
-            partition_value_ids: BTreeSet::new(),
-        }
+        let mut filter = PartitionTableFilter::new(predicate);
+        let mut visitor = AggregateVisitor::new();
+        self.visit_tables(&mut filter, &mut visitor).await?;
+        Ok(visitor.plans(&[], aggregate, Some(window))?.into())
     }
-}
 
-impl<'a> Visitor for ValueVisitor<'a> {
-    fn pre_visit_partition(&mut self, partition: &Partition) -> Result<()> {
-        self.partition_value_ids.clear();
+    async fn table_to_arrow(
+        &self,
+        table_name: &str,
+        columns: &[&str],
+    ) -> Result<Vec<RecordBatch>, Self::Error> {
+        let partitions = self.partitions.read().await;
 
-        self.column_id = Some(
-            partition
-                .dictionary
-                .lookup_value(self.column_name)
-                .context(ColumnNameNotFoundInDictionary {
-                    column_name: self.column_name,
-                    partition: &partition.key,
-                })?,
+        let batches = partitions
+            .iter()
+            .filter(|p| p.has_table(table_name))
+            .map(|p| p.table_to_arrow(table_name, columns))
+            .collect::<Result<Vec<_>, crate::partition::Error>>()?;
+
+        ensure!(
+            !batches.is_empty(),
+            TableNotFound {
+                table: table_name,
+                database: &self.name,
+            }
         );
 
+        Ok(batches)
+    }
+
+    async fn query(&self, query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
+        let config = ExecutionConfig::new().with_batch_size(1024 * 1024);
+        let mut ctx = ExecutionContext::with_config(config);
+
+        register_tables(&mut ctx, &self.partitions)
+            .await
+            .context(RegisteringTables { query })?;
+
+        let plan = ctx
+            .create_logical_plan(&query)
+            .context(QueryError { query })?;
+        let plan = ctx.optimize(&plan).context(QueryError { query })?;
+        let plan = ctx
+            .create_physical_plan(&plan)
+            .context(QueryError { query })?;
+
+        ctx.collect(plan).await.context(QueryError { query })
+    }
+}
+
+/// This trait is used to implement a "Visitor" pattern for Database
+/// which can be used to define logic that shares a common Depth First
+/// Search (DFS) traversal of the Database --> Partition --> Table -->
+/// Column datastructure heirarchy.
+///
+/// Specifically, if we had a database like the following:
+///
+/// YesterdayPartition
+///   CPU Table1
+///    Col1
+/// TodayPartition
+///   CPU Table2
+///    Col2
+///
+/// Then the methods would be invoked in the following order
+///
+///  visitor.pre_visit_partition(YesterdayPartition)
+///  visitor.pre_visit_table(CPU Table1)
+///  visitor.visit_column(Col1)
+///  visitor.post_visit_table(CPU Table1)
+///  visitor.post_visit_partition(YesterdayPartition)
+///  visitor.pre_visit_partition(TodayPartition)
+///  visitor.pre_visit_table(CPU Table2)
+///  visitor.visit_column(Col2)
+///  visitor.post_visit_table(CPU Table2)
+///  visitor.post_visit_partition(TodayPartition)
+trait Visitor {
+    // called once before any column in a partition is visisted
+    fn pre_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
+        Ok(())
+    }
+
+    // called once before any column in a Table is visited
+    fn pre_visit_table(
+        &mut self,
+        _table: &Table,
+        _partition: &Partition,
+        _filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    // called every time a column is visited
+    fn visit_column(
+        &mut self,
+        _table: &Table,
+        _column_id: u32,
+        _column: &Column,
+        _filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    // called once after all columns in a Table are visited
+    fn post_visit_table(&mut self, _table: &Table, _partition: &Partition) -> Result<()> {
+        Ok(())
+    }
+
+    // called once after all columns in a partition is visited
+    fn post_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Db {
+    /// returns the number of partitions in this database
+    pub async fn len(&self) -> usize {
+        self.partitions.read().await.len()
+    }
+
+    /// returns true if the database has no partititons
+    pub async fn is_empty(&self) -> bool {
+        self.partitions.read().await.is_empty()
+    }
+
+    /// Traverse this database's tables, calling the relevant
+    /// functions, in order, of `visitor`, as described on the Visitor
+    /// trait.
+    ///
+    /// Skips visiting any table or columns of `filter.should_visit_table` returns false
+    async fn visit_tables<V: Visitor>(
+        &self,
+        filter: &mut PartitionTableFilter,
+        visitor: &mut V,
+    ) -> Result<()> {
+        let partitions = self.partitions.read().await;
+
+        for partition in partitions.iter() {
+            visitor.pre_visit_partition(partition)?;
+            filter.pre_visit_partition(partition)?;
+
+            for table in partition.tables.values() {
+                if filter.should_visit_table(table)? {
+                    visitor.pre_visit_table(table, partition, filter)?;
+
+                    for (column_id, column_index) in &table.column_id_to_index {
+                        visitor.visit_column(
+                            table,
+                            *column_id,
+                            &table.columns[*column_index],
+                            filter,
+                        )?
+                    }
+
+                    visitor.post_visit_table(table, partition)?;
+                }
+            }
+            visitor.post_visit_partition(partition)?;
+        } // next partition
+
+        Ok(())
+    }
+}
+
+/// Common logic for processing and filtering tables in the write buffer
+///
+/// Note that since each partition has its own dictionary, mappings
+/// between Strings --> we cache the String->id mappings per partition
+///
+/// b) the table doesn't have a column range that overlaps the
+/// predicate values, e.g., if you have env = "us-west" and a
+/// table's env column has the range ["eu-south", "us-north"].
+#[derive(Debug)]
+struct PartitionTableFilter {
+    predicate: Predicate,
+
+    /// If specififed, only tables with all specified columns will be
+    /// visited. Note that just becuase a table has all these columns,
+    /// it might not be visited for other reasons (e.g. it is filted
+    /// out by a table_name predicate)
+    additional_required_columns: Option<HashSet<String>>,
+
+    /// A 'compiled' version of the predicate to evaluate on tables /
+    /// columns in a particular partition during the walk
+    partition_predicate: Option<PartitionPredicate>,
+}
+
+impl PartitionTableFilter {
+    fn new(predicate: Predicate) -> Self {
+        Self {
+            predicate,
+            additional_required_columns: None,
+            partition_predicate: None,
+        }
+    }
+
+    /// adds the specified columns to a list of columns that must be
+    /// present in a table.
+    fn add_required_columns(mut self, column_names: &[String]) -> Self {
+        let mut required_columns = self
+            .additional_required_columns
+            .take()
+            .unwrap_or_else(HashSet::new);
+
+        for c in column_names {
+            if !required_columns.contains(c) {
+                required_columns.insert(c.clone());
+            }
+        }
+
+        self.additional_required_columns = Some(required_columns);
+        self
+    }
+
+    /// Called when each partition gets visited. Since ids are
+    /// specific to each partitition, the predicates much get
+    /// translated each time.
+    fn pre_visit_partition(&mut self, partition: &Partition) -> Result<()> {
+        let mut partition_predicate = partition.compile_predicate(&self.predicate)?;
+
+        // add any additional column needs
+        if let Some(additional_required_columns) = &self.additional_required_columns {
+            partition.add_required_columns_to_predicate(
+                additional_required_columns,
+                &mut partition_predicate,
+            );
+        }
+
+        self.partition_predicate = Some(partition_predicate);
+
+        Ok(())
+    }
+
+    /// If returns false, skips visiting _table and all its columns
+    fn should_visit_table(&mut self, table: &Table) -> Result<bool> {
+        Ok(table.could_match_predicate(self.partition_predicate())?)
+    }
+
+    pub fn partition_predicate(&self) -> &PartitionPredicate {
+        self.partition_predicate
+            .as_ref()
+            .expect("Visited partition to compile predicate")
+    }
+}
+
+/// return all column names in this database, while applying only the
+/// timestamp range (has no general purpose predicates)
+struct NameVisitor {
+    column_names: StringSet,
+    partition_column_ids: BTreeSet<u32>,
+}
+
+impl NameVisitor {
+    fn new() -> Self {
+        Self {
+            column_names: StringSet::new(),
+            partition_column_ids: BTreeSet::new(),
+        }
+    }
+}
+
+impl Visitor for NameVisitor {
+    fn visit_column(
+        &mut self,
+        table: &Table,
+        column_id: u32,
+        column: &Column,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        if let Column::Tag(column, _) = column {
+            if table.column_matches_predicate(column, filter.partition_predicate())? {
+                self.partition_column_ids.insert(column_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn pre_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
+        self.partition_column_ids.clear();
+        Ok(())
+    }
+
+    fn post_visit_partition(&mut self, partition: &Partition) -> Result<()> {
+        // convert all the partition's column_ids to Strings
+        for &column_id in &self.partition_column_ids {
+            let column_name = partition.dictionary.lookup_id(column_id).context(
+                ColumnIdNotFoundInDictionary {
+                    column_id,
+                    partition: &partition.key,
+                },
+            )?;
+
+            if !self.column_names.contains(column_name) {
+                self.column_names.insert(column_name.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Return all column names in this database, while applying a
+/// general purpose predicates
+struct NamePredVisitor {
+    plans: Vec<LogicalPlan>,
+}
+
+impl NamePredVisitor {
+    fn new() -> Self {
+        Self { plans: Vec::new() }
+    }
+}
+
+impl Visitor for NamePredVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        self.plans
+            .push(table.tag_column_names_plan(filter.partition_predicate(), partition)?);
+        Ok(())
+    }
+}
+
+/// Return the names of the tables in this database with rows that
+/// pass a general purpose predicate
+struct TableNamePredVisitor {
+    plans: Vec<LogicalPlan>,
+}
+
+impl TableNamePredVisitor {
+    fn new() -> Self {
+        Self { plans: Vec::new() }
+    }
+}
+
+impl Visitor for TableNamePredVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        self.plans
+            .push(table.table_name_plan(filter.partition_predicate(), partition)?);
+        Ok(())
+    }
+}
+
+/// return a plan that selects all values from field columns after
+/// applying timestamp and other predicates
+#[derive(Debug)]
+struct TableFieldPredVisitor {
+    // As Each table can be spread across multiple Partitions, we
+    // collect all the relevant plans and Union them together.
+    plans: Vec<LogicalPlan>,
+}
+
+impl Visitor for TableFieldPredVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        self.plans
+            .push(table.field_names_plan(filter.partition_predicate(), partition)?);
+        Ok(())
+    }
+}
+
+impl TableFieldPredVisitor {
+    fn new() -> Self {
+        let plans = Vec::new();
+        Self { plans }
+    }
+
+    fn into_fieldlist_plan(self) -> FieldListPlan {
+        FieldListPlan::Plans(self.plans)
+    }
+}
+
+/// return all values in the `column_name` column
+/// in this database, while applying the timestamp range
+///
+/// Potential optimizations: Run this in parallel (in different
+/// futures) for each partition / table, rather than a single one
+/// -- but that will require building up parallel hash tables.
+struct ValueVisitor<'a> {
+    column_name: &'a str,
+    // what column id we are looking for
+    column_id: Option<u32>,
+    partition_value_ids: BTreeSet<u32>,
+    column_values: StringSet,
+}
+
+impl<'a> ValueVisitor<'a> {
+    fn new(column_name: &'a str) -> Self {
+        Self {
+            column_name,
+            column_id: None,
+            column_values: StringSet::new(),
+            partition_value_ids: BTreeSet::new(),
+        }
+    }
+}
+
+impl<'a> Visitor for ValueVisitor<'a> {
+    fn pre_visit_partition(&mut self, partition: &Partition) -> Result<()> {
+        self.partition_value_ids.clear();
+
+        // partitions without the column have no values for it
+        self.column_id = partition.dictionary.id(self.column_name);
+
+        Ok(())
+    }
+
+    fn visit_column(
+        &mut self,
+        table: &Table,
+        column_id: u32,
+        column: &Column,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        if Some(column_id) != self.column_id {
+            return Ok(());
+        }
+
+        match column {
+            Column::Tag(column, _) => {
+                // if we have a timestamp prediate, find all values
+                // where the timestamp is within range. Otherwise take
+                // all values.
+                let partition_predicate = filter.partition_predicate();
+                match partition_predicate.range {
+                    None => {
+                        // take all non-null values
+                        column.iter().filter_map(|&s| s).for_each(|value_id| {
+                            self.partition_value_ids.insert(value_id);
+                        });
+                    }
+                    Some(range) => {
+                        // filter out all values that don't match the timestmap
+                        let time_column = table.column_i64(partition_predicate.time_column_id)?;
+
+                        column
+                            .iter()
+                            .zip(time_column.iter())
+                            .filter_map(|(&column_value_id, &timestamp_value)| {
+                                if range.contains_opt(timestamp_value) {
+                                    column_value_id
+                                } else {
+                                    None
+                                }
+                            })
+                            .for_each(|value_id| {
+                                self.partition_value_ids.insert(value_id);
+                            });
+                    }
+                }
+                Ok(())
+            }
+            _ => UnsupportedColumnTypeForListingValues {
+                column_name: self.column_name,
+            }
+            .fail(),
+        }
+    }
+
+    fn post_visit_partition(&mut self, partition: &Partition) -> Result<()> {
+        // convert all the partition's column_ids to Strings
+        for &value_id in &self.partition_value_ids {
+            let value = partition.dictionary.lookup_id(value_id).context(
+                ColumnValueIdNotFoundInDictionary {
+                    value_id,
+                    partition: &partition.key,
+                },
+            )?;
+
+            if !self.column_values.contains(value) {
+                self.column_values.insert(value.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// return all column values for the specified column in this
+/// database, while applying the timestamp range and predicate
+struct ValuePredVisitor<'a> {
+    column_name: &'a str,
+    plans: Vec<LogicalPlan>,
+}
+
+impl<'a> ValuePredVisitor<'a> {
+    fn new(column_name: &'a str) -> Self {
+        Self {
+            column_name,
+            plans: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Visitor for ValuePredVisitor<'a> {
+    // TODO try and rule out entire tables based on the same critera
+    // as explained on NamePredVisitor
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        // skip table entirely if there are no rows that fall in the timestamp
+        if table.could_match_predicate(filter.partition_predicate())? {
+            self.plans.push(table.tag_values_plan(
+                self.column_name,
+                filter.partition_predicate(),
+                partition,
+            )?);
+        }
+        Ok(())
+    }
+}
+
+/// Return DataFusion plans to calculate which series pass the
+/// specified predicate.
+struct SeriesVisitor {
+    plans: Vec<SeriesSetPlan>,
+}
+
+impl SeriesVisitor {
+    fn new() -> Self {
+        Self { plans: Vec::new() }
+    }
+}
+
+impl Visitor for SeriesVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        self.plans
+            .push(table.series_set_plan(filter.partition_predicate(), partition)?);
+
+        Ok(())
+    }
+}
+
+/// Return DataFusion plans to calculate series that pass the
+/// specified predicate, grouped according to grouped_columns
+struct GroupsVisitor {
+    group_columns: Vec<String>,
+    plans: Vec<GroupedSeriesSetPlan>,
+}
+
+impl GroupsVisitor {
+    fn new(group_columns: Vec<String>) -> Self {
+        Self {
+            group_columns,
+            plans: Vec::new(),
+        }
+    }
+}
+
+impl Visitor for GroupsVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        self.plans.push(table.grouped_series_set_plan(
+            filter.partition_predicate(),
+            &self.group_columns,
+            partition,
+        )?);
+
+        Ok(())
+    }
+}
+
+/// The rows and columns of a table, from all the partitions visited, that
+/// an `AggregateVisitor` aggregates
+#[derive(Debug, Default)]
+struct AggregateTable {
+    tag_columns: BTreeSet<Arc<String>>,
+    field_columns: BTreeSet<Arc<String>>,
+    /// The rows of the table in each partition, with the partition's key
+    batches: Vec<(String, RecordBatch)>,
+    filter_expr: Option<Expr>,
+}
+
+/// Return DataFusion plans to aggregate each series that passes the
+/// specified predicate into a single row, or a row per window. Unlike the
+/// other visitors, the plans are created once all partitions are visited,
+/// as a series may have rows in several.
+struct AggregateVisitor {
+    tables: BTreeMap<String, AggregateTable>,
+}
+
+impl AggregateVisitor {
+    fn new() -> Self {
+        Self {
+            tables: BTreeMap::new(),
+        }
+    }
+
+    /// Returns a plan per table, with the tag columns starting with
+    /// `prefix_columns`
+    fn plans(
+        self,
+        prefix_columns: &[String],
+        aggregate: Aggregate,
+        window: Option<Window>,
+    ) -> Result<Vec<SeriesSetPlan>> {
+        let mut plans = Vec::with_capacity(self.tables.len());
+
+        for (table_name, table) in self.tables {
+            let data = union_batches(&table_name, table.batches)
+                .context(ReadingAggregateTable { table: &table_name })?;
+
+            let plan = aggregate_series_set_plan(
+                Arc::new(table_name),
+                data,
+                table.tag_columns.into_iter().collect(),
+                table.field_columns.into_iter().collect(),
+                table.filter_expr,
+                prefix_columns,
+                aggregate,
+                window,
+            )?;
+            plans.extend(plan);
+        }
+
+        Ok(plans)
+    }
+}
+
+impl Visitor for AggregateVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        let partition_predicate = filter.partition_predicate();
+        if !table.could_match_predicate(partition_predicate)? {
+            return Ok(());
+        }
+
+        let table_name =
+            partition
+                .dictionary
+                .lookup_id(table.id)
+                .context(TableIdNotFoundInDictionary {
+                    table: table.id,
+                    partition: &partition.key,
+                })?;
+        let (tag_columns, field_columns) =
+            table.tag_and_field_column_names(partition_predicate, partition)?;
+
+        let aggregate_table = self.tables.entry(table_name.to_string()).or_default();
+        aggregate_table.tag_columns.extend(tag_columns);
+        aggregate_table.field_columns.extend(field_columns);
+        aggregate_table
+            .batches
+            .push((partition.key.clone(), table.all_to_arrow(partition)?));
+        // the filter only refers to columns by name, so it is the same
+        // in every partition
+        aggregate_table.filter_expr = partition_predicate.filter_expr();
+
+        Ok(())
+    }
+}
+
+/// Serializes `rules` into the rules file of the WAL directory `wal_dir`
+async fn write_rules(database: &str, rules: &DatabaseRules, wal_dir: &Path) -> Result<()> {
+    let rules_path = wal_dir.join(RULES_FILE_NAME);
+    let data = serde_json::to_string(rules).context(SerializingRules { database })?;
+
+    tokio::fs::write(&rules_path, data)
+        .await
+        .context(WritingRules {
+            rules_path: &rules_path,
+        })
+}
+
+/// Reads the rules persisted in the WAL directory `wal_dir`, falling
+/// back to `default_rules` if none were persisted
+async fn read_rules(database: &str, wal_dir: &Path) -> Result<DatabaseRules> {
+    let rules_path = wal_dir.join(RULES_FILE_NAME);
+
+    match tokio::fs::read_to_string(&rules_path).await {
+        Ok(data) => serde_json::from_str(&data).context(DeserializingRules { database }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(default_rules()),
+        Err(e) => Err(e).context(ReadingRules {
+            rules_path: &rules_path,
+        }),
+    }
+}
+
+/// Writes the sequence number of the last WAL entry snapshotted for each
+/// partition key to the WAL directory `wal_dir`
+async fn write_snapshot_sequences(
+    database: &str,
+    sequences: &BTreeMap<String, SequenceNumber>,
+    wal_dir: &Path,
+) -> Result<()> {
+    let path = wal_dir.join(SNAPSHOT_SEQUENCES_FILE_NAME);
+    let data =
+        serde_json::to_string(sequences).context(SerializingSnapshotSequences { database })?;
+
+    tokio::fs::write(&path, data)
+        .await
+        .context(WritingSnapshotSequences { path: &path })
+}
+
+/// Reads the sequence numbers of the last WAL entries snapshotted persisted in
+/// the WAL directory `wal_dir`, if any
+async fn read_snapshot_sequences(
+    database: &str,
+    wal_dir: &Path,
+) -> Result<BTreeMap<String, SequenceNumber>> {
+    let path = wal_dir.join(SNAPSHOT_SEQUENCES_FILE_NAME);
+
+    match tokio::fs::read_to_string(&path).await {
+        Ok(data) => {
+            serde_json::from_str(&data).context(DeserializingSnapshotSequences { database })
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e).context(ReadingSnapshotSequences { path: &path }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::datafusion::{
+        logical_plan::{self, Literal},
+        scalar::ScalarValue,
+    };
+    use logical_plan::{Expr, Operator};
+    use storage::{
+        exec::fieldlist::{Field, FieldList},
+        exec::{
+            seriesset::{Error as SeriesSetError, GroupedSeriesSetItem, SeriesSet},
+            Executor,
+        },
+        predicate::PredicateBuilder,
+        regex_match::RegexMatch,
+        Database,
+    };
+
+    use arrow::{
+        array::{Array, Float64Array, Int64Array, StringArray},
+        datatypes::DataType,
+        util::pretty::pretty_format_batches,
+    };
+    use data_types::data::lines_to_replicated_write;
+    use influxdb_line_protocol::parse_lines;
+    use test_helpers::str_pair_vec_to_vec;
+    use tokio::sync::mpsc;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn to_set(v: &[&str]) -> BTreeSet<String> {
+        v.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>()
+    }
+
+    // Abstract a bit of boilerplate around table assertions and improve the failure output.
+    // The default failure message uses Debug formatting, which prints newlines as `\n`.
+    // This prints the pretty_format_batches using Display so it's easier to read the tables.
+    fn assert_table_eq(table: &str, partitions: &[arrow::record_batch::RecordBatch]) {
+        let res = pretty_format_batches(partitions).unwrap();
+        assert_eq!(table, res, "\n\nleft:\n\n{}\nright:\n\n{}", table, res);
+    }
+
+    // query the table names, with optional range predicate
+    async fn table_names(db: &Db, predicate: Predicate) -> Result<StringSet> {
+        let plan = db.table_names(predicate).await?;
+        let executor = Executor::default();
+        let s = executor.to_string_set(plan).await?;
+
+        // unwrap it (for easy test comparisons)
+        let s = Arc::try_unwrap(s).expect("only one reference to the set in the test");
+        Ok(s)
+    }
+
+    #[tokio::test]
+    async fn list_table_names() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let db = Db::try_with_wal("mydb", &mut dir).await?;
+
+        // no tables initially
+        assert_eq!(
+            table_names(&db, Predicate::default()).await?,
+            BTreeSet::new()
+        );
+
+        // write two different tables
+        let lines: Vec<_> =
+            parse_lines("cpu,region=west user=23.2 10\ndisk,region=east bytes=99i 11")
+                .map(|l| l.unwrap())
+                .collect();
+        db.write_lines(&lines).await?;
+
+        // Now, we should see the two tables
+        assert_eq!(
+            table_names(&db, Predicate::default()).await?,
+            to_set(&["cpu", "disk"])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_table_names_timestamps() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let db = Db::try_with_wal("mydb", &mut dir).await?;
+
+        // write two different tables at the following times:
+        // cpu: 100 and 150
+        // disk: 200
+        let lines: Vec<_> =
+            parse_lines("cpu,region=west user=23.2 100\ncpu,region=west user=21.0 150\ndisk,region=east bytes=99i 200")
+                .map(|l| l.unwrap())
+                .collect();
+        db.write_lines(&lines).await?;
+
+        // Cover all times
+        let predicate = PredicateBuilder::default().timestamp_range(0, 201).build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu", "disk"]));
+
+        // Right before disk
+        let predicate = PredicateBuilder::default().timestamp_range(0, 200).build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+
+        // only one point of cpu
+        let predicate = PredicateBuilder::default().timestamp_range(50, 101).build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+
+        // no ranges
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(250, 350)
+            .build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&[]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_table_names_predicate() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let db = Db::try_with_wal("mydb", &mut dir).await?;
+
+        let lines: Vec<_> = parse_lines(
+            "cpu,region=west user=23.2 100\n\
+             cpu,region=east user=21.0 150\n\
+             disk,region=east bytes=99i 200\n\
+             mem,host=a free=10i 300",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_column_eq_expr("region", "east"))
+            .build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu", "disk"]));
+
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_column_eq_expr("region", "west"))
+            .build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+
+        // measurement, timestamp and predicate
+        let predicate = PredicateBuilder::default()
+            .table("cpu")
+            .timestamp_range(120, 250)
+            .add_expr(make_column_eq_expr("region", "east"))
+            .build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+
+        let predicate = PredicateBuilder::default()
+            .table("disk")
+            .add_expr(make_column_eq_expr("region", "west"))
+            .build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&[]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_tags_are_null() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let db = Db::try_with_wal("mydb", &mut dir).await?;
+
+        // Note the `region` tag is introduced in the second line, so
+        // the values in prior rows for the region column are
+        // null. Likewise the `core` tag is introduced in the third
+        // line so the prior columns are null
+        let lines: Vec<_> = parse_lines(
+            "cpu,region=west user=23.2 10\n\
+                         cpu, user=10.0 11\n\
+                         cpu,core=one user=10.0 11\n",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let partitions = db.table_to_arrow("cpu", &["region", "core"]).await?;
+        let columns = partitions[0].columns();
+
+        assert_eq!(
+            2,
+            columns.len(),
+            "Got only two columns in partiton: {:#?}",
+            columns
+        );
+
+        let region_col = columns[0]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .expect("Get region column as a string");
+
+        assert_eq!(region_col.len(), 3);
+        assert_eq!(region_col.value(0), "west", "region_col: {:?}", region_col);
+        assert!(!region_col.is_null(0), "is_null(0): {:?}", region_col);
+        assert!(region_col.is_null(1), "is_null(1): {:?}", region_col);
+        assert!(region_col.is_null(2), "is_null(1): {:?}", region_col);
+
+        let host_col = columns[1]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .expect("Get host column as a string");
+
+        assert_eq!(host_col.len(), 3);
+        assert!(host_col.is_null(0), "is_null(0): {:?}", host_col);
+        assert!(host_col.is_null(1), "is_null(1): {:?}", host_col);
+        assert!(!host_col.is_null(2), "is_null(2): {:?}", host_col);
+        assert_eq!(host_col.value(2), "one", "host_col: {:?}", host_col);
+
+        let err = db.table_to_arrow("mem", &["region"]).await.unwrap_err();
+        assert!(
+            matches!(&err, Error::TableNotFound { table, .. } if table == "mem"),
+            "{}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_data_and_recover() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let expected_cpu_table = r#"+--------+------+------+-------+-------------+------+------+---------+-----------+
+| region | host | user | other | str         | b    | time | new_tag | new_field |
++--------+------+------+-------+-------------+------+------+---------+-----------+
+| west   | A    | 23.2 | 1     | some string | true | 10   |         |           |
+| west   | B    | 23.1 |       |             |      | 15   |         |           |
+|        | A    |      |       |             |      | 20   | foo     | 15.1      |
++--------+------+------+-------+-------------+------+------+---------+-----------+
+"#;
+        let expected_mem_table = r#"+--------+------+-------+------+
+| region | host | val   | time |
++--------+------+-------+------+
+| east   | C    | 23432 | 10   |
++--------+------+-------+------+
+"#;
+        let expected_disk_table = r#"+--------+------+----------+--------------+------+
+| region | host | bytes    | used_percent | time |
++--------+------+----------+--------------+------+
+| west   | A    | 23432323 | 76.2         | 10   |
++--------+------+----------+--------------+------+
+"#;
+
+        let cpu_columns = &[
+            "region",
+            "host",
+            "user",
+            "other",
+            "str",
+            "b",
+            "time",
+            "new_tag",
+            "new_field",
+        ];
+        let mem_columns = &["region", "host", "val", "time"];
+        let disk_columns = &["region", "host", "bytes", "used_percent", "time"];
+
+        {
+            let db = Db::try_with_wal("mydb", &mut dir).await?;
+            let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i,str=\"some string\",b=true 10\ndisk,region=west,host=A bytes=23432323i,used_percent=76.2 10").map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("cpu,region=west,host=B user=23.1 15")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("cpu,host=A,new_tag=foo new_field=15.1 20")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("mem,region=east,host=C val=23432 10")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+
+            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+
+            let partitions = db.table_to_arrow("mem", mem_columns).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+
+            let partitions = db.table_to_arrow("disk", disk_columns).await?;
+            assert_table_eq(expected_disk_table, &partitions);
+        }
+
+        // check that it recovers from the wal
+        {
+            let db = Db::restore_from_wal(dir).await?;
+
+            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+
+            let partitions = db.table_to_arrow("mem", mem_columns).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+
+            let partitions = db.table_to_arrow("disk", disk_columns).await?;
+            assert_table_eq(expected_disk_table, &partitions);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_and_query() -> Result {
+        let db = Db::new("foo");
+
+        let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i 10")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
+
+        let results = db.query("select * from cpu").await?;
+
+        let expected_cpu_table = r#"+------+-------+--------+------+------+
+| host | other | region | time | user |
++------+-------+--------+------+------+
+| A    | 1     | west   | 10   | 23.2 |
++------+-------+--------+------+------+
+"#;
+
+        assert_table_eq(expected_cpu_table, &results);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_across_partitions_with_filters_and_subqueries() -> Result {
+        let db = Db::new("foo");
+
+        // the rows are in different hourly partitions
+        let lines: Vec<_> = parse_lines(
+            "cpu,host=a usage=1.5 10\n\
+             cpu,host=b usage=2.5,cores=4i 3600000000010",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        // columns the table lacks in a partition are null for its rows
+        let results = db.query("select * from cpu order by time").await?;
+        let expected_cpu_table = r#"+-------+------+---------------+-------+
+| cores | host | time          | usage |
++-------+------+---------------+-------+
+|       | a    | 10            | 1.5   |
+| 4     | b    | 3600000000010 | 2.5   |
++-------+------+---------------+-------+
+"#;
+        assert_table_eq(expected_cpu_table, &results);
+
+        let results = db
+            .query("select host, usage from cpu where usage > 2")
+            .await?;
+        let expected_filtered = r#"+------+-------+
+| host | usage |
++------+-------+
+| b    | 2.5   |
++------+-------+
+"#;
+        assert_table_eq(expected_filtered, &results);
+
+        let results = db
+            .query(
+                "select host, max_usage from \
+                 (select host, max(usage) as max_usage from cpu group by host) \
+                 where max_usage > 2",
+            )
+            .await?;
+        let expected_subquery = r#"+------+-----------+
+| host | max_usage |
++------+-----------+
+| b    | 2.5       |
++------+-----------+
+"#;
+        assert_table_eq(expected_subquery, &results);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_with_joins_ctes_and_unions() -> Result {
+        let db = Db::new("foo");
+
+        let lines: Vec<_> = parse_lines(
+            "cpu,host=a usage=1.5 10\n\
+             cpu,host=b usage=2.5 3600000000010\n\
+             mem,host=a free=100i 20\n\
+             mem,host=c free=300i 3600000000020",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let results = db
+            .query(
+                "select cpu.host as host, usage, free from cpu \
+                 join mem on cpu.host = mem.host",
+            )
+            .await?;
+        let expected_join = r#"+------+-------+------+
+| host | usage | free |
++------+-------+------+
+| a    | 1.5   | 100  |
++------+-------+------+
+"#;
+        assert_table_eq(expected_join, &results);
+
+        let results = db
+            .query(
+                "with busy as (select host, usage from cpu where usage > 2) \
+                 select host from busy",
+            )
+            .await?;
+        let expected_cte = r#"+------+
+| host |
++------+
+| b    |
++------+
+"#;
+        assert_table_eq(expected_cte, &results);
+
+        let results = db
+            .query(
+                "select host from \
+                 (select host from cpu union all select host from mem) \
+                 order by host",
+            )
+            .await?;
+        let expected_union = r#"+------+
+| host |
++------+
+| a    |
+| a    |
+| b    |
+| c    |
++------+
+"#;
+        assert_table_eq(expected_union, &results);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_and_query_unsigned() -> Result {
+        let db = Db::new("foo");
+
+        let lines: Vec<_> = parse_lines(
+            "disk,region=west bytes=42u 10\n\
+             disk,region=east bytes=18446744073709551615u 20",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let results = db.query("select * from disk").await?;
+
+        let expected_disk_table = r#"+----------------------+--------+------+
+| bytes                | region | time |
++----------------------+--------+------+
+| 42                   | west   | 10   |
+| 18446744073709551615 | east   | 20   |
++----------------------+--------+------+
+"#;
+
+        assert_table_eq(expected_disk_table, &results);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recover_partial_entries() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let expected_cpu_table = r#"+--------+------+------+-------+-------------+------+------+---------+-----------+
+| region | host | user | other | str         | b    | time | new_tag | new_field |
++--------+------+------+-------+-------------+------+------+---------+-----------+
+| west   | A    | 23.2 | 1     | some string | true | 10   |         |           |
+| west   | B    | 23.1 |       |             |      | 15   |         |           |
+|        | A    |      |       |             |      | 20   | foo     | 15.1      |
++--------+------+------+-------+-------------+------+------+---------+-----------+
+"#;
+
+        let expected_mem_table = r#"+--------+------+-------+------+
+| region | host | val   | time |
++--------+------+-------+------+
+| east   | C    | 23432 | 10   |
++--------+------+-------+------+
+"#;
+        let expected_disk_table = r#"+--------+------+----------+--------------+------+
+| region | host | bytes    | used_percent | time |
++--------+------+----------+--------------+------+
+| west   | A    | 23432323 | 76.2         | 10   |
++--------+------+----------+--------------+------+
+"#;
+
+        let cpu_columns = &[
+            "region",
+            "host",
+            "user",
+            "other",
+            "str",
+            "b",
+            "time",
+            "new_tag",
+            "new_field",
+        ];
+        let mem_columns = &["region", "host", "val", "time"];
+        let disk_columns = &["region", "host", "bytes", "used_percent", "time"];
+        {
+            let db = Db::try_with_wal("mydb", &mut dir).await?;
+            let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i,str=\"some string\",b=true 10\ndisk,region=west,host=A bytes=23432323i,used_percent=76.2 10").map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("cpu,region=west,host=B user=23.1 15")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("cpu,host=A,new_tag=foo new_field=15.1 20")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("mem,region=east,host=C val=23432 10")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+
+            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+
+            let partitions = db.table_to_arrow("mem", mem_columns).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+
+            let partitions = db.table_to_arrow("disk", disk_columns).await?;
+            assert_table_eq(expected_disk_table, &partitions);
+        }
+
+        // check that it can recover from the last 2 self-describing entries of the wal
+        {
+            let name = dir.iter().last().unwrap().to_str().unwrap().to_string();
+
+            let wal_builder = WalBuilder::new(&dir);
+
+            let wal_entries = wal_builder
+                .entries()
+                .context(LoadingWal { database: &name })?;
+
+            // Skip the first 2 entries in the wal; only restore from the last 2
+            let wal_entries = wal_entries.skip(2);
+
+            let (partitions, _stats) =
+                restore_partitions_from_wal(wal_entries, &Default::default())?;
+
+            let db = Db {
+                name,
+                rules: RwLock::new(default_rules()),
+                partitions: Arc::new(RwLock::new(partitions)),
+                applied_writes: Default::default(),
+                wal_details: None,
+                next_wal_sequence: Default::default(),
+                wal_writes: Default::default(),
+                snapshot_sequences: Default::default(),
+                memory: Default::default(),
+                next_snapshot_generation: Default::default(),
+                object_store: None,
+                writer: None,
+                persistence: Default::default(),
+            };
+
+            // some cpu
+            let smaller_cpu_table = r#"+------+---------+-----------+------+
+| host | new_tag | new_field | time |
++------+---------+-----------+------+
+| A    | foo     | 15.1      | 20   |
++------+---------+-----------+------+
+"#;
+            let smaller_cpu_columns = &["host", "new_tag", "new_field", "time"];
+            let partitions = db.table_to_arrow("cpu", smaller_cpu_columns).await?;
+            assert_table_eq(smaller_cpu_table, &partitions);
+
+            // all of mem
+            let partitions = db.table_to_arrow("mem", mem_columns).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+
+            // no disk
+            let nonexistent_table = db.table_to_arrow("disk", disk_columns).await;
+            assert!(nonexistent_table.is_err());
+            let actual_message = format!("{:?}", nonexistent_table);
+            let expected_message = "TableNameNotFoundInDictionary";
+            assert!(
+                actual_message.contains(expected_message),
+                "Did not find '{}' in '{}'",
+                expected_message,
+                actual_message
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn partition_keys(db: &Db) -> Vec<String> {
+        let partitions = db.partitions.read().await;
+        partitions.iter().map(|p| p.key.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn db_partition_key() -> Result {
+        let db = Db::new("foo");
+
+        let lines: Vec<_> = parse_lines(
+            "\
+cpu user=23.2 1600107710000000000
+disk bytes=23432323i 1600136510000000000",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        assert_eq!(
+            partition_keys(&db).await,
+            vec!["2020-09-14T18", "2020-09-15T02"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn db_partition_key_without_timestamp() -> Result {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![
+                TemplatePart::Table,
+                TemplatePart::TimeFormat("%Y".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let db = Db::new_with_rules("foo", rules);
+
+        let lines: Vec<_> = parse_lines("cpu user=23.2").map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        let keys = partition_keys(&db).await;
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].starts_with("cpu-"), "unexpected key {:?}", keys);
+
         Ok(())
     }
 
-    fn visit_column(
-        &mut self,
-        table: &Table,
-        column_id: u32,
-        column: &Column,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        if Some(column_id) != self.column_id {
-            return Ok(());
-        }
+    #[tokio::test]
+    async fn db_rules_persisted_and_changed() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
 
-        match column {
-            Column::Tag(column, _) => {
-                // if we have a timestamp prediate, find all values
-                // where the timestamp is within range. Otherwise take
-                // all values.
-                let partition_predicate = filter.partition_predicate();
-                match partition_predicate.range {
-                    None => {
-                        // take all non-null values
-                        column.iter().filter_map(|&s| s).for_each(|value_id| {
-                            self.partition_value_ids.insert(value_id);
-                        });
-                    }
-                    Some(range) => {
-                        // filter out all values that don't match the timestmap
-                        let time_column = table.column_i64(partition_predicate.time_column_id)?;
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![TemplatePart::Table]),
+            ..Default::default()
+        };
 
-                        column
-                            .iter()
-                            .zip(time_column.iter())
-                            .filter_map(|(&column_value_id, &timestamp_value)| {
-                                if range.contains_opt(timestamp_value) {
-                                    column_value_id
-                                } else {
-                                    None
-                                }
-                            })
-                            .for_each(|value_id| {
-                                self.partition_value_ids.insert(value_id);
-                            });
-                    }
-                }
-                Ok(())
-            }
-            _ => UnsupportedColumnTypeForListingValues {
-                column_name: self.column_name,
-            }
-            .fail(),
+        {
+            let db = Db::try_with_rules_and_wal("mydb", rules.clone(), &mut dir).await?;
+            let lines: Vec<_> = parse_lines("cpu user=23.2 10")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            assert_eq!(partition_keys(&db).await, vec!["cpu"]);
         }
-    }
 
-    fn post_visit_partition(&mut self, partition: &Partition) -> Result<()> {
-        // convert all the partition's column_ids to Strings
-        for &value_id in &self.partition_value_ids {
-            let value = partition.dictionary.lookup_id(value_id).context(
-                ColumnValueIdNotFoundInDictionary {
-                    value_id,
-                    partition: &partition.key,
-                },
-            )?;
+        let new_rules = DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![TemplatePart::Column(
+                "region".to_string(),
+            )]),
+            ..Default::default()
+        };
 
-            if !self.column_values.contains(value) {
-                self.column_values.insert(value.to_string());
-            }
+        {
+            let db = Db::restore_from_wal(dir.clone()).await?;
+            assert_eq!(db.rules().await, rules);
+            assert_eq!(partition_keys(&db).await, vec!["cpu"]);
+
+            // new rules only apply to subsequent writes
+            db.set_rules(new_rules.clone()).await?;
+            let lines: Vec<_> = parse_lines("cpu,region=west user=21.0 20")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            assert_eq!(partition_keys(&db).await, vec!["cpu", "region_west"]);
         }
+
+        let db = Db::restore_from_wal(dir).await?;
+        assert_eq!(db.rules().await, new_rules);
+
         Ok(())
     }
-}
-
-/// return all column values for the specified column in this
-/// database, while applying the timestamp range and predicate
-struct ValuePredVisitor<'a> {
-    column_name: &'a str,
-    plans: Vec<LogicalPlan>,
-}
 
-impl<'a> ValuePredVisitor<'a> {
-    fn new(column_name: &'a str) -> Self {
-        Self {
-            column_name,
-            plans: Vec::new(),
-        }
+    fn replicated_write(writer: u32, sequence: u64, lp: &str) -> ReplicatedWrite {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        lines_to_replicated_write(writer, sequence, &lines, &DatabaseRules::default()).unwrap()
     }
-}
 
-impl<'a> Visitor for ValuePredVisitor<'a> {
-    // TODO try and rule out entire tables based on the same critera
-    // as explained on NamePredVisitor
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        // skip table entirely if there are no rows that fall in the timestamp
-        if table.could_match_predicate(filter.partition_predicate())? {
-            self.plans.push(table.tag_values_plan(
-                self.column_name,
-                filter.partition_predicate(),
-                partition,
-            )?);
-        }
+    #[tokio::test]
+    async fn replicated_write_duplicates_ignored() -> Result {
+        let db = Db::new("foo");
+
+        let write = replicated_write(1, 1, "cpu val=1 10");
+        db.store_replicated_write(&write).await?;
+        db.store_replicated_write(&write).await?;
+
+        // a write with the same sequence but another payload is part of a split write
+        db.store_replicated_write(&replicated_write(1, 1, "cpu val=2 20"))
+            .await?;
+        db.store_replicated_write(&replicated_write(2, 1, "cpu val=3 30"))
+            .await?;
+        db.store_replicated_write(&replicated_write(1, 3, "cpu val=4 40"))
+            .await?;
+        // copies of writes older than the high-water mark of writer 1
+        db.store_replicated_write(&write).await?;
+        // a write arriving late, e.g. retried from a replication queue
+        db.store_replicated_write(&replicated_write(1, 2, "cpu val=5 50"))
+            .await?;
+        db.store_replicated_write(&replicated_write(1, 2, "cpu val=5 50"))
+            .await?;
+
+        let results = db.query("select * from cpu").await?;
+        let expected_cpu_table = r#"+------+-----+
+| time | val |
++------+-----+
+| 10   | 1   |
+| 20   | 2   |
+| 30   | 3   |
+| 40   | 4   |
+| 50   | 5   |
++------+-----+
+"#;
+        assert_table_eq(expected_cpu_table, &results);
+
+        assert_eq!(db.high_water_mark(1).await, Some(3));
+        assert_eq!(db.high_water_mark(2).await, Some(1));
+        assert_eq!(db.high_water_mark(3).await, None);
+
         Ok(())
     }
-}
 
-/// Return DataFusion plans to calculate which series pass the
-/// specified predicate.
-struct SeriesVisitor {
-    plans: Vec<SeriesSetPlan>,
-}
+    #[tokio::test]
+    async fn replicated_write_checksum_mismatch() -> Result {
+        let db = Db::new("foo");
+        let write = replicated_write(1, 1, "cpu val=1 10");
+
+        let mut fbb = flatbuffers::FlatBufferBuilder::new();
+        let payload = fbb.create_vector_direct(write.to_fb().payload().unwrap());
+        let corrupted = wb::ReplicatedWrite::create(
+            &mut fbb,
+            &wb::ReplicatedWriteArgs {
+                writer: 1,
+                sequence: 1,
+                checksum: write.to_fb().checksum().wrapping_add(1),
+                payload: Some(payload),
+            },
+        );
+        fbb.finish(corrupted, None);
+        let corrupted = ReplicatedWrite {
+            data: fbb.finished_data().to_vec(),
+        };
 
-impl SeriesVisitor {
-    fn new() -> Self {
-        Self { plans: Vec::new() }
-    }
-}
+        let err = db.store_replicated_write(&corrupted).await.unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch { .. }), "{}", err);
 
-impl Visitor for SeriesVisitor {
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        self.plans
-            .push(table.series_set_plan(filter.partition_predicate(), partition)?);
+        // the corrupted write wasn't applied, so the real one still is
+        assert_eq!(db.high_water_mark(1).await, None);
+        db.store_replicated_write(&write).await?;
+        assert_eq!(db.high_water_mark(1).await, Some(1));
 
         Ok(())
     }
-}
 
-/// Return DataFusion plans to calculate series that pass the
-/// specified predicate, grouped according to grouped_columns
-struct GroupsVisitor {
-    group_columns: Vec<String>,
-    plans: Vec<GroupedSeriesSetPlan>,
-}
+    #[tokio::test]
+    async fn replicated_write_applied_writes_recovered() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
 
-impl GroupsVisitor {
-    fn new(group_columns: Vec<String>) -> Self {
-        Self {
-            group_columns,
-            plans: Vec::new(),
+        {
+            let db = Db::try_with_wal("mydb", &mut dir).await?;
+            db.store_replicated_write(&replicated_write(1, 3, "cpu val=1 10"))
+                .await?;
+            db.store_replicated_write(&replicated_write(2, 7, "cpu val=2 20"))
+                .await?;
+            let lines: Vec<_> = parse_lines("cpu val=3 30").map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
         }
-    }
-}
 
-impl Visitor for GroupsVisitor {
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        self.plans.push(table.grouped_series_set_plan(
-            filter.partition_predicate(),
-            &self.group_columns,
-            partition,
-        )?);
+        let db = Db::restore_from_wal(dir).await?;
+        assert_eq!(db.high_water_mark(1).await, Some(3));
+        assert_eq!(db.high_water_mark(2).await, Some(7));
+
+        db.store_replicated_write(&replicated_write(1, 3, "cpu val=1 10"))
+            .await?;
+
+        let results = db.query("select * from cpu").await?;
+        let expected_cpu_table = r#"+------+-----+
+| time | val |
++------+-----+
+| 10   | 1   |
+| 20   | 2   |
+| 30   | 3   |
++------+-----+
+"#;
+        assert_table_eq(expected_cpu_table, &results);
 
         Ok(())
     }
-}
-
-// partition_key returns the partition key for the given line. The key will be the prefix of a
-// partition name (multiple partitions can exist for each key). It uses the user defined
-// partitioning rules to construct this key
-pub fn partition_key(line: &ParsedLine<'_>) -> String {
-    // TODO - wire this up to use partitioning rules, for now just partition by day
-    let ts = line.timestamp.unwrap();
-    let dt = Utc.timestamp_nanos(ts);
-    dt.format("%Y-%m-%dT%H").to_string()
-}
 
-struct ArrowTable {
-    name: String,
-    schema: Arc<ArrowSchema>,
-    data: Vec<RecordBatch>,
-}
+    #[tokio::test]
+    async fn read_only_partitions_queried() -> Result {
+        use arrow::{
+            array::{Float64Array, Int64Array},
+            datatypes::{Field, Schema},
+            record_batch::RecordBatch,
+        };
+        use arrow_deps::parquet::arrow::arrow_writer::ArrowWriter;
+        use data_types::partition_metadata::{self, Column, ColumnSummary, Statistics};
+        use object_store::InMemory;
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("region", DataType::Utf8, false),
+            Field::new("user", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            Arc::clone(&schema),
+            vec![
+                Arc::new(StringArray::from(vec!["west", "east"])),
+                Arc::new(Float64Array::from(vec![Some(23.2), None])),
+                Arc::new(Int64Array::from(vec![10, 20])),
+            ],
+        )?;
+
+        let dir = test_helpers::tmp_dir()?;
+        let path = dir.path().join("cpu.parquet");
+        let mut writer = ArrowWriter::try_new(std::fs::File::create(&path)?, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+
+        let meta = partition_metadata::Partition {
+            key: "2020-10-01".to_string(),
+            tables: vec![partition_metadata::Table {
+                name: "cpu".to_string(),
+                columns: vec![ColumnSummary {
+                    name: "region".to_string(),
+                    stats: Column::Tag(Statistics {
+                        min: "east".to_string(),
+                        max: "west".to_string(),
+                        count: 2,
+                    }),
+                }],
+            }],
+            deletes: vec![],
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use arrow_deps::datafusion::{
-        logical_plan::{self, Literal},
-        scalar::ScalarValue,
-    };
-    use logical_plan::{Expr, Operator};
-    use storage::{
-        exec::fieldlist::{Field, FieldList},
-        exec::{
-            seriesset::{Error as SeriesSetError, SeriesSet},
-            Executor,
-        },
-        predicate::PredicateBuilder,
-        Database,
-    };
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        put_object(&store, "p1/meta.json", serde_json::to_vec(&meta)?).await?;
+        put_object(&store, "p1/cpu.parquet", std::fs::read(&path)?).await?;
 
-    use arrow::{
-        array::{Array, StringArray},
-        datatypes::DataType,
-        util::pretty::pretty_format_batches,
-    };
-    use influxdb_line_protocol::parse_lines;
-    use test_helpers::str_pair_vec_to_vec;
-    use tokio::sync::mpsc;
+        let rules = DatabaseRules {
+            read_only_partitions: vec!["p1".to_string()],
+            ..Default::default()
+        };
+        let db = Db::new_with_rules("foo", rules);
+        let lines: Vec<_> = parse_lines("cpu,region=north user=1.5 30\nmem val=1 30")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
 
-    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
-    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+        // loading again replaces the partitions loaded before
+        assert_eq!(db.load_read_only_partitions(&store).await?, 1);
+        assert_eq!(db.load_read_only_partitions(&store).await?, 1);
+
+        let results = db.query("select * from cpu order by time").await?;
+        let expected_cpu_table = r#"+--------+------+------+
+| region | time | user |
++--------+------+------+
+| west   | 10   | 23.2 |
+| east   | 20   |      |
+| north  | 30   | 1.5  |
++--------+------+------+
+"#;
+        assert_table_eq(expected_cpu_table, &results);
 
-    fn to_set(v: &[&str]) -> BTreeSet<String> {
-        v.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>()
-    }
+        let results = db.query("select * from mem").await?;
+        assert_eq!(results.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
 
-    // Abstract a bit of boilerplate around table assertions and improve the failure output.
-    // The default failure message uses Debug formatting, which prints newlines as `\n`.
-    // This prints the pretty_format_batches using Display so it's easier to read the tables.
-    fn assert_table_eq(table: &str, partitions: &[arrow::record_batch::RecordBatch]) {
-        let res = pretty_format_batches(partitions).unwrap();
-        assert_eq!(table, res, "\n\nleft:\n\n{}\nright:\n\n{}", table, res);
-    }
+        let names = table_names(&db, Predicate::default()).await?;
+        assert_eq!(names, to_set(&["cpu", "mem"]));
 
-    // query the table names, with optional range predicate
-    async fn table_names(db: &Db, predicate: Predicate) -> Result<StringSet> {
-        let plan = db.table_names(predicate).await?;
-        let executor = Executor::default();
-        let s = executor.to_string_set(plan).await?;
+        // the read-only partition takes no writes, and its memory isn't counted
+        let partitions = db.partitions.read().await;
+        let read_only = partitions
+            .iter()
+            .find(|p| p.read_only_id.is_some())
+            .expect("read-only partition was loaded");
+        assert!(!read_only.is_open);
+        assert!(read_only.has_table("cpu"));
+        let written_size: usize = partitions
+            .iter()
+            .filter(|p| p.read_only_id.is_none())
+            .map(|p| p.size())
+            .sum();
+        assert_eq!(db.size(), written_size);
 
-        // unwrap it (for easy test comparisons)
-        let s = Arc::try_unwrap(s).expect("only one reference to the set in the test");
-        Ok(s)
+        Ok(())
     }
 
     #[tokio::test]
-    async fn list_table_names() -> Result {
+    async fn snapshot_partition_to_object_store() -> Result {
+        use data_types::partition_metadata::{Column as ColumnStats, Statistics};
+        use object_store::InMemory;
+
         let mut dir = test_helpers::tmp_dir()?.into_path();
+        let store = ObjectStore::new_in_memory(InMemory::new());
+
+        {
+            let db = Db::try_with_wal("mydb", &mut dir).await?;
+            // timestamps that aren't whole microseconds must survive the snapshot
+            let lines: Vec<_> = parse_lines(
+                "cpu,region=west user=23.2,count=2i 10001\n\
+                 cpu,region=east user=10.0,active=true 20999\n\
+                 mem val=1u 30000",
+            )
+            .map(|l| l.unwrap())
+            .collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("disk bytes=5i 7200000000000")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+
+            let queries = ["select * from cpu", "select * from mem"];
+            let mut expected = vec![];
+            for query in &queries {
+                expected.push(pretty_format_batches(&db.query(query).await?)?);
+            }
+            let size = db.size();
+
+            let (partition_id, metadata) =
+                db.snapshot_partition(&store, 1, "1970-01-01T00").await?;
+            assert_eq!(partition_id, "1/mydb/1970-01-01T00/0");
+            assert_eq!(metadata.key, "1970-01-01T00");
+            let tables: Vec<_> = metadata.tables.iter().map(|t| t.name.as_str()).collect();
+            assert_eq!(tables, vec!["cpu", "mem"]);
+            assert_eq!(
+                metadata.tables[0].column("region"),
+                Some(&ColumnStats::Tag(Statistics {
+                    min: "east".to_string(),
+                    max: "west".to_string(),
+                    count: 2,
+                }))
+            );
 
-        let db = Db::try_with_wal("mydb", &mut dir).await?;
+            // the snapshotted partition is dropped from memory
+            {
+                let partitions = db.partitions.read().await;
+                let sequences: Vec<_> = partitions
+                    .iter()
+                    .map(|p| (p.key.as_str(), p.oldest_wal_sequence))
+                    .collect();
+                assert_eq!(sequences, vec![("1970-01-01T02", Some(1))]);
+                assert_eq!(db.size(), partitions[0].size());
+                assert!(db.size() < size);
+            }
+            assert!(db.query("select * from cpu").await.is_err());
 
-        // no tables initially
-        assert_eq!(
-            table_names(&db, Predicate::default()).await?,
-            BTreeSet::new()
-        );
+            // so there's nothing to snapshot now
+            let err = db
+                .snapshot_partition(&store, 1, "1970-01-01T00")
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, Error::SnapshotPartitionNotFound { .. }),
+                "{}",
+                err
+            );
 
-        // write two different tables
-        let lines: Vec<_> =
-            parse_lines("cpu,region=west user=23.2 10\ndisk,region=east bytes=99i 11")
-                .map(|l| l.unwrap())
-                .collect();
-        db.write_lines(&lines).await?;
+            // the snapshot holds the same data when loaded back
+            let rules = DatabaseRules {
+                read_only_partitions: vec!["1/mydb/1970-01-01T00/0".to_string()],
+                ..Default::default()
+            };
+            let loaded = Db::new_with_rules("loaded", rules);
+            loaded.load_read_only_partitions(&store).await?;
+            for (query, expected) in queries.iter().zip(&expected) {
+                assert_table_eq(expected, &loaded.query(query).await?);
+            }
+        }
 
-        // Now, we should see the two tables
-        assert_eq!(
-            table_names(&db, Predicate::default()).await?,
-            to_set(&["cpu", "disk"])
-        );
+        // entries are only deleted a whole file at a time, but the writes to the
+        // partition snapshotted aren't replayed
+        let db = Db::restore_from_wal(dir).await?;
+        assert_eq!(partition_keys(&db).await, vec!["1970-01-01T02"]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn list_table_names_timestamps() -> Result {
+    async fn restore_skips_snapshotted_writes() -> Result {
+        use data_types::partition_metadata::Column as ColumnStats;
+        use object_store::InMemory;
+
         let mut dir = test_helpers::tmp_dir()?.into_path();
+        let store = ObjectStore::new_in_memory(InMemory::new());
 
-        let db = Db::try_with_wal("mydb", &mut dir).await?;
+        {
+            let db = Db::try_with_wal("foo", &mut dir).await?;
+            for lp in &["cpu bar=1 10", "cpu bar=2 20"] {
+                let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+                db.write_lines(&lines).await?;
+            }
+            db.snapshot_partition(&store, 1, "1970-01-01T00").await?;
 
-        // write two different tables at the following times:
-        // cpu: 100 and 150
-        // disk: 200
-        let lines: Vec<_> =
-            parse_lines("cpu,region=west user=23.2 100\ncpu,region=west user=21.0 150\ndisk,region=east bytes=99i 200")
-                .map(|l| l.unwrap())
-                .collect();
-        db.write_lines(&lines).await?;
+            // goes to a new partition with the same key
+            let lines: Vec<_> = parse_lines("cpu bar=3 30").map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+        }
 
-        // Cover all times
-        let predicate = PredicateBuilder::default().timestamp_range(0, 201).build();
-        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu", "disk"]));
+        {
+            let db = Db::restore_from_wal(dir.clone()).await?;
+            let results = db.query("select * from cpu").await?;
+            assert_eq!(results.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+            let expected_cpu_table = r#"+-----+------+
+| bar | time |
++-----+------+
+| 3   | 30   |
++-----+------+
+"#;
+            assert_table_eq(expected_cpu_table, &results);
 
-        // Right before disk
-        let predicate = PredicateBuilder::default().timestamp_range(0, 200).build();
-        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+            db.snapshot_partition(&store, 1, "1970-01-01T00").await?;
+        }
 
-        // only one point of cpu
-        let predicate = PredicateBuilder::default().timestamp_range(50, 101).build();
-        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+        // the snapshots hold every row written, and nothing is left to replay
+        let mut counts = vec![];
+        for id in &["1/foo/1970-01-01T00/0", "1/foo/1970-01-01T00/1"] {
+            let metadata = load_metadata(&store, id).await?;
+            match metadata.tables[0].column("bar") {
+                Some(ColumnStats::F64(stats)) => counts.push(stats.count),
+                column => panic!("unexpected bar column {:?}", column),
+            }
+        }
+        assert_eq!(counts, vec![2, 1]);
 
-        // no ranges
-        let predicate = PredicateBuilder::default()
-            .timestamp_range(250, 350)
-            .build();
-        assert_eq!(table_names(&db, predicate).await?, to_set(&[]));
+        let db = Db::restore_from_wal(dir).await?;
+        assert!(partition_keys(&db).await.is_empty());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn missing_tags_are_null() -> Result {
-        let mut dir = test_helpers::tmp_dir()?.into_path();
+    async fn snapshots_of_a_partition_key_have_unique_ids() -> Result {
+        use data_types::partition_metadata::{Column as ColumnStats, Statistics};
+        use object_store::InMemory;
 
-        let db = Db::try_with_wal("mydb", &mut dir).await?;
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let db = Db::new("foo");
 
-        // Note the `region` tag is introduced in the second line, so
-        // the values in prior rows for the region column are
-        // null. Likewise the `core` tag is introduced in the third
-        // line so the prior columns are null
-        let lines: Vec<_> = parse_lines(
-            "cpu,region=west user=23.2 10\n\
-                         cpu, user=10.0 11\n\
-                         cpu,core=one user=10.0 11\n",
-        )
-        .map(|l| l.unwrap())
-        .collect();
-        db.write_lines(&lines).await?;
+        let mut ids = vec![];
+        for lp in &["cpu bar=1 10", "cpu bar=2 20"] {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+            let (id, _) = db.snapshot_partition(&store, 1, "1970-01-01T00").await?;
+            ids.push(id);
+        }
 
-        let partitions = db.table_to_arrow("cpu", &["region", "core"]).await?;
-        let columns = partitions[0].columns();
+        // a new database with the same name, as after a restart, continues from the
+        // snapshots in the store
+        let db = Db::new("foo");
+        let lines: Vec<_> = parse_lines("cpu bar=3 30").map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+        let (id, _) = db.snapshot_partition(&store, 1, "1970-01-01T00").await?;
+        ids.push(id);
 
         assert_eq!(
-            2,
-            columns.len(),
-            "Got only two columns in partiton: {:#?}",
-            columns
+            ids,
+            vec![
+                "1/foo/1970-01-01T00/0",
+                "1/foo/1970-01-01T00/1",
+                "1/foo/1970-01-01T00/2"
+            ]
         );
 
-        let region_col = columns[0]
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .expect("Get region column as a string");
+        // each snapshot kept its own data
+        for (id, bar) in ids.iter().zip(&[1.0, 2.0, 3.0]) {
+            let metadata = load_metadata(&store, id).await?;
+            assert_eq!(
+                metadata.tables[0].column("bar"),
+                Some(&ColumnStats::F64(Statistics {
+                    min: *bar,
+                    max: *bar,
+                    count: 1,
+                }))
+            );
+        }
 
-        assert_eq!(region_col.len(), 3);
-        assert_eq!(region_col.value(0), "west", "region_col: {:?}", region_col);
-        assert!(!region_col.is_null(0), "is_null(0): {:?}", region_col);
-        assert!(region_col.is_null(1), "is_null(1): {:?}", region_col);
-        assert!(region_col.is_null(2), "is_null(1): {:?}", region_col);
+        Ok(())
+    }
 
-        let host_col = columns[1]
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .expect("Get host column as a string");
+    #[tokio::test]
+    async fn lifecycle_rules_close_partitions() -> Result {
+        use data_types::database_rules::LifecycleRules;
+        use object_store::InMemory;
+
+        let rules = DatabaseRules {
+            lifecycle_rules: LifecycleRules {
+                max_open_partitions: Some(1),
+                ..Default::default()
+            },
+            ..default_rules()
+        };
+        let db = Db::new_with_rules("foo", rules);
+        let store = ObjectStore::new_in_memory(InMemory::new());
 
-        assert_eq!(host_col.len(), 3);
-        assert!(host_col.is_null(0), "is_null(0): {:?}", host_col);
-        assert!(host_col.is_null(1), "is_null(1): {:?}", host_col);
-        assert!(!host_col.is_null(2), "is_null(2): {:?}", host_col);
-        assert_eq!(host_col.value(2), "one", "host_col: {:?}", host_col);
+        for lp in &["cpu bar=1 10", "cpu bar=2 7200000000000"] {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+        }
+
+        let size = db.size();
+        let closed = db.apply_lifecycle_rules(&store, 1, Instant::now()).await?;
+        assert_eq!(closed, vec!["1970-01-01T00"]);
+
+        // the closed partition is dropped once it's snapshotted, freeing its memory
+        assert_eq!(partition_keys(&db).await, vec!["1970-01-01T02"]);
+        assert!(db.size() < size);
+        let partitions_size: usize = db.partitions.read().await.iter().map(|p| p.size()).sum();
+        assert_eq!(db.size(), partitions_size);
+        let metadata = load_metadata(&store, "1/foo/1970-01-01T00/0").await?;
+        assert_eq!(metadata.max_time(), Some(10));
+
+        // nothing is added to the rules for every partition closed
+        assert!(db.rules().await.read_only_partitions.is_empty());
+        let results = db.query("select * from cpu order by time").await?;
+        let expected_cpu_table = r#"+-----+---------------+
+| bar | time          |
++-----+---------------+
+| 2   | 7200000000000 |
++-----+---------------+
+"#;
+        assert_table_eq(expected_cpu_table, &results);
+
+        let closed = db.apply_lifecycle_rules(&store, 1, Instant::now()).await?;
+        assert!(closed.is_empty());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn write_data_and_recover() -> Result {
-        let mut dir = test_helpers::tmp_dir()?.into_path();
+    async fn retention_drops_expired_data() -> Result {
+        use chrono::TimeZone;
+        use object_store::InMemory;
 
-        let expected_cpu_table = r#"+--------+------+------+-------+-------------+------+------+---------+-----------+
-| region | host | user | other | str         | b    | time | new_tag | new_field |
-+--------+------+------+-------+-------------+------+------+---------+-----------+
-| west   | A    | 23.2 | 1     | some string | true | 10   |         |           |
-| west   | B    | 23.1 |       |             |      | 15   |         |           |
-|        | A    |      |       |             |      | 20   | foo     | 15.1      |
-+--------+------+------+-------+-------------+------+------+---------+-----------+
-"#;
-        let expected_mem_table = r#"+--------+------+-------+------+
-| region | host | val   | time |
-+--------+------+-------+------+
-| east   | C    | 23432 | 10   |
-+--------+------+-------+------+
-"#;
-        let expected_disk_table = r#"+--------+------+----------+--------------+------+
-| region | host | bytes    | used_percent | time |
-+--------+------+----------+--------------+------+
-| west   | A    | 23432323 | 76.2         | 10   |
-+--------+------+----------+--------------+------+
-"#;
+        let db = Db::new("foo");
+        let store = ObjectStore::new_in_memory(InMemory::new());
+
+        for lp in &[
+            "cpu bar=1 10",
+            "cpu bar=2 3600000000000",
+            "cpu bar=3 7200000000000",
+        ] {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+        }
+        db.snapshot_partition(&store, 1, "1970-01-01T00").await?;
+        db.snapshot_partition(&store, 1, "1970-01-01T02").await?;
+        let lines: Vec<_> = parse_lines("cpu bar=3 7200000000000")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
 
-        let cpu_columns = &[
-            "region",
-            "host",
-            "user",
-            "other",
-            "str",
-            "b",
-            "time",
-            "new_tag",
-            "new_field",
-        ];
-        let mem_columns = &["region", "host", "val", "time"];
-        let disk_columns = &["region", "host", "bytes", "used_percent", "time"];
+        // nothing expires without a retention period
+        let now = Utc.timestamp_nanos(9_000_000_000_000);
+        assert!(db.drop_expired_partitions(&now).await?.is_empty());
+        assert!(db
+            .delete_expired_snapshots(&store, 1, &now)
+            .await?
+            .is_empty());
+
+        db.set_rules(DatabaseRules {
+            retention_period_seconds: Some(3600),
+            ..default_rules()
+        })
+        .await?;
+
+        // the snapshotted partitions were already dropped from memory
+        let dropped = db.drop_expired_partitions(&now).await?;
+        assert_eq!(dropped, vec!["1970-01-01T01"]);
+        assert_eq!(partition_keys(&db).await, vec!["1970-01-01T02"]);
+
+        let deleted = db.delete_expired_snapshots(&store, 1, &now).await?;
+        assert_eq!(deleted, vec!["1/foo/1970-01-01T00/0"]);
+        let remaining: Vec<String> = store.list(None).await?.try_concat().await?;
+        assert!(!remaining.is_empty());
+        assert!(remaining
+            .iter()
+            .all(|location| location.starts_with("1/foo/1970-01-01T02/")));
+
+        let lines: Vec<_> = parse_lines("cpu bar=4 10").map(|l| l.unwrap()).collect();
+        let err = db.write_lines(&lines).await.unwrap_err();
+        assert!(
+            matches!(err, Error::WriteOutsideRetention { timestamp: 10, .. }),
+            "{}",
+            err
+        );
 
-        {
-            let db = Db::try_with_wal("mydb", &mut dir).await?;
-            let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i,str=\"some string\",b=true 10\ndisk,region=west,host=A bytes=23432323i,used_percent=76.2 10").map(|l| l.unwrap()).collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("cpu,region=west,host=B user=23.1 15")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("cpu,host=A,new_tag=foo new_field=15.1 20")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("mem,region=east,host=C val=23432 10")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
+        // replicated writes from other servers are held to the same retention period
+        let write = lines_to_replicated_write(2, 1, &lines, &default_rules())?;
+        let err = db.store_replicated_write(&write).await.unwrap_err();
+        assert!(
+            matches!(err, Error::WriteOutsideRetention { timestamp: 10, .. }),
+            "{}",
+            err
+        );
 
-            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
-            assert_table_eq(expected_cpu_table, &partitions);
+        // lines without a timestamp are written at the current time
+        let lines: Vec<_> = parse_lines("cpu bar=5").map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
 
-            let partitions = db.table_to_arrow("mem", mem_columns).await?;
-            assert_table_eq(expected_mem_table, &partitions);
+        Ok(())
+    }
 
-            let partitions = db.table_to_arrow("disk", disk_columns).await?;
-            assert_table_eq(expected_disk_table, &partitions);
+    #[tokio::test]
+    async fn restart_after_expired_snapshots_deleted() -> Result {
+        use chrono::TimeZone;
+        use object_store::InMemory;
+
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let writer = Db::new("foo");
+        let mut ids = vec![];
+        for (lp, key) in &[
+            ("cpu bar=1 10", "1970-01-01T00"),
+            ("cpu bar=2 7200000000000", "1970-01-01T02"),
+        ] {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            writer.write_lines(&lines).await?;
+            let (id, _) = writer.snapshot_partition(&store, 1, key).await?;
+            ids.push(id);
         }
 
-        // check that it recovers from the wal
+        let mut dir = test_helpers::tmp_dir()?.into_path();
         {
-            let db = Db::restore_from_wal(dir).await?;
-
-            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
-            assert_table_eq(expected_cpu_table, &partitions);
+            let rules = DatabaseRules {
+                read_only_partitions: ids.clone(),
+                retention_period_seconds: Some(3600),
+                ..default_rules()
+            };
+            let db = Db::try_with_rules_and_wal("foo", rules, &mut dir).await?;
+            assert_eq!(db.load_read_only_partitions(&store).await?, 2);
+
+            let now = Utc.timestamp_nanos(9_000_000_000_000);
+            let deleted = db.delete_expired_snapshots(&store, 1, &now).await?;
+            assert_eq!(deleted, vec!["1/foo/1970-01-01T00/0"]);
+            assert_eq!(
+                db.rules().await.read_only_partitions,
+                vec!["1/foo/1970-01-01T02/1"]
+            );
+            assert_eq!(partition_keys(&db).await, vec!["1970-01-01T02"]);
+        }
 
-            let partitions = db.table_to_arrow("mem", mem_columns).await?;
-            assert_table_eq(expected_mem_table, &partitions);
+        // the rules persisted leave out the snapshot deleted, so it loads after a restart
+        let db = Db::restore_from_wal(dir).await?;
+        assert_eq!(
+            db.rules().await.read_only_partitions,
+            vec!["1/foo/1970-01-01T02/1"]
+        );
+        assert_eq!(db.load_read_only_partitions(&store).await?, 1);
+        let results = db.query("select * from cpu").await?;
+        let expected_cpu_table = r#"+-----+---------------+
+| bar | time          |
++-----+---------------+
+| 2   | 7200000000000 |
++-----+---------------+
+"#;
+        assert_table_eq(expected_cpu_table, &results);
 
-            let partitions = db.table_to_arrow("disk", disk_columns).await?;
-            assert_table_eq(expected_disk_table, &partitions);
-        }
+        // a snapshot the rules still refer to, as when deleting it was interrupted, is skipped
+        db.set_rules(DatabaseRules {
+            read_only_partitions: ids,
+            ..default_rules()
+        })
+        .await?;
+        assert_eq!(db.load_read_only_partitions(&store).await?, 1);
+        assert_eq!(partition_keys(&db).await, vec!["1970-01-01T02"]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn write_and_query() -> Result {
-        let db = Db::new("foo");
+    async fn memory_limits_reject_writes() -> Result {
+        use data_types::database_rules::LifecycleRules;
 
-        let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i 10")
+        let lines: Vec<_> = parse_lines("cpu,host=A bar=1 10")
             .map(|l| l.unwrap())
             .collect();
+
+        let rules = DatabaseRules {
+            lifecycle_rules: LifecycleRules {
+                buffer_size_hard: Some(0),
+                ..Default::default()
+            },
+            ..default_rules()
+        };
+        let db = Db::new_with_rules("foo", rules);
+        assert_eq!(db.size(), 0);
+
+        // the write that goes over the limit is accepted, the next ones aren't
         db.write_lines(&lines).await?;
+        let size = db.size();
+        assert!(size > 0);
 
-        let results = db.query("select * from cpu").await?;
+        let err = db.write_lines(&lines).await.unwrap_err();
+        assert!(matches!(err, Error::BufferFull { limit: 0, .. }), "{}", err);
+        assert!(err.is_resource_exhausted());
 
-        let expected_cpu_table = r#"+------+-------+--------+------+------+
-| host | other | region | time | user |
-+------+-------+--------+------+------+
-| A    | 1     | west   | 10   | 23.2 |
-+------+-------+--------+------+------+
-"#;
+        let write = lines_to_replicated_write(1, 1, &lines, &default_rules())?;
+        let err = db.store_replicated_write(&write).await.unwrap_err();
+        assert!(matches!(err, Error::BufferFull { .. }), "{}", err);
 
-        assert_table_eq(expected_cpu_table, &results);
+        db.set_rules(default_rules()).await?;
+        db.write_lines(&lines).await?;
+        assert!(db.size() > size);
+
+        // the server limit applies to all the databases sharing the tracker
+        let memory = Arc::new(MemoryTracker::new(Some(db.size())));
+        let db = db.with_memory_tracker(Arc::clone(&memory));
+        let other = Db::new("bar").with_memory_tracker(Arc::clone(&memory));
+        assert_eq!(memory.used(), db.size());
+
+        other.write_lines(&lines).await?;
+        assert_eq!(memory.used(), db.size() + other.size());
+
+        let err = db.write_lines(&lines).await.unwrap_err();
+        assert!(matches!(err, Error::ServerBufferFull { .. }), "{}", err);
+        assert!(err.is_resource_exhausted());
+
+        // dropping data frees up memory
+        other
+            .set_rules(DatabaseRules {
+                retention_period_seconds: Some(1),
+                ..default_rules()
+            })
+            .await?;
+        other.drop_expired_partitions(&Utc::now()).await?;
+        assert_eq!(other.size(), 0);
+        assert_eq!(memory.used(), db.size());
+        db.write_lines(&lines).await?;
+
+        // the size is kept up to date as partitions are written to
+        let partitions_size: usize = db.partitions.read().await.iter().map(|p| p.size()).sum();
+        assert_eq!(db.size(), partitions_size);
+
+        // and a database that's dropped releases what it used
+        let other = Db::new("baz");
+        other.write_lines(&lines).await?;
+        let other = other.with_memory_tracker(Arc::clone(&memory));
+        assert!(other.size() > 0);
+        assert_eq!(memory.used(), db.size() + other.size());
+        drop(other);
+        assert_eq!(memory.used(), db.size());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn recover_partial_entries() -> Result {
+    async fn restore_many_partitions_from_wal() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
 
-        let expected_cpu_table = r#"+--------+------+------+-------+-------------+------+------+---------+-----------+
-| region | host | user | other | str         | b    | time | new_tag | new_field |
-+--------+------+------+-------+-------------+------+------+---------+-----------+
-| west   | A    | 23.2 | 1     | some string | true | 10   |         |           |
-| west   | B    | 23.1 |       |             |      | 15   |         |           |
-|        | A    |      |       |             |      | 20   | foo     | 15.1      |
-+--------+------+------+-------+-------------+------+------+---------+-----------+
-"#;
+        let db = Db::try_with_wal("manydb", &mut dir).await?;
+        for hour in 0..10 {
+            let lp = format!("cpu,host=A bar={} {}", hour, hour * 3_600_000_000_000i64);
+            let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+        }
+        // deletes apply to every partition written before them
+        db.delete(&Delete::from_influx(0, i64::MAX, r#"host="A""#)?)
+            .await?;
+        let lines: Vec<_> = parse_lines("cpu,host=A bar=10 10")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
 
-        let expected_mem_table = r#"+--------+------+-------+------+
-| region | host | val   | time |
-+--------+------+-------+------+
-| east   | C    | 23432 | 10   |
-+--------+------+-------+------+
-"#;
-        let expected_disk_table = r#"+--------+------+----------+--------------+------+
-| region | host | bytes    | used_percent | time |
-+--------+------+----------+--------------+------+
-| west   | A    | 23432323 | 76.2         | 10   |
-+--------+------+----------+--------------+------+
+        let wal_builder = WalBuilder::new(&dir);
+        let (partitions, stats) =
+            restore_partitions_from_wal(wal_builder.entries()?, &Default::default())?;
+
+        let keys: Vec<_> = partitions.iter().map(|p| p.key.clone()).collect();
+        let expected: Vec<_> = (0..10).map(|h| format!("1970-01-01T{:02}", h)).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(stats.wal_entries, 12);
+        assert_eq!(stats.last_wal_sequence, Some(11));
+        assert_eq!(stats.row_count, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_and_restore_from_wal() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let expected_cpu_table = r#"+------+-------+------+
+| host | usage | time |
++------+-------+------+
+| B    | 2     | 20   |
+| A    | 3     | 30   |
++------+-------+------+
 "#;
+        let cpu_columns = &["host", "usage", "time"];
 
-        let cpu_columns = &[
-            "region",
-            "host",
-            "user",
-            "other",
-            "str",
-            "b",
-            "time",
-            "new_tag",
-            "new_field",
-        ];
-        let mem_columns = &["region", "host", "val", "time"];
-        let disk_columns = &["region", "host", "bytes", "used_percent", "time"];
         {
-            let db = Db::try_with_wal("mydb", &mut dir).await?;
-            let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i,str=\"some string\",b=true 10\ndisk,region=west,host=A bytes=23432323i,used_percent=76.2 10").map(|l| l.unwrap()).collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("cpu,region=west,host=B user=23.1 15")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("cpu,host=A,new_tag=foo new_field=15.1 20")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("mem,region=east,host=C val=23432 10")
-                .map(|l| l.unwrap())
-                .collect();
+            let db = Db::try_with_wal("deletedb", &mut dir).await?;
+            let lines: Vec<_> = parse_lines(
+                "cpu,host=A usage=1 10\n\
+                 cpu,host=B usage=2 20\n\
+                 cpu,host=A usage=3 30\n\
+                 mem,host=A used=4 10",
+            )
+            .map(|l| l.unwrap())
+            .collect();
             db.write_lines(&lines).await?;
 
+            db.delete(&Delete::from_influx(
+                0,
+                20,
+                r#"_measurement="cpu" AND host="A""#,
+            )?)
+            .await?;
+            db.delete(&Delete {
+                table_name: Some("mem".to_string()),
+                predicate: None,
+            })
+            .await?;
+
             let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
             assert_table_eq(expected_cpu_table, &partitions);
-
-            let partitions = db.table_to_arrow("mem", mem_columns).await?;
-            assert_table_eq(expected_mem_table, &partitions);
-
-            let partitions = db.table_to_arrow("disk", disk_columns).await?;
-            assert_table_eq(expected_disk_table, &partitions);
+            assert!(!db
+                .partitions
+                .read()
+                .await
+                .iter()
+                .any(|p| p.has_table("mem")));
         }
 
-        // check that it can recover from the last 2 self-describing entries of the wal
+        // check that the deletes are replayed from the wal
         {
-            let name = dir.iter().last().unwrap().to_str().unwrap().to_string();
+            let db = Db::restore_from_wal(dir).await?;
 
-            let wal_builder = WalBuilder::new(&dir);
+            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+            assert!(!db
+                .partitions
+                .read()
+                .await
+                .iter()
+                .any(|p| p.has_table("mem")));
+        }
 
-            let wal_entries = wal_builder
-                .entries()
-                .context(LoadingWal { database: &name })?;
+        Ok(())
+    }
 
-            // Skip the first 2 entries in the wal; only restore from the last 2
-            let wal_entries = wal_entries.skip(2);
+    #[tokio::test]
+    async fn deletes_of_snapshotted_data_survive_restarts() -> Result {
+        use object_store::InMemory;
 
-            let (partitions, _stats) = restore_partitions_from_wal(wal_entries)?;
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let partition_id = "1/foo/1970-01-01T00/0";
 
-            let db = Db {
-                name,
-                partitions: RwLock::new(partitions),
-                wal_details: None,
-            };
+        {
+            let db = Db::try_with_wal("foo", &mut dir)
+                .await?
+                .with_object_store(Arc::clone(&store), Some(1));
+            let lines: Vec<_> = parse_lines(
+                "cpu,host=A bar=1 10
+                 cpu,host=B bar=2 20
+                 mem,host=A val=3 30",
+            )
+            .map(|l| l.unwrap())
+            .collect();
+            db.write_lines(&lines).await?;
+            db.snapshot_partition(&store, 1, "1970-01-01T00").await?;
+
+            db.delete(&Delete::from_influx(
+                0,
+                i64::MAX,
+                r#"_measurement="cpu" AND host="A""#,
+            )?)
+            .await?;
+            assert_eq!(load_metadata(&store, partition_id).await?.deletes.len(), 1);
+        }
 
-            // some cpu
-            let smaller_cpu_table = r#"+------+---------+-----------+------+
-| host | new_tag | new_field | time |
-+------+---------+-----------+------+
-| A    | foo     | 15.1      | 20   |
-+------+---------+-----------+------+
+        // the rows deleted from the snapshot stay deleted when it's loaded after a restart
+        let db = Db::restore_from_wal(dir)
+            .await?
+            .with_object_store(Arc::clone(&store), Some(1));
+        db.set_rules(DatabaseRules {
+            read_only_partitions: vec![partition_id.to_string()],
+            ..default_rules()
+        })
+        .await?;
+        assert_eq!(db.load_read_only_partitions(&store).await?, 1);
+
+        let expected_cpu_table = r#"+-----+------+------+
+| bar | host | time |
++-----+------+------+
+| 2   | B    | 20   |
++-----+------+------+
 "#;
-            let smaller_cpu_columns = &["host", "new_tag", "new_field", "time"];
-            let partitions = db.table_to_arrow("cpu", smaller_cpu_columns).await?;
-            assert_table_eq(smaller_cpu_table, &partitions);
+        let results = db.query("select * from cpu").await?;
+        assert_table_eq(expected_cpu_table, &results);
+        let names = table_names(&db, Predicate::default()).await?;
+        assert_eq!(names, to_set(&["cpu", "mem"]));
 
-            // all of mem
-            let partitions = db.table_to_arrow("mem", mem_columns).await?;
-            assert_table_eq(expected_mem_table, &partitions);
+        // as do those deleted from a read-only partition
+        db.delete(&Delete {
+            table_name: Some("mem".to_string()),
+            predicate: None,
+        })
+        .await?;
+        assert_eq!(load_metadata(&store, partition_id).await?.deletes.len(), 2);
+        assert_eq!(db.load_read_only_partitions(&store).await?, 1);
 
-            // no disk
-            let nonexistent_table = db.table_to_arrow("disk", disk_columns).await;
-            assert!(nonexistent_table.is_err());
-            let actual_message = format!("{:?}", nonexistent_table);
-            let expected_message = "TableNameNotFoundInDictionary";
-            assert!(
-                actual_message.contains(expected_message),
-                "Did not find '{}' in '{}'",
-                expected_message,
-                actual_message
-            );
-        }
+        let results = db.query("select * from cpu").await?;
+        assert_table_eq(expected_cpu_table, &results);
+        let names = table_names(&db, Predicate::default()).await?;
+        assert_eq!(names, to_set(&["cpu"]));
 
         Ok(())
     }
 
-    #[tokio::test]
-    async fn db_partition_key() -> Result {
-        let partition_keys: Vec<_> = parse_lines(
-            "\
-cpu user=23.2 1600107710000000000
-disk bytes=23432323i 1600136510000000000",
-        )
-        .map(|line| partition_key(&line.unwrap()))
-        .collect();
-
-        assert_eq!(partition_keys, vec!["2020-09-14T18", "2020-09-15T02"]);
-
+    async fn put_object(store: &ObjectStore, location: &str, data: Vec<u8>) -> Result {
+        let len = data.len();
+        let data = std::io::Result::Ok(bytes::Bytes::from(data));
+        store
+            .put(location, futures::stream::once(async move { data }), len)
+            .await?;
         Ok(())
     }
 
@@ -1685,6 +3873,51 @@ disk bytes=23432323i 1600136510000000000",
         Ok(())
     }
 
+    #[tokio::test]
+    async fn list_column_values_partitions() -> Result {
+        // each table in its own partition, so the o2 partition has
+        // no `city` column at all
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![TemplatePart::Table]),
+            ..Default::default()
+        };
+        let db = Db::new_with_rules("column_valuesdb", rules);
+
+        let lp_data = "h2o,state=CA,city=LA temp=70.4 100\n\
+                       h2o,state=MA,city=Boston temp=72.4 250\n\
+                       o2,state=MA temp=50.4 200\n";
+        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        let executor = Executor::default();
+        let cases = vec![
+            (PredicateBuilder::default().build(), vec!["Boston", "LA"]),
+            (PredicateBuilder::default().table("o2").build(), vec![]),
+            (
+                PredicateBuilder::default()
+                    .add_expr(make_column_eq_expr("state", "MA"))
+                    .build(),
+                vec!["Boston"],
+            ),
+            (
+                PredicateBuilder::default()
+                    .table("o2")
+                    .add_expr(make_column_eq_expr("state", "MA"))
+                    .build(),
+                vec![],
+            ),
+        ];
+
+        for (predicate, expected) in cases {
+            let description = format!("{:?}", predicate);
+            let plan = db.column_values("city", predicate).await?;
+            let actual = executor.to_string_set(plan).await?;
+            assert_eq!(*actual, to_set(&expected), "predicate: {}", description);
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn list_column_values() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
@@ -1783,6 +4016,15 @@ disk bytes=23432323i 1600136510000000000",
                     .build(),
                 expected_column_values: Ok(vec!["NY"]),
             },
+            TestCase {
+                description: "Restrictions: measurement name and predicate, with nulls",
+                column_name: "city",
+                predicate: PredicateBuilder::default()
+                    .table("o2")
+                    .add_expr(make_column_neq_expr("state", "NY")) // state!=NY
+                    .build(),
+                expected_column_values: Ok(vec!["Boston"]),
+            },
             TestCase {
                 description: "Restrictions: measurement name, timestamp and predicate: no match",
                 column_name: "state",
@@ -1960,6 +4202,195 @@ disk bytes=23432323i 1600136510000000000",
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_query_groups_aggregate() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+
+        // the second hour is in another partition, in which h2o has
+        // another field
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.5 100",
+            "h2o,state=MA,city=Boston temp=72.0 250",
+            "h2o,state=CA,city=LA temp=90.0 200",
+            "h2o,state=MA,city=Boston temp=60.0,reading=5i 3600000000100",
+            "o2,state=MA,city=Boston reading=\"good\" 100",
+        ];
+
+        let lp_data = lp_lines.join("\n");
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+        assert_eq!(db.len().await, 2);
+
+        let plans = db
+            .query_groups(
+                Predicate::default(),
+                vec!["state".into()],
+                Some(Aggregate::Sum),
+            )
+            .await?;
+        let series = run_and_gather_aggregates(plans).await;
+
+        // o2 has no field that can be summed
+        assert_eq!(
+            series,
+            vec![
+                "h2o state=CA city=LA reading=null temp=90 time=200",
+                "h2o state=MA city=Boston reading=5 temp=202.5 time=3600000000100",
+            ]
+        );
+
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(200, 3600000000200)
+            .build();
+        let plans = db
+            .query_groups(predicate, vec!["state".into()], Some(Aggregate::Count))
+            .await?;
+        let series = run_and_gather_aggregates(plans).await;
+
+        assert_eq!(
+            series,
+            vec![
+                "h2o state=CA city=LA reading=0 temp=1 time=200",
+                "h2o state=MA city=Boston reading=1 temp=2 time=3600000000100",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_window_aggregate() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.5 100",
+            "h2o,state=MA,city=Boston temp=72.0 250",
+            "h2o,state=MA,city=Boston temp=60.0 3600000000100",
+            "h2o,state=CA,city=LA temp=90.0 200",
+            "h2o,state=CA,city=LA temp=80.0 300",
+            "o2,state=MA,city=Boston reading=\"good\" 150",
+            "o2,state=MA,city=Boston reading=\"bad\" 180",
+        ];
+
+        let lp_data = lp_lines.join("\n");
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        // windows are [50, 150), [150, 250) ...
+        let window = Window::new(100, 50)?;
+
+        let plans = db
+            .query_window_aggregate(Predicate::default(), window, Aggregate::Mean)
+            .await?;
+        let series = gather_aggregates(run_and_gather_results(plans).await);
+
+        // o2 has no field that can be averaged
+        assert_eq!(
+            series,
+            vec![
+                "h2o city=Boston state=MA temp=60 time=3600000000150",
+                "h2o city=Boston state=MA temp=70.5 time=150",
+                "h2o city=Boston state=MA temp=72 time=350",
+                "h2o city=LA state=CA temp=90 time=250",
+                "h2o city=LA state=CA temp=80 time=350",
+            ]
+        );
+
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(150, 300)
+            .build();
+        let plans = db
+            .query_window_aggregate(predicate, window, Aggregate::Last)
+            .await?;
+        let series = gather_aggregates(run_and_gather_results(plans).await);
+
+        // the window [250, 350) is cut short by the range
+        assert_eq!(
+            series,
+            vec![
+                "h2o city=Boston state=MA temp=72 time=300",
+                "h2o city=LA state=CA temp=90 time=250",
+                "o2 city=Boston state=MA reading=bad time=250",
+            ]
+        );
+
+        Ok(())
+    }
+
+    /// Runs `plans` and returns the rows of each series as lines with
+    /// their table, tags, fields and timestamp
+    async fn run_and_gather_aggregates(plans: GroupedSeriesSetPlans) -> Vec<String> {
+        let (tx, mut rx) = mpsc::channel(100);
+
+        let executor = Executor::default();
+        executor
+            .to_grouped_series_set(plans, tx)
+            .await
+            .expect("Running grouped series set plan");
+
+        let mut results = Vec::new();
+        while let Some(item) = rx.recv().await {
+            match item {
+                Ok(GroupedSeriesSetItem::GroupStart(_)) => (),
+                Ok(GroupedSeriesSetItem::GroupData(series_set)) => results.push(Ok(series_set)),
+                Err(e) => results.push(Err(e)),
+            }
+        }
+
+        gather_aggregates(results)
+    }
+
+    /// Returns the rows of `results` as sorted lines with their table,
+    /// tags, fields and timestamp
+    fn gather_aggregates(results: Vec<Result<SeriesSet, SeriesSetError>>) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for series_set in results {
+            let series_set = series_set.expect("Correctly converted");
+            let batch = &series_set.batch;
+
+            let value = |i: usize, row: usize| {
+                let column = batch.column(i);
+                if column.is_null(row) {
+                    return "null".to_string();
+                }
+                match column.data_type() {
+                    DataType::Float64 => {
+                        let values = column.as_any().downcast_ref::<Float64Array>().unwrap();
+                        values.value(row).to_string()
+                    }
+                    DataType::Int64 => {
+                        let values = column.as_any().downcast_ref::<Int64Array>().unwrap();
+                        values.value(row).to_string()
+                    }
+                    DataType::Utf8 => {
+                        let values = column.as_any().downcast_ref::<StringArray>().unwrap();
+                        values.value(row).to_string()
+                    }
+                    data_type => panic!("unexpected aggregate type {:?}", data_type),
+                }
+            };
+
+            for row in series_set.start_row..series_set.start_row + series_set.num_rows {
+                let mut line = series_set.table_name.to_string();
+                for (key, tag_value) in &series_set.tags {
+                    line.push_str(&format!(" {}={}", key, tag_value));
+                }
+                for &i in series_set.field_indices.iter() {
+                    let name = batch.schema().field(i).name().clone();
+                    line.push_str(&format!(" {}={}", name, value(i, row)));
+                }
+                line.push_str(&format!(" time={}", value(series_set.timestamp_index, row)));
+                lines.push(line);
+            }
+        }
+
+        lines.sort();
+        lines
+    }
+
     #[tokio::test]
     async fn test_query_series_pred_refers_to_column_not_in_table() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
@@ -2043,6 +4474,73 @@ disk bytes=23432323i 1600136510000000000",
         db.query_series(predicate).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_query_series_regex_pred() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=CA,city=LA temp=90.0 200",
+            "o2,state=MA,city=Boston temp=50.4 100",
+        ];
+
+        let lp_data = lp_lines.join("\n");
+
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        // city =~ /^L/: no city of o2 matches, so it is pruned
+        let predicate = PredicateBuilder::default()
+            .add_expr(RegexMatch::new("city", "^L", false)?.to_datafusion_expr())
+            .build();
+
+        let plans = db.query_series(predicate).await?;
+        assert_eq!(plans.plans.len(), 1);
+
+        let results = run_and_gather_results(plans).await;
+        assert_eq!(results.len(), 1);
+
+        let series_set0 = results[0].as_ref().expect("Correctly converted");
+        assert_eq!(*series_set0.table_name, "h2o");
+        assert_eq!(
+            series_set0.tags,
+            str_pair_vec_to_vec(&[("city", "LA"), ("state", "CA")])
+        );
+
+        // city !~ /^L/
+        let predicate = PredicateBuilder::default()
+            .add_expr(RegexMatch::new("city", "^L", true)?.to_datafusion_expr())
+            .build();
+
+        let plans = db.query_series(predicate).await?;
+        assert_eq!(plans.plans.len(), 2);
+
+        let results = run_and_gather_results(plans).await;
+        let tables_and_tags: Vec<_> = results
+            .iter()
+            .map(|r| {
+                let series_set = r.as_ref().expect("Correctly converted");
+                (series_set.table_name.to_string(), series_set.tags.clone())
+            })
+            .collect();
+        assert_eq!(
+            tables_and_tags,
+            vec![
+                (
+                    "h2o".to_string(),
+                    str_pair_vec_to_vec(&[("city", "Boston"), ("state", "MA")])
+                ),
+                (
+                    "o2".to_string(),
+                    str_pair_vec_to_vec(&[("city", "Boston"), ("state", "MA")])
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_field_columns() -> Result {
         // Ensure that the database queries are hooked up correctly
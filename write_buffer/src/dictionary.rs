@@ -1,6 +1,7 @@
 //! Contains a structure to map from strings to u32 symbols based on
 //! string interning.
 use snafu::{OptionExt, Snafu};
+use std::mem;
 use string_interner::{
     backend::StringBackend, DefaultHashBuilder, DefaultSymbol, StringInterner, Symbol,
 };
@@ -17,9 +18,11 @@ pub enum Error {
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Debug)]
-pub struct Dictionary(
-    StringInterner<DefaultSymbol, StringBackend<DefaultSymbol>, DefaultHashBuilder>,
-);
+pub struct Dictionary {
+    interner: StringInterner<DefaultSymbol, StringBackend<DefaultSymbol>, DefaultHashBuilder>,
+    /// The total length of the strings in the dictionary
+    string_bytes: usize,
+}
 
 impl Default for Dictionary {
     fn default() -> Self {
@@ -29,13 +32,21 @@ impl Default for Dictionary {
 
 impl Dictionary {
     pub fn new() -> Self {
-        Self(StringInterner::new())
+        Self {
+            interner: StringInterner::new(),
+            string_bytes: 0,
+        }
     }
 
     /// Returns the id corresponding to value, adding an entry for the
     /// id if it is not yet present in the dictionary.
     pub fn lookup_value_or_insert(&mut self, value: &str) -> u32 {
-        symbol_to_u32(self.0.get_or_intern(value))
+        let len = self.interner.len();
+        let symbol = self.interner.get_or_intern(value);
+        if self.interner.len() > len {
+            self.string_bytes += value.len();
+        }
+        symbol_to_u32(symbol)
     }
 
     /// Returns the ID in self.dictionary that corresponds to `value`, if any. Returns an error if
@@ -47,7 +58,7 @@ impl Dictionary {
     /// Returns the ID in self.dictionary that corresponds to `value`,
     /// if any. No error is returned to avoid an allocation when no value is present
     pub fn id(&self, value: &str) -> Option<u32> {
-        self.0.get(value).map(symbol_to_u32)
+        self.interner.get(value).map(symbol_to_u32)
     }
 
     /// Returns the str in self.dictionary that corresponds to `id`,
@@ -55,10 +66,26 @@ impl Dictionary {
     pub fn lookup_id(&self, id: u32) -> Result<&str> {
         let symbol =
             Symbol::try_from_usize(id as usize).expect("to be able to convert u32 to symbol");
-        self.0
+        self.interner
             .resolve(symbol)
             .context(DictionaryIdLookupError { id })
     }
+
+    /// Returns the ids and values of all the strings in the dictionary
+    pub fn values(&self) -> impl Iterator<Item = (u32, &str)> + '_ {
+        (0..self.interner.len() as u32).map(move |id| {
+            let value = self
+                .lookup_id(id)
+                .expect("ids are less than the dictionary length");
+            (id, value)
+        })
+    }
+
+    /// Returns an estimate of the memory, in bytes, taken up by the strings
+    /// in this dictionary and their ids
+    pub fn size(&self) -> usize {
+        self.string_bytes + self.interner.len() * (mem::size_of::<usize>() + mem::size_of::<u32>())
+    }
 }
 
 fn symbol_to_u32(sym: DefaultSymbol) -> u32 {
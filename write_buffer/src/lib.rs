@@ -11,12 +11,22 @@
 mod column;
 mod database;
 mod dictionary;
+mod lifecycle;
+mod memory;
 mod partition;
+mod provider;
+mod read_only;
+mod replicated_write;
+mod retention;
+mod snapshot;
 mod store;
 mod table;
 
 // Allow restore partitions to be used outside of this crate (for
 // benchmarking)
 pub use crate::database::Db;
+pub use crate::lifecycle::start_lifecycle_task;
+pub use crate::memory::MemoryTracker;
 pub use crate::partition::restore_partitions_from_wal;
+pub use crate::retention::start_retention_task;
 pub use crate::store::WriteBufferDatabases;
@@ -0,0 +1,206 @@
+//! Applies the `LifecycleRules` of databases to the partitions of their write
+//! buffers: partitions the rules select are closed, snapshotted to object
+//! storage and dropped from memory by a background task.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use data_types::database_rules::LifecycleRules;
+use object_store::ObjectStore;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::{partition::Partition, store::WriteBufferDatabases};
+
+/// Returns the keys of the open partitions that `rules` say should be closed
+/// as of `now`
+pub fn partitions_to_close(
+    rules: &LifecycleRules,
+    partitions: &[Partition],
+    now: Instant,
+) -> Vec<String> {
+    let mut open: Vec<_> = partitions.iter().filter(|p| p.is_open).collect();
+    // least recently written first
+    open.sort_by_key(|p| p.last_write_at);
+
+    let excess = rules
+        .max_open_partitions
+        .map_or(0, |max| open.len().saturating_sub(max));
+
+    // the memory still taken up by partitions that aren't backed by object
+    // storage once the partitions closed so far are snapshotted
+    let mut buffer_size: usize = match rules.buffer_size_soft {
+        Some(_) => partitions
+            .iter()
+            .filter(|p| p.read_only_id.is_none())
+            .map(|p| p.size())
+            .sum(),
+        None => 0,
+    };
+
+    open.iter()
+        .enumerate()
+        .filter(|(i, p)| {
+            let close = *i < excess
+                || exceeds(rules.max_partition_age_seconds, now, p.created_at)
+                || exceeds(rules.max_idle_seconds, now, p.last_write_at)
+                || rules
+                    .max_partition_bytes
+                    .map_or(false, |max| p.size() > max)
+                || rules
+                    .buffer_size_soft
+                    .map_or(false, |max| buffer_size > max);
+
+            if close && rules.buffer_size_soft.is_some() {
+                buffer_size = buffer_size.saturating_sub(p.size());
+            }
+            close
+        })
+        .map(|(_, p)| p.key.clone())
+        .collect()
+}
+
+/// Returns true if at least `max_seconds` have passed between `since` and `now`
+fn exceeds(max_seconds: Option<u64>, now: Instant, since: Instant) -> bool {
+    max_seconds.map_or(false, |max| {
+        now.saturating_duration_since(since) >= Duration::from_secs(max)
+    })
+}
+
+/// Starts a task that applies the lifecycle rules of all the databases every
+/// `interval`, snapshotting the partitions it closes to `store` as `writer`
+pub fn start_lifecycle_task(
+    databases: Arc<WriteBufferDatabases>,
+    store: Arc<ObjectStore>,
+    writer: u32,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+
+        loop {
+            ticks.tick().await;
+
+            for db in databases.dbs().await {
+                match db
+                    .apply_lifecycle_rules(&store, writer, Instant::now())
+                    .await
+                {
+                    Ok(keys) if !keys.is_empty() => {
+                        info!("{} persisted and closed partitions {:?}", db.name, keys)
+                    }
+                    Ok(_) => (),
+                    Err(e) => error!("Error applying lifecycle rules to {}: {}", db.name, e),
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partition(key: &str, created_at: Instant, last_write_at: Instant) -> Partition {
+        let mut p = Partition::new(key);
+        p.created_at = created_at;
+        p.last_write_at = last_write_at;
+        p
+    }
+
+    #[test]
+    fn close_partitions() {
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+
+        let mut closed = partition("closed", start, start);
+        closed.is_open = false;
+        let partitions = vec![
+            partition("a", start, secs(50)),
+            partition("b", secs(20), secs(90)),
+            partition("c", secs(40), secs(60)),
+            closed,
+        ];
+        let now = secs(100);
+
+        let keys = |rules: LifecycleRules| partitions_to_close(&rules, &partitions, now);
+
+        assert!(keys(LifecycleRules::default()).is_empty());
+
+        let rules = LifecycleRules {
+            max_partition_age_seconds: Some(80),
+            ..Default::default()
+        };
+        assert_eq!(keys(rules), vec!["a", "b"]);
+
+        let rules = LifecycleRules {
+            max_idle_seconds: Some(40),
+            ..Default::default()
+        };
+        assert_eq!(keys(rules), vec!["a", "c"]);
+
+        let rules = LifecycleRules {
+            max_open_partitions: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(keys(rules), vec!["a", "c"]);
+
+        let rules = LifecycleRules {
+            max_open_partitions: Some(2),
+            max_partition_age_seconds: Some(70),
+            ..Default::default()
+        };
+        assert_eq!(keys(rules), vec!["a", "b"]);
+
+        // empty partitions take up no memory
+        let rules = LifecycleRules {
+            max_partition_bytes: Some(0),
+            ..Default::default()
+        };
+        assert!(keys(rules).is_empty());
+        let rules = LifecycleRules {
+            buffer_size_soft: Some(0),
+            ..Default::default()
+        };
+        assert!(keys(rules).is_empty());
+    }
+
+    #[test]
+    fn close_partitions_over_soft_limit() {
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+
+        let mut partitions = vec![
+            partition("a", start, secs(30)),
+            partition("b", start, secs(10)),
+            partition("c", start, secs(20)),
+        ];
+        for p in &mut partitions {
+            p.dictionary.lookup_value_or_insert("0123456789");
+        }
+        let size = partitions[0].size();
+        assert!(size > 0);
+
+        // read-only partitions can be loaded again from object storage
+        let mut read_only = partition("d", start, start);
+        read_only.dictionary.lookup_value_or_insert("0123456789");
+        read_only.is_open = false;
+        read_only.read_only_id = Some("1/foo/d/0".to_string());
+        partitions.push(read_only);
+
+        let keys = |max| {
+            let rules = LifecycleRules {
+                buffer_size_soft: Some(max),
+                ..Default::default()
+            };
+            partitions_to_close(&rules, &partitions, secs(40))
+        };
+
+        assert!(keys(3 * size).is_empty());
+        assert_eq!(keys(2 * size), vec!["b"]);
+        assert_eq!(keys(size + 1), vec!["b", "c"]);
+        assert_eq!(keys(0), vec!["b", "c", "a"]);
+    }
+}
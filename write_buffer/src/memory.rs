@@ -0,0 +1,141 @@
+//! Accounts for the memory taken up by the write buffers of all the databases
+//! of a server, so that writes can be rejected once it reaches a limit.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Tracks the bytes used by the partitions of a set of databases against an
+/// optional limit. Each database reports changes to its own size.
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    limit: Option<usize>,
+    used: AtomicUsize,
+}
+
+impl MemoryTracker {
+    /// Creates a tracker that considers memory full once more than `limit`
+    /// bytes are used, or never if it's `None`
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Returns the bytes used by all the databases tracked
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if more than the limit is used
+    pub fn is_full(&self) -> bool {
+        self.limit.map_or(false, |limit| self.used() > limit)
+    }
+
+    /// Records that a database went from using `old` to `new` bytes
+    pub fn resize(&self, old: usize, new: usize) {
+        if new > old {
+            self.used.fetch_add(new - old, Ordering::SeqCst);
+        } else {
+            self.used.fetch_sub(old - new, Ordering::SeqCst);
+        }
+    }
+}
+
+/// The bytes used by the partitions of one database, which are counted by the
+/// `MemoryTracker` it shares with the other databases of its server until it
+/// is dropped
+#[derive(Debug, Default)]
+pub struct MemoryUsage {
+    used: AtomicUsize,
+    tracker: Arc<MemoryTracker>,
+}
+
+impl MemoryUsage {
+    /// Returns the bytes used by the database
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    pub fn tracker(&self) -> &MemoryTracker {
+        &self.tracker
+    }
+
+    /// Moves the bytes used by the database to `tracker`
+    pub fn set_tracker(&mut self, tracker: Arc<MemoryTracker>) {
+        let used = *self.used.get_mut();
+        self.tracker.resize(used, 0);
+        tracker.resize(0, used);
+        self.tracker = tracker;
+    }
+
+    /// Records that part of the database, such as a partition, went from
+    /// using `old` to `new` bytes
+    pub fn resize(&self, old: usize, new: usize) {
+        if new > old {
+            self.used.fetch_add(new - old, Ordering::SeqCst);
+        } else {
+            self.used.fetch_sub(old - new, Ordering::SeqCst);
+        }
+        self.tracker.resize(old, new);
+    }
+}
+
+impl Drop for MemoryUsage {
+    fn drop(&mut self) {
+        self.tracker.resize(*self.used.get_mut(), 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit() {
+        let unlimited = MemoryTracker::default();
+        unlimited.resize(0, usize::MAX);
+        assert!(!unlimited.is_full());
+
+        let tracker = MemoryTracker::new(Some(100));
+        tracker.resize(0, 60);
+        tracker.resize(0, 40);
+        assert_eq!(tracker.used(), 100);
+        assert!(!tracker.is_full());
+
+        tracker.resize(40, 41);
+        assert!(tracker.is_full());
+
+        tracker.resize(60, 10);
+        assert_eq!(tracker.used(), 51);
+        assert!(!tracker.is_full());
+    }
+
+    #[test]
+    fn usage_released_on_drop() {
+        let tracker = Arc::new(MemoryTracker::default());
+
+        let mut usage = MemoryUsage::default();
+        usage.resize(0, 30);
+        usage.set_tracker(Arc::clone(&tracker));
+        usage.resize(10, 20);
+        assert_eq!(usage.used(), 40);
+        assert_eq!(tracker.used(), 40);
+
+        let other = MemoryUsage {
+            used: AtomicUsize::new(0),
+            tracker: Arc::clone(&tracker),
+        };
+        other.resize(0, 5);
+        assert_eq!(tracker.used(), 45);
+
+        drop(usage);
+        assert_eq!(tracker.used(), 5);
+    }
+}
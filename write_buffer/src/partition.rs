@@ -4,16 +4,22 @@ use arrow_deps::{
     datafusion::scalar::ScalarValue,
 };
 use generated_types::wal as wb;
+use rayon::prelude::*;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
-use wal::{Entry as WalEntry, Result as WalResult};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wal::{Entry as WalEntry, Result as WalResult, SequenceNumber};
 
-use data_types::TIME_COLUMN_NAME;
+use data_types::{database_rules::PartitionId, delete::Delete, TIME_COLUMN_NAME};
 use storage::{
     predicate::{Predicate, TimestampRange},
+    regex_match::RegexMatch,
     util::{visit_expression, AndExprBuilder, ExpressionVisitor},
 };
 
+use crate::column::Column;
 use crate::dictionary::Dictionary;
+use crate::replicated_write::{AppliedWrites, WalRecord};
 use crate::table::Table;
 
 use snafu::{OptionExt, ResultExt, Snafu};
@@ -68,6 +74,9 @@ pub enum Error {
 
     #[snafu(display("Error restoring WAL entry, missing partition key"))]
     MissingPartitionKey,
+
+    #[snafu(display("Error restoring WAL delete entry: {}", source))]
+    InvalidDelete { source: data_types::delete::Error },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -86,6 +95,25 @@ pub struct Partition {
     pub tables: HashMap<u32, Table>,
 
     pub is_open: bool,
+
+    /// For a read-only partition loaded from object storage, the
+    /// location it was loaded from. These are never written to.
+    pub read_only_id: Option<PartitionId>,
+
+    /// The lowest WAL sequence number an entry holding data for this
+    /// partition could have. WAL entries from here on are needed to
+    /// restore the partition, until it has been snapshotted.
+    pub oldest_wal_sequence: Option<SequenceNumber>,
+
+    /// For a partition being snapshotted to object storage, the location
+    /// it's written to. The partition is dropped once it's written.
+    pub snapshot_id: Option<PartitionId>,
+
+    /// When the partition was created
+    pub created_at: Instant,
+
+    /// When data was last written to the partition
+    pub last_write_at: Instant,
 }
 
 /// Describes the result of translating a set of strings into
@@ -128,6 +156,12 @@ pub struct PartitionPredicate {
     /// to pass the predicate
     pub required_columns: Option<PartitionIdSet>,
 
+    /// The regular expression predicates among `partition_exprs`, as
+    /// the id of a column and the ids of the dictionary values they
+    /// select. Only tables with at least one of those values in that
+    /// column, if it is a tag, can pass the predicate
+    pub tag_value_restrictions: Vec<(u32, BTreeSet<u32>)>,
+
     /// The id of the "time" column in this partition
     pub time_column_id: u32,
 
@@ -203,11 +237,18 @@ fn make_range_expr(range: &TimestampRange) -> Expr {
 
 impl Partition {
     pub fn new(key: impl Into<String>) -> Self {
+        let now = Instant::now();
+
         Self {
             key: key.into(),
             dictionary: Dictionary::new(),
             tables: HashMap::new(),
             is_open: true,
+            read_only_id: None,
+            oldest_wal_sequence: None,
+            snapshot_id: None,
+            created_at: now,
+            last_write_at: now,
         }
     }
 
@@ -217,10 +258,58 @@ impl Partition {
                 self.write_table_batch(&batch)?;
             }
         }
+        self.last_write_at = Instant::now();
 
         Ok(())
     }
 
+    /// Returns an estimate of the memory, in bytes, taken up by the data in
+    /// this partition and its dictionary
+    pub fn size(&self) -> usize {
+        self.dictionary.size() + self.tables.values().map(|t| t.size()).sum::<usize>()
+    }
+
+    /// Returns the newest timestamp of the data in this partition, if it has any
+    pub fn max_time(&self) -> Option<i64> {
+        let time_column_id = self.dictionary.id(TIME_COLUMN_NAME)?;
+
+        self.tables
+            .values()
+            .filter_map(|table| {
+                let index = table.column_id_to_index.get(&time_column_id)?;
+                match &table.columns[*index] {
+                    Column::I64(_, stats) => Some(stats.max),
+                    _ => None,
+                }
+            })
+            .max()
+    }
+
+    /// Removes the rows matching `delete`, and the tables left empty. Returns
+    /// the number of rows removed.
+    pub fn delete(&mut self, delete: &Delete) -> usize {
+        let table_id = match &delete.table_name {
+            Some(table_name) => match self.dictionary.id(table_name) {
+                Some(id) => Some(id),
+                None => return 0,
+            },
+            None => None,
+        };
+
+        let dictionary = &self.dictionary;
+        let predicate = delete.predicate.as_ref();
+        let deleted = self
+            .tables
+            .iter_mut()
+            .filter(|(id, _)| table_id.map_or(true, |table_id| **id == table_id))
+            .map(|(_, table)| table.delete_rows(dictionary, predicate))
+            .sum();
+
+        self.tables.retain(|_, table| table.row_count() > 0);
+
+        deleted
+    }
+
     fn write_table_batch(&mut self, batch: &wb::TableWriteBatch<'_>) -> Result<()> {
         let table_name = batch.name().context(TableWriteWithoutName)?;
         let table_id = self.dictionary.lookup_value_or_insert(table_name);
@@ -273,16 +362,40 @@ impl Partition {
             Some(self.make_partition_ids(predicate_columns.iter()))
         };
 
+        let tag_value_restrictions = self.compile_regex_matches(&partition_exprs);
+
         Ok(PartitionPredicate {
             table_name_predicate,
             field_restriction,
             partition_exprs,
             required_columns,
+            tag_value_restrictions,
             time_column_id,
             range,
         })
     }
 
+    /// Finds the ids of the values of this partition's dictionary that
+    /// the regular expression predicates in `exprs` select, for the
+    /// columns they apply to. Columns not in the dictionary are left
+    /// out, as `required_columns` already rules out every table.
+    fn compile_regex_matches(&self, exprs: &[Expr]) -> Vec<(u32, BTreeSet<u32>)> {
+        exprs
+            .iter()
+            .filter_map(RegexMatch::from_datafusion_expr)
+            .filter_map(|regex_match| {
+                let column_id = self.dictionary.id(&regex_match.column)?;
+                let value_ids = self
+                    .dictionary
+                    .values()
+                    .filter(|(_, value)| regex_match.matches(value))
+                    .map(|(id, _)| id)
+                    .collect();
+                Some((column_id, value_ids))
+            })
+            .collect()
+    }
+
     /// Converts a potential set of strings into a set of ids in terms
     /// of this dictionary. If there are no matching Strings in the
     /// partitions dictionary, those strings are ignored and a
@@ -343,6 +456,13 @@ impl Partition {
         self.key.starts_with(key) && self.is_open
     }
 
+    /// returns true if this partition has data for the table `table_name`
+    pub fn has_table(&self, table_name: &str) -> bool {
+        self.dictionary
+            .id(table_name)
+            .map_or(false, |id| self.tables.contains_key(&id))
+    }
+
     /// Convert the table specified in this partition into an arrow record batch
     pub fn table_to_arrow(&self, table_name: &str, columns: &[&str]) -> Result<RecordBatch> {
         let table_id =
@@ -392,6 +512,7 @@ impl ExpressionVisitor for SupportVisitor {
         match expr {
             Expr::Literal(..) => {}
             Expr::Column(..) => {}
+            Expr::ScalarUDF { .. } if RegexMatch::from_datafusion_expr(expr).is_some() => {}
             Expr::BinaryExpr { op, .. } => {
                 match op {
                     Operator::Eq
@@ -423,25 +544,87 @@ impl ExpressionVisitor for SupportVisitor {
 pub struct RestorationStats {
     pub row_count: usize,
     pub tables: BTreeSet<String>,
+    /// The replicated writes found in the WAL
+    pub applied_writes: AppliedWrites,
+    /// Sequence number of the first entry in the WAL
+    pub first_wal_sequence: Option<SequenceNumber>,
+    /// Sequence number of the last entry in the WAL
+    pub last_wal_sequence: Option<SequenceNumber>,
+    /// Number of entries read from the WAL
+    pub wal_entries: usize,
+    /// How long reading and applying the WAL entries took
+    pub elapsed: Duration,
+}
+
+/// The number of WAL entries read before the writes to them are applied
+const RESTORE_CHUNK_SIZE: usize = 1024;
+
+/// A change to a partition found in the WAL, borrowed from the WAL entries
+/// of the chunk being restored
+enum RestoreOp<'a> {
+    Write(wb::WriteBufferEntry<'a>, SequenceNumber),
+    Delete(Arc<Delete>),
 }
 
-/// Given a set of WAL entries, restore them into a set of Partitions.
+/// Given a set of WAL entries, restore them into a set of Partitions. Writes to a
+/// partition key in entries up to its sequence number in `snapshot_sequences` are
+/// skipped, as their data has been snapshotted.
+///
+/// The entries are read in chunks on the calling thread, and each is decoded once into
+/// the changes it makes to each partition: writes go to the partition with their key and
+/// deletes to every partition written before them. The changes of a chunk are then
+/// applied in WAL order to each partition, with the partitions restored in parallel on
+/// the thread pool shared by all the databases being restored.
 pub fn restore_partitions_from_wal(
     wal_entries: impl Iterator<Item = WalResult<WalEntry>>,
+    snapshot_sequences: &BTreeMap<String, SequenceNumber>,
 ) -> Result<(Vec<Partition>, RestorationStats)> {
+    let start = Instant::now();
     let mut stats = RestorationStats::default();
+    let mut partitions: BTreeMap<String, Partition> = BTreeMap::new();
+
+    let mut wal_entries = wal_entries.peekable();
+    while wal_entries.peek().is_some() {
+        let chunk = wal_entries
+            .by_ref()
+            .take(RESTORE_CHUNK_SIZE)
+            .collect::<WalResult<Vec<_>>>()
+            .context(WalEntryRead)?;
+
+        let mut ops: BTreeMap<String, Vec<RestoreOp<'_>>> = BTreeMap::new();
+        for wal_entry in &chunk {
+            let wal_sequence = wal_entry.sequence_number();
+            stats.wal_entries += 1;
+            stats.first_wal_sequence.get_or_insert(wal_sequence);
+            stats.last_wal_sequence = Some(wal_sequence);
+
+            let record = WalRecord::decode(wal_entry.as_data());
+            if let WalRecord::Replicated(write) = &record {
+                stats
+                    .applied_writes
+                    .insert(write.writer(), write.sequence(), write.checksum());
+            }
 
-    let mut partitions = BTreeMap::new();
-
-    for wal_entry in wal_entries {
-        let wal_entry = wal_entry.context(WalEntryRead)?;
-        let bytes = wal_entry.as_data();
-
-        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&bytes);
-
-        if let Some(entries) = batch.entries() {
+            let entries = match record.batch().and_then(|batch| batch.entries()) {
+                Some(entries) => entries,
+                None => continue,
+            };
             for entry in entries {
+                if let Some(delete) = entry.delete() {
+                    let delete = Arc::new(Delete::from_fb(&delete).context(InvalidDelete)?);
+                    for key in partitions.keys() {
+                        push_restore_op(&mut ops, key, RestoreOp::Delete(Arc::clone(&delete)));
+                    }
+                    continue;
+                }
+
                 let partition_key = entry.partition_key().context(MissingPartitionKey)?;
+                let snapshotted = snapshot_sequences
+                    .get(partition_key)
+                    .map_or(false, |sequence| wal_sequence <= *sequence);
+                if snapshotted {
+                    continue;
+                }
 
                 if !partitions.contains_key(partition_key) {
                     partitions.insert(
@@ -449,17 +632,33 @@ pub fn restore_partitions_from_wal(
                         Partition::new(partition_key.to_string()),
                     );
                 }
-
-                let partition = partitions
-                    .get_mut(partition_key)
-                    .context(PartitionNotFound {
-                        partition: partition_key,
-                    })?;
-
-                partition.write_entry(&entry)?;
+                push_restore_op(
+                    &mut ops,
+                    partition_key,
+                    RestoreOp::Write(entry, wal_sequence),
+                );
             }
         }
+
+        partitions
+            .par_iter_mut()
+            .filter_map(|(key, partition)| Some((partition, ops.get(key)?)))
+            .try_for_each(|(partition, ops)| {
+                for op in ops {
+                    match op {
+                        RestoreOp::Write(entry, wal_sequence) => {
+                            partition.write_entry(entry)?;
+                            partition.oldest_wal_sequence.get_or_insert(*wal_sequence);
+                        }
+                        RestoreOp::Delete(delete) => {
+                            partition.delete(delete);
+                        }
+                    }
+                }
+                Ok(())
+            })?;
     }
+
     let partitions = partitions
         .into_iter()
         .map(|(_, p)| p)
@@ -480,9 +679,24 @@ pub fn restore_partitions_from_wal(
         }
     }
 
+    stats.elapsed = start.elapsed();
+
     Ok((partitions, stats))
 }
 
+fn push_restore_op<'a>(
+    ops: &mut BTreeMap<String, Vec<RestoreOp<'a>>>,
+    partition_key: &str,
+    op: RestoreOp<'a>,
+) {
+    match ops.get_mut(partition_key) {
+        Some(partition_ops) => partition_ops.push(op),
+        None => {
+            ops.insert(partition_key.to_string(), vec![op]);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
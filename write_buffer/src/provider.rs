@@ -0,0 +1,631 @@
+//! Exposes the tables of a write buffer database to DataFusion, so that SQL
+//! queries of any shape can be run against it. Each table is a
+//! `TableProvider` that only converts the columns a query projects, and
+//! only the rows that can match its filters, to arrow.
+
+use std::{any::Any, collections::BTreeMap, convert::TryFrom, fmt, sync::Arc};
+
+use arrow_deps::{
+    arrow,
+    arrow::{
+        array::{
+            ArrayRef, BooleanBuilder, Float64Builder, Int64Builder, StringBuilder, UInt64Builder,
+        },
+        datatypes::{DataType as ArrowDataType, Field as ArrowField, Schema, SchemaRef},
+        record_batch::RecordBatch,
+    },
+    datafusion::{
+        datasource::{
+            datasource::{Statistics, TableProviderFilterPushDown},
+            TableProvider,
+        },
+        error::{DataFusionError, Result as DataFusionResult},
+        execution::context::ExecutionContext,
+        logical_plan::{Expr, Operator},
+        physical_plan::{
+            common::SizedRecordBatchStream, Distribution, ExecutionPlan, Partitioning,
+            SendableRecordBatchStream,
+        },
+        scalar::ScalarValue,
+    },
+};
+use async_trait::async_trait;
+use data_types::row_predicate::{CompareOp, Literal, RowPredicate};
+use snafu::{ensure, ResultExt, Snafu};
+use tokio::sync::RwLock;
+
+use crate::{dictionary::Error as DictionaryError, partition::Partition, table::Table};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Table id {} not found in dictionary of partition {}: {}",
+        table,
+        partition,
+        source
+    ))]
+    TableIdNotFound {
+        table: u32,
+        partition: String,
+        source: DictionaryError,
+    },
+
+    #[snafu(display("Error reading table {}: {}", table_name, source))]
+    ReadingTable {
+        table_name: String,
+        source: crate::table::Error,
+    },
+
+    #[snafu(display(
+        "Column {} of table {} is {:?} in partition {} but {:?} in others",
+        column,
+        table_name,
+        partition_type,
+        expected_type,
+        partition
+    ))]
+    ColumnTypeConflict {
+        table_name: String,
+        column: String,
+        partition: String,
+        partition_type: ArrowDataType,
+        expected_type: ArrowDataType,
+    },
+
+    #[snafu(display("Error building record batch for table {}: {}", table_name, source))]
+    BuildingBatch {
+        table_name: String,
+        source: arrow::error::ArrowError,
+    },
+
+    #[snafu(display(
+        "Can't fill column {} of table {} with nulls: unsupported type {:?}",
+        column,
+        table_name,
+        data_type
+    ))]
+    UnsupportedNullColumn {
+        table_name: String,
+        column: String,
+        data_type: ArrowDataType,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Registers a `DbTableProvider` in `ctx` for every table in `partitions`
+pub async fn register_tables(
+    ctx: &mut ExecutionContext,
+    partitions: &Arc<RwLock<Vec<Partition>>>,
+) -> Result<()> {
+    let schemas = table_schemas(&partitions.read().await)?;
+
+    for (table_name, schema) in schemas {
+        let provider = DbTableProvider {
+            table_name: table_name.clone(),
+            schema,
+            partitions: Arc::clone(partitions),
+        };
+        ctx.register_table(&table_name, Box::new(provider));
+    }
+
+    Ok(())
+}
+
+/// Returns the schema of every table in `partitions`, with the columns the
+/// table has in any of them, sorted by name
+fn table_schemas(partitions: &[Partition]) -> Result<BTreeMap<String, SchemaRef>> {
+    let mut tables: BTreeMap<String, BTreeMap<String, ArrowField>> = BTreeMap::new();
+
+    for partition in partitions {
+        for table in partition.tables.values() {
+            let table_name = partition
+                .dictionary
+                .lookup_id(table.id)
+                .context(TableIdNotFound {
+                    table: table.id,
+                    partition: &partition.key,
+                })?;
+            let fields = table
+                .arrow_fields(partition)
+                .context(ReadingTable { table_name })?;
+
+            let table_fields = tables.entry(table_name.to_string()).or_default();
+            for field in fields {
+                match table_fields.get(field.name()) {
+                    Some(existing) => ensure!(
+                        existing.data_type() == field.data_type(),
+                        ColumnTypeConflict {
+                            table_name,
+                            column: field.name(),
+                            partition: &partition.key,
+                            partition_type: field.data_type().clone(),
+                            expected_type: existing.data_type().clone(),
+                        }
+                    ),
+                    None => {
+                        table_fields.insert(field.name().to_string(), field);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(tables
+        .into_iter()
+        .map(|(table_name, fields)| {
+            let schema = Schema::new(fields.into_iter().map(|(_, field)| field).collect());
+            (table_name, Arc::new(schema))
+        })
+        .collect())
+}
+
+/// Gives the batches of the table `table_name`, each read from the
+/// partition whose key it is paired with, the same schema: the columns any
+/// of them has, sorted by name. The columns a batch doesn't have are null.
+pub(crate) fn union_batches(
+    table_name: &str,
+    batches: Vec<(String, RecordBatch)>,
+) -> Result<Vec<RecordBatch>> {
+    let mut fields: BTreeMap<String, ArrowField> = BTreeMap::new();
+
+    for (partition_key, batch) in &batches {
+        for field in batch.schema().fields() {
+            match fields.get(field.name()) {
+                Some(existing) => ensure!(
+                    existing.data_type() == field.data_type(),
+                    ColumnTypeConflict {
+                        table_name,
+                        column: field.name(),
+                        partition: partition_key,
+                        partition_type: field.data_type().clone(),
+                        expected_type: existing.data_type().clone(),
+                    }
+                ),
+                None => {
+                    fields.insert(field.name().to_string(), field.clone());
+                }
+            }
+        }
+    }
+
+    let schema = Arc::new(Schema::new(
+        fields.into_iter().map(|(_, field)| field).collect(),
+    ));
+
+    batches
+        .into_iter()
+        .map(|(_, batch)| {
+            let columns = schema
+                .fields()
+                .iter()
+                .map(|field| match batch.schema().index_of(field.name()) {
+                    Ok(i) => Ok(batch.column(i).clone()),
+                    Err(_) => null_array(table_name, field, batch.num_rows()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            RecordBatch::try_new(Arc::clone(&schema), columns).context(BuildingBatch { table_name })
+        })
+        .collect()
+}
+
+/// A table of a write buffer database, read from all of its partitions
+/// when a query is executed
+pub struct DbTableProvider {
+    table_name: String,
+    schema: SchemaRef,
+    partitions: Arc<RwLock<Vec<Partition>>>,
+}
+
+impl fmt::Debug for DbTableProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DbTableProvider")
+            .field("table_name", &self.table_name)
+            .field("schema", &self.schema)
+            .finish()
+    }
+}
+
+impl TableProvider for DbTableProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+        filters: &[Expr],
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let schema = match projection {
+            Some(projection) => {
+                let fields = projection
+                    .iter()
+                    .map(|&i| self.schema.field(i).clone())
+                    .collect();
+                Arc::new(Schema::new(fields))
+            }
+            None => self.schema.clone(),
+        };
+
+        // rows are selected with the filters that can be evaluated on the
+        // table's columns; DataFusion applies all of them again afterwards
+        let predicate =
+            filters
+                .iter()
+                .filter_map(to_row_predicate)
+                .fold(None, |predicate, filter| match predicate {
+                    Some(predicate) => {
+                        Some(RowPredicate::And(Box::new(predicate), Box::new(filter)))
+                    }
+                    None => Some(filter),
+                });
+
+        Ok(Arc::new(DbTableScanExec {
+            table_name: self.table_name.clone(),
+            schema,
+            predicate,
+            partitions: Arc::clone(&self.partitions),
+        }))
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics::default()
+    }
+
+    fn supports_filter_pushdown(
+        &self,
+        filter: &Expr,
+    ) -> DataFusionResult<TableProviderFilterPushDown> {
+        Ok(match to_row_predicate(filter) {
+            Some(_) => TableProviderFilterPushDown::Inexact,
+            None => TableProviderFilterPushDown::Unsupported,
+        })
+    }
+}
+
+/// Physical operator that reads the projected columns of the rows of a
+/// table matching `predicate` from every partition of a database
+#[derive(Clone)]
+pub struct DbTableScanExec {
+    table_name: String,
+    /// Output schema
+    schema: SchemaRef,
+    predicate: Option<RowPredicate>,
+    partitions: Arc<RwLock<Vec<Partition>>>,
+}
+
+impl fmt::Debug for DbTableScanExec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DbTableScanExec: {}", self.table_name)?;
+        if let Some(predicate) = &self.predicate {
+            write!(f, " where {}", predicate)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for DbTableScanExec {
+    fn as_any(&self) -> &(dyn std::any::Any + 'static) {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn required_child_distribution(&self) -> Distribution {
+        Distribution::UnspecifiedDistribution
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        match children.len() {
+            0 => Ok(Arc::new(self.clone())),
+            _ => Err(DataFusionError::Internal(
+                "DbTableScanExec wrong number of children".to_string(),
+            )),
+        }
+    }
+
+    /// Execute one partition and return an iterator over RecordBatch
+    async fn execute(&self, partition: usize) -> DataFusionResult<SendableRecordBatchStream> {
+        if 0 != partition {
+            return Err(DataFusionError::Internal(format!(
+                "DbTableScanExec invalid partition {}",
+                partition
+            )));
+        }
+
+        let batches = {
+            let partitions = self.partitions.read().await;
+            partitions
+                .iter()
+                .filter_map(|partition| {
+                    let table_id = partition.dictionary.id(&self.table_name)?;
+                    let table = partition.tables.get(&table_id)?;
+                    self.read_table(partition, table).transpose()
+                })
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| DataFusionError::Execution(e.to_string()))?
+        };
+
+        Ok(Box::pin(SizedRecordBatchStream::new(
+            self.schema(),
+            batches.into_iter().map(Arc::new).collect(),
+        )))
+    }
+}
+
+impl DbTableScanExec {
+    /// Converts the rows of `table` matching the predicate to a batch with
+    /// the output schema, or returns `None` if there are none. The columns
+    /// the table doesn't have in `partition` are null.
+    fn read_table(&self, partition: &Partition, table: &Table) -> Result<Option<RecordBatch>> {
+        let table_name = &self.table_name;
+
+        let rows = self
+            .predicate
+            .as_ref()
+            .map(|predicate| table.possibly_matching_rows(&partition.dictionary, predicate));
+        let num_rows = match &rows {
+            Some(rows) => rows.iter().filter(|&&matches| matches).count(),
+            None => table.row_count(),
+        };
+        if num_rows == 0 {
+            return Ok(None);
+        }
+
+        let columns_with_index = self
+            .schema
+            .fields()
+            .iter()
+            .filter_map(|field| {
+                let column_id = partition.dictionary.id(field.name())?;
+                let column_index = *table.column_id_to_index.get(&column_id)?;
+                Some((field.name().as_str(), column_index))
+            })
+            .collect::<Vec<_>>();
+
+        // the table may have none of the projected columns in this partition
+        let data = if columns_with_index.is_empty() {
+            None
+        } else {
+            let data = table
+                .to_arrow_impl(partition, &columns_with_index, rows.as_deref())
+                .context(ReadingTable { table_name })?;
+            Some(data)
+        };
+
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let column = data.as_ref().and_then(|data| {
+                    let i = data.schema().index_of(field.name()).ok()?;
+                    Some((data.schema().field(i).data_type().clone(), data.column(i)))
+                });
+
+                match column {
+                    Some((column_type, column)) => {
+                        ensure!(
+                            &column_type == field.data_type(),
+                            ColumnTypeConflict {
+                                table_name,
+                                column: field.name(),
+                                partition: &partition.key,
+                                partition_type: column_type,
+                                expected_type: field.data_type().clone(),
+                            }
+                        );
+                        Ok(column.clone())
+                    }
+                    None => null_array(table_name, field, num_rows),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        RecordBatch::try_new(self.schema(), columns)
+            .map(Some)
+            .context(BuildingBatch { table_name })
+    }
+}
+
+/// Returns an array of `len` nulls for the column `field` of the table
+/// `table_name`, of one of the types `Table::to_arrow_impl` produces
+fn null_array(table_name: &str, field: &ArrowField, len: usize) -> Result<ArrayRef> {
+    let array: ArrayRef = match field.data_type() {
+        ArrowDataType::Utf8 => {
+            let mut builder = StringBuilder::new(len);
+            for _ in 0..len {
+                builder
+                    .append_null()
+                    .context(BuildingBatch { table_name })?;
+            }
+            Arc::new(builder.finish())
+        }
+        ArrowDataType::Float64 => {
+            let mut builder = Float64Builder::new(len);
+            for _ in 0..len {
+                builder
+                    .append_null()
+                    .context(BuildingBatch { table_name })?;
+            }
+            Arc::new(builder.finish())
+        }
+        ArrowDataType::Int64 => {
+            let mut builder = Int64Builder::new(len);
+            for _ in 0..len {
+                builder
+                    .append_null()
+                    .context(BuildingBatch { table_name })?;
+            }
+            Arc::new(builder.finish())
+        }
+        ArrowDataType::UInt64 => {
+            let mut builder = UInt64Builder::new(len);
+            for _ in 0..len {
+                builder
+                    .append_null()
+                    .context(BuildingBatch { table_name })?;
+            }
+            Arc::new(builder.finish())
+        }
+        ArrowDataType::Boolean => {
+            let mut builder = BooleanBuilder::new(len);
+            for _ in 0..len {
+                builder
+                    .append_null()
+                    .context(BuildingBatch { table_name })?;
+            }
+            Arc::new(builder.finish())
+        }
+        data_type => {
+            return UnsupportedNullColumn {
+                table_name,
+                column: field.name(),
+                data_type: data_type.clone(),
+            }
+            .fail()
+        }
+    };
+
+    Ok(array)
+}
+
+/// Converts a DataFusion filter to a predicate that selects a superset of the
+/// rows it does, if its comparisons are all of a column with a literal. The
+/// parts of an `AND` that can't be converted are left out.
+fn to_row_predicate(expr: &Expr) -> Option<RowPredicate> {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => match (to_row_predicate(left), to_row_predicate(right)) {
+            (Some(left), Some(right)) => Some(RowPredicate::And(Box::new(left), Box::new(right))),
+            (Some(predicate), None) | (None, Some(predicate)) => Some(predicate),
+            (None, None) => None,
+        },
+        Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => Some(RowPredicate::Or(
+            Box::new(to_row_predicate(left)?),
+            Box::new(to_row_predicate(right)?),
+        )),
+        Expr::BinaryExpr { left, op, right } => {
+            let op = compare_op(op)?;
+            match (left.as_ref(), right.as_ref()) {
+                (Expr::Column(column), Expr::Literal(value)) => compare(column, op, value),
+                (Expr::Literal(value), Expr::Column(column)) => compare(column, reverse(op), value),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn compare(column: &str, op: CompareOp, value: &ScalarValue) -> Option<RowPredicate> {
+    let value = match value {
+        ScalarValue::Utf8(Some(v)) => Literal::String(v.clone()),
+        ScalarValue::Int64(Some(v)) => Literal::I64(*v),
+        ScalarValue::Int32(Some(v)) => Literal::I64(i64::from(*v)),
+        ScalarValue::UInt64(Some(v)) => Literal::I64(i64::try_from(*v).ok()?),
+        ScalarValue::Float64(Some(v)) => Literal::F64(*v),
+        ScalarValue::Boolean(Some(v)) => Literal::Bool(*v),
+        _ => return None,
+    };
+
+    Some(RowPredicate::Compare {
+        column: column.to_string(),
+        op,
+        value,
+    })
+}
+
+fn compare_op(op: &Operator) -> Option<CompareOp> {
+    Some(match op {
+        Operator::Eq => CompareOp::Eq,
+        Operator::NotEq => CompareOp::NotEq,
+        Operator::Lt => CompareOp::Lt,
+        Operator::LtEq => CompareOp::LtEq,
+        Operator::Gt => CompareOp::Gt,
+        Operator::GtEq => CompareOp::GtEq,
+        _ => return None,
+    })
+}
+
+/// Returns the operator comparing the same values with its sides swapped
+fn reverse(op: CompareOp) -> CompareOp {
+    match op {
+        CompareOp::Eq => CompareOp::Eq,
+        CompareOp::NotEq => CompareOp::NotEq,
+        CompareOp::Lt => CompareOp::Gt,
+        CompareOp::LtEq => CompareOp::GtEq,
+        CompareOp::Gt => CompareOp::Lt,
+        CompareOp::GtEq => CompareOp::LtEq,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::datafusion::logical_plan::{col, Literal as _};
+
+    #[test]
+    fn filters_to_row_predicates() {
+        let cases = vec![
+            (col("host").eq("a".lit()), Some("host = 'a'")),
+            (10i64.lit().lt(col("time")), Some("time > 10")),
+            (
+                col("time")
+                    .gt_eq(10i64.lit())
+                    .and(col("usage").lt(1.5f64.lit())),
+                Some("time >= 10 AND usage < 1.5"),
+            ),
+            (
+                col("host").eq("a".lit()).or(col("host").eq("b".lit())),
+                Some("host = 'a' OR host = 'b'"),
+            ),
+            // the part of an AND that can't be pushed down is left out
+            (
+                col("host")
+                    .eq(col("region"))
+                    .and(col("usage").gt(1i64.lit())),
+                Some("usage > 1"),
+            ),
+            // an OR can only be pushed down whole
+            (
+                col("host")
+                    .eq(col("region"))
+                    .or(col("usage").gt(1i64.lit())),
+                None,
+            ),
+            (col("host").eq(col("region")), None),
+        ];
+
+        for (expr, expected) in cases {
+            let predicate = to_row_predicate(&expr).map(|p| p.to_string());
+            assert_eq!(predicate.as_deref(), expected, "expr: {:?}", expr);
+        }
+    }
+}
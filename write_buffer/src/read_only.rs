@@ -0,0 +1,244 @@
+//! Loads read-only partitions from object storage, where they are stored
+//! as described in `data_types::partition_metadata`, so their data can be
+//! queried along with the data written to a database.
+
+use std::rc::Rc;
+
+use arrow_deps::{
+    arrow::{
+        array::{
+            Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray,
+            TimestampMicrosecondArray, UInt64Array,
+        },
+        datatypes::{DataType, TimeUnit},
+        error::ArrowError,
+        record_batch::RecordBatch,
+    },
+    parquet::{
+        arrow::arrow_reader::{ArrowReader, ParquetFileArrowReader},
+        errors::ParquetError,
+        file::{reader::SerializedFileReader, serialized_reader::SliceableCursor},
+    },
+};
+use data_types::{
+    data::{rows_to_write_buffer_batch, RowValue, TableRows},
+    partition_metadata::{
+        self, partition_file_location, table_file_name, Column, METADATA_FILE_NAME,
+    },
+};
+use futures::TryStreamExt;
+use generated_types::wal as wb;
+use object_store::ObjectStore;
+use snafu::{ResultExt, Snafu};
+
+use crate::partition::Partition;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error reading {} from object storage: {}", location, source))]
+    ReadingObject {
+        location: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error parsing partition metadata {}: {}", location, source))]
+    ParsingMetadata {
+        location: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error reading Parquet file {}: {}", location, source))]
+    ReadingParquet {
+        location: String,
+        source: ParquetError,
+    },
+
+    #[snafu(display("Error reading record batch from {}: {}", location, source))]
+    ReadingBatch {
+        location: String,
+        source: ArrowError,
+    },
+
+    #[snafu(display(
+        "Column {} in {} has unsupported type {:?}",
+        column,
+        location,
+        data_type
+    ))]
+    UnsupportedColumnType {
+        column: String,
+        location: String,
+        data_type: DataType,
+    },
+
+    #[snafu(display("Error loading data from {}: {}", location, source))]
+    WritingPartition {
+        location: String,
+        source: crate::partition::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Number of rows read from a Parquet file at a time
+const BATCH_SIZE: usize = 8 * 1024;
+
+type Row<'a> = Vec<(&'a str, RowValue<'a>)>;
+
+/// Loads the partition stored under `partition_id`, without the rows removed
+/// by the deletes recorded in its metadata. The partition is closed, so
+/// nothing gets written to it.
+pub async fn load_partition(store: &ObjectStore, partition_id: &str) -> Result<Partition> {
+    let meta = load_metadata(store, partition_id).await?;
+
+    let mut partition = Partition::new(&meta.key);
+    for table in &meta.tables {
+        let location = partition_file_location(partition_id, &table_file_name(&table.name));
+        let data = read_object(store, &location).await?;
+        let batches = read_parquet(&location, data)?;
+
+        for batch in &batches {
+            write_batch(&mut partition, table, &location, batch)?;
+        }
+    }
+
+    for delete in &meta.deletes {
+        partition.delete(delete);
+    }
+
+    partition.is_open = false;
+    partition.read_only_id = Some(partition_id.to_string());
+
+    Ok(partition)
+}
+
+/// Loads the metadata of the partition stored under `partition_id`
+pub async fn load_metadata(
+    store: &ObjectStore,
+    partition_id: &str,
+) -> Result<partition_metadata::Partition> {
+    let location = partition_file_location(partition_id, METADATA_FILE_NAME);
+    let data = read_object(store, &location).await?;
+
+    serde_json::from_slice(&data).context(ParsingMetadata { location })
+}
+
+async fn read_object(store: &ObjectStore, location: &str) -> Result<Vec<u8>> {
+    store
+        .get(location)
+        .await
+        .context(ReadingObject { location })?
+        .map_ok(|b| b.to_vec())
+        .try_concat()
+        .await
+        .context(ReadingObject { location })
+}
+
+fn read_parquet(location: &str, data: Vec<u8>) -> Result<Vec<RecordBatch>> {
+    let reader = SerializedFileReader::new(SliceableCursor::new(data))
+        .context(ReadingParquet { location })?;
+    let mut reader = ParquetFileArrowReader::new(Rc::new(reader));
+
+    reader
+        .get_record_reader(BATCH_SIZE)
+        .context(ReadingParquet { location })?
+        .collect::<Result<Vec<_>, _>>()
+        .context(ReadingBatch { location })
+}
+
+/// Writes the rows of `batch` to the `table` of the partition. Which string
+/// columns are tags is taken from the metadata of the table, as is whether
+/// the `UINT_64` columns of IOx Parquet files hold signed integers.
+/// Snapshots store timestamps as nanoseconds, but microsecond timestamps,
+/// as in other IOx Parquet files, are converted back to nanoseconds.
+fn write_batch(
+    partition: &mut Partition,
+    table: &partition_metadata::Table,
+    location: &str,
+    batch: &RecordBatch,
+) -> Result<()> {
+    let schema = batch.schema();
+    let mut rows: Vec<Row<'_>> = vec![Vec::with_capacity(batch.num_columns()); batch.num_rows()];
+
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let name = field.name().as_str();
+
+        match field.data_type() {
+            DataType::Utf8 if table.is_tag(name) => {
+                push_values(&mut rows, name, column, |a: &StringArray, i| {
+                    RowValue::Tag(a.value(i))
+                })
+            }
+            DataType::Utf8 => push_values(&mut rows, name, column, |a: &StringArray, i| {
+                RowValue::String(a.value(i))
+            }),
+            DataType::Float64 => push_values(&mut rows, name, column, |a: &Float64Array, i| {
+                RowValue::F64(a.value(i))
+            }),
+            DataType::Int64 => push_values(&mut rows, name, column, |a: &Int64Array, i| {
+                RowValue::I64(a.value(i))
+            }),
+            DataType::UInt64 if matches!(table.column(name), Some(Column::I64(_))) => {
+                push_values(&mut rows, name, column, |a: &UInt64Array, i| {
+                    RowValue::I64(a.value(i) as i64)
+                })
+            }
+            DataType::Timestamp(TimeUnit::Microsecond, _) => push_values(
+                &mut rows,
+                name,
+                column,
+                |a: &TimestampMicrosecondArray, i| RowValue::I64(a.value(i) * 1000),
+            ),
+            DataType::UInt64 => push_values(&mut rows, name, column, |a: &UInt64Array, i| {
+                RowValue::U64(a.value(i))
+            }),
+            DataType::Boolean => push_values(&mut rows, name, column, |a: &BooleanArray, i| {
+                RowValue::Bool(a.value(i))
+            }),
+            data_type => {
+                return UnsupportedColumnType {
+                    column: name,
+                    location,
+                    data_type: data_type.clone(),
+                }
+                .fail()
+            }
+        }
+    }
+
+    let table_rows = TableRows {
+        name: &table.name,
+        rows,
+    };
+    let data = rows_to_write_buffer_batch(&partition.key, &[table_rows]);
+    let write_batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+    if let Some(entries) = write_batch.entries() {
+        for entry in entries {
+            partition
+                .write_entry(&entry)
+                .context(WritingPartition { location })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the non-null values of `column` to `rows`
+fn push_values<'a, A: Array + 'static>(
+    rows: &mut [Row<'a>],
+    name: &'a str,
+    column: &'a ArrayRef,
+    value: impl Fn(&'a A, usize) -> RowValue<'a>,
+) {
+    let array = column
+        .as_any()
+        .downcast_ref::<A>()
+        .expect("array should match the type of its field");
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        if array.is_valid(i) {
+            row.push((name, value(array, i)));
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Tracks the `ReplicatedWrite`s a database has already applied so that copies
+//! of them, either retried by a writer or fanned in from several hosts, are only
+//! applied once.
+//!
+//! Writes are identified by their writer, sequence number and checksum: a write
+//! that is split up by partition shares its sequence number with the other
+//! parts, and the checksums tell them apart. Only a write matching all three
+//! of an applied write is a duplicate. Writes can arrive out of order, e.g. when
+//! retried from a replication queue, so one with a sequence below the highest
+//! applied from its writer (its high-water mark) is still applied.
+//!
+//! Only the writes within `RECENT_SEQUENCES` of each writer's high-water mark
+//! are remembered, so that the memory they take up is bounded. Writes older
+//! than that are treated as duplicates: retries are expected to arrive well
+//! within the window, and applying a write twice can't be undone.
+//!
+//! Replicated writes are stored whole in the WAL, after a `REPLICATED_WRITE_PREFIX`,
+//! so the applied writes can be rebuilt when the WAL is restored.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use data_types::data::ReplicatedWrite;
+use generated_types::wal as wb;
+
+/// Marks WAL entries holding a `ReplicatedWrite` rather than a
+/// `WriteBufferBatch`. A flatbuffer starts with the little endian offset of
+/// its root table, which this prefix would put well past the end of any WAL
+/// entry, so it can't be confused with the start of a `WriteBufferBatch`.
+const REPLICATED_WRITE_PREFIX: &[u8] = b"IOXR";
+
+/// The contents of a WAL entry
+#[derive(Debug)]
+pub enum WalRecord<'a> {
+    Batch(wb::WriteBufferBatch<'a>),
+    Replicated(wb::ReplicatedWrite<'a>),
+}
+
+impl<'a> WalRecord<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Self {
+        if bytes.starts_with(REPLICATED_WRITE_PREFIX) {
+            let write = &bytes[REPLICATED_WRITE_PREFIX.len()..];
+            Self::Replicated(flatbuffers::get_root::<wb::ReplicatedWrite<'_>>(write))
+        } else {
+            Self::Batch(flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(bytes))
+        }
+    }
+
+    /// Returns the batch of entries to apply to the partitions
+    pub fn batch(&self) -> Option<wb::WriteBufferBatch<'a>> {
+        match self {
+            Self::Batch(batch) => Some(*batch),
+            Self::Replicated(write) => write
+                .payload()
+                .map(|payload| flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(payload)),
+        }
+    }
+}
+
+/// Returns the bytes to store in the WAL for `write`
+pub fn encode_wal_entry(write: &ReplicatedWrite) -> Vec<u8> {
+    let mut data = Vec::with_capacity(REPLICATED_WRITE_PREFIX.len() + write.data.len());
+    data.extend_from_slice(REPLICATED_WRITE_PREFIX);
+    data.extend_from_slice(&write.data);
+    data
+}
+
+/// How many sequences below a writer's high-water mark the applied writes are
+/// remembered for
+pub const RECENT_SEQUENCES: u64 = 10_000;
+
+/// The writes applied from every writer that has sent this database writes
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AppliedWrites {
+    writers: BTreeMap<u32, WriterWrites>,
+}
+
+/// The writes applied from a single writer
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WriterWrites {
+    high_water_mark: u64,
+    /// The sequences and checksums of the writes applied within
+    /// `RECENT_SEQUENCES` of `high_water_mark`
+    recent: BTreeSet<(u64, u32)>,
+}
+
+impl WriterWrites {
+    /// Returns the lowest sequence whose writes are remembered
+    fn oldest_sequence(&self) -> u64 {
+        self.high_water_mark.saturating_sub(RECENT_SEQUENCES)
+    }
+}
+
+impl AppliedWrites {
+    /// Returns true if the write with this `writer`, `sequence` and `checksum`
+    /// has already been applied, or is too old to tell
+    pub fn is_duplicate(&self, writer: u32, sequence: u64, checksum: u32) -> bool {
+        self.writers.get(&writer).map_or(false, |writes| {
+            sequence < writes.oldest_sequence() || writes.recent.contains(&(sequence, checksum))
+        })
+    }
+
+    /// Records that the write with this `writer`, `sequence` and `checksum`
+    /// has been applied, forgetting the writes that fall out of the window of
+    /// recent sequences
+    pub fn insert(&mut self, writer: u32, sequence: u64, checksum: u32) {
+        let writes = self.writers.entry(writer).or_insert_with(|| WriterWrites {
+            high_water_mark: sequence,
+            recent: BTreeSet::new(),
+        });
+        writes.high_water_mark = writes.high_water_mark.max(sequence);
+        writes.recent.insert((sequence, checksum));
+
+        let oldest = writes.oldest_sequence();
+        if writes
+            .recent
+            .iter()
+            .next()
+            .map_or(false, |&(s, _)| s < oldest)
+        {
+            writes.recent = writes.recent.split_off(&(oldest, 0));
+        }
+    }
+
+    /// Returns the highest sequence applied from `writer`
+    pub fn high_water_mark(&self, writer: u32) -> Option<u64> {
+        self.writers
+            .get(&writer)
+            .map(|writes| writes.high_water_mark)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::{data::lines_to_replicated_write, database_rules::DatabaseRules};
+    use influxdb_line_protocol::parse_lines;
+
+    #[test]
+    fn duplicates() {
+        let mut applied = AppliedWrites::default();
+        assert!(!applied.is_duplicate(1, 5, 100));
+
+        applied.insert(1, 5, 100);
+        assert!(applied.is_duplicate(1, 5, 100));
+        // another part of the same write
+        assert!(!applied.is_duplicate(1, 5, 200));
+        assert!(!applied.is_duplicate(1, 6, 100));
+        // other writers have their own writes
+        assert!(!applied.is_duplicate(2, 5, 100));
+
+        applied.insert(1, 5, 200);
+        assert!(applied.is_duplicate(1, 5, 200));
+
+        applied.insert(1, 7, 300);
+        assert!(applied.is_duplicate(1, 5, 200));
+        assert_eq!(applied.high_water_mark(1), Some(7));
+        assert_eq!(applied.high_water_mark(2), None);
+
+        // writes older than the high-water mark that weren't applied yet are
+        // not duplicates
+        assert!(!applied.is_duplicate(1, 6, 400));
+        assert!(!applied.is_duplicate(1, 4, 200));
+
+        // applying an older write doesn't move the mark back
+        applied.insert(1, 6, 400);
+        assert!(applied.is_duplicate(1, 6, 400));
+        assert_eq!(applied.high_water_mark(1), Some(7));
+        assert!(!applied.is_duplicate(1, 7, 400));
+    }
+
+    #[test]
+    fn old_writes_are_forgotten() {
+        let mut applied = AppliedWrites::default();
+        applied.insert(1, 1, 100);
+        applied.insert(1, 2, 100);
+        applied.insert(1, RECENT_SEQUENCES + 1, 100);
+        assert_eq!(applied.writers[&1].recent.len(), 3);
+
+        // moving the mark forgets the writes that fall out of the window
+        applied.insert(1, RECENT_SEQUENCES + 2, 100);
+        let recent: Vec<_> = applied.writers[&1].recent.iter().copied().collect();
+        assert_eq!(
+            recent,
+            vec![
+                (2, 100),
+                (RECENT_SEQUENCES + 1, 100),
+                (RECENT_SEQUENCES + 2, 100)
+            ]
+        );
+
+        // writes older than the window are duplicates, whatever their checksum
+        assert!(applied.is_duplicate(1, 1, 100));
+        assert!(applied.is_duplicate(1, 1, 200));
+        assert!(applied.is_duplicate(1, 2, 100));
+        assert!(!applied.is_duplicate(1, 2, 200));
+        assert!(!applied.is_duplicate(1, 3, 100));
+
+        // other writers have their own window
+        assert!(!applied.is_duplicate(2, 1, 100));
+    }
+
+    #[test]
+    fn wal_entry_roundtrip() {
+        let lines: Vec<_> = parse_lines("cpu val=1 10").map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(3, 9, &lines, &DatabaseRules::default()).unwrap();
+
+        let data = encode_wal_entry(&write);
+        let record = WalRecord::decode(&data);
+        match &record {
+            WalRecord::Replicated(fb) => {
+                assert_eq!(fb.writer(), 3);
+                assert_eq!(fb.sequence(), 9);
+            }
+            WalRecord::Batch(_) => panic!("expected a replicated write"),
+        }
+        let entries = record.batch().unwrap().entries().unwrap();
+        assert_eq!(entries.len(), 1);
+
+        let batch = write.to_fb().payload().unwrap();
+        let record = WalRecord::decode(batch);
+        assert!(matches!(record, WalRecord::Batch(_)));
+        assert_eq!(record.batch().unwrap().entries().unwrap().len(), 1);
+    }
+}
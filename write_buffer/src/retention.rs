@@ -0,0 +1,51 @@
+//! Enforces the retention periods of databases: a background task drops the
+//! partitions holding only expired data from the write buffers, truncating
+//! their WALs, and deletes the expired partitions they snapshotted to object
+//! storage.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use object_store::ObjectStore;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::store::WriteBufferDatabases;
+
+/// Starts a task that applies the retention periods of all the databases every
+/// `interval`. Partitions are deleted from object storage only when `snapshots`
+/// gives the store and writer id they are snapshotted with.
+pub fn start_retention_task(
+    databases: Arc<WriteBufferDatabases>,
+    snapshots: Option<(Arc<ObjectStore>, u32)>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+
+        loop {
+            ticks.tick().await;
+            let now = Utc::now();
+
+            for db in databases.dbs().await {
+                match db.drop_expired_partitions(&now).await {
+                    Ok(keys) if !keys.is_empty() => {
+                        info!("{} dropped expired partitions {:?}", db.name, keys)
+                    }
+                    Ok(_) => (),
+                    Err(e) => error!("Error dropping expired partitions of {}: {}", db.name, e),
+                }
+
+                if let Some((store, writer)) = &snapshots {
+                    match db.delete_expired_snapshots(store, *writer, &now).await {
+                        Ok(ids) if !ids.is_empty() => {
+                            info!("{} deleted expired snapshots {:?}", db.name, ids)
+                        }
+                        Ok(_) => (),
+                        Err(e) => error!("Error deleting expired snapshots of {}: {}", db.name, e),
+                    }
+                }
+            }
+        }
+    })
+}
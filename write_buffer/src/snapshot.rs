@@ -0,0 +1,292 @@
+//! Snapshots partitions of the write buffer to object storage, in the layout
+//! described in `data_types::partition_metadata`: a Parquet file for each
+//! table, written by `IOxParquetTableWriter`, and the partition's metadata.
+//!
+//! Unlike the rest of IOx's Parquet files, timestamps are stored as plain
+//! nanoseconds rather than `TIMESTAMP_MICROS`, so that the partitions loaded
+//! from a snapshot have exactly the timestamps that were written.
+
+use std::{
+    io::{self, Cursor, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex},
+};
+
+use arrow_deps::parquet::{data_type::ByteArray, file::writer::TryClone};
+use data_types::{
+    partition_metadata::{
+        self, partition_file_location, table_file_name, ColumnSummary, METADATA_FILE_NAME,
+    },
+    table_schema::{DataType, SchemaBuilder},
+    TIME_COLUMN_NAME,
+};
+use ingest::parquet::writer::{CompressionLevel, IOxParquetTableWriter, TimestampPrecision};
+use object_store::ObjectStore;
+use packers::{IOxTableWriter, Packer, Packers};
+use snafu::{ResultExt, Snafu};
+
+use crate::{column::Column, partition::Partition, table::Table};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error writing {} to object storage: {}", location, source))]
+    WritingObject {
+        location: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error serializing partition metadata: {}", source))]
+    SerializingMetadata { source: serde_json::Error },
+
+    #[snafu(display("Error creating Parquet writer for table {}: {}", table, source))]
+    CreatingParquetWriter {
+        table: String,
+        source: ingest::parquet::writer::Error,
+    },
+
+    // the packers error isn't Send, so only its message is kept
+    #[snafu(display("Error writing table {} to Parquet: {}", table, message))]
+    WritingParquet { table: String, message: String },
+
+    #[snafu(display("Error snapshotting partition {}: {}", partition, source))]
+    DictionaryLookup {
+        partition: String,
+        source: crate::dictionary::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The encoded files of a partition, ready to be written to object storage
+#[derive(Debug)]
+pub struct Snapshot {
+    pub metadata: partition_metadata::Partition,
+    /// The Parquet file of each table, in the same order as the tables of
+    /// the metadata
+    tables: Vec<Vec<u8>>,
+}
+
+impl Snapshot {
+    /// Encodes the tables of `partition` as Parquet and summarises them.
+    pub fn new(partition: &Partition) -> Result<Self> {
+        let mut metadata = partition_metadata::Partition {
+            key: partition.key.clone(),
+            tables: Vec::with_capacity(partition.tables.len()),
+            deletes: vec![],
+        };
+        let mut tables = Vec::with_capacity(partition.tables.len());
+
+        let mut partition_tables: Vec<_> = partition.tables.values().collect();
+        partition_tables.sort_by_key(|t| t.id);
+
+        for table in partition_tables {
+            let (summary, data) = encode_table(partition, table)?;
+            metadata.tables.push(summary);
+            tables.push(data);
+        }
+
+        Ok(Self { metadata, tables })
+    }
+
+    /// Writes the snapshot to `store` in the directory `partition_id`. The
+    /// metadata is written last, so a partition with metadata is complete.
+    pub async fn write(
+        self,
+        store: &ObjectStore,
+        partition_id: &str,
+    ) -> Result<partition_metadata::Partition> {
+        for (table, data) in self.metadata.tables.iter().zip(self.tables) {
+            let location = partition_file_location(partition_id, &table_file_name(&table.name));
+            put_object(store, &location, data).await?;
+        }
+
+        write_metadata(store, partition_id, &self.metadata).await?;
+
+        Ok(self.metadata)
+    }
+}
+
+/// Writes `metadata` as the metadata of the partition stored under
+/// `partition_id`, replacing what was there
+pub async fn write_metadata(
+    store: &ObjectStore,
+    partition_id: &str,
+    metadata: &partition_metadata::Partition,
+) -> Result<()> {
+    let location = partition_file_location(partition_id, METADATA_FILE_NAME);
+    let data = serde_json::to_vec(metadata).context(SerializingMetadata)?;
+
+    put_object(store, &location, data).await
+}
+
+async fn put_object(store: &ObjectStore, location: &str, data: Vec<u8>) -> Result<()> {
+    let len = data.len();
+    let data = io::Result::Ok(bytes::Bytes::from(data));
+
+    store
+        .put(location, futures::stream::once(async move { data }), len)
+        .await
+        .context(WritingObject { location })
+}
+
+/// Returns the summary of `table` and its contents as a Parquet file
+fn encode_table(
+    partition: &Partition,
+    table: &Table,
+) -> Result<(partition_metadata::Table, Vec<u8>)> {
+    let lookup = |id| {
+        partition
+            .dictionary
+            .lookup_id(id)
+            .context(DictionaryLookup {
+                partition: &partition.key,
+            })
+    };
+
+    let table_name = lookup(table.id)?;
+
+    let mut columns = table
+        .column_id_to_index
+        .iter()
+        .map(|(&id, &index)| lookup(id).map(|name| (name, &table.columns[index])))
+        .collect::<Result<Vec<_>>>()?;
+    columns.sort_by_key(|&(name, _)| name);
+
+    let mut builder = SchemaBuilder::new(table_name);
+    for &(name, column) in &columns {
+        builder = match column {
+            Column::Tag(_, _) => builder.tag(name),
+            _ if name == TIME_COLUMN_NAME => builder,
+            Column::F64(_, _) => builder.field(name, DataType::Float),
+            Column::I64(_, _) | Column::U64(_, _) => builder.field(name, DataType::Integer),
+            Column::String(_, _, _) => builder.field(name, DataType::String),
+            Column::Bool(_, _) => builder.field(name, DataType::Boolean),
+        };
+    }
+    let schema = builder.build();
+
+    let packers = schema
+        .get_col_defs()
+        .iter()
+        .map(|col_def| {
+            let column = columns
+                .iter()
+                .find(|&&(name, _)| name == col_def.name)
+                .map(|&(_, column)| column);
+
+            match column {
+                Some(column) => column_packers(partition, column),
+                // rows without a timestamp
+                None => Ok(Packers::from(vec![None::<i64>; table.row_count()])),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mem = MemWriter::default();
+    let mut writer = IOxParquetTableWriter::new_with_timestamp_precision(
+        &schema,
+        CompressionLevel::Compatibility,
+        TimestampPrecision::Nanosecond,
+        mem.clone(),
+    )
+    .context(CreatingParquetWriter { table: table_name })?;
+    writer
+        .write_batch(&packers)
+        .and_then(|_| writer.close())
+        .map_err(|e| Error::WritingParquet {
+            table: table_name.to_string(),
+            message: e.to_string(),
+        })?;
+
+    let summary = partition_metadata::Table {
+        name: table_name.to_string(),
+        columns: columns
+            .iter()
+            .map(|&(name, column)| ColumnSummary {
+                name: name.to_string(),
+                stats: column_stats(column),
+            })
+            .collect(),
+    };
+
+    Ok((summary, mem.into_inner()))
+}
+
+/// Returns the values of `column` packed for the Parquet writer. Timestamps
+/// are written as they are, in nanoseconds.
+fn column_packers(partition: &Partition, column: &Column) -> Result<Packers> {
+    Ok(match column {
+        Column::F64(vals, _) => Packers::from(vals.clone()),
+        Column::I64(vals, _) => Packers::from(vals.clone()),
+        Column::U64(vals, _) => Packers::from(vals.clone()),
+        Column::Bool(vals, _) => Packers::from(vals.clone()),
+        Column::String(vals, _, _) => Packers::String(Packer::from(
+            vals.iter()
+                .map(|v| v.as_deref().map(ByteArray::from))
+                .collect::<Vec<_>>(),
+        )),
+        Column::Tag(vals, _) => Packers::String(Packer::from(
+            vals.iter()
+                .map(|v| {
+                    v.map(|id| {
+                        partition
+                            .dictionary
+                            .lookup_id(id)
+                            .map(ByteArray::from)
+                            .context(DictionaryLookup {
+                                partition: &partition.key,
+                            })
+                    })
+                    .transpose()
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )),
+    })
+}
+
+fn column_stats(column: &Column) -> partition_metadata::Column {
+    match column {
+        Column::F64(_, stats) => partition_metadata::Column::F64(stats.clone()),
+        Column::I64(_, stats) => partition_metadata::Column::I64(stats.clone()),
+        Column::U64(_, stats) => partition_metadata::Column::U64(stats.clone()),
+        Column::String(_, stats, _) => partition_metadata::Column::String(stats.clone()),
+        Column::Bool(_, stats) => partition_metadata::Column::Bool(stats.clone()),
+        Column::Tag(_, stats) => partition_metadata::Column::Tag(stats.clone()),
+    }
+}
+
+/// An in-memory destination for the Parquet writer, which writes through
+/// clones of its destination, so the clones share one buffer.
+#[derive(Debug, Default, Clone)]
+struct MemWriter {
+    mem: Arc<Mutex<Cursor<Vec<u8>>>>,
+}
+
+impl MemWriter {
+    /// Returns the bytes written
+    fn into_inner(self) -> Vec<u8> {
+        let mut mem = self.mem.lock().expect("mutex poisoned");
+        std::mem::take(mem.get_mut())
+    }
+}
+
+impl Write for MemWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.mem.lock().expect("mutex poisoned").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.mem.lock().expect("mutex poisoned").flush()
+    }
+}
+
+impl Seek for MemWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.mem.lock().expect("mutex poisoned").seek(pos)
+    }
+}
+
+impl TryClone for MemWriter {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+}
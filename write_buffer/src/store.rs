@@ -1,13 +1,26 @@
 use async_trait::async_trait;
+use object_store::ObjectStore;
 use snafu::{ResultExt, Snafu};
-use storage::DatabaseStore;
+use storage::{DatabaseStore, RecoveryProgress};
 use tokio::sync::RwLock;
 
-use std::{fs, sync::Arc};
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
-use crate::database::Db;
+use crate::{
+    database::{wal_dir_database_name, Db},
+    memory::MemoryTracker,
+};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -22,6 +35,9 @@ pub enum Error {
 
     #[snafu(display("Error reading metadata: {}", source))]
     ReadMetadataError { source: std::io::Error },
+
+    #[snafu(display("Database {} is still being recovered", name))]
+    DatabaseRecovering { name: String },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -30,6 +46,14 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub struct WriteBufferDatabases {
     databases: RwLock<BTreeMap<String, Arc<Db>>>,
     base_dir: PathBuf,
+    /// Tracks the memory used by all the databases
+    memory: Arc<MemoryTracker>,
+    /// The object store the databases persist partitions to, and the
+    /// writer id they snapshot them as
+    object_store: Option<(Arc<ObjectStore>, Option<u32>)>,
+    /// The number of WAL entries replayed so far for each database still
+    /// being recovered
+    recovering: RwLock<BTreeMap<String, Arc<AtomicUsize>>>,
 }
 
 impl WriteBufferDatabases {
@@ -37,9 +61,31 @@ impl WriteBufferDatabases {
         Self {
             databases: RwLock::new(BTreeMap::new()),
             base_dir: base_dir.into(),
+            memory: Arc::new(MemoryTracker::default()),
+            object_store: None,
+            recovering: RwLock::new(BTreeMap::new()),
         }
     }
 
+    /// Rejects writes to any of the databases while their write buffers
+    /// together take up more than `limit` bytes. Set before adding databases.
+    pub fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.memory = Arc::new(MemoryTracker::new(Some(limit)));
+        self
+    }
+
+    /// Has the databases record their deletes in the partitions they persist
+    /// to `store`, as `writer`. Set before adding databases.
+    pub fn with_object_store(mut self, store: Arc<ObjectStore>, writer: Option<u32>) -> Self {
+        self.object_store = Some((store, writer));
+        self
+    }
+
+    /// Returns the tracker of the memory used by all the databases
+    pub fn memory(&self) -> &MemoryTracker {
+        &self.memory
+    }
+
     /// wal_dirs will traverse the directories from the service base directory and return
     /// the directories that contain WALs for databases, which can be used to restore those DBs.
     pub fn wal_dirs(&self) -> Result<Vec<PathBuf>> {
@@ -69,10 +115,43 @@ impl WriteBufferDatabases {
         Ok(dirs)
     }
 
+    /// Marks the database whose WAL is in `wal_dir` as being recovered
+    /// until it is added, so that it is neither used nor created in the
+    /// meantime. Returns the counter of the WAL entries replayed to pass
+    /// to `Db::restore_from_wal_with_progress`.
+    pub async fn start_recovery(&self, wal_dir: &Path) -> Result<Arc<AtomicUsize>> {
+        let name = wal_dir_database_name(wal_dir).context(DatabaseError)?;
+        let progress = Arc::default();
+        self.recovering
+            .write()
+            .await
+            .insert(name, Arc::clone(&progress));
+
+        Ok(progress)
+    }
+
+    /// Adds `db`, marking it as recovered if it was being recovered
     pub async fn add_db(&self, db: Db) {
+        let db = self.with_settings(db);
         let mut databases = self.databases.write().await;
+        self.recovering.write().await.remove(&db.name);
         databases.insert(db.name.clone(), Arc::new(db));
     }
+
+    /// Returns all of the databases
+    pub async fn dbs(&self) -> Vec<Arc<Db>> {
+        let databases = self.databases.read().await;
+        databases.values().cloned().collect()
+    }
+
+    /// Shares the memory tracker and object store with `db`
+    fn with_settings(&self, db: Db) -> Db {
+        let db = db.with_memory_tracker(Arc::clone(&self.memory));
+        match &self.object_store {
+            Some((store, writer)) => db.with_object_store(Arc::clone(store), *writer),
+            None => db,
+        }
+    }
 }
 
 #[async_trait]
@@ -99,6 +178,11 @@ impl DatabaseStore for WriteBufferDatabases {
         // database doesn't exist yet so acquire the write lock and get or insert
         let mut databases = self.databases.write().await;
 
+        // a database being recovered only exists once it is added
+        if self.recovering.read().await.contains_key(name) {
+            return DatabaseRecovering { name }.fail();
+        }
+
         // make sure it didn't get inserted by someone else while we were waiting for the write lock
         if let Some(db) = databases.get(name) {
             return Ok(db.clone());
@@ -107,9 +191,29 @@ impl DatabaseStore for WriteBufferDatabases {
         let db = Db::try_with_wal(name, &mut self.base_dir.clone())
             .await
             .context(DatabaseError)?;
-        let db = Arc::new(db);
+        let db = Arc::new(self.with_settings(db));
         databases.insert(name.to_string(), db.clone());
 
         Ok(db)
     }
+
+    async fn recovery_progress(&self, name: &str) -> Option<RecoveryProgress> {
+        let recovering = self.recovering.read().await;
+        recovering.get(name).map(|wal_entries| RecoveryProgress {
+            wal_entries: wal_entries.load(Ordering::SeqCst),
+        })
+    }
+
+    async fn recovering(&self) -> BTreeMap<String, RecoveryProgress> {
+        let recovering = self.recovering.read().await;
+        recovering
+            .iter()
+            .map(|(name, wal_entries)| {
+                let progress = RecoveryProgress {
+                    wal_entries: wal_entries.load(Ordering::SeqCst),
+                };
+                (name.clone(), progress)
+            })
+            .collect()
+    }
 }
@@ -1,11 +1,12 @@
 use generated_types::wal as wb;
 use storage::{
+    aggregate::{Aggregate, Window},
     exec::{make_schema_pivot, GroupedSeriesSetPlan, SeriesSetPlan},
     util::dump_plan,
 };
 use tracing::debug;
 
-use std::{collections::BTreeSet, collections::HashMap, sync::Arc};
+use std::{collections::BTreeSet, collections::HashMap, mem, sync::Arc};
 
 use crate::{
     column,
@@ -14,20 +15,24 @@ use crate::{
     partition::PartitionIdSet,
     partition::{Partition, PartitionPredicate},
 };
-use data_types::TIME_COLUMN_NAME;
+use data_types::{data::RowValue, row_predicate::RowPredicate, TIME_COLUMN_NAME};
 use snafu::{OptionExt, ResultExt, Snafu};
 
 use arrow_deps::{
     arrow,
     arrow::{
-        array::{ArrayRef, BooleanBuilder, Float64Builder, Int64Builder, StringBuilder},
+        array::{
+            ArrayRef, BooleanBuilder, Float64Builder, Int64Builder, StringBuilder, UInt64Builder,
+        },
         datatypes::{DataType as ArrowDataType, Field as ArrowField, Schema as ArrowSchema},
         record_batch::RecordBatch,
     },
     datafusion,
+    datafusion::logical_plan,
     datafusion::logical_plan::Expr,
     datafusion::logical_plan::LogicalPlan,
     datafusion::logical_plan::LogicalPlanBuilder,
+    datafusion::scalar::ScalarValue,
 };
 
 #[derive(Debug, Snafu)]
@@ -237,6 +242,12 @@ impl Table {
         self.columns.first().map_or(0, |v| v.len())
     }
 
+    /// Returns an estimate of the memory, in bytes, taken up by the values
+    /// of this table
+    pub fn size(&self) -> usize {
+        self.columns.iter().map(|c| c.size()).sum()
+    }
+
     /// Returns a reference to the specified column
     fn column(&self, column_id: u32) -> Result<&Column> {
         Ok(self
@@ -275,6 +286,84 @@ impl Table {
         Ok(())
     }
 
+    /// Removes the rows matching `predicate`, or every row if it is `None`,
+    /// and the columns left without values. Returns the number of rows
+    /// removed.
+    pub fn delete_rows(
+        &mut self,
+        dictionary: &Dictionary,
+        predicate: Option<&RowPredicate>,
+    ) -> usize {
+        let keep: Vec<bool> = match predicate {
+            Some(predicate) => self
+                .matching_rows(dictionary, predicate)
+                .into_iter()
+                .map(|matches| !matches)
+                .collect(),
+            None => vec![false; self.row_count()],
+        };
+
+        let deleted = keep.iter().filter(|&&keep| !keep).count();
+        if deleted == 0 {
+            return 0;
+        }
+
+        let mut column_ids = vec![0; self.columns.len()];
+        for (&column_id, &idx) in &self.column_id_to_index {
+            column_ids[idx] = column_id;
+        }
+
+        let columns = mem::take(&mut self.columns);
+        self.column_id_to_index.clear();
+        for (column_id, column) in column_ids.into_iter().zip(columns) {
+            if let Some(column) = column.retain_rows(&keep, dictionary) {
+                self.column_id_to_index
+                    .insert(column_id, self.columns.len());
+                self.columns.push(column);
+            }
+        }
+
+        deleted
+    }
+
+    /// Returns whether each row of this table matches `predicate`
+    pub fn matching_rows(&self, dictionary: &Dictionary, predicate: &RowPredicate) -> Vec<bool> {
+        self.evaluate_rows(dictionary, |value| predicate.matches_values(value))
+    }
+
+    /// Returns whether each row of this table might match `predicate`, as
+    /// `RowPredicate::might_match_values` decides
+    pub fn possibly_matching_rows(
+        &self,
+        dictionary: &Dictionary,
+        predicate: &RowPredicate,
+    ) -> Vec<bool> {
+        self.evaluate_rows(dictionary, |value| predicate.might_match_values(value))
+    }
+
+    /// Returns the result of `evaluate` for each row of this table, given the
+    /// values of the row's columns
+    fn evaluate_rows<'a>(
+        &'a self,
+        dictionary: &'a Dictionary,
+        evaluate: impl Fn(&dyn Fn(&str) -> Option<RowValue<'a>>) -> bool,
+    ) -> Vec<bool> {
+        let columns: HashMap<&str, &Column> = self
+            .column_id_to_index
+            .iter()
+            .map(|(&column_id, &idx)| {
+                let name = dictionary
+                    .lookup_id(column_id)
+                    .expect("column id should be in the dictionary");
+                (name, &self.columns[idx])
+            })
+            .collect();
+
+        (0..self.row_count())
+            .map(|row| evaluate(&|name| columns.get(name)?.row_value(row, dictionary)))
+            .collect()
+    }
+
     /// Creates and adds a datafuson filtering expression, if any out of the
     /// combination of predicate and timestamp. Returns the builder
     fn add_datafusion_predicate(
@@ -328,7 +417,7 @@ impl Table {
             .collect::<Vec<_>>();
 
         // TODO avoid materializing here
-        let data = self.to_arrow_impl(partition, &requested_columns_with_index)?;
+        let data = self.to_arrow_impl(partition, &requested_columns_with_index, None)?;
 
         let schema = data.schema();
 
@@ -417,13 +506,69 @@ impl Table {
 
         let plan_builder = Self::add_datafusion_predicate(plan_builder, partition_predicate)?;
 
+        // rows without a value for the tag have no value to report
+        let not_null = Expr::IsNotNull(Box::new(Expr::Column(column_name.into())));
+
         plan_builder
+            .filter(not_null)
+            .context(BuildingPlan)?
             .project(select_exprs)
             .context(BuildingPlan)?
             .build()
             .context(BuildingPlan)
     }
 
+    /// Creates a DataFusion LogicalPlan that returns the table's name,
+    /// as a single column of Strings, if any of its rows match the
+    /// predicate, and nothing otherwise
+    ///
+    /// The created plan looks like:
+    ///
+    ///    Limit(1)
+    ///      Projection(table name)
+    ///        Filter(predicate)
+    ///          InMemoryScan
+    pub fn table_name_plan(
+        &self,
+        partition_predicate: &PartitionPredicate,
+        partition: &Partition,
+    ) -> Result<LogicalPlan> {
+        let table_name = partition
+            .dictionary
+            .lookup_id(self.id)
+            .expect("looking up table name in dictionary");
+
+        // TODO avoid materializing all the columns here (ideally
+        // DataFusion can prune them out)
+        let data = self.all_to_arrow(partition)?;
+
+        let schema = data.schema();
+
+        let projection = None;
+        let projected_schema = schema.clone();
+        let select_exprs = vec![Expr::Alias(
+            Box::new(Expr::Literal(ScalarValue::Utf8(Some(table_name.into())))),
+            "_measurement".into(),
+        )];
+
+        let plan_builder = LogicalPlanBuilder::from(&LogicalPlan::InMemoryScan {
+            data: vec![vec![data]],
+            schema,
+            projection,
+            projected_schema,
+        });
+
+        let plan_builder = Self::add_datafusion_predicate(plan_builder, partition_predicate)?;
+
+        plan_builder
+            .project(select_exprs)
+            .context(BuildingPlan)?
+            .limit(1)
+            .context(BuildingPlan)?
+            .build()
+            .context(BuildingPlan)
+    }
+
     /// Creates a SeriesSet plan that produces an output table with rows that match the predicate
     ///
     /// The output looks like:
@@ -603,7 +748,7 @@ impl Table {
     // Returns (tag_columns, field_columns) vectors with the names of
     // all tag and field columns, respectively. The vectors are sorted
     // by name.
-    fn tag_and_field_column_names(
+    pub fn tag_and_field_column_names(
         &self,
         partition_predicate: &PartitionPredicate,
         partition: &Partition,
@@ -691,7 +836,7 @@ impl Table {
         } else {
             let columns_with_index = self.column_names_with_index(partition, requested_columns)?;
 
-            self.to_arrow_impl(partition, &columns_with_index)
+            self.to_arrow_impl(partition, &columns_with_index, None)
         }
     }
 
@@ -742,27 +887,47 @@ impl Table {
 
         requested_columns_with_index.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-        self.to_arrow_impl(partition, &requested_columns_with_index)
+        self.to_arrow_impl(partition, &requested_columns_with_index, None)
+    }
+
+    /// Returns the arrow fields of the columns of this table, with the
+    /// types `to_arrow_impl` converts them to
+    pub fn arrow_fields(&self, partition: &Partition) -> Result<Vec<ArrowField>> {
+        self.column_id_to_index
+            .iter()
+            .map(|(&column_id, &column_index)| {
+                let column_name = partition.dictionary.lookup_id(column_id).context(
+                    ColumnIdNotFoundInDictionary {
+                        column_id,
+                        partition: &partition.key,
+                    },
+                )?;
+                let data_type = arrow_data_type(&self.columns[column_index]);
+                Ok(ArrowField::new(column_name, data_type, true))
+            })
+            .collect()
     }
 
     /// Converts this table to an arrow record batch,
     ///
-    /// requested columns with index are tuples of column_name, column_index
+    /// requested columns with index are tuples of column_name, column_index.
+    /// If `rows` is given, only the rows for which it is true are converted.
     pub fn to_arrow_impl(
         &self,
         partition: &Partition,
         requested_columns_with_index: &[(&str, usize)],
+        rows: Option<&[bool]>,
     ) -> Result<RecordBatch> {
         let mut fields = Vec::with_capacity(requested_columns_with_index.len());
         let mut columns: Vec<ArrayRef> = Vec::with_capacity(requested_columns_with_index.len());
 
         for &(column_name, column_index) in requested_columns_with_index.iter() {
             let arrow_col: ArrayRef = match &self.columns[column_index] {
-                Column::String(vals, _) => {
+                Column::String(vals, _, _) => {
                     fields.push(ArrowField::new(column_name, ArrowDataType::Utf8, true));
                     let mut builder = StringBuilder::with_capacity(vals.len(), vals.len() * 10);
 
-                    for v in vals {
+                    for v in selected(vals, rows) {
                         match v {
                             None => builder.append_null(),
                             Some(s) => builder.append_value(s),
@@ -776,7 +941,7 @@ impl Table {
                     fields.push(ArrowField::new(column_name, ArrowDataType::Utf8, true));
                     let mut builder = StringBuilder::with_capacity(vals.len(), vals.len() * 10);
 
-                    for v in vals {
+                    for v in selected(vals, rows) {
                         match v {
                             None => builder.append_null(),
                             Some(value_id) => {
@@ -798,7 +963,7 @@ impl Table {
                     fields.push(ArrowField::new(column_name, ArrowDataType::Float64, true));
                     let mut builder = Float64Builder::new(vals.len());
 
-                    for v in vals {
+                    for v in selected(vals, rows) {
                         builder.append_option(*v).context(ArrowError {})?;
                     }
 
@@ -808,7 +973,17 @@ impl Table {
                     fields.push(ArrowField::new(column_name, ArrowDataType::Int64, true));
                     let mut builder = Int64Builder::new(vals.len());
 
-                    for v in vals {
+                    for v in selected(vals, rows) {
+                        builder.append_option(*v).context(ArrowError {})?;
+                    }
+
+                    Arc::new(builder.finish())
+                }
+                Column::U64(vals, _) => {
+                    fields.push(ArrowField::new(column_name, ArrowDataType::UInt64, true));
+                    let mut builder = UInt64Builder::new(vals.len());
+
+                    for v in selected(vals, rows) {
                         builder.append_option(*v).context(ArrowError {})?;
                     }
 
@@ -818,7 +993,7 @@ impl Table {
                     fields.push(ArrowField::new(column_name, ArrowDataType::Boolean, true));
                     let mut builder = BooleanBuilder::new(vals.len());
 
-                    for v in vals {
+                    for v in selected(vals, rows) {
                         builder.append_option(*v).context(ArrowError {})?;
                     }
 
@@ -846,10 +1021,30 @@ impl Table {
                     partition_predicate.table_name_predicate.as_ref(),
                 )
                 && self.matches_timestamp_predicate(partition_predicate)?
-                && self.has_columns(partition_predicate.required_columns.as_ref()),
+                && self.has_columns(partition_predicate.required_columns.as_ref())
+                && self.matches_tag_values(&partition_predicate.tag_value_restrictions),
         )
     }
 
+    /// Returns true if, for each (column id, value ids) restriction,
+    /// the column has at least one of the values, or is not a tag
+    /// column of this table
+    fn matches_tag_values(&self, tag_value_restrictions: &[(u32, BTreeSet<u32>)]) -> bool {
+        tag_value_restrictions.iter().all(|(column_id, value_ids)| {
+            let column = self
+                .column_id_to_index
+                .get(column_id)
+                .map(|&index| &self.columns[index]);
+
+            match column {
+                Some(Column::Tag(values, _)) => values
+                    .iter()
+                    .any(|value| value.map_or(false, |id| value_ids.contains(&id))),
+                _ => true,
+            }
+        })
+    }
+
     /// Returns true if the table contains at least one of the fields
     /// requested or there are no specific fields requested.
     fn matches_column_selection(&self, column_selection: Option<&BTreeSet<u32>>) -> bool {
@@ -931,6 +1126,148 @@ impl Table {
     }
 }
 
+/// Returns the arrow type `Table::to_arrow_impl` converts `column` to
+fn arrow_data_type(column: &Column) -> ArrowDataType {
+    match column {
+        Column::String(..) | Column::Tag(..) => ArrowDataType::Utf8,
+        Column::F64(..) => ArrowDataType::Float64,
+        Column::I64(..) => ArrowDataType::Int64,
+        Column::U64(..) => ArrowDataType::UInt64,
+        Column::Bool(..) => ArrowDataType::Boolean,
+    }
+}
+
+/// Returns the values whose entry in `rows` is true, or all of them if
+/// `rows` is `None`
+fn selected<'a, T>(values: &'a [T], rows: Option<&'a [bool]>) -> impl Iterator<Item = &'a T> {
+    values
+        .iter()
+        .enumerate()
+        .filter(move |(i, _)| rows.map_or(true, |rows| rows[*i]))
+        .map(|(_, v)| v)
+}
+
+/// Creates a SeriesSet plan that aggregates the rows of a table that
+/// match `filter_expr` into a single row per series, or per `window` of
+/// each series if specified. `data` holds the rows of the table in all the
+/// partitions it is read from, with the same schema.
+///
+/// The output looks like:
+/// (prefix_column1, prefix_column2, ... tag_col1, tag_col2, ... field1, field2, ... timestamp)
+///
+/// where each field is `aggregate` of its values in the series, or
+/// window, and the timestamp is that of the last row of the series, or the
+/// end of the window. The fields that can't be aggregated that way are
+/// left out; returns `None` if no field is left.
+///
+/// The data is sorted on (tag_col1, tag_col2, ..., timestamp)
+///
+/// The created plan looks like:
+///
+///    Projection (select the columns needed)
+///      Order by (tag_columns, timestamp_column)
+///        Aggregate (group by tag_columns and window)
+///          Filter(predicate)
+///            InMemoryScan
+#[allow(clippy::too_many_arguments)]
+pub fn aggregate_series_set_plan(
+    table_name: Arc<String>,
+    data: Vec<RecordBatch>,
+    tag_columns: Vec<Arc<String>>,
+    field_columns: Vec<Arc<String>>,
+    filter_expr: Option<Expr>,
+    prefix_columns: &[String],
+    aggregate: Aggregate,
+    window: Option<Window>,
+) -> Result<Option<SeriesSetPlan>> {
+    let schema = match data.first() {
+        Some(batch) => batch.schema(),
+        None => return Ok(None),
+    };
+
+    // Aggregation, keeping the names of the columns
+    let mut aggregate_exprs = Vec::with_capacity(field_columns.len() + 1);
+    let mut aggregated_field_columns = Vec::with_capacity(field_columns.len());
+    for c in field_columns {
+        let data_type = schema
+            .field_with_name(&c)
+            .context(ArrowError {})?
+            .data_type();
+
+        if let Some(expr) = aggregate.to_datafusion_expr(&c, data_type, TIME_COLUMN_NAME) {
+            aggregate_exprs.push(Expr::Alias(Box::new(expr), c.as_ref().clone()));
+            aggregated_field_columns.push(c);
+        }
+    }
+    let field_columns = aggregated_field_columns;
+    if field_columns.is_empty() {
+        return Ok(None);
+    }
+
+    let tag_columns = reorder_prefix(prefix_columns, tag_columns)?;
+
+    let mut group_exprs = tag_columns
+        .iter()
+        .map(|c| c.into_expr())
+        .collect::<Vec<_>>();
+    match window {
+        Some(window) => group_exprs.push(Expr::Alias(
+            Box::new(window.to_end_expr(TIME_COLUMN_NAME)),
+            TIME_COLUMN_NAME.to_string(),
+        )),
+        None => aggregate_exprs.push(Expr::Alias(
+            Box::new(logical_plan::max(TIME_COLUMN_NAME.into_expr())),
+            TIME_COLUMN_NAME.to_string(),
+        )),
+    }
+
+    let projection = None;
+    let projected_schema = schema.clone();
+
+    // And build the plan from the bottom up
+    let plan_builder = LogicalPlanBuilder::from(&LogicalPlan::InMemoryScan {
+        data: vec![data],
+        schema,
+        projection,
+        projected_schema,
+    });
+
+    // Filtering
+    let plan_builder = match filter_expr {
+        Some(df_predicate) => plan_builder.filter(df_predicate).context(BuildingPlan)?,
+        None => plan_builder,
+    };
+
+    let plan_builder = plan_builder
+        .aggregate(group_exprs, aggregate_exprs)
+        .context(BuildingPlan)?;
+
+    // Order by
+    let mut sort_exprs = Vec::new();
+    sort_exprs.extend(tag_columns.iter().map(|c| c.into_sort_expr()));
+    sort_exprs.push(TIME_COLUMN_NAME.into_sort_expr());
+
+    let plan_builder = plan_builder.sort(sort_exprs).context(BuildingPlan)?;
+
+    // Selection
+    let mut select_exprs = Vec::new();
+    select_exprs.extend(tag_columns.iter().map(|c| c.into_expr()));
+    select_exprs.extend(field_columns.iter().map(|c| aggregate.to_output_expr(c)));
+    select_exprs.push(TIME_COLUMN_NAME.into_expr());
+
+    let plan_builder = plan_builder.project(select_exprs).context(BuildingPlan)?;
+
+    // and finally create the plan
+    let plan = plan_builder.build().context(BuildingPlan)?;
+
+    Ok(Some(SeriesSetPlan {
+        table_name,
+        plan,
+        tag_columns,
+        field_columns,
+    }))
+}
+
 /// Reorders tag_columns so that its prefix matches exactly
 /// prefix_columns. Returns an error if there are duplicates, or other
 /// untoward inputs
@@ -1022,8 +1359,9 @@ impl IntoExpr for str {
 #[cfg(test)]
 mod tests {
     use arrow::util::pretty::pretty_format_batches;
+    use chrono::{DateTime, Utc};
     use data_types::data::split_lines_into_write_entry_partitions;
-    use datafusion::{logical_plan::Operator, scalar::ScalarValue};
+    use datafusion::logical_plan::Operator;
     use influxdb_line_protocol::{parse_lines, ParsedLine};
     use storage::{exec::Executor, predicate::PredicateBuilder};
     use test_helpers::str_vec_to_arc_vec;
@@ -1104,6 +1442,38 @@ mod tests {
         assert!(!table.matches_table_name_predicate(Some(&set)));
     }
 
+    #[test]
+    fn test_matches_tag_values() {
+        // setup a test table
+        let mut partition = Partition::new("dummy_partition_key");
+        let dictionary = &mut partition.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o"));
+
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=CA,city=LA temp=72.4 250",
+        ];
+        write_lines_to_table(&mut table, dictionary, lp_lines);
+
+        let state_symbol = dictionary.id("state").unwrap();
+        let temp_symbol = dictionary.id("temp").unwrap();
+        let ca_symbol = dictionary.id("CA").unwrap();
+        let boston_symbol = dictionary.id("Boston").unwrap();
+
+        assert!(table.matches_tag_values(&[]));
+
+        let set: BTreeSet<_> = vec![ca_symbol].into_iter().collect();
+        assert!(table.matches_tag_values(&[(state_symbol, set)]));
+
+        // Boston is a city, not a state
+        let set: BTreeSet<_> = vec![boston_symbol].into_iter().collect();
+        assert!(!table.matches_tag_values(&[(state_symbol, set)]));
+        assert!(!table.matches_tag_values(&[(state_symbol, BTreeSet::new())]));
+
+        // only the values of tags are restricted
+        assert!(table.matches_tag_values(&[(temp_symbol, BTreeSet::new())]));
+    }
+
     #[tokio::test]
     async fn test_series_set_plan() {
         // setup a test table
@@ -1462,7 +1832,8 @@ mod tests {
 
         let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
 
-        let data = split_lines_into_write_entry_partitions(partition_key_func, &lines);
+        let data = split_lines_into_write_entry_partitions(partition_key_func, &lines)
+            .expect("computed partition keys");
 
         let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
         let entries = batch.entries().expect("at least one entry");
@@ -1478,7 +1849,10 @@ mod tests {
         }
     }
 
-    fn partition_key_func(_: &ParsedLine<'_>) -> String {
-        String::from("the_partition_key")
+    fn partition_key_func(
+        _: &ParsedLine<'_>,
+        _: &DateTime<Utc>,
+    ) -> Result<String, std::convert::Infallible> {
+        Ok(String::from("the_partition_key"))
     }
 }